@@ -1,7 +1,35 @@
 use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 fn main() {
-    if env::var("DOCS_RS") == Ok("1".to_string()) {
+    println!("cargo:rerun-if-changed=build.rs");
+    let docs_rs = env::var("DOCS_RS") == Ok("1".to_string());
+    // The fixture plugins are only used by `lilv::testing`, so they are only
+    // built with the `testing` feature, and a failure to build them should
+    // not fail the build of the library itself.
+    if env::var_os("CARGO_FEATURE_TESTING").is_some() {
+        println!("cargo:rerun-if-changed=fixtures");
+        let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap_or_default());
+        let built = if docs_rs {
+            Ok(())
+        } else {
+            build_fixture_plugins(&out_dir)
+        };
+        if let Err(err) = &built {
+            println!(
+                "cargo:warning=could not build the LV2 test fixture plugins: {}",
+                err
+            );
+        }
+        // `lilv::testing` embeds the library, so it must exist.
+        if docs_rs || built.is_err() {
+            let _ = fs::write(out_dir.join("fixtures.so"), []);
+        }
+    }
+    if docs_rs {
         return;
     }
     pkg_config::Config::new()
@@ -12,4 +40,51 @@ fn main() {
         .atleast_version("0.30.0")
         .probe("serd-0")
         .expect("serd-0 could not be found with pkg_config.");
+    // `jack_host` declares the parts of the JACK API it uses, like those of
+    // Serd, so the library is found the same way.
+    if env::var_os("CARGO_FEATURE_JACK").is_some() {
+        pkg_config::Config::new().probe("jack").expect(
+            "jack could not be found with pkg_config. The jack feature needs the JACK \
+             development files, like libjack-jackd2-dev.",
+        );
+    }
+}
+
+/// Compile `fixtures/plugins.rs` to `$OUT_DIR/fixtures.so` for the target.
+fn build_fixture_plugins(out_dir: &Path) -> io::Result<()> {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let mut command = Command::new(rustc);
+    command
+        .args(["--crate-type", "cdylib", "--crate-name", "lilv_fixtures"])
+        .args(["--edition", "2018", "--cap-lints", "allow"])
+        .args([
+            "-C",
+            "opt-level=s",
+            "-C",
+            "panic=abort",
+            "-C",
+            "strip=symbols",
+        ])
+        .arg("-o")
+        .arg(out_dir.join("fixtures.so"))
+        .arg(Path::new("fixtures").join("plugins.rs"));
+    if let Some(target) = env::var_os("TARGET") {
+        command.arg("--target").arg(target);
+    }
+    if let Some(linker) = env::var_os("RUSTC_LINKER") {
+        command
+            .arg("-C")
+            .arg(format!("linker={}", linker.to_string_lossy()));
+    }
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        // Warnings of the build script are a single line.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let error = stderr.lines().find(|line| line.starts_with("error"));
+        Err(io::Error::other(
+            error.unwrap_or("rustc failed").to_string(),
+        ))
+    }
 }
@@ -0,0 +1,16 @@
+fn main() {
+    match lilv::bridge::serve_from_env() {
+        Some(Ok(())) => {}
+        Some(Err(err)) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        None => {
+            eprintln!(
+                "bridge_server is started by BridgedInstance::spawn with {} set",
+                lilv::bridge::ADDRESS_VAR
+            );
+            std::process::exit(1);
+        }
+    }
+}
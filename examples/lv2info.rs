@@ -1,8 +1,11 @@
-use lilv::{node::Node, plugin::Plugin, port::FloatRanges, World};
+use lilv::{
+    node::Node,
+    plugin::Plugin,
+    port::{FloatRanges, PortKind},
+    World,
+};
 
 struct Nodes {
-    control_class: Node,
-    event_class: Node,
     group_pred: Node,
     label_pred: Node,
     preset_class: Node,
@@ -31,7 +34,7 @@ fn print_port(p: &Plugin, index: usize, port_ranges: &FloatRanges, nodes: &Nodes
         print!("{}", value.as_uri().unwrap());
     }
 
-    if port.is_a(&nodes.event_class) {
+    if port.kind() == PortKind::Atom {
         let supported = port.value(&nodes.supports_event_pred);
         if supported.count() > 0 {
             println!("\n\t\tSupported events:\n");
@@ -71,7 +74,7 @@ fn print_port(p: &Plugin, index: usize, port_ranges: &FloatRanges, nodes: &Nodes
         println!("\t\tDesignation: {}", designation.as_str().unwrap(),);
     }
 
-    if port.is_a(&nodes.control_class) {
+    if port.kind() == PortKind::Control {
         let (min, max, def) = (port_ranges.min, port_ranges.max, port_ranges.default);
 
         if !min.is_nan() {
@@ -237,13 +240,21 @@ fn main() {
     w.load_all();
 
     let nodes = Nodes {
-        control_class: w.new_uri("http://lv2plug.in/ns/lv2core#ControlPort"),
-        event_class: w.new_uri("http://lv2plug.in/ns/ext/atom#AtomPort"),
-        group_pred: w.new_uri("http://lv2plug.in/ns/ext/port-groups#group"),
-        label_pred: w.new_uri("http://www.w3.org/2000/01/rdf-schema#label"),
-        preset_class: w.new_uri("http://lv2plug.in/ns/ext/presets#Preset"),
-        designation_pred: w.new_uri("http://lv2plug.in/ns/lv2core#designation"),
-        supports_event_pred: w.new_uri("http://lv2plug.in/ns/ext/atom#supportsEvent"),
+        group_pred: w
+            .new_uri("http://lv2plug.in/ns/ext/port-groups#group")
+            .unwrap(),
+        label_pred: w
+            .new_uri("http://www.w3.org/2000/01/rdf-schema#label")
+            .unwrap(),
+        preset_class: w
+            .new_uri("http://lv2plug.in/ns/ext/presets#Preset")
+            .unwrap(),
+        designation_pred: w
+            .new_uri("http://lv2plug.in/ns/lv2core#designation")
+            .unwrap(),
+        supports_event_pred: w
+            .new_uri("http://lv2plug.in/ns/ext/atom#supportsEvent")
+            .unwrap(),
     };
 
     for p in w.plugins().iter().filter(Plugin::verify) {
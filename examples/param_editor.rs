@@ -0,0 +1,28 @@
+use lilv::{tui::ParameterEditor, World};
+
+fn main() {
+    let uri = match std::env::args().nth(1) {
+        Some(uri) => uri,
+        None => {
+            eprintln!("usage: param_editor PLUGIN_URI");
+            std::process::exit(1);
+        }
+    };
+    let world = World::with_load_all();
+    let plugin = world
+        .new_uri(&uri)
+        .ok()
+        .and_then(|uri| world.plugins().plugin(&uri))
+        .unwrap_or_else(|| {
+            eprintln!("plugin {} not found", uri);
+            std::process::exit(1);
+        });
+    let mut editor = unsafe { ParameterEditor::new(plugin, 44100.0) }.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let stdin = std::io::stdin();
+    if let Err(err) = editor.run(stdin.lock(), std::io::stdout()) {
+        eprintln!("{}", err);
+    }
+}
@@ -0,0 +1,660 @@
+//! The plugins of the fixture bundles, built into one library by `build.rs`.
+//!
+//! The plugins only depend on `std`, so the build script compiles them with
+//! `rustc` directly. They are as small as they can be while still behaving
+//! like real plugins:
+//!
+//! - `gain` scales its input by the `gain` port in dB, like `eg-amp`.
+//! - `midi-through` copies the MIDI events of its input to its output.
+//! - `sampler` loads a file of little-endian `f32` samples set with a
+//!   `patch:Set` of `#sample` through the worker and loops it. It writes an
+//!   overview of the sample with `state:makePath` and saves both paths in its
+//!   state, like `eg-sampler` does with its sample.
+
+use std::ffi::CStr;
+use std::fs;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+const GAIN_URI: &[u8] = b"urn:lilv-rs:fixture:gain\0";
+const MIDI_THROUGH_URI: &[u8] = b"urn:lilv-rs:fixture:midi-through\0";
+const SAMPLER_URI: &[u8] = b"urn:lilv-rs:fixture:sampler\0";
+const SAMPLER_SAMPLE: &[u8] = b"urn:lilv-rs:fixture:sampler#sample\0";
+const SAMPLER_OVERVIEW: &[u8] = b"urn:lilv-rs:fixture:sampler#overview\0";
+
+const ATOM_OBJECT: &[u8] = b"http://lv2plug.in/ns/ext/atom#Object\0";
+const ATOM_PATH: &[u8] = b"http://lv2plug.in/ns/ext/atom#Path\0";
+const ATOM_SEQUENCE: &[u8] = b"http://lv2plug.in/ns/ext/atom#Sequence\0";
+const MIDI_EVENT: &[u8] = b"http://lv2plug.in/ns/ext/midi#MidiEvent\0";
+const PATCH_SET: &[u8] = b"http://lv2plug.in/ns/ext/patch#Set\0";
+const PATCH_PROPERTY: &[u8] = b"http://lv2plug.in/ns/ext/patch#property\0";
+const PATCH_VALUE: &[u8] = b"http://lv2plug.in/ns/ext/patch#value\0";
+const STATE_FREE_PATH: &[u8] = b"http://lv2plug.in/ns/ext/state#freePath\0";
+const STATE_INTERFACE: &[u8] = b"http://lv2plug.in/ns/ext/state#interface\0";
+const STATE_MAKE_PATH: &[u8] = b"http://lv2plug.in/ns/ext/state#makePath\0";
+const STATE_MAP_PATH: &[u8] = b"http://lv2plug.in/ns/ext/state#mapPath\0";
+const URID_MAP: &[u8] = b"http://lv2plug.in/ns/ext/urid#map\0";
+const WORKER_INTERFACE: &[u8] = b"http://lv2plug.in/ns/ext/worker#interface\0";
+const WORKER_SCHEDULE: &[u8] = b"http://lv2plug.in/ns/ext/worker#schedule\0";
+
+// LV2_STATE_IS_POD | LV2_STATE_IS_PORTABLE.
+const STATE_POD_PORTABLE: u32 = 1 | 2;
+
+type Handle = *mut c_void;
+
+#[repr(C)]
+pub struct Feature {
+    uri: *const c_char,
+    data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct Descriptor {
+    uri: *const c_char,
+    instantiate: unsafe extern "C" fn(
+        *const Descriptor,
+        f64,
+        *const c_char,
+        *const *const Feature,
+    ) -> Handle,
+    connect_port: unsafe extern "C" fn(Handle, u32, *mut c_void),
+    activate: Option<unsafe extern "C" fn(Handle)>,
+    run: unsafe extern "C" fn(Handle, u32),
+    deactivate: Option<unsafe extern "C" fn(Handle)>,
+    cleanup: unsafe extern "C" fn(Handle),
+    extension_data: unsafe extern "C" fn(*const c_char) -> *const c_void,
+}
+
+struct Descriptors([Descriptor; 3]);
+
+// The descriptors only contain pointers to static data.
+unsafe impl Sync for Descriptors {}
+
+static DESCRIPTORS: Descriptors = Descriptors([
+    Descriptor {
+        uri: GAIN_URI.as_ptr().cast(),
+        instantiate: gain_instantiate,
+        connect_port: gain_connect_port,
+        activate: None,
+        run: gain_run,
+        deactivate: None,
+        cleanup: cleanup::<Gain>,
+        extension_data: no_extension_data,
+    },
+    Descriptor {
+        uri: MIDI_THROUGH_URI.as_ptr().cast(),
+        instantiate: midi_through_instantiate,
+        connect_port: midi_through_connect_port,
+        activate: None,
+        run: midi_through_run,
+        deactivate: None,
+        cleanup: cleanup::<MidiThrough>,
+        extension_data: no_extension_data,
+    },
+    Descriptor {
+        uri: SAMPLER_URI.as_ptr().cast(),
+        instantiate: sampler_instantiate,
+        connect_port: sampler_connect_port,
+        activate: Some(sampler_activate),
+        run: sampler_run,
+        deactivate: None,
+        cleanup: cleanup::<Sampler>,
+        extension_data: sampler_extension_data,
+    },
+]);
+
+/// The entry point lilv looks up in the library.
+#[no_mangle]
+pub extern "C" fn lv2_descriptor(index: u32) -> *const Descriptor {
+    DESCRIPTORS
+        .0
+        .get(index as usize)
+        .map_or(ptr::null(), |descriptor| descriptor)
+}
+
+unsafe extern "C" fn cleanup<T>(instance: Handle) {
+    drop(unsafe { Box::from_raw(instance.cast::<T>()) });
+}
+
+unsafe extern "C" fn no_extension_data(_uri: *const c_char) -> *const c_void {
+    ptr::null()
+}
+
+// The data of the feature `uri`, or null if it was not provided.
+unsafe fn feature(features: *const *const Feature, uri: &[u8]) -> *mut c_void {
+    let uri = CStr::from_bytes_with_nul(uri).unwrap_or_default();
+    let mut feature = features;
+    while !feature.is_null() && !unsafe { *feature }.is_null() {
+        let f = unsafe { &**feature };
+        if unsafe { CStr::from_ptr(f.uri) } == uri {
+            return f.data;
+        }
+        feature = unsafe { feature.add(1) };
+    }
+    ptr::null_mut()
+}
+
+fn boxed<T>(instance: T) -> Handle {
+    Box::into_raw(Box::new(instance)).cast()
+}
+
+// Atoms in containers are aligned to 8 bytes.
+fn pad(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+#[repr(C)]
+struct UridMap {
+    handle: *mut c_void,
+    map: extern "C" fn(*mut c_void, *const c_char) -> u32,
+}
+
+impl UridMap {
+    fn map(&self, uri: &[u8]) -> u32 {
+        (self.map)(self.handle, uri.as_ptr().cast())
+    }
+}
+
+// LV2_Atom, followed by the body of the atom.
+#[repr(C)]
+struct Atom {
+    size: u32,
+    mytype: u32,
+}
+
+// The events of the sequence `atom` as (type, body) pairs.
+unsafe fn events<'a>(atom: *const Atom) -> impl Iterator<Item = (u32, &'a [u8])> {
+    let body: &'a [u8] = if atom.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(atom.add(1).cast(), (*atom).size as usize) }
+    };
+    // The sequence body has a unit and padding, then each event has a time,
+    // then an atom header.
+    let mut offset = 8;
+    std::iter::from_fn(move || {
+        let header = body.get(offset..offset + 16)?;
+        let size = u32::from_ne_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let mytype = u32::from_ne_bytes([header[12], header[13], header[14], header[15]]);
+        let event = body.get(offset + 16..offset + 16 + size)?;
+        offset += pad(16 + size);
+        Some((mytype, event))
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+struct Gain {
+    gain: *const f32,
+    input: *const f32,
+    output: *mut f32,
+}
+
+unsafe extern "C" fn gain_instantiate(
+    _descriptor: *const Descriptor,
+    _rate: f64,
+    _bundle_path: *const c_char,
+    _features: *const *const Feature,
+) -> Handle {
+    boxed(Gain {
+        gain: ptr::null(),
+        input: ptr::null(),
+        output: ptr::null_mut(),
+    })
+}
+
+unsafe extern "C" fn gain_connect_port(instance: Handle, port: u32, data: *mut c_void) {
+    let gain = unsafe { &mut *instance.cast::<Gain>() };
+    match port {
+        0 => gain.gain = data.cast(),
+        1 => gain.input = data.cast(),
+        2 => gain.output = data.cast(),
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn gain_run(instance: Handle, sample_count: u32) {
+    let gain = unsafe { &*instance.cast::<Gain>() };
+    let db = unsafe { *gain.gain };
+    let coefficient = if db > -90.0 {
+        10_f32.powf(db * 0.05)
+    } else {
+        0.0
+    };
+    // The input and output may be the same buffer.
+    for i in 0..sample_count as usize {
+        unsafe { *gain.output.add(i) = *gain.input.add(i) * coefficient };
+    }
+}
+
+struct MidiThrough {
+    input: *const Atom,
+    output: *mut Atom,
+    sequence: u32,
+    midi_event: u32,
+}
+
+unsafe extern "C" fn midi_through_instantiate(
+    _descriptor: *const Descriptor,
+    _rate: f64,
+    _bundle_path: *const c_char,
+    features: *const *const Feature,
+) -> Handle {
+    let map = unsafe { feature(features, URID_MAP) }.cast::<UridMap>();
+    let map = match unsafe { map.as_ref() } {
+        Some(map) => map,
+        None => return ptr::null_mut(),
+    };
+    boxed(MidiThrough {
+        input: ptr::null(),
+        output: ptr::null_mut(),
+        sequence: map.map(ATOM_SEQUENCE),
+        midi_event: map.map(MIDI_EVENT),
+    })
+}
+
+unsafe extern "C" fn midi_through_connect_port(instance: Handle, port: u32, data: *mut c_void) {
+    let through = unsafe { &mut *instance.cast::<MidiThrough>() };
+    match port {
+        0 => through.input = data.cast(),
+        1 => through.output = data.cast(),
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn midi_through_run(instance: Handle, _sample_count: u32) {
+    let through = unsafe { &*instance.cast::<MidiThrough>() };
+    // The host sets the size of the output to its capacity.
+    let capacity = unsafe { (*through.output).size } as usize;
+    if capacity < 8 {
+        return;
+    }
+    let out = unsafe { through.output.add(1).cast::<u8>() };
+    // An empty sequence body with frames as the unit.
+    unsafe { ptr::write_bytes(out, 0, 8) };
+    let mut len = 8;
+    // Events start with their time, which is right before their atom.
+    let input = through.input;
+    let body = unsafe { input.add(1).cast::<u8>() };
+    for (mytype, event) in unsafe { events(input) } {
+        let start = unsafe { event.as_ptr().offset_from(body) } as usize - 16;
+        let size = 16 + event.len();
+        if mytype != through.midi_event || len + size > capacity {
+            continue;
+        }
+        unsafe { ptr::copy_nonoverlapping(body.add(start), out.add(len), size) };
+        len = pad(len + size).min(capacity);
+    }
+    unsafe {
+        (*through.output).size = len as u32;
+        (*through.output).mytype = through.sequence;
+    }
+}
+
+// LV2_Worker_Schedule.
+#[repr(C)]
+struct WorkerSchedule {
+    handle: *mut c_void,
+    schedule_work: extern "C" fn(*mut c_void, u32, *const c_void) -> u32,
+}
+
+type Respond = extern "C" fn(*mut c_void, u32, *const c_void) -> u32;
+
+// LV2_Worker_Interface.
+#[repr(C)]
+struct WorkerInterface {
+    work: unsafe extern "C" fn(Handle, Respond, *mut c_void, u32, *const c_void) -> u32,
+    work_response: unsafe extern "C" fn(Handle, u32, *const c_void) -> u32,
+    end_run: Option<unsafe extern "C" fn(Handle) -> u32>,
+}
+
+type Store = extern "C" fn(*mut c_void, u32, *const c_void, usize, u32, u32) -> u32;
+type Retrieve = extern "C" fn(*mut c_void, u32, *mut usize, *mut u32, *mut u32) -> *const c_void;
+
+// LV2_State_Interface.
+#[repr(C)]
+struct StateInterface {
+    save: unsafe extern "C" fn(Handle, Store, *mut c_void, u32, *const *const Feature) -> u32,
+    restore: unsafe extern "C" fn(Handle, Retrieve, *mut c_void, u32, *const *const Feature) -> u32,
+}
+
+// LV2_State_Map_Path.
+#[repr(C)]
+struct MapPath {
+    handle: *mut c_void,
+    abstract_path: extern "C" fn(*mut c_void, *const c_char) -> *mut c_char,
+    absolute_path: extern "C" fn(*mut c_void, *const c_char) -> *mut c_char,
+}
+
+// LV2_State_Make_Path.
+#[repr(C)]
+struct MakePath {
+    handle: *mut c_void,
+    path: extern "C" fn(*mut c_void, *const c_char) -> *mut c_char,
+}
+
+// LV2_State_Free_Path.
+#[repr(C)]
+struct FreePath {
+    handle: *mut c_void,
+    free_path: extern "C" fn(*mut c_void, *mut c_char),
+}
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
+
+static WORKER: WorkerInterface = WorkerInterface {
+    work: sampler_work,
+    work_response: sampler_work_response,
+    end_run: None,
+};
+
+static STATE: StateInterface = StateInterface {
+    save: sampler_save,
+    restore: sampler_restore,
+};
+
+struct SamplerUrids {
+    object: u32,
+    path: u32,
+    patch_set: u32,
+    patch_property: u32,
+    patch_value: u32,
+    sample: u32,
+    overview: u32,
+}
+
+struct Sampler {
+    control: *const Atom,
+    output: *mut f32,
+    schedule: *const WorkerSchedule,
+    make_path: *const MakePath,
+    free_path: *const FreePath,
+    urids: SamplerUrids,
+    sample: Option<Box<Sample>>,
+    frame: usize,
+}
+
+struct Sample {
+    path: String,
+    overview: Option<String>,
+    frames: Vec<f32>,
+}
+
+unsafe extern "C" fn sampler_instantiate(
+    _descriptor: *const Descriptor,
+    _rate: f64,
+    _bundle_path: *const c_char,
+    features: *const *const Feature,
+) -> Handle {
+    let map = unsafe { feature(features, URID_MAP) }.cast::<UridMap>();
+    let schedule = unsafe { feature(features, WORKER_SCHEDULE) }.cast::<WorkerSchedule>();
+    let map = match unsafe { map.as_ref() } {
+        Some(map) if !schedule.is_null() => map,
+        _ => return ptr::null_mut(),
+    };
+    boxed(Sampler {
+        control: ptr::null(),
+        output: ptr::null_mut(),
+        schedule,
+        make_path: unsafe { feature(features, STATE_MAKE_PATH) }.cast(),
+        free_path: unsafe { feature(features, STATE_FREE_PATH) }.cast(),
+        urids: SamplerUrids {
+            object: map.map(ATOM_OBJECT),
+            path: map.map(ATOM_PATH),
+            patch_set: map.map(PATCH_SET),
+            patch_property: map.map(PATCH_PROPERTY),
+            patch_value: map.map(PATCH_VALUE),
+            sample: map.map(SAMPLER_SAMPLE),
+            overview: map.map(SAMPLER_OVERVIEW),
+        },
+        sample: None,
+        frame: 0,
+    })
+}
+
+unsafe extern "C" fn sampler_connect_port(instance: Handle, port: u32, data: *mut c_void) {
+    let sampler = unsafe { &mut *instance.cast::<Sampler>() };
+    match port {
+        0 => sampler.control = data.cast(),
+        1 => sampler.output = data.cast(),
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn sampler_activate(instance: Handle) {
+    unsafe { &mut *instance.cast::<Sampler>() }.frame = 0;
+}
+
+unsafe extern "C" fn sampler_run(instance: Handle, sample_count: u32) {
+    let sampler = unsafe { &mut *instance.cast::<Sampler>() };
+    for (mytype, body) in unsafe { events(sampler.control) } {
+        if mytype != sampler.urids.object {
+            continue;
+        }
+        if let Some(path) = sampler.set_sample(body) {
+            let schedule = unsafe { &*sampler.schedule };
+            (schedule.schedule_work)(schedule.handle, path.len() as u32, path.as_ptr().cast());
+        }
+    }
+    let frames = sampler.sample.as_ref().map_or(&[][..], |s| &s.frames[..]);
+    for i in 0..sample_count as usize {
+        let value = if frames.is_empty() {
+            0.0
+        } else {
+            frames[(sampler.frame + i) % frames.len()]
+        };
+        unsafe { *sampler.output.add(i) = value };
+    }
+    sampler.frame += sample_count as usize;
+}
+
+impl Sampler {
+    // The path of a `patch:Set` of the sample in the object `body`.
+    fn set_sample<'a>(&self, body: &'a [u8]) -> Option<&'a [u8]> {
+        if read_u32(body, 4)? != self.urids.patch_set {
+            return None;
+        }
+        let (mut property, mut value) = (None, None);
+        // The object body has an id and a type, then each property has a key
+        // and a context, then an atom.
+        let mut offset = 8;
+        while let Some(key) = read_u32(body, offset) {
+            let size = read_u32(body, offset + 8)? as usize;
+            let mytype = read_u32(body, offset + 12)?;
+            let atom = body.get(offset + 16..offset + 16 + size)?;
+            if key == self.urids.patch_property {
+                property = read_u32(atom, 0);
+            } else if key == self.urids.patch_value && mytype == self.urids.path {
+                value = Some(atom);
+            }
+            offset += pad(16 + size);
+        }
+        if property != Some(self.urids.sample) {
+            return None;
+        }
+        // The path without its NUL terminator.
+        let value = value?;
+        Some(&value[..value.iter().position(|b| *b == 0).unwrap_or(value.len())])
+    }
+
+    // Load the sample at `path` and write its overview with `make_path`.
+    fn load(&self, path: &str) -> Option<Box<Sample>> {
+        let bytes = fs::read(path).ok()?;
+        let frames: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let overview = unsafe { self.make_path.as_ref() }.and_then(|make_path| {
+            let name = b"overview.raw\0";
+            let path = (make_path.path)(make_path.handle, name.as_ptr().cast());
+            let overview = self.take_path(path)?;
+            let peaks: Vec<u8> = frames
+                .iter()
+                .flat_map(|frame| frame.abs().to_le_bytes())
+                .collect();
+            fs::write(&overview, peaks).ok()?;
+            Some(overview)
+        });
+        Some(Box::new(Sample {
+            path: path.to_string(),
+            overview,
+            frames,
+        }))
+    }
+
+    // Copy and free a path allocated by the host.
+    fn take_path(&self, path: *mut c_char) -> Option<String> {
+        if path.is_null() {
+            return None;
+        }
+        let string = unsafe { CStr::from_ptr(path) }.to_str().ok().map(str::to_string);
+        match unsafe { self.free_path.as_ref() } {
+            Some(free_path) => (free_path.free_path)(free_path.handle, path),
+            None => unsafe { free(path.cast()) },
+        }
+        string
+    }
+}
+
+unsafe extern "C" fn sampler_work(
+    instance: Handle,
+    respond: Respond,
+    handle: *mut c_void,
+    size: u32,
+    data: *const c_void,
+) -> u32 {
+    let sampler = unsafe { &*instance.cast::<Sampler>() };
+    let path = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), size as usize) };
+    let sample = match std::str::from_utf8(path).ok().and_then(|p| sampler.load(p)) {
+        Some(sample) => sample,
+        // LV2_WORKER_ERR_UNKNOWN.
+        None => return 1,
+    };
+    // The response is the pointer to the loaded sample.
+    let sample = Box::into_raw(sample);
+    respond(
+        handle,
+        std::mem::size_of::<*mut Sample>() as u32,
+        (&sample as *const *mut Sample).cast(),
+    )
+}
+
+unsafe extern "C" fn sampler_work_response(instance: Handle, size: u32, body: *const c_void) -> u32 {
+    let sampler = unsafe { &mut *instance.cast::<Sampler>() };
+    if size as usize != std::mem::size_of::<*mut Sample>() {
+        return 1;
+    }
+    let sample = unsafe { body.cast::<*mut Sample>().read_unaligned() };
+    sampler.sample = Some(unsafe { Box::from_raw(sample) });
+    0
+}
+
+unsafe extern "C" fn sampler_extension_data(uri: *const c_char) -> *const c_void {
+    let uri = unsafe { CStr::from_ptr(uri) }.to_bytes_with_nul();
+    if uri == STATE_INTERFACE {
+        (&STATE as *const StateInterface).cast()
+    } else if uri == WORKER_INTERFACE {
+        (&WORKER as *const WorkerInterface).cast()
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn sampler_save(
+    instance: Handle,
+    store: Store,
+    handle: *mut c_void,
+    _flags: u32,
+    features: *const *const Feature,
+) -> u32 {
+    let sampler = unsafe { &*instance.cast::<Sampler>() };
+    let map_path = unsafe { feature(features, STATE_MAP_PATH) }.cast::<MapPath>();
+    let (sample, map_path) = match (&sampler.sample, unsafe { map_path.as_ref() }) {
+        (Some(sample), Some(map_path)) => (sample, map_path),
+        (None, _) => return 0,
+        // LV2_STATE_ERR_NO_FEATURE.
+        (Some(_), None) => return 5,
+    };
+    let paths = [
+        (sampler.urids.sample, Some(&sample.path)),
+        (sampler.urids.overview, sample.overview.as_ref()),
+    ];
+    for (key, path) in paths.iter() {
+        let path = match path.and_then(|p| std::ffi::CString::new(p.as_str()).ok()) {
+            Some(path) => path,
+            None => continue,
+        };
+        let abstract_path = (map_path.abstract_path)(map_path.handle, path.as_ptr());
+        if abstract_path.is_null() {
+            continue;
+        }
+        let len = unsafe { CStr::from_ptr(abstract_path) }.to_bytes_with_nul().len();
+        store(
+            handle,
+            *key,
+            abstract_path.cast(),
+            len,
+            sampler.urids.path,
+            STATE_POD_PORTABLE,
+        );
+        unsafe { free_host_path(features, abstract_path) };
+    }
+    0
+}
+
+unsafe extern "C" fn sampler_restore(
+    instance: Handle,
+    retrieve: Retrieve,
+    handle: *mut c_void,
+    _flags: u32,
+    features: *const *const Feature,
+) -> u32 {
+    let sampler = unsafe { &mut *instance.cast::<Sampler>() };
+    let map_path = unsafe { feature(features, STATE_MAP_PATH) }.cast::<MapPath>();
+    let map_path = match unsafe { map_path.as_ref() } {
+        Some(map_path) => map_path,
+        None => return 5,
+    };
+    let absolute = |key| {
+        let (mut size, mut mytype, mut flags) = (0, 0, 0);
+        let value = retrieve(handle, key, &mut size, &mut mytype, &mut flags);
+        if value.is_null() || mytype != sampler.urids.path {
+            return None;
+        }
+        let path = (map_path.absolute_path)(map_path.handle, value.cast());
+        if path.is_null() {
+            return None;
+        }
+        let string = unsafe { CStr::from_ptr(path) }.to_str().ok().map(str::to_string);
+        unsafe { free_host_path(features, path) };
+        string
+    };
+    let overview = absolute(sampler.urids.overview);
+    sampler.sample = absolute(sampler.urids.sample).and_then(|path| {
+        let bytes = fs::read(&path).ok()?;
+        let frames = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Some(Box::new(Sample {
+            path,
+            overview,
+            frames,
+        }))
+    });
+    0
+}
+
+// Free a path returned by the `state:mapPath` of `features`.
+unsafe fn free_host_path(features: *const *const Feature, path: *mut c_char) {
+    let free_path = unsafe { feature(features, STATE_FREE_PATH) }.cast::<FreePath>();
+    match unsafe { free_path.as_ref() } {
+        Some(free_path) => (free_path.free_path)(free_path.handle, path),
+        None => unsafe { free(path.cast()) },
+    }
+}
@@ -0,0 +1,211 @@
+use crate::instance::ActiveInstance;
+
+/// The audio ports of an instance to drive with test signals.
+///
+/// The host must connect every other port, like control inputs, before using
+/// the config, and keep that data alive until the analysis returns.
+#[derive(Debug)]
+pub struct InstanceConfig<'a> {
+    /// The instance to analyse.
+    pub instance: &'a mut ActiveInstance,
+    /// The indices of the audio input ports.
+    pub audio_inputs: Vec<usize>,
+    /// The indices of the audio output ports.
+    pub audio_outputs: Vec<usize>,
+    /// The number of frames to pass to each `run` call.
+    pub block_size: usize,
+}
+
+impl<'a> InstanceConfig<'a> {
+    /// Run `input` through the instance and return one buffer per audio
+    /// output of the same length as `input`.
+    ///
+    /// The same signal is fed to every audio input. The audio ports are
+    /// connected to NULL before this returns, since their buffers are freed.
+    unsafe fn process(&mut self, input: &[f32]) -> Vec<Vec<f32>> {
+        let block_size = self.block_size.max(1);
+        let mut inputs = vec![vec![0.0_f32; block_size]; self.audio_inputs.len()];
+        let mut outputs = vec![vec![0.0_f32; block_size]; self.audio_outputs.len()];
+        let mut result = vec![Vec::with_capacity(input.len()); self.audio_outputs.len()];
+        for (&index, buffer) in self.audio_inputs.iter().zip(&inputs) {
+            unsafe {
+                self.instance
+                    .instance_mut()
+                    .connect_port(index, buffer.as_ptr());
+            }
+        }
+        for (&index, buffer) in self.audio_outputs.iter().zip(&mut outputs) {
+            unsafe {
+                self.instance
+                    .instance_mut()
+                    .connect_port_mut(index, buffer.as_mut_ptr());
+            }
+        }
+        for block in input.chunks(block_size) {
+            for buffer in &mut inputs {
+                buffer[..block.len()].copy_from_slice(block);
+            }
+            unsafe { self.instance.run(block.len()) };
+            for (output, buffer) in result.iter_mut().zip(&outputs) {
+                output.extend_from_slice(&buffer[..block.len()]);
+            }
+        }
+        for &index in self.audio_inputs.iter().chain(&self.audio_outputs) {
+            unsafe {
+                self.instance
+                    .instance_mut()
+                    .connect_port_mut(index, std::ptr::null_mut::<f32>());
+            }
+        }
+        result
+    }
+}
+
+/// Drive a unit impulse through the instance and return the first `length`
+/// frames of its impulse response.
+///
+/// The impulse is fed to every audio input at once. The response is
+/// interleaved by audio output, in the order of `config.audio_outputs`, like
+/// the samples of a WAV file, so it has `length * audio_outputs.len()`
+/// samples.
+///
+/// # Safety
+/// Running the instance calls the plugin's code, which itself may be unsafe.
+/// The audio ports are disconnected when this returns, so they must be
+/// connected again before the instance is run.
+#[must_use]
+pub unsafe fn impulse_response(config: &mut InstanceConfig<'_>, length: usize) -> Vec<f32> {
+    let mut impulse = vec![0.0; length];
+    if let Some(first) = impulse.first_mut() {
+        *first = 1.0;
+    }
+    interleave(&unsafe { config.process(&impulse) })
+}
+
+/// Measure the latency of the instance in frames by cross-correlating its
+/// output with a test signal.
+///
+/// This is a fallback for plugins without a latency port, see
+/// `Plugin::latency_port_index`. Latencies of up to `max_latency` frames are
+/// detected. The first audio output is measured. Returns `None` if the
+/// instance has no audio inputs or outputs, or if the output does not
+/// correlate with the input, like for a synth or a plugin that outputs
+/// silence.
+///
+/// # Safety
+/// Same as `impulse_response`.
+#[must_use]
+pub unsafe fn measure_latency(
+    config: &mut InstanceConfig<'_>,
+    max_latency: usize,
+) -> Option<usize> {
+    if config.audio_inputs.is_empty() {
+        return None;
+    }
+    let signal = test_signal(max_latency.max(1) * 4);
+    let mut input = signal.clone();
+    input.resize(signal.len() + max_latency, 0.0);
+    let output = unsafe { config.process(&input) }.into_iter().next()?;
+    best_lag(&signal, &output, max_latency)
+}
+
+/// A deterministic white noise signal, which correlates well only with
+/// itself.
+fn test_signal(len: usize) -> Vec<f32> {
+    let mut state = 0x1234_5678_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            #[allow(clippy::cast_precision_loss)]
+            let unit = state as f32 / u32::MAX as f32;
+            unit.mul_add(2.0, -1.0) * 0.5
+        })
+        .collect()
+}
+
+/// The lag at which `output` correlates most with `signal`, or `None` if the
+/// correlation is too weak to be meaningful.
+fn best_lag(signal: &[f32], output: &[f32], max_latency: usize) -> Option<usize> {
+    let energy = |s: &[f32]| s.iter().map(|x| f64::from(*x) * f64::from(*x)).sum::<f64>();
+    let signal_energy = energy(signal);
+    let mut best = None;
+    let mut best_score = 0.5;
+    for lag in 0..=max_latency {
+        let shifted = match output.get(lag..lag + signal.len()) {
+            Some(shifted) => shifted,
+            None => break,
+        };
+        let output_energy = energy(shifted);
+        if output_energy == 0.0 {
+            continue;
+        }
+        let correlation: f64 = signal
+            .iter()
+            .zip(shifted)
+            .map(|(a, b)| f64::from(*a) * f64::from(*b))
+            .sum();
+        // Normalized, so the score does not depend on the gain of the plugin.
+        let score = correlation.abs() / (signal_energy * output_energy).sqrt();
+        if score > best_score {
+            best_score = score;
+            best = Some(lag);
+        }
+    }
+    best
+}
+
+fn interleave(channels: &[Vec<f32>]) -> Vec<f32> {
+    let frames = channels.first().map_or(0, Vec::len);
+    (0..frames)
+        .flat_map(|frame| channels.iter().map(move |channel| channel[frame]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_best_lag() {
+        let signal = test_signal(256);
+        let mut output = vec![0.0; 17];
+        output.extend(signal.iter().map(|x| -0.25 * x));
+        output.resize(256 + 64, 0.0);
+        assert_eq!(best_lag(&signal, &output, 64), Some(17));
+        assert_eq!(best_lag(&signal, &vec![0.0; 256 + 64], 64), None);
+    }
+
+    #[test]
+    fn test_interleave() {
+        assert_eq!(
+            interleave(&[vec![1.0, 2.0], vec![3.0, 4.0]]),
+            vec![1.0, 3.0, 2.0, 4.0]
+        );
+    }
+
+    #[test]
+    fn test_impulse_response() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let gain = 0.0_f32;
+        let mut instance = unsafe { plugin.instantiate(44100.0, []).unwrap() };
+        unsafe { instance.connect_port(0, &gain) };
+        let mut instance = unsafe { instance.activate() };
+        let mut config = InstanceConfig {
+            instance: &mut instance,
+            audio_inputs: vec![1],
+            audio_outputs: vec![2],
+            block_size: 16,
+        };
+        let ir = unsafe { impulse_response(&mut config, 40) };
+        assert_eq!(ir.len(), 40);
+        assert!((ir[0] - 1.0).abs() < 1e-6);
+        assert!(ir[1..].iter().all(|x| x.abs() < 1e-6));
+        assert_eq!(unsafe { measure_latency(&mut config, 64) }, Some(0));
+        // The plugin is not left with pointers to the freed buffers.
+        assert_eq!(instance.instance().unconnected_ports(), vec![1, 2]);
+    }
+}
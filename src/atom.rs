@@ -0,0 +1,914 @@
+use crate::feature::LV2UridUnmap;
+use lv2_raw::atom::LV2Atom;
+use lv2_raw::{LV2Urid, LV2UridMap};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
+use std::mem::size_of;
+
+const ATOM_BEAT_TIME: &str = "http://lv2plug.in/ns/ext/atom#beatTime";
+pub(crate) const ATOM_BLANK: &str = "http://lv2plug.in/ns/ext/atom#Blank";
+pub(crate) const ATOM_BOOL: &str = "http://lv2plug.in/ns/ext/atom#Bool";
+pub(crate) const ATOM_DOUBLE: &str = "http://lv2plug.in/ns/ext/atom#Double";
+pub(crate) const ATOM_FLOAT: &str = "http://lv2plug.in/ns/ext/atom#Float";
+pub(crate) const ATOM_INT: &str = "http://lv2plug.in/ns/ext/atom#Int";
+const ATOM_LITERAL: &str = "http://lv2plug.in/ns/ext/atom#Literal";
+const ATOM_LONG: &str = "http://lv2plug.in/ns/ext/atom#Long";
+pub(crate) const ATOM_OBJECT: &str = "http://lv2plug.in/ns/ext/atom#Object";
+pub(crate) const ATOM_PATH: &str = "http://lv2plug.in/ns/ext/atom#Path";
+pub(crate) const ATOM_RESOURCE: &str = "http://lv2plug.in/ns/ext/atom#Resource";
+pub(crate) const ATOM_SEQUENCE: &str = "http://lv2plug.in/ns/ext/atom#Sequence";
+const ATOM_STRING: &str = "http://lv2plug.in/ns/ext/atom#String";
+const ATOM_TUPLE: &str = "http://lv2plug.in/ns/ext/atom#Tuple";
+const ATOM_URI: &str = "http://lv2plug.in/ns/ext/atom#URI";
+const ATOM_URID: &str = "http://lv2plug.in/ns/ext/atom#URID";
+const ATOM_VECTOR: &str = "http://lv2plug.in/ns/ext/atom#Vector";
+
+// The deepest nesting of tuples, objects and sequences that is converted. It
+// keeps the recursion from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+// The deepest nesting of JSON arrays and objects that is parsed. An object
+// atom takes three levels for each level of atoms: the atom, its properties
+// and the property.
+const MAX_JSON_DEPTH: usize = 3 * MAX_DEPTH + 1;
+
+/// The reason `from_json` could not convert JSON to an atom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonError(pub(crate) String);
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid atom JSON: {}", self.0)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// Convert an atom to JSON, for logging, display or bridges to other
+/// protocols.
+///
+/// Every atom becomes an object with the URI of its type in `"type"` and its
+/// value in `"value"`:
+///
+/// - Numbers become numbers, except for non-finite floats, which become
+///   `"NaN"`, `"Infinity"` or `"-Infinity"`. Bools become `true` or `false`.
+/// - URIDs become their URI. Strings, URIs and paths become strings.
+/// - Literals become strings, with the URIs in `"datatype"` and `"lang"`.
+/// - Tuples become arrays of atoms, and vectors arrays of values with the
+///   child type in `"childType"`.
+/// - Objects have `"id"` and `"otype"` URIs and an array of `"properties"`,
+///   each with a `"key"` URI and a `"value"` atom.
+/// - Sequences have a `"unit"` URI and an array of events in `"value"`, each
+///   with a `"time"` in frames or beats and a `"value"` atom.
+/// - Everything else, like MIDI events, becomes a string of hex bytes.
+///
+/// URIDs that `unmap` does not know become numbers, and missing URIDs that
+/// are 0 become `null`.
+///
+/// # Safety
+/// `atom` must point to a valid atom, followed by its body.
+///
+/// # Errors
+/// Returns an error if the atom is nested more than 64 levels deep.
+pub unsafe fn to_json(atom: *const LV2Atom, unmap: &LV2UridUnmap) -> Result<String, JsonError> {
+    let (mytype, body) = unsafe {
+        let body = std::slice::from_raw_parts(
+            atom.cast::<u8>().add(size_of::<LV2Atom>()),
+            (*atom).size as usize,
+        );
+        ((*atom).mytype, body)
+    };
+    let mut json = String::new();
+    Reader { unmap }.atom(mytype, body, 0)?.write(&mut json);
+    Ok(json)
+}
+
+/// Convert JSON written by `to_json` back to an atom. URIs are mapped with
+/// `map`.
+///
+/// The atom starts at the beginning of the returned buffer, which is aligned
+/// to 8 bytes like atoms must be.
+///
+/// # Errors
+/// Returns an error if `json` is not valid JSON, does not describe an atom or
+/// is nested more than 64 levels deep.
+pub fn from_json(json: &str, map: &LV2UridMap) -> Result<Vec<u64>, JsonError> {
+    let json = Parser::parse(json)?;
+    let mut writer = Writer {
+        map,
+        out: Vec::new(),
+    };
+    writer.atom(&json, 0)?;
+    let mut atom = vec![0_u64; writer.out.len().div_ceil(8)];
+    for (word, bytes) in atom.iter_mut().zip(writer.out.chunks(8)) {
+        let mut padded = [0; 8];
+        padded[..bytes.len()].copy_from_slice(bytes);
+        *word = u64::from_ne_bytes(padded);
+    }
+    Ok(atom)
+}
+
+// A parsed JSON value. Numbers are kept as text so 64 bit integers do not
+// lose precision.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn field(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Number(number) => out.push_str(number),
+            Json::String(string) => write_string(string, out),
+            Json::Array(values) => {
+                out.push('[');
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    value.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    write_string(key, out);
+                    out.push_str(": ");
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_string(string: &str, out: &mut String) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Converts atoms to JSON.
+struct Reader<'a> {
+    unmap: &'a LV2UridUnmap,
+}
+
+impl<'a> Reader<'a> {
+    fn unmap(&self, urid: LV2Urid) -> Option<String> {
+        let uri = (self.unmap.unmap)(self.unmap.handle, urid);
+        if uri.is_null() {
+            return None;
+        }
+        Some(
+            unsafe { CStr::from_ptr(uri) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    fn uri(&self, urid: LV2Urid) -> Json {
+        match (urid, self.unmap(urid)) {
+            (0, _) => Json::Null,
+            (_, Some(uri)) => Json::String(uri),
+            (urid, None) => Json::Number(urid.to_string()),
+        }
+    }
+
+    fn atom(&self, atom_type: LV2Urid, body: &[u8], depth: usize) -> Result<Json, JsonError> {
+        let uri = self.unmap(atom_type);
+        let uri = uri.as_deref();
+        if let Some(value) = scalar(uri, body, |urid| self.uri(urid)) {
+            return Ok(Json::object(vec![
+                ("type", self.uri(atom_type)),
+                ("value", value),
+            ]));
+        }
+        if depth >= MAX_DEPTH {
+            return Err(JsonError("atom is nested too deeply".to_string()));
+        }
+        let fields = match uri {
+            Some(ATOM_LITERAL) if body.len() >= 8 => vec![
+                ("type", self.uri(atom_type)),
+                ("value", Json::String(c_string(&body[8..]))),
+                ("datatype", self.uri(read_u32(body, 0))),
+                ("lang", self.uri(read_u32(body, 4))),
+            ],
+            Some(ATOM_TUPLE) => vec![
+                ("type", self.uri(atom_type)),
+                (
+                    "value",
+                    Json::Array(
+                        children(body, 0, 0)
+                            .map(|(_, child_type, child)| self.atom(child_type, child, depth + 1))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                ),
+            ],
+            Some(ATOM_VECTOR) if body.len() >= 8 => {
+                let child_size = read_u32(body, 0) as usize;
+                let child_type = read_u32(body, 4);
+                let child_uri = self.unmap(child_type);
+                let values: Option<Vec<Json>> = body[8..]
+                    .chunks(child_size.max(1))
+                    .map(|child| scalar(child_uri.as_deref(), child, |urid| self.uri(urid)))
+                    .collect();
+                match values {
+                    Some(values) if child_size > 0 => vec![
+                        ("type", self.uri(atom_type)),
+                        ("childType", self.uri(child_type)),
+                        ("value", Json::Array(values)),
+                    ],
+                    _ => self.chunk(atom_type, body),
+                }
+            }
+            Some(ATOM_OBJECT | ATOM_BLANK | ATOM_RESOURCE) if body.len() >= 8 => vec![
+                ("type", self.uri(atom_type)),
+                ("id", self.uri(read_u32(body, 0))),
+                ("otype", self.uri(read_u32(body, 4))),
+                (
+                    "properties",
+                    Json::Array(
+                        children(body, 8, 8)
+                            .map(|(header, value_type, value)| {
+                                Ok(Json::object(vec![
+                                    ("key", self.uri(read_u32(header, 0))),
+                                    ("value", self.atom(value_type, value, depth + 1)?),
+                                ]))
+                            })
+                            .collect::<Result<_, JsonError>>()?,
+                    ),
+                ),
+            ],
+            Some(ATOM_SEQUENCE) if body.len() >= 8 => {
+                let unit = read_u32(body, 0);
+                let beats = self.unmap(unit).as_deref() == Some(ATOM_BEAT_TIME);
+                let events = children(body, 8, 8)
+                    .map(|(header, event_type, event)| {
+                        let time = if beats {
+                            float(f64::from_ne_bytes(read_array(header, 0)))
+                        } else {
+                            Json::Number(i64::from_ne_bytes(read_array(header, 0)).to_string())
+                        };
+                        Ok(Json::object(vec![
+                            ("time", time),
+                            ("value", self.atom(event_type, event, depth + 1)?),
+                        ]))
+                    })
+                    .collect::<Result<_, JsonError>>()?;
+                vec![
+                    ("type", self.uri(atom_type)),
+                    ("unit", self.uri(unit)),
+                    ("value", Json::Array(events)),
+                ]
+            }
+            _ => self.chunk(atom_type, body),
+        };
+        Ok(Json::object(fields))
+    }
+
+    fn chunk(&self, atom_type: LV2Urid, body: &[u8]) -> Vec<(&'static str, Json)> {
+        let mut hex = String::with_capacity(body.len() * 2);
+        for byte in body {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        vec![("type", self.uri(atom_type)), ("value", Json::String(hex))]
+    }
+}
+
+// The value of an atom that is a single JSON value, or `None` for other
+// atoms. This is shared with the children of vectors.
+fn scalar(uri: Option<&str>, body: &[u8], urid: impl Fn(LV2Urid) -> Json) -> Option<Json> {
+    let value = match uri? {
+        ATOM_INT if body.len() >= 4 => {
+            Json::Number(i32::from_ne_bytes(read_array(body, 0)).to_string())
+        }
+        ATOM_LONG if body.len() >= 8 => {
+            Json::Number(i64::from_ne_bytes(read_array(body, 0)).to_string())
+        }
+        ATOM_FLOAT if body.len() >= 4 => float(f32::from_ne_bytes(read_array(body, 0))),
+        ATOM_DOUBLE if body.len() >= 8 => float(f64::from_ne_bytes(read_array(body, 0))),
+        ATOM_BOOL if body.len() >= 4 => Json::Bool(read_u32(body, 0) != 0),
+        ATOM_URID if body.len() >= 4 => urid(read_u32(body, 0)),
+        ATOM_STRING | ATOM_URI | ATOM_PATH => Json::String(c_string(body)),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn float<T: Copy + std::fmt::Display + Into<f64>>(value: T) -> Json {
+    let wide: f64 = value.into();
+    if wide.is_finite() {
+        // Formatted as `T` for the shortest text that converts back exactly.
+        Json::Number(value.to_string())
+    } else if wide.is_nan() {
+        Json::String("NaN".to_string())
+    } else if wide > 0.0 {
+        Json::String("Infinity".to_string())
+    } else {
+        Json::String("-Infinity".to_string())
+    }
+}
+
+// The children of a container atom that start at `offset` in `body`. Each
+// child has a header of `header_size` bytes, like the time of an event,
+// followed by an atom. Yields the header, the type and the body of the atom.
+pub(crate) fn children(
+    body: &[u8],
+    offset: usize,
+    header_size: usize,
+) -> impl '_ + Iterator<Item = (&[u8], LV2Urid, &[u8])> {
+    let mut offset = offset;
+    std::iter::from_fn(move || {
+        let atom = offset + header_size;
+        let start = atom + size_of::<LV2Atom>();
+        let size = read_u32(body.get(atom..start)?, 0) as usize;
+        let child = body.get(start..start + size)?;
+        let header = &body[offset..atom];
+        let child_type = read_u32(body, atom + 4);
+        offset = pad(start + size);
+        Some((header, child_type, child))
+    })
+}
+
+fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    let mut array = [0; N];
+    array.copy_from_slice(&bytes[offset..offset + N]);
+    array
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(read_array(bytes, offset))
+}
+
+// The string up to the NUL terminator.
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+// Atoms in containers are aligned to 8 bytes.
+fn pad(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+// Converts JSON to atoms.
+struct Writer<'a> {
+    map: &'a LV2UridMap,
+    out: Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    fn urid(&self, json: Option<&Json>) -> Result<LV2Urid, JsonError> {
+        match json {
+            None | Some(Json::Null) => Ok(0),
+            Some(Json::String(uri)) => {
+                let uri = CString::new(uri.as_str())
+                    .map_err(|_| JsonError(format!("URI contains a NUL byte: {:?}", uri)))?;
+                Ok((self.map.map)(self.map.handle, uri.as_ptr()))
+            }
+            Some(Json::Number(urid)) => urid
+                .parse()
+                .map_err(|_| JsonError(format!("invalid URID: {}", urid))),
+            Some(json) => Err(JsonError(format!("expected a URI, found {:?}", json))),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn pad(&mut self) {
+        self.out.resize(pad(self.out.len()), 0);
+    }
+
+    fn atom(&mut self, json: &Json, depth: usize) -> Result<(), JsonError> {
+        if depth >= MAX_DEPTH {
+            return Err(JsonError("atom is nested too deeply".to_string()));
+        }
+        let type_json = json
+            .field("type")
+            .ok_or_else(|| JsonError("atom has no \"type\"".to_string()))?;
+        let atom_type = self.urid(Some(type_json))?;
+        let start = self.out.len();
+        self.push(&[0; 8]);
+        let uri = match type_json {
+            Json::String(uri) => Some(uri.as_str()),
+            _ => None,
+        };
+        self.body(uri, json, depth)?;
+        let size = u32::try_from(self.out.len() - start - 8)
+            .map_err(|_| JsonError("atom is too large".to_string()))?;
+        self.out[start..start + 4].copy_from_slice(&size.to_ne_bytes());
+        self.out[start + 4..start + 8].copy_from_slice(&atom_type.to_ne_bytes());
+        Ok(())
+    }
+
+    fn body(&mut self, uri: Option<&str>, json: &Json, depth: usize) -> Result<(), JsonError> {
+        let value = json.field("value").unwrap_or(&Json::Null);
+        if self.scalar(uri, value)? {
+            return Ok(());
+        }
+        match uri {
+            Some(ATOM_LITERAL) => {
+                let datatype = self.urid(json.field("datatype"))?;
+                let lang = self.urid(json.field("lang"))?;
+                self.push(&datatype.to_ne_bytes());
+                self.push(&lang.to_ne_bytes());
+                self.string(value)?;
+            }
+            Some(ATOM_TUPLE) => {
+                for child in array(value)? {
+                    self.atom(child, depth + 1)?;
+                    self.pad();
+                }
+            }
+            Some(ATOM_VECTOR) => {
+                let child_type = json.field("childType");
+                let child_uri = match child_type {
+                    Some(Json::String(uri)) => uri.as_str(),
+                    _ => return Err(JsonError("vector has no \"childType\"".to_string())),
+                };
+                let child_size: u32 = match child_uri {
+                    ATOM_INT | ATOM_FLOAT | ATOM_BOOL | ATOM_URID => 4,
+                    ATOM_LONG | ATOM_DOUBLE => 8,
+                    _ => {
+                        return Err(JsonError(format!(
+                            "unsupported vector child type: {}",
+                            child_uri
+                        )))
+                    }
+                };
+                let child_type = self.urid(child_type)?;
+                self.push(&child_size.to_ne_bytes());
+                self.push(&child_type.to_ne_bytes());
+                for child in array(value)? {
+                    self.scalar(Some(child_uri), child)?;
+                }
+            }
+            Some(ATOM_OBJECT | ATOM_BLANK | ATOM_RESOURCE) => {
+                let id = self.urid(json.field("id"))?;
+                let otype = self.urid(json.field("otype"))?;
+                self.push(&id.to_ne_bytes());
+                self.push(&otype.to_ne_bytes());
+                for property in array(json.field("properties").unwrap_or(&Json::Null))? {
+                    let key = self.urid(property.field("key"))?;
+                    self.push(&key.to_ne_bytes());
+                    // The context is unused and always 0.
+                    self.push(&[0; 4]);
+                    self.atom(property.field("value").unwrap_or(&Json::Null), depth + 1)?;
+                    self.pad();
+                }
+            }
+            Some(ATOM_SEQUENCE) => {
+                let unit_json = json.field("unit");
+                let beats = matches!(unit_json, Some(Json::String(uri)) if uri == ATOM_BEAT_TIME);
+                let unit = self.urid(unit_json)?;
+                self.push(&unit.to_ne_bytes());
+                self.push(&[0; 4]);
+                for event in array(value)? {
+                    let time = event.field("time").unwrap_or(&Json::Null);
+                    if beats {
+                        let time: f64 = number(time)?;
+                        self.push(&time.to_ne_bytes());
+                    } else {
+                        let time: i64 = number(time)?;
+                        self.push(&time.to_ne_bytes());
+                    }
+                    self.atom(event.field("value").unwrap_or(&Json::Null), depth + 1)?;
+                    self.pad();
+                }
+            }
+            _ => match value {
+                Json::String(hex) if hex.len() % 2 == 0 => {
+                    for index in (0..hex.len()).step_by(2) {
+                        let byte = hex
+                            .get(index..index + 2)
+                            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                            .ok_or_else(|| JsonError(format!("invalid hex bytes: {}", hex)))?;
+                        self.out.push(byte);
+                    }
+                }
+                value => return Err(JsonError(format!("expected hex bytes, found {:?}", value))),
+            },
+        }
+        Ok(())
+    }
+
+    // Write the body of an atom that is a single JSON value. Returns `false`
+    // if `uri` is not the type of such an atom.
+    fn scalar(&mut self, uri: Option<&str>, value: &Json) -> Result<bool, JsonError> {
+        match uri {
+            Some(ATOM_INT) => self.push(&number::<i32>(value)?.to_ne_bytes()),
+            Some(ATOM_LONG) => self.push(&number::<i64>(value)?.to_ne_bytes()),
+            Some(ATOM_FLOAT) => self.push(&number::<f32>(value)?.to_ne_bytes()),
+            Some(ATOM_DOUBLE) => self.push(&number::<f64>(value)?.to_ne_bytes()),
+            Some(ATOM_BOOL) => match value {
+                Json::Bool(value) => self.push(&i32::from(*value).to_ne_bytes()),
+                value => return Err(JsonError(format!("expected a bool, found {:?}", value))),
+            },
+            Some(ATOM_URID) => {
+                let urid = self.urid(Some(value))?;
+                self.push(&urid.to_ne_bytes());
+            }
+            Some(ATOM_STRING | ATOM_URI | ATOM_PATH) => self.string(value)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn string(&mut self, value: &Json) -> Result<(), JsonError> {
+        match value {
+            Json::String(string) => {
+                self.push(string.as_bytes());
+                self.out.push(0);
+                Ok(())
+            }
+            value => Err(JsonError(format!("expected a string, found {:?}", value))),
+        }
+    }
+}
+
+fn array(json: &Json) -> Result<&[Json], JsonError> {
+    match json {
+        Json::Array(values) => Ok(values),
+        json => Err(JsonError(format!("expected an array, found {:?}", json))),
+    }
+}
+
+fn number<T: std::str::FromStr>(json: &Json) -> Result<T, JsonError> {
+    let text = match json {
+        Json::Number(text) => text.as_str(),
+        Json::String(text) if text == "NaN" => "NaN",
+        Json::String(text) if text == "Infinity" => "inf",
+        Json::String(text) if text == "-Infinity" => "-inf",
+        json => return Err(JsonError(format!("expected a number, found {:?}", json))),
+    };
+    text.parse()
+        .map_err(|_| JsonError(format!("invalid number: {}", text)))
+}
+
+// A recursive descent JSON parser.
+pub(crate) struct Parser<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    // The number of arrays and objects the parser is in.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn parse(json: &str) -> Result<Json, JsonError> {
+        let mut parser = Parser {
+            bytes: json.as_bytes(),
+            offset: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.offset < parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: &str) -> JsonError {
+        JsonError(format!("{} at offset {}", message, self.offset))
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.bytes.get(self.offset) {
+            self.offset += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.bytes.get(self.offset).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.peek() == Some(byte) {
+            self.offset += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, JsonError> {
+        if self.bytes[self.offset..].starts_with(keyword.as_bytes()) {
+            self.offset += keyword.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid value"))
+        }
+    }
+
+    // An array or an object.
+    fn container(&mut self) -> Result<Json, JsonError> {
+        match self.peek() {
+            Some(b'[') => {
+                self.offset += 1;
+                let mut values = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.offset += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    match self.peek() {
+                        Some(b',') => self.offset += 1,
+                        Some(b']') => {
+                            self.offset += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.offset += 1;
+                let mut fields = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.offset += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    match self.peek() {
+                        Some(b',') => self.offset += 1,
+                        Some(b'}') => {
+                            self.offset += 1;
+                            return Ok(Json::Object(fields));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            _ => Err(self.error("invalid value")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        if let Some(b'[' | b'{') = self.peek() {
+            if self.depth >= MAX_JSON_DEPTH {
+                return Err(self.error("too deeply nested"));
+            }
+            self.depth += 1;
+            let value = self.container();
+            self.depth -= 1;
+            return value;
+        }
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.offset;
+                while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') =
+                    self.bytes.get(self.offset)
+                {
+                    self.offset += 1;
+                }
+                let number = std::str::from_utf8(&self.bytes[start..self.offset])
+                    .map_err(|_| self.error("invalid number"))?;
+                if number.parse::<f64>().is_err() {
+                    return Err(self.error("invalid number"));
+                }
+                Ok(Json::Number(number.to_string()))
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self
+                .bytes
+                .get(self.offset)
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.offset += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self
+                        .bytes
+                        .get(self.offset)
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.offset += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        // The input is a `str` and escapes are encoded above, so this only
+        // fails if a string was split inside of a character, which it is not.
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    // The character of a `\u` escape, after the `u`.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let first = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&first) {
+            // A surrogate pair.
+            if !self.bytes[self.offset..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.offset += 2;
+            let second = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&second) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00)
+        } else {
+            first
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let hex = self
+            .bytes
+            .get(self.offset..self.offset + 4)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.offset += 4;
+        Ok(hex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestHost;
+
+    fn round_trip(host: &TestHost, json: &str) -> String {
+        let atom = from_json(json, host.urid_map()).unwrap();
+        unsafe { to_json(atom.as_ptr().cast(), host.urid_unmap()) }.unwrap()
+    }
+
+    #[test]
+    fn test_scalars() {
+        let host = TestHost::new(44100.0, 512);
+        for json in [
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Int", "value": -3}"#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Long", "value": 9007199254740993}"#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Float", "value": 0.1}"#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Double", "value": "-Infinity"}"#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Bool", "value": true}"#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#URID", "value": "http://example.org/a"}"#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#String", "value": "a \"b\"\n"}"#,
+            r#"{"type": "http://lv2plug.in/ns/midi#MidiEvent", "value": "903c64"}"#,
+        ] {
+            assert_eq!(round_trip(&host, json), json);
+        }
+    }
+
+    #[test]
+    fn test_containers() {
+        let host = TestHost::new(44100.0, 512);
+        let object = concat!(
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Object", "id": null, "#,
+            r#""otype": "http://lv2plug.in/ns/ext/patch#Set", "properties": ["#,
+            r#"{"key": "http://lv2plug.in/ns/ext/patch#property", "#,
+            r#""value": {"type": "http://lv2plug.in/ns/ext/atom#URID", "value": "http://example.org/gain"}}, "#,
+            r#"{"key": "http://lv2plug.in/ns/ext/patch#value", "#,
+            r#""value": {"type": "http://lv2plug.in/ns/ext/atom#Float", "value": 0.5}}]}"#,
+        );
+        assert_eq!(round_trip(&host, object), object);
+
+        let sequence = concat!(
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": ["#,
+            r#"{"time": 0, "value": {"type": "http://lv2plug.in/ns/midi#MidiEvent", "value": "903c64"}}, "#,
+            r#"{"time": 64, "value": {"type": "http://lv2plug.in/ns/ext/atom#Tuple", "value": ["#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Int", "value": 1}, "#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Vector", "#,
+            r#""childType": "http://lv2plug.in/ns/ext/atom#Float", "value": [1, 2.5]}]}}]}"#,
+        );
+        assert_eq!(round_trip(&host, sequence), sequence);
+    }
+
+    #[test]
+    fn test_layout() {
+        let host = TestHost::new(44100.0, 512);
+        let atom = from_json(
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Int", "value": 7}"#,
+            host.urid_map(),
+        )
+        .unwrap();
+        let atom = unsafe { &*atom.as_ptr().cast::<lv2_raw::atom::LV2AtomInt>() };
+        assert_eq!(atom.atom.size, 4);
+        assert_eq!(atom.atom.mytype, host.map(ATOM_INT));
+        assert_eq!(atom.body, 7);
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let host = TestHost::new(44100.0, 512);
+        for json in [
+            "",
+            "{\"type\": }",
+            "[1, 2",
+            r#"{"value": 1}"#,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Int", "value": "1"}"#,
+            r#"{"type": "http://lv2plug.in/ns/midi#MidiEvent", "value": "9"}"#,
+        ] {
+            assert!(from_json(json, host.urid_map()).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_depth() {
+        let host = TestHost::new(44100.0, 512);
+        let nested = |depth: usize| {
+            let tuple = r#"{"type": "http://lv2plug.in/ns/ext/atom#Tuple", "value": ["#;
+            format!("{}{}", tuple.repeat(depth), "]}".repeat(depth))
+        };
+        assert!(from_json(&nested(MAX_DEPTH - 1), host.urid_map()).is_ok());
+        assert!(from_json(&nested(MAX_DEPTH), host.urid_map()).is_err());
+        assert!(from_json(&"[".repeat(100_000), host.urid_map()).is_err());
+
+        // Tuples in tuples, with the innermost one empty.
+        let tuple = host.map(ATOM_TUPLE);
+        let atom: Vec<u64> = (0..=MAX_DEPTH)
+            .map(|level| {
+                let size = u32::try_from(8 * (MAX_DEPTH - level)).unwrap();
+                let mut header = [0; 8];
+                header[..4].copy_from_slice(&size.to_ne_bytes());
+                header[4..].copy_from_slice(&tuple.to_ne_bytes());
+                u64::from_ne_bytes(header)
+            })
+            .collect();
+        assert!(unsafe { to_json(atom.as_ptr().cast(), host.urid_unmap()) }.is_err());
+        let inner = &atom[1..];
+        assert!(unsafe { to_json(inner.as_ptr().cast(), host.urid_unmap()) }.is_ok());
+    }
+
+    #[test]
+    fn test_parse_escapes() {
+        assert_eq!(
+            Parser::parse(r#""\u00e9\ud83c\udfb9\/""#),
+            Ok(Json::String("é🎹/".to_string()))
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use crate::buffer::PortBuffer;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Interpolates a control value towards a target over a number of samples.
+///
+/// Control ports only take one value per `run` call. A ramp can either be
+/// advanced once per block with `advance`, split into smaller runs with
+/// `segments` for finer changes, or written out per sample with `fill` for
+/// ports that accept a CV buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ControlRamp {
+    value: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+}
+
+impl ControlRamp {
+    /// Create a new ramp that holds `value`.
+    #[must_use]
+    pub fn new(value: f32) -> ControlRamp {
+        ControlRamp {
+            value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    /// Ramp linearly from the current value to `target` over `samples`. The
+    /// value jumps to `target` if `samples` is 0.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn set_target(&mut self, target: f32, samples: usize) {
+        self.target = target;
+        if samples == 0 {
+            self.jump_to(target);
+        } else {
+            self.step = (target - self.value) / samples as f32;
+            self.remaining = samples;
+        }
+    }
+
+    /// Set the value immediately and stop ramping.
+    pub fn jump_to(&mut self, value: f32) {
+        self.value = value;
+        self.target = value;
+        self.step = 0.0;
+        self.remaining = 0;
+    }
+
+    /// The current value.
+    #[must_use]
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// The value the ramp is heading towards.
+    #[must_use]
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    /// Returns `true` if the ramp has not reached its target yet.
+    #[must_use]
+    pub fn is_ramping(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// Advance the ramp by `samples` and return the new value.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn advance(&mut self, samples: usize) -> f32 {
+        if samples >= self.remaining {
+            self.jump_to(self.target);
+        } else {
+            self.value += self.step * samples as f32;
+            self.remaining -= samples;
+        }
+        self.value
+    }
+
+    /// Write one value per sample into `buffer`, advancing the ramp by the
+    /// length of the buffer. This is suitable for CV ports.
+    pub fn fill(&mut self, buffer: &mut [f32]) {
+        for sample in buffer {
+            *sample = self.advance(1);
+        }
+    }
+
+    /// Split a block of `block_size` samples into segments of at most
+    /// `max_segment` samples. Each segment contains the value the control port
+    /// should hold while running that part of the block. The ramp is advanced
+    /// as the segments are consumed.
+    ///
+    /// Segments are only split while the ramp is moving; once the target is
+    /// reached the rest of the block is a single segment.
+    pub fn segments(&mut self, block_size: usize, max_segment: usize) -> Segments<'_> {
+        Segments {
+            ramp: self,
+            offset: 0,
+            block_size,
+            max_segment: max_segment.max(1),
+        }
+    }
+}
+
+/// A part of a block to run with a single control value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Segment {
+    /// The offset of the segment from the start of the block in samples.
+    pub offset: usize,
+    /// The length of the segment in samples.
+    pub len: usize,
+    /// The control value for the segment.
+    pub value: f32,
+}
+
+/// An iterator over the segments of a block. See `ControlRamp::segments`.
+#[derive(Debug)]
+pub struct Segments<'a> {
+    ramp: &'a mut ControlRamp,
+    offset: usize,
+    block_size: usize,
+    max_segment: usize,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment;
+
+    fn next(&mut self) -> Option<Segment> {
+        let left = self.block_size - self.offset;
+        if left == 0 {
+            return None;
+        }
+        let len = if self.ramp.is_ramping() {
+            left.min(self.max_segment)
+        } else {
+            left
+        };
+        let segment = Segment {
+            offset: self.offset,
+            len,
+            value: self.ramp.advance(len),
+        };
+        self.offset += len;
+        Some(segment)
+    }
+}
+
+/// A control input buffer that ramps towards new values instead of jumping.
+///
+/// Changing a control port from one `run` call to the next often produces
+/// audible steps, known as zipper noise. Connect the port to `as_ptr` and call
+/// `advance` before each `run` to move the value towards its target over the
+/// smoothing time.
+#[derive(Debug)]
+pub struct SmoothedControl {
+    // Keeps its address when the control is moved.
+    buffer: PortBuffer,
+    ramp: ControlRamp,
+    ramp_samples: usize,
+}
+
+impl SmoothedControl {
+    /// Create a control holding `value` that ramps over `smoothing_ms`
+    /// milliseconds at `sample_rate`.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn new(value: f32, sample_rate: f64, smoothing_ms: f64) -> SmoothedControl {
+        SmoothedControl {
+            buffer: PortBuffer::new(Box::new([value])),
+            ramp: ControlRamp::new(value),
+            ramp_samples: (sample_rate * smoothing_ms / 1000.0).max(0.0).round() as usize,
+        }
+    }
+
+    /// The pointer to connect to the control port with
+    /// `Instance::connect_port`. It stays valid until the control is dropped.
+    #[must_use]
+    pub fn as_ptr(&self) -> *const f32 {
+        self.buffer.as_mut_ptr(0)
+    }
+
+    /// Ramp towards `target` over the smoothing time.
+    pub fn set_target(&mut self, target: f32) {
+        self.ramp.set_target(target, self.ramp_samples);
+    }
+
+    /// Set the value immediately, like when loading a preset.
+    pub fn jump_to(&mut self, value: f32) {
+        self.ramp.jump_to(value);
+        self.buffer.set(0, value);
+    }
+
+    /// The value the control is ramping towards.
+    #[must_use]
+    pub fn target(&self) -> f32 {
+        self.ramp.target()
+    }
+
+    /// The value in the buffer.
+    #[must_use]
+    pub fn value(&self) -> f32 {
+        self.buffer.value(0)
+    }
+
+    /// Advance the ramp by `sample_count` and write the new value to the
+    /// buffer. Call this before running the instance for `sample_count`
+    /// frames.
+    pub fn advance(&mut self, sample_count: usize) -> f32 {
+        let value = self.ramp.advance(sample_count);
+        self.buffer.set(0, value);
+        value
+    }
+}
+
+/// Create a queue of control changes with room for `capacity` changes, and
+/// return its two ends.
+///
+/// The queue is a lock-free single producer, single consumer ring buffer. The
+/// sender is typically owned by the GUI thread and the receiver by the audio
+/// thread, which applies the changes at block boundaries with
+/// `ActiveInstance::run_with_params`. Pushing and applying changes never
+/// allocates or blocks.
+#[must_use]
+pub fn param_queue(capacity: usize) -> (ParamSender, ParamReceiver) {
+    let ring = Arc::new(ParamRing {
+        slots: (0..capacity.max(1)).map(|_| Slot::default()).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (ParamSender { ring: ring.clone() }, ParamReceiver { ring })
+}
+
+// The slots are atomics so no unsafe code is needed. They only need relaxed
+// ordering since `head` and `tail` order them, which also keeps them to 32
+// bit atomics that every target has.
+struct ParamRing {
+    slots: Box<[Slot]>,
+    // The number of changes pushed, only written by the sender.
+    head: AtomicUsize,
+    // The number of changes popped, only written by the receiver.
+    tail: AtomicUsize,
+}
+
+// A change, with the bits of the value.
+#[derive(Default)]
+struct Slot {
+    port_index: AtomicU32,
+    value: AtomicU32,
+}
+
+/// The end of a `param_queue` that pushes changes.
+pub struct ParamSender {
+    ring: Arc<ParamRing>,
+}
+
+/// The end of a `param_queue` that receives changes.
+pub struct ParamReceiver {
+    ring: Arc<ParamRing>,
+}
+
+impl ParamSender {
+    /// Queue setting the control port at `port_index` to `value`. Returns
+    /// `false` if the queue is full.
+    pub fn push(&mut self, port_index: usize, value: f32) -> bool {
+        let index = match u32::try_from(port_index) {
+            Ok(index) => index,
+            Err(_) => return false,
+        };
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(ring.tail.load(Ordering::Acquire)) == ring.slots.len() {
+            return false;
+        }
+        let slot = &ring.slots[head % ring.slots.len()];
+        slot.port_index.store(index, Ordering::Relaxed);
+        slot.value.store(value.to_bits(), Ordering::Relaxed);
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+impl ParamReceiver {
+    /// Take the oldest queued change as `(port_index, value)`.
+    pub fn pop(&mut self) -> Option<(usize, f32)> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail == ring.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let slot = &ring.slots[tail % ring.slots.len()];
+        let port_index = slot.port_index.load(Ordering::Relaxed) as usize;
+        let value = f32::from_bits(slot.value.load(Ordering::Relaxed));
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some((port_index, value))
+    }
+
+    /// Write all the queued changes into `controls`, the buffers of the
+    /// control ports indexed by port index. Changes to ports outside of
+    /// `controls` are dropped. Returns the number of changes applied.
+    pub fn apply(&mut self, controls: &mut [f32]) -> usize {
+        let mut applied = 0;
+        while let Some((index, value)) = self.pop() {
+            if let Some(control) = controls.get_mut(index) {
+                *control = value;
+                applied += 1;
+            }
+        }
+        applied
+    }
+}
+
+impl std::fmt::Debug for ParamSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParamSender")
+            .field("capacity", &self.ring.slots.len())
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for ParamReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParamReceiver")
+            .field("capacity", &self.ring.slots.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let mut ramp = ControlRamp::new(0.0);
+        ramp.set_target(1.0, 4);
+        assert!(ramp.is_ramping());
+        assert_eq!(ramp.advance(2), 0.5);
+        assert_eq!(ramp.advance(1), 0.75);
+        assert_eq!(ramp.advance(8), 1.0);
+        assert!(!ramp.is_ramping());
+        assert_eq!(ramp.advance(8), 1.0);
+
+        ramp.set_target(-1.0, 0);
+        assert_eq!(ramp.value(), -1.0);
+        assert!(!ramp.is_ramping());
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut ramp = ControlRamp::new(0.0);
+        ramp.set_target(1.0, 4);
+        let mut buffer = [0.0; 6];
+        ramp.fill(&mut buffer);
+        assert_eq!(buffer, [0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_segments() {
+        let mut ramp = ControlRamp::new(0.0);
+        ramp.set_target(1.0, 64);
+        let segments: Vec<_> = ramp.segments(128, 32).collect();
+        assert_eq!(
+            segments,
+            vec![
+                Segment {
+                    offset: 0,
+                    len: 32,
+                    value: 0.5
+                },
+                Segment {
+                    offset: 32,
+                    len: 32,
+                    value: 1.0
+                },
+                Segment {
+                    offset: 64,
+                    len: 64,
+                    value: 1.0
+                },
+            ]
+        );
+        assert_eq!(ramp.value(), 1.0);
+    }
+
+    #[test]
+    fn test_param_queue() {
+        let (mut sender, mut receiver) = param_queue(2);
+        assert!(sender.push(0, 0.5));
+        assert!(sender.push(3, -1.0));
+        assert!(!sender.push(1, 2.0));
+        assert_eq!(receiver.pop(), Some((0, 0.5)));
+        assert!(sender.push(1, 2.0));
+        let mut controls = [0.0; 2];
+        assert_eq!(receiver.apply(&mut controls), 1);
+        assert_eq!(controls, [0.0, 2.0]);
+        assert_eq!(receiver.pop(), None);
+
+        let consumer = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            while received.len() < 1000 {
+                if let Some((_, value)) = receiver.pop() {
+                    received.push(value);
+                }
+            }
+            received
+        });
+        for i in 0..1000_u16 {
+            while !sender.push(0, f32::from(i)) {
+                std::thread::yield_now();
+            }
+        }
+        let received = consumer.join().unwrap();
+        assert!(received
+            .iter()
+            .zip(0_u16..)
+            .all(|(v, i)| *v == f32::from(i)));
+    }
+
+    #[test]
+    fn test_smoothed_control() {
+        let mut control = SmoothedControl::new(0.0, 48000.0, 1.0);
+        let ptr = control.as_ptr();
+        control.set_target(1.0);
+        assert_eq!(control.value(), 0.0);
+        assert_eq!(control.advance(24), 0.5);
+        assert_eq!(unsafe { *ptr }, 0.5);
+        assert_eq!(control.advance(24), 1.0);
+
+        let mut control = Box::new(control);
+        control.jump_to(-1.0);
+        assert_eq!(control.as_ptr(), ptr);
+        assert_eq!(unsafe { *ptr }, -1.0);
+    }
+}
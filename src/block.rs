@@ -0,0 +1,156 @@
+/// Runs an instance with a fixed number of frames from callbacks of any size.
+///
+/// Plugins with `FeaturesSummary::fixed_block_length` or
+/// `FeaturesSummary::power_of_2_block_length` can not be run with the
+/// number of frames an audio callback provides. The adapter collects the
+/// input into blocks of `block_length` frames and runs the instance once per
+/// full block, which delays the output by `latency` frames.
+///
+/// Connect the audio ports of the instance to `input_ptr` and
+/// `output_ptr` once. The buffers keep their address until the adapter is
+/// dropped. `process` does not allocate, so it can be called from the audio
+/// thread.
+#[derive(Debug)]
+pub struct BlockAdapter {
+    block_length: usize,
+    inputs: Vec<Box<[f32]>>,
+    outputs: Vec<Box<[f32]>>,
+    // The number of frames written to the input blocks, which is also the
+    // number of frames read from the output blocks.
+    position: usize,
+}
+
+impl BlockAdapter {
+    /// Create an adapter for blocks of `block_length` frames, with
+    /// `inputs` input and `outputs` output channels. A `block_length` of 0 is
+    /// treated as 1.
+    #[must_use]
+    pub fn new(block_length: usize, inputs: usize, outputs: usize) -> BlockAdapter {
+        let block_length = block_length.max(1);
+        let buffer = || vec![0.0; block_length].into_boxed_slice();
+        BlockAdapter {
+            block_length,
+            inputs: (0..inputs).map(|_| buffer()).collect(),
+            outputs: (0..outputs).map(|_| buffer()).collect(),
+            position: 0,
+        }
+    }
+
+    /// The number of frames the instance is run with.
+    #[must_use]
+    pub fn block_length(&self) -> usize {
+        self.block_length
+    }
+
+    /// The number of frames the output is delayed by, which is one block.
+    /// Hosts add it to the latency the plugin reports.
+    #[must_use]
+    pub fn latency(&self) -> usize {
+        self.block_length
+    }
+
+    /// The buffer to connect to the input port of `channel`, or `None` if
+    /// there is no such channel.
+    #[must_use]
+    pub fn input_ptr(&self, channel: usize) -> Option<*const f32> {
+        self.inputs.get(channel).map(|buffer| buffer.as_ptr())
+    }
+
+    /// The buffer to connect to the output port of `channel`, or `None` if
+    /// there is no such channel.
+    pub fn output_ptr(&mut self, channel: usize) -> Option<*mut f32> {
+        self.outputs
+            .get_mut(channel)
+            .map(|buffer| buffer.as_mut_ptr())
+    }
+
+    /// Feed `inputs` to the adapter and fill `outputs`, calling `run` each
+    /// time a block is full. `run` should run the instance for
+    /// `block_length` frames.
+    ///
+    /// All the channels must have the same number of frames. Channels beyond
+    /// the ones the adapter was created with are ignored, and outputs
+    /// without a channel are filled with silence.
+    pub fn process(
+        &mut self,
+        inputs: &[&[f32]],
+        outputs: &mut [&mut [f32]],
+        mut run: impl FnMut(),
+    ) {
+        let frames = inputs
+            .iter()
+            .map(|input| input.len())
+            .chain(outputs.iter().map(|output| output.len()))
+            .min()
+            .unwrap_or(0);
+        for output in outputs.iter_mut().skip(self.outputs.len()) {
+            output.fill(0.0);
+        }
+        let mut offset = 0;
+        while offset < frames {
+            let len = (self.block_length - self.position).min(frames - offset);
+            let block = self.position..self.position + len;
+            for (input, buffer) in inputs.iter().zip(&mut self.inputs) {
+                buffer[block.clone()].copy_from_slice(&input[offset..offset + len]);
+            }
+            for (output, buffer) in outputs.iter_mut().zip(&self.outputs) {
+                output[offset..offset + len].copy_from_slice(&buffer[block.clone()]);
+            }
+            offset += len;
+            self.position += len;
+            if self.position == self.block_length {
+                run();
+                self.position = 0;
+            }
+        }
+    }
+
+    /// Silence the buffered audio, like when the transport jumps.
+    pub fn reset(&mut self) {
+        for buffer in self.inputs.iter_mut().chain(&mut self.outputs) {
+            buffer.fill(0.0);
+        }
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process() {
+        let mut adapter = BlockAdapter::new(4, 1, 1);
+        let input_ptr = adapter.input_ptr(0).unwrap();
+        let output_ptr = adapter.output_ptr(0).unwrap();
+        // Doubles the input like a plugin connected to the buffers would.
+        let double = || unsafe {
+            for i in 0..4 {
+                *output_ptr.add(i) = 2.0 * *input_ptr.add(i);
+            }
+        };
+        let mut result = Vec::new();
+        let input: Vec<f32> = (1..=11).map(|i| i as f32).collect();
+        let mut runs = 0;
+        for chunk in [&input[..3], &input[3..4], &input[4..11]] {
+            let mut output = vec![-1.0; chunk.len()];
+            adapter.process(&[chunk], &mut [&mut output], || {
+                double();
+                runs += 1;
+            });
+            result.extend(output);
+        }
+        assert_eq!(runs, 2);
+        assert_eq!(adapter.latency(), 4);
+        let expected: Vec<f32> = (0..11)
+            .map(|i| if i < 4 { 0.0 } else { 2.0 * (i - 3) as f32 })
+            .collect();
+        assert_eq!(result, expected);
+
+        adapter.reset();
+        let mut output = [1.0; 2];
+        let mut missing = [1.0; 2];
+        adapter.process(&[&[1.0, 1.0]], &mut [&mut output, &mut missing], || {});
+        assert_eq!((output, missing), ([0.0; 2], [0.0; 2]));
+    }
+}
@@ -0,0 +1,1249 @@
+use crate::atom::ATOM_SEQUENCE;
+use crate::feature::LV2UridUnmap;
+use crate::host::{Host, UridSource};
+use crate::instance::{ActiveInstance, Instance};
+use crate::plugin::Plugin;
+use crate::port::{PortDirection, PortKind};
+use crate::world::World;
+use lv2_raw::atom::LV2Atom;
+use lv2_raw::{LV2Urid, LV2UridMap};
+use std::ffi::{CStr, CString, OsString};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::mem::size_of;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{fence, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The environment variable that passes the address of the host to the
+/// server process. See `serve_from_env`.
+pub const ADDRESS_VAR: &str = "LILV_BRIDGE_ADDRESS";
+
+/// The environment variable that passes a random token to the server
+/// process, which it sends first so the host knows the connection is from
+/// its server and not from another local process. See `serve_from_env`.
+pub const TOKEN_VAR: &str = "LILV_BRIDGE_TOKEN";
+
+// The default timeout of `run` in blocks, and its minimum.
+const RUN_TIMEOUT_BLOCKS: f64 = 8.0;
+const MIN_RUN_TIMEOUT: Duration = Duration::from_millis(50);
+// How long a connection may take to send the token before it is closed, so
+// other local processes can not keep the server from connecting.
+const TOKEN_TIMEOUT: Duration = Duration::from_millis(500);
+// The longest string either process accepts, like a URI or an error.
+const MAX_STR_LEN: usize = 64 * 1024;
+
+// The commands of the host.
+const ACTIVATE: u8 = b'a';
+const DEACTIVATE: u8 = b'd';
+const RUN: u8 = b'r';
+const QUIT: u8 = b'q';
+// The replies of the server, and its requests to map URIs while it handles
+// a command.
+const OK: u8 = 0;
+const FAILED: u8 = 1;
+const MAP: u8 = b'm';
+const UNMAP: u8 = b'u';
+
+/// The reason a bridged instance failed.
+#[derive(Debug)]
+pub enum BridgeError {
+    /// The port with the index can not be bridged, since it is not an audio,
+    /// CV, control or atom port and is not `lv2:connectionOptional`.
+    UnsupportedPort(usize),
+    /// The server reported an error, like a plugin that could not be
+    /// instantiated.
+    Server(String),
+    /// The server process exited, usually because the plugin crashed.
+    Exited(ExitStatus),
+    /// The server process did not connect or reply in time.
+    Timeout,
+    /// Communicating with the server failed, or it sent an invalid reply.
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BridgeError::UnsupportedPort(index) => write!(f, "port {} can not be bridged", index),
+            BridgeError::Server(message) => write!(f, "bridge server failed: {}", message),
+            BridgeError::Exited(status) => write!(f, "bridge server exited with {}", status),
+            BridgeError::Timeout => write!(f, "bridge server did not reply in time"),
+            BridgeError::Io(err) => write!(f, "bridge connection failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+impl From<std::io::Error> for BridgeError {
+    fn from(err: std::io::Error) -> BridgeError {
+        match err.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => BridgeError::Timeout,
+            _ => BridgeError::Io(err),
+        }
+    }
+}
+
+// How a port is passed between the processes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Bridged {
+    // An audio or CV port with a value for each frame.
+    Samples(PortDirection),
+    // A control port with a single value.
+    Control(PortDirection),
+    // An atom port. The server maps URIs with the map of the host, so atoms
+    // are passed as they are.
+    Atom(PortDirection),
+    // An optional port of another kind, which is not connected.
+    Unused,
+}
+
+impl Bridged {
+    fn len(self, sample_count: usize) -> usize {
+        match self {
+            Bridged::Samples(_) => sample_count,
+            Bridged::Control(_) => 1,
+            Bridged::Atom(_) | Bridged::Unused => 0,
+        }
+    }
+
+    fn is(self, direction: PortDirection) -> bool {
+        match self {
+            Bridged::Samples(d) | Bridged::Control(d) | Bridged::Atom(d) => d == direction,
+            Bridged::Unused => false,
+        }
+    }
+}
+
+fn bridged_ports(plugin: &Plugin) -> Result<Vec<Bridged>, BridgeError> {
+    plugin
+        .iter_ports()
+        .map(|port| match (port.kind(), port.direction()) {
+            (PortKind::Audio | PortKind::Cv, Some(direction)) => Ok(Bridged::Samples(direction)),
+            (PortKind::Control, Some(direction)) => Ok(Bridged::Control(direction)),
+            (PortKind::Atom, Some(direction)) => Ok(Bridged::Atom(direction)),
+            _ if port.is_connection_optional() => Ok(Bridged::Unused),
+            _ => Err(BridgeError::UnsupportedPort(port.index())),
+        })
+        .collect()
+}
+
+// The offsets of the buffers of `ports` in the shared memory, and its size.
+// Both processes compute them from the same plugin. The buffers are aligned
+// to 8 bytes like atoms.
+fn layout(ports: &[Bridged], block_length: usize, atom_capacity: usize) -> (Vec<usize>, usize) {
+    let mut size = 0;
+    let offsets = ports
+        .iter()
+        .map(|port| {
+            let offset = size;
+            let len = match port {
+                Bridged::Samples(_) => 4 * block_length,
+                Bridged::Control(_) => 4,
+                Bridged::Atom(_) => atom_capacity,
+                Bridged::Unused => 0,
+            };
+            size += len.div_ceil(8) * 8;
+            offset
+        })
+        .collect();
+    (offsets, size.max(8))
+}
+
+// A file that both processes map, which holds the buffers of the ports.
+struct SharedMemory {
+    data: *mut u8,
+    len: usize,
+    // The file until both processes mapped it.
+    path: Option<PathBuf>,
+}
+
+impl SharedMemory {
+    // Create the file at `path` that only this user can open.
+    fn create(path: &Path, len: usize) -> std::io::Result<SharedMemory> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+        let mut memory = SharedMemory {
+            data: std::ptr::null_mut(),
+            len,
+            path: Some(path.to_path_buf()),
+        };
+        file.set_len(len as u64)?;
+        memory.data = SharedMemory::map(&file, len)?;
+        Ok(memory)
+    }
+
+    fn open(path: &Path, len: usize) -> std::io::Result<SharedMemory> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        if file.metadata()?.len() != len as u64 {
+            let message = "the shared memory does not match the ports";
+            return Err(std::io::Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(SharedMemory {
+            data: SharedMemory::map(&file, len)?,
+            len,
+            path: None,
+        })
+    }
+
+    fn map(file: &File, len: usize) -> std::io::Result<*mut u8> {
+        let data = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(data.cast())
+        }
+    }
+
+    // Remove the file, which stays mapped until both processes are done.
+    fn unlink(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    // The buffer at `offset`, which `layout` keeps in bounds.
+    fn at(&self, offset: usize) -> *mut u8 {
+        debug_assert!(offset < self.len);
+        unsafe { self.data.add(offset) }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        if !self.data.is_null() {
+            unsafe { libc::munmap(self.data.cast(), self.len) };
+        }
+        self.unlink();
+    }
+}
+
+// The directory of the shared memory, which is in memory on Linux.
+fn shared_memory_dir() -> PathBuf {
+    let shm = Path::new("/dev/shm");
+    if shm.is_dir() {
+        shm.to_path_buf()
+    } else {
+        std::env::temp_dir()
+    }
+}
+
+/// Options for `BridgedInstance::spawn`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BridgeOptions {
+    /// How long `run` waits for the server to process a block. `None` waits
+    /// for 8 blocks at the sample rate of the host, but at least 50 ms, since
+    /// an audio thread can not wait much longer than a block anyway.
+    pub run_timeout: Option<Duration>,
+    /// How long the server may take to connect, to instantiate the plugin
+    /// and to activate or deactivate it. This includes loading the world of
+    /// the server. The default is 10 seconds.
+    pub setup_timeout: Duration,
+    /// The size of the shared buffers of the atom ports in bytes, which
+    /// limits the atoms that can be passed in each run. It is rounded up to
+    /// a multiple of 8 and at least 16. The default is 8192.
+    pub atom_capacity: usize,
+}
+
+impl Default for BridgeOptions {
+    fn default() -> BridgeOptions {
+        BridgeOptions {
+            run_timeout: None,
+            setup_timeout: Duration::from_secs(10),
+            atom_capacity: 8192,
+        }
+    }
+}
+
+/// A plugin instance that runs in a server process, so a plugin that crashes
+/// or hangs only takes down the server. Requires the `bridge` feature and a
+/// Unix system.
+///
+/// The instance is used like an `Instance`: buffers are connected with
+/// `connect_port_mut` and `connect_atom_port`, and `run` copies the inputs
+/// to buffers that both processes map, has the server run the plugin on
+/// them and copies the outputs back. Audio, CV, control and atom ports are
+/// bridged. The server instantiates the plugin with the features of a
+/// `host::Host` that maps URIs with the map of the host, so atoms keep their
+/// URIDs. The processes only exchange commands over a loopback TCP socket,
+/// and `run` does not allocate, but it waits for the server and a plugin
+/// that maps URIs while it runs waits for the host. This is experimental.
+pub struct BridgedInstance<'h> {
+    child: Child,
+    stream: TcpStream,
+    memory: SharedMemory,
+    ports: Vec<Bridged>,
+    offsets: Vec<usize>,
+    buffers: Vec<Buffer>,
+    block_length: usize,
+    atom_capacity: usize,
+    map: &'h LV2UridMap,
+    unmap: &'h LV2UridUnmap,
+    sequence: LV2Urid,
+    // The URIs the server asks to map, up to `MAX_STR_LEN`.
+    uri: Vec<u8>,
+    run_timeout: Duration,
+    setup_timeout: Duration,
+    // The read timeout of the stream, which is only changed when it differs.
+    timeout: Duration,
+}
+
+// The buffer a port is connected to, with its size in bytes if it is an
+// atom buffer.
+#[derive(Copy, Clone)]
+struct Buffer {
+    data: *mut u8,
+    capacity: usize,
+}
+
+unsafe impl Send for BridgedInstance<'_> {}
+
+impl<'h> BridgedInstance<'h> {
+    /// Run `server` and instantiate `plugin` at the sample rate of `host` in
+    /// it. Each run may process up to the block length of `host`, and the
+    /// server maps URIs with the URID map of `host`.
+    ///
+    /// `server` must call `serve_from_env`, like the `bridge_server` example
+    /// or the host itself when it checks for `ADDRESS_VAR` at startup.
+    ///
+    /// # Errors
+    /// Returns an error if a port can not be bridged, the server could not be
+    /// started, or the plugin could not be instantiated in it.
+    pub fn spawn(
+        mut server: Command,
+        plugin: &Plugin,
+        host: &'h Host,
+        options: &BridgeOptions,
+    ) -> Result<BridgedInstance<'h>, BridgeError> {
+        let ports = bridged_ports(plugin)?;
+        let uri = plugin.uri().as_uri().unwrap_or_default().to_string();
+        let bundle_uri = plugin.bundle_uri().as_uri().unwrap_or_default().to_string();
+        let sample_rate = host.sample_rate();
+        let block_length = host.block_length();
+        let atom_capacity = options.atom_capacity.max(16).div_ceil(8) * 8;
+        let (offsets, size) = layout(&ports, block_length, atom_capacity);
+        let token = random_token();
+        let path = shared_memory_dir().join(format!(
+            "lilv-bridge-{}-{}",
+            std::process::id(),
+            &token[..16]
+        ));
+        let memory = SharedMemory::create(&path, size)?;
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let address = listener.local_addr()?;
+        let mut child = server
+            .env(ADDRESS_VAR, address.to_string())
+            .env(TOKEN_VAR, &token)
+            .stdin(Stdio::null())
+            .spawn()?;
+        let stream = match accept(&listener, &mut child, &token, options.setup_timeout) {
+            Ok(stream) => stream,
+            Err(err) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(err);
+            }
+        };
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(options.setup_timeout))?;
+        let run_timeout = options
+            .run_timeout
+            .unwrap_or_else(|| default_run_timeout(sample_rate, block_length));
+        let mut instance = BridgedInstance {
+            child,
+            stream,
+            memory,
+            buffers: vec![
+                Buffer {
+                    data: std::ptr::null_mut(),
+                    capacity: 0,
+                };
+                ports.len()
+            ],
+            ports,
+            offsets,
+            block_length,
+            atom_capacity,
+            map: host.urid_map(),
+            unmap: host.urid_unmap(),
+            sequence: host.map(ATOM_SEQUENCE),
+            uri: Vec::with_capacity(MAX_STR_LEN + 1),
+            run_timeout,
+            setup_timeout: options.setup_timeout,
+            timeout: options.setup_timeout,
+        };
+        let mut message = Vec::new();
+        write_bytes(&mut message, uri.as_bytes());
+        write_bytes(&mut message, bundle_uri.as_bytes());
+        message.extend_from_slice(&sample_rate.to_le_bytes());
+        write_len(&mut message, block_length);
+        write_len(&mut message, atom_capacity);
+        write_bytes(&mut message, path.as_os_str().as_bytes());
+        write_len(&mut message, size);
+        instance.request(&message)?;
+        // Both processes mapped the memory.
+        instance.memory.unlink();
+        Ok(instance)
+    }
+
+    /// Connect the port at `port_index` to `data`, or disconnect it if `data`
+    /// is null. Inputs of ports that are not connected are zeros.
+    ///
+    /// # Safety
+    /// `data` must point to enough `f32` values for the port, which is one
+    /// for control ports and the number of frames of each `run` for audio
+    /// and CV ports, until the port is connected elsewhere or the instance
+    /// is dropped.
+    pub unsafe fn connect_port_mut(&mut self, port_index: usize, data: *mut f32) {
+        if let Some(buffer) = self.buffers.get_mut(port_index) {
+            *buffer = Buffer {
+                data: data.cast(),
+                capacity: 0,
+            };
+        }
+    }
+
+    /// Connect the atom port at `port_index` to the buffer `data` of
+    /// `capacity` bytes, or disconnect it if `data` is null. Inputs of atom
+    /// ports that are not connected are empty sequences. Atoms that do not
+    /// fit into the buffer they are copied to are replaced with empty
+    /// sequences, or with an atom header of size 0 if the buffer is shorter
+    /// than an empty sequence.
+    ///
+    /// # Safety
+    /// `data` must point to `capacity` bytes that are aligned to 8 bytes
+    /// until the port is connected elsewhere or the instance is dropped. The
+    /// buffer of an input must hold a valid atom when `run` is called.
+    pub unsafe fn connect_atom_port(
+        &mut self,
+        port_index: usize,
+        data: *mut LV2Atom,
+        capacity: usize,
+    ) {
+        if let Some(buffer) = self.buffers.get_mut(port_index) {
+            *buffer = Buffer {
+                data: data.cast(),
+                capacity,
+            };
+        }
+    }
+
+    /// Activate the plugin in the server.
+    ///
+    /// # Errors
+    /// Returns an error if the server failed.
+    pub fn activate(&mut self) -> Result<(), BridgeError> {
+        self.set_timeout(self.setup_timeout)?;
+        self.request(&[ACTIVATE])
+    }
+
+    /// Deactivate the plugin in the server.
+    ///
+    /// # Errors
+    /// Returns an error if the server failed.
+    pub fn deactivate(&mut self) -> Result<(), BridgeError> {
+        self.set_timeout(self.setup_timeout)?;
+        self.request(&[DEACTIVATE])
+    }
+
+    /// Run the activated plugin for `sample_count` frames, which must be at
+    /// most the block length of the instance. This does not allocate unless
+    /// it fails.
+    ///
+    /// # Safety
+    /// The buffers of the connected ports must be valid, see
+    /// `connect_port_mut`.
+    ///
+    /// # Errors
+    /// Returns an error if the server failed, like when the plugin crashed.
+    /// The instance can not be used anymore after an error other than
+    /// `BridgeError::Server`.
+    pub unsafe fn run(&mut self, sample_count: usize) -> Result<(), BridgeError> {
+        if sample_count > self.block_length {
+            return Err(BridgeError::Server(format!(
+                "{} frames exceed the block length {}",
+                sample_count, self.block_length
+            )));
+        }
+        self.set_timeout(self.run_timeout)?;
+        for (index, port) in self.ports.iter().enumerate() {
+            let (buffer, shared) = (self.buffers[index], self.memory.at(self.offsets[index]));
+            match port {
+                Bridged::Atom(PortDirection::Input) => unsafe {
+                    let source = buffer.data.cast_const();
+                    copy_atom(
+                        source,
+                        buffer.capacity,
+                        shared,
+                        self.atom_capacity,
+                        self.sequence,
+                    );
+                },
+                _ if port.is(PortDirection::Input) => {
+                    let len = 4 * port.len(sample_count);
+                    if buffer.data.is_null() {
+                        unsafe { std::ptr::write_bytes(shared, 0, len) };
+                    } else {
+                        unsafe { std::ptr::copy_nonoverlapping(buffer.data, shared, len) };
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut command = [RUN, 0, 0, 0, 0];
+        #[allow(clippy::cast_possible_truncation)]
+        command[1..].copy_from_slice(&(sample_count as u32).to_le_bytes());
+        fence(Ordering::SeqCst);
+        self.request(&command)?;
+        fence(Ordering::SeqCst);
+        for (index, port) in self.ports.iter().enumerate() {
+            let (buffer, shared) = (self.buffers[index], self.memory.at(self.offsets[index]));
+            if buffer.data.is_null() || !port.is(PortDirection::Output) {
+                continue;
+            }
+            match port {
+                Bridged::Atom(_) => unsafe {
+                    let target = buffer.data;
+                    copy_atom(
+                        shared,
+                        self.atom_capacity,
+                        target,
+                        buffer.capacity,
+                        self.sequence,
+                    );
+                },
+                _ => {
+                    let len = 4 * port.len(sample_count);
+                    unsafe { std::ptr::copy_nonoverlapping(shared, buffer.data, len) };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The id of the server process.
+    #[must_use]
+    pub fn server_id(&self) -> u32 {
+        self.child.id()
+    }
+
+    // Send `message` and wait for the reply, mapping the URIs the server
+    // asks for in the meantime.
+    fn request(&mut self, message: &[u8]) -> Result<(), BridgeError> {
+        self.stream
+            .write_all(message)
+            .map_err(|err| self.failure(err))?;
+        loop {
+            let mut status = [0];
+            self.read(&mut status)?;
+            match status[0] {
+                OK => return Ok(()),
+                FAILED => {
+                    let message = read_bytes(&mut self.stream, MAX_STR_LEN)
+                        .map_err(|err| self.failure(err))?;
+                    return Err(BridgeError::Server(
+                        String::from_utf8_lossy(&message).into_owned(),
+                    ));
+                }
+                MAP => self.answer_map()?,
+                UNMAP => self.answer_unmap()?,
+                other => {
+                    let message = format!("unexpected reply {}", other);
+                    return Err(std::io::Error::new(ErrorKind::InvalidData, message).into());
+                }
+            }
+        }
+    }
+
+    fn answer_map(&mut self) -> Result<(), BridgeError> {
+        let len = self.read_len()?;
+        // The buffer has room for the longest URI, so this does not
+        // allocate.
+        self.uri.resize(len, 0);
+        let mut uri = std::mem::take(&mut self.uri);
+        let read = self.read(&mut uri);
+        uri.push(0);
+        let urid = match (read, CStr::from_bytes_with_nul(&uri)) {
+            (Err(err), _) => Err(err),
+            (Ok(()), Ok(uri)) => Ok((self.map.map)(self.map.handle, uri.as_ptr())),
+            (Ok(()), Err(_)) => Ok(0),
+        };
+        uri.clear();
+        self.uri = uri;
+        self.write(&urid?.to_le_bytes())
+    }
+
+    fn answer_unmap(&mut self) -> Result<(), BridgeError> {
+        let mut urid = [0; 4];
+        self.read(&mut urid)?;
+        let uri = (self.unmap.unmap)(self.unmap.handle, u32::from_le_bytes(urid));
+        let uri = if uri.is_null() {
+            &[][..]
+        } else {
+            unsafe { CStr::from_ptr(uri) }.to_bytes()
+        };
+        let len = if uri.len() > MAX_STR_LEN {
+            0
+        } else {
+            uri.len()
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        self.write(&(len as u32).to_le_bytes())?;
+        self.write(&uri[..len])
+    }
+
+    fn read(&mut self, bytes: &mut [u8]) -> Result<(), BridgeError> {
+        self.stream
+            .read_exact(bytes)
+            .map_err(|err| self.failure(err))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), BridgeError> {
+        self.stream
+            .write_all(bytes)
+            .map_err(|err| self.failure(err))
+    }
+
+    fn read_len(&mut self) -> Result<usize, BridgeError> {
+        read_len(&mut self.stream, MAX_STR_LEN).map_err(|err| self.failure(err))
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), BridgeError> {
+        if timeout != self.timeout {
+            self.stream.set_read_timeout(Some(timeout))?;
+            self.timeout = timeout;
+        }
+        Ok(())
+    }
+
+    // Tell apart a crashed server from other errors.
+    fn failure(&mut self, err: std::io::Error) -> BridgeError {
+        match self.child.try_wait() {
+            Ok(Some(status)) => BridgeError::Exited(status),
+            _ => err.into(),
+        }
+    }
+}
+
+impl Drop for BridgedInstance<'_> {
+    fn drop(&mut self) {
+        let _ = self.stream.write_all(&[QUIT]);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl std::fmt::Debug for BridgedInstance<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BridgedInstance")
+            .field("server_id", &self.child.id())
+            .field("block_length", &self.block_length)
+            .field("atom_capacity", &self.atom_capacity)
+            .field("run_timeout", &self.run_timeout)
+            .finish()
+    }
+}
+
+fn default_run_timeout(sample_rate: f64, block_length: usize) -> Duration {
+    #[allow(clippy::cast_precision_loss)]
+    let block = block_length as f64 / sample_rate;
+    if block.is_finite() && block > 0.0 {
+        Duration::from_secs_f64(block * RUN_TIMEOUT_BLOCKS).max(MIN_RUN_TIMEOUT)
+    } else {
+        MIN_RUN_TIMEOUT
+    }
+}
+
+// A token that other processes can not guess. `RandomState` is seeded by
+// the operating system, which is enough to keep other local processes from
+// posing as the server.
+fn random_token() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    (0..2_u8)
+        .map(|n| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u8(n);
+            hasher.write_u128(nanos);
+            hasher.write_u32(std::process::id());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+// A connection that did not send the token yet.
+struct Pending {
+    stream: TcpStream,
+    received: Vec<u8>,
+    since: Instant,
+}
+
+// Accept the connection of the server, which sends `token` first. Other
+// connections are closed once they sent something else or after
+// `TOKEN_TIMEOUT`, and are read without blocking so they can not hold up
+// the server.
+fn accept(
+    listener: &TcpListener,
+    child: &mut Child,
+    token: &str,
+    timeout: Duration,
+) -> Result<TcpStream, BridgeError> {
+    listener.set_nonblocking(true)?;
+    let start = Instant::now();
+    let mut pending: Vec<Pending> = Vec::new();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(true)?;
+                pending.push(Pending {
+                    stream,
+                    received: Vec::new(),
+                    since: Instant::now(),
+                });
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err.into()),
+        }
+        let mut accepted = None;
+        pending.retain_mut(|connection| {
+            if accepted.is_some() {
+                return true;
+            }
+            let mut bytes = [0; 64];
+            let wanted = (4 + token.len() - connection.received.len()).min(bytes.len());
+            match connection.stream.read(&mut bytes[..wanted]) {
+                Ok(0) => return false,
+                Ok(read) => connection.received.extend_from_slice(&bytes[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => return false,
+            }
+            match check_token(&connection.received, token) {
+                Some(true) => {
+                    accepted = connection.stream.try_clone().ok();
+                    false
+                }
+                Some(false) => false,
+                None => connection.since.elapsed() < TOKEN_TIMEOUT,
+            }
+        });
+        if let Some(stream) = accepted {
+            stream.set_nonblocking(false)?;
+            return Ok(stream);
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(BridgeError::Exited(status));
+        }
+        if start.elapsed() > timeout {
+            return Err(BridgeError::Timeout);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+// Check the token that was received so far, which is sent like
+// `write_bytes` writes it. Returns `None` if more bytes are needed.
+fn check_token(received: &[u8], token: &str) -> Option<bool> {
+    let len = received.get(..4)?;
+    if u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize != token.len() {
+        return Some(false);
+    }
+    let received = received.get(4..4 + token.len())?;
+    Some(received == token.as_bytes())
+}
+
+// Copy the atom at `source`, which has `available` bytes, to the buffer
+// `target` of `capacity` bytes. An atom that is missing or does not fit into
+// either is replaced with an empty sequence.
+unsafe fn copy_atom(
+    source: *const u8,
+    available: usize,
+    target: *mut u8,
+    capacity: usize,
+    sequence: LV2Urid,
+) {
+    let header = size_of::<LV2Atom>();
+    let len = if source.is_null() || available < header {
+        None
+    } else {
+        let atom = unsafe { std::ptr::read_unaligned(source.cast::<LV2Atom>()) };
+        Some(header + atom.size as usize).filter(|len| *len <= available && *len <= capacity)
+    };
+    match len {
+        Some(len) => unsafe { std::ptr::copy_nonoverlapping(source, target, len) },
+        None => unsafe { write_empty_sequence(target, capacity, sequence) },
+    }
+}
+
+// Write an empty sequence with the type `sequence` to the buffer `target` of
+// `capacity` bytes, or only its header with a size of 0 if the body does not
+// fit.
+#[allow(clippy::cast_possible_truncation)]
+unsafe fn write_empty_sequence(target: *mut u8, capacity: usize, sequence: LV2Urid) {
+    let header = size_of::<LV2Atom>();
+    if capacity < header {
+        return;
+    }
+    let size = if capacity < 2 * header { 0 } else { header };
+    let atom = LV2Atom {
+        size: size as u32,
+        mytype: sequence,
+    };
+    unsafe {
+        std::ptr::write_unaligned(target.cast::<LV2Atom>(), atom);
+        // The time unit 0 means frames.
+        std::ptr::write_bytes(target.add(header), 0, size);
+    }
+}
+
+/// Serve a `BridgedInstance` if the process was started by
+/// `BridgedInstance::spawn`, that is if `ADDRESS_VAR` is set. Returns `None`
+/// if it is not set, or the result of serving the host once the host drops
+/// the instance. Requires the `bridge` feature.
+///
+/// The server loads all the bundles on the system with
+/// `World::with_load_all`, and the bundle of the plugin if it is not one of
+/// them, like the fixture bundles of `lilv::testing`. It authenticates with
+/// `TOKEN_VAR`, and fails if it is not set.
+pub fn serve_from_env() -> Option<std::io::Result<()>> {
+    let address = std::env::var(ADDRESS_VAR).ok()?;
+    let token = match std::env::var(TOKEN_VAR) {
+        Ok(token) => token,
+        Err(_) => {
+            let message = format!("{} is not set", TOKEN_VAR);
+            return Some(Err(std::io::Error::new(ErrorKind::InvalidInput, message)));
+        }
+    };
+    Some(serve(&address, &token))
+}
+
+enum Served {
+    Inactive(Instance),
+    Active(ActiveInstance),
+}
+
+// Maps URIs with the host over the connection, so the URIDs of the server
+// are the URIDs of the host. The host answers while it waits for the reply
+// to a command.
+struct Remote {
+    stream: TcpStream,
+}
+
+impl UridSource for Remote {
+    fn map(&mut self, uri: &CStr) -> LV2Urid {
+        let mut message = vec![MAP];
+        write_bytes(&mut message, uri.to_bytes());
+        let mut urid = [0; 4];
+        match self
+            .stream
+            .write_all(&message)
+            .and_then(|()| self.stream.read_exact(&mut urid))
+        {
+            Ok(()) => u32::from_le_bytes(urid),
+            Err(_) => 0,
+        }
+    }
+
+    fn unmap(&mut self, urid: LV2Urid) -> Option<CString> {
+        let mut message = vec![UNMAP];
+        message.extend_from_slice(&urid.to_le_bytes());
+        self.stream.write_all(&message).ok()?;
+        let uri = read_bytes(&mut self.stream, MAX_STR_LEN).ok()?;
+        if uri.is_empty() {
+            None
+        } else {
+            CString::new(uri).ok()
+        }
+    }
+}
+
+fn serve(address: &str, token: &str) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let mut hello = Vec::new();
+    write_bytes(&mut hello, token.as_bytes());
+    stream.write_all(&hello)?;
+    let uri = read_str(&mut stream)?;
+    let bundle_uri = read_str(&mut stream)?;
+    let mut sample_rate = [0; 8];
+    stream.read_exact(&mut sample_rate)?;
+    let sample_rate = f64::from_le_bytes(sample_rate);
+    let block_length = read_len(&mut stream, usize::MAX)?;
+    let atom_capacity = read_len(&mut stream, usize::MAX)?;
+    let path = PathBuf::from(OsString::from_vec(read_bytes(&mut stream, MAX_STR_LEN)?));
+    let size = read_len(&mut stream, usize::MAX)?;
+
+    let world = World::with_load_all();
+    let find = || {
+        world
+            .new_uri(&uri)
+            .ok()
+            .and_then(|uri| world.plugins().plugin(&uri))
+    };
+    let plugin = find().or_else(|| {
+        world.load_bundle(&world.new_uri(&bundle_uri).ok()?);
+        find()
+    });
+    let plugin = match plugin {
+        Some(plugin) => plugin,
+        None => return reply_error(&mut stream, &format!("plugin {} not found", uri)),
+    };
+    let ports = match bridged_ports(&plugin) {
+        Ok(ports) => ports,
+        Err(err) => return reply_error(&mut stream, &err.to_string()),
+    };
+    let (offsets, expected) = layout(&ports, block_length, atom_capacity);
+    let memory = match SharedMemory::open(&path, size) {
+        Ok(_) if size != expected => {
+            return reply_error(&mut stream, "the shared memory does not match the ports")
+        }
+        Ok(memory) => memory,
+        Err(err) => return reply_error(&mut stream, &err.to_string()),
+    };
+    let remote = Remote {
+        stream: stream.try_clone()?,
+    };
+    let host = Host::with_urid_source(sample_rate, block_length, Box::new(remote));
+    let mut instance = match plugin.instantiate_with(host.features(), &host.caps(), sample_rate) {
+        Ok(instance) => instance,
+        Err(err) => return reply_error(&mut stream, &err.to_string()),
+    };
+    for (index, (port, offset)) in ports.iter().zip(&offsets).enumerate() {
+        let data: *mut f32 = match port {
+            Bridged::Unused => std::ptr::null_mut(),
+            _ => memory.at(*offset).cast(),
+        };
+        unsafe { instance.connect_port_mut(index, data) };
+    }
+    stream.write_all(&[OK])?;
+
+    let mut served = Some(Served::Inactive(instance));
+    let mut command = [0];
+    loop {
+        if stream.read_exact(&mut command).is_err() {
+            return Ok(());
+        }
+        let result = match (command[0], served.take()) {
+            (QUIT, _) | (_, None) => return Ok(()),
+            (ACTIVATE, Some(Served::Inactive(instance))) => {
+                served = Some(Served::Active(unsafe { instance.activate() }));
+                Ok(())
+            }
+            (DEACTIVATE, Some(Served::Active(instance))) => {
+                served = Some(Served::Inactive(unsafe { instance.deactivate() }));
+                Ok(())
+            }
+            (RUN, Some(mut state)) => {
+                let sample_count = read_len(&mut stream, usize::MAX)?;
+                let result = match &mut state {
+                    Served::Active(_) if sample_count > block_length => Err(format!(
+                        "{} frames exceed the block length {}",
+                        sample_count, block_length
+                    )),
+                    Served::Active(instance) => {
+                        fence(Ordering::SeqCst);
+                        for (port, offset) in ports.iter().zip(&offsets) {
+                            if let Bridged::Atom(PortDirection::Output) = port {
+                                // The plugin writes the atom into the
+                                // capacity that the host sets as its size.
+                                let size = atom_capacity - size_of::<LV2Atom>();
+                                #[allow(clippy::cast_possible_truncation)]
+                                let atom = LV2Atom {
+                                    size: size as u32,
+                                    mytype: 0,
+                                };
+                                let target = memory.at(*offset).cast::<LV2Atom>();
+                                unsafe { std::ptr::write_unaligned(target, atom) };
+                            }
+                        }
+                        unsafe { instance.run(sample_count) };
+                        fence(Ordering::SeqCst);
+                        Ok(())
+                    }
+                    Served::Inactive(_) => Err("the plugin is not active".to_string()),
+                };
+                served = Some(state);
+                result
+            }
+            (ACTIVATE, Some(state)) => {
+                served = Some(state);
+                Err("the plugin is already active".to_string())
+            }
+            (DEACTIVATE, Some(state)) => {
+                served = Some(state);
+                Err("the plugin is not active".to_string())
+            }
+            (other, Some(state)) => {
+                served = Some(state);
+                Err(format!("unknown command {}", other))
+            }
+        };
+        match result {
+            Ok(()) => stream.write_all(&[OK])?,
+            Err(message) => reply_error(&mut stream, &message)?,
+        }
+    }
+}
+
+fn reply_error(stream: &mut TcpStream, message: &str) -> std::io::Result<()> {
+    let mut reply = vec![FAILED];
+    write_bytes(
+        &mut reply,
+        &message.as_bytes()[..message.len().min(MAX_STR_LEN)],
+    );
+    stream.write_all(&reply)
+}
+
+// Lengths are sent as `u32`, which is enough for block lengths and URIs.
+#[allow(clippy::cast_possible_truncation)]
+fn write_len(message: &mut Vec<u8>, len: usize) {
+    message.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn write_bytes(message: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(message, bytes.len());
+    message.extend_from_slice(bytes);
+}
+
+// Read a length, which must be at most `max` so a broken process can not
+// make the other one allocate without bounds.
+fn read_len(stream: &mut impl Read, max: usize) -> std::io::Result<usize> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > max {
+        let message = format!("length {} exceeds {}", len, max);
+        return Err(std::io::Error::new(ErrorKind::InvalidData, message));
+    }
+    Ok(len)
+}
+
+fn read_bytes(stream: &mut impl Read, max: usize) -> std::io::Result<Vec<u8>> {
+    let mut bytes = vec![0; read_len(stream, max)?];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_str(stream: &mut impl Read) -> std::io::Result<String> {
+    String::from_utf8(read_bytes(stream, MAX_STR_LEN)?)
+        .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom::{from_json, to_json};
+
+    // Run by `test_bridged_instance` in a separate process.
+    #[test]
+    #[ignore]
+    fn bridge_server() {
+        if let Some(result) = serve_from_env() {
+            result.unwrap();
+        }
+    }
+
+    fn server() -> Command {
+        let mut server = Command::new(std::env::current_exe().unwrap());
+        server.args([
+            "bridge::tests::bridge_server",
+            "--exact",
+            "--ignored",
+            "--quiet",
+        ]);
+        server
+    }
+
+    #[test]
+    fn test_bridged_instance() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let host = Host::new(44100.0, 64);
+        // A long timeout, since the test may run on a loaded machine.
+        let options = BridgeOptions {
+            run_timeout: Some(Duration::from_secs(5)),
+            ..BridgeOptions::default()
+        };
+        let mut instance = BridgedInstance::spawn(server(), &plugin, &host, &options).unwrap();
+        let mut gain = -6.0_f32;
+        let mut input = [1.0_f32; 64];
+        let mut output = [0.0_f32; 64];
+        unsafe {
+            instance.connect_port_mut(0, &mut gain);
+            instance.connect_port_mut(1, input.as_mut_ptr());
+            instance.connect_port_mut(2, output.as_mut_ptr());
+        }
+        match unsafe { instance.run(64) } {
+            Err(BridgeError::Server(message)) => assert_eq!(message, "the plugin is not active"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        instance.activate().unwrap();
+        assert!(instance.activate().is_err());
+        unsafe { instance.run(64).unwrap() };
+        assert!((output[63] - 0.501).abs() < 1e-3, "{}", output[63]);
+        assert!(unsafe { instance.run(65) }.is_err());
+        instance.deactivate().unwrap();
+
+        instance.activate().unwrap();
+        let killed = Command::new("kill")
+            .args(["-9", &instance.server_id().to_string()])
+            .status();
+        if matches!(killed, Ok(status) if status.success()) {
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(matches!(
+                unsafe { instance.run(64) },
+                Err(BridgeError::Exited(_) | BridgeError::Io(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_bridged_atoms() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::MIDI_THROUGH_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let host = Host::new(48000.0, 64);
+        let options = BridgeOptions {
+            run_timeout: Some(Duration::from_secs(5)),
+            ..BridgeOptions::default()
+        };
+        let mut instance = BridgedInstance::spawn(server(), &plugin, &host, &options).unwrap();
+        let sequence = concat!(
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": ["#,
+            r#"{"time": 3, "value": {"type": "http://lv2plug.in/ns/midi#MidiEvent", "value": "903c64"}}]}"#,
+        );
+        let mut input = from_json(sequence, host.urid_map()).unwrap();
+        input.resize(64, 0);
+        let mut output = vec![0_u64; 64];
+        unsafe {
+            instance.connect_atom_port(0, input.as_mut_ptr().cast(), 8 * input.len());
+            instance.connect_atom_port(1, output.as_mut_ptr().cast(), 8 * output.len());
+        }
+        instance.activate().unwrap();
+        unsafe { instance.run(64).unwrap() };
+        let json = unsafe { to_json(output.as_ptr().cast(), host.urid_unmap()) }.unwrap();
+        assert_eq!(json, sequence);
+
+        // An output that does not fit becomes an empty sequence.
+        unsafe { instance.connect_atom_port(1, output.as_mut_ptr().cast(), 16) };
+        unsafe { instance.run(64).unwrap() };
+        let json = unsafe { to_json(output.as_ptr().cast(), host.urid_unmap()) }.unwrap();
+        assert_eq!(
+            json,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": []}"#
+        );
+    }
+
+    #[test]
+    fn test_copy_atom() {
+        let sequence = 7;
+        let mut source = [0_u64; 3];
+        source[0] = u64::from_ne_bytes({
+            let mut header = [0; 8];
+            header[..4].copy_from_slice(&16_u32.to_ne_bytes());
+            header[4..].copy_from_slice(&9_u32.to_ne_bytes());
+            header
+        });
+        source[1] = 1;
+        source[2] = 2;
+        let mut target = [u64::MAX; 3];
+        let (from, to) = (source.as_ptr().cast(), target.as_mut_ptr().cast());
+        unsafe { copy_atom(from, 24, to, 24, sequence) };
+        assert_eq!(target, source);
+
+        let header = |words: &[u64]| unsafe { std::ptr::read(words.as_ptr().cast::<LV2Atom>()) };
+        // The size of the source is checked against the bytes it has.
+        unsafe { copy_atom(from, 16, to, 24, sequence) };
+        assert_eq!(
+            (header(&target).size, header(&target).mytype),
+            (8, sequence)
+        );
+        assert_eq!(target[1], 0);
+        unsafe { copy_atom(std::ptr::null(), 0, to, 24, sequence) };
+        assert_eq!(header(&target).size, 8);
+        // A buffer too short for an empty sequence gets an empty header.
+        let mut target = [u64::MAX; 1];
+        unsafe { copy_atom(from, 24, target.as_mut_ptr().cast(), 8, sequence) };
+        assert_eq!(
+            (header(&target).size, header(&target).mytype),
+            (0, sequence)
+        );
+    }
+
+    #[test]
+    fn test_shared_memory() {
+        let ports = [
+            Bridged::Samples(PortDirection::Input),
+            Bridged::Control(PortDirection::Input),
+            Bridged::Unused,
+            Bridged::Atom(PortDirection::Output),
+        ];
+        let (offsets, size) = layout(&ports, 3, 16);
+        assert_eq!((offsets, size), (vec![0, 16, 24, 24], 40));
+
+        let path = shared_memory_dir().join(format!("lilv-bridge-test-{}", random_token()));
+        let mut host = SharedMemory::create(&path, size).unwrap();
+        assert!(SharedMemory::create(&path, size).is_err());
+        assert!(SharedMemory::open(&path, size + 8).is_err());
+        let server = SharedMemory::open(&path, size).unwrap();
+        host.unlink();
+        assert!(!path.exists());
+        unsafe { *host.at(24) = 42 };
+        assert_eq!(unsafe { *server.at(24) }, 42);
+    }
+
+    #[test]
+    fn test_check_token() {
+        let mut message = Vec::new();
+        write_bytes(&mut message, b"secret");
+        assert_eq!(check_token(&message, "secret"), Some(true));
+        assert_eq!(check_token(&message[..7], "secret"), None);
+        assert_eq!(check_token(&message[..2], "secret"), None);
+        assert_eq!(check_token(&message, "secret!"), Some(false));
+        assert_eq!(check_token(&message, "public"), Some(false));
+
+        let token = random_token();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, random_token());
+    }
+
+    #[test]
+    fn test_read_len() {
+        let mut message = Vec::new();
+        write_bytes(&mut message, b"uri");
+        assert_eq!(read_bytes(&mut &message[..], 3).unwrap(), b"uri");
+        assert!(read_bytes(&mut &message[..], 2).is_err());
+    }
+
+    #[test]
+    fn test_default_run_timeout() {
+        assert_eq!(
+            default_run_timeout(48000.0, 4800),
+            Duration::from_millis(800)
+        );
+        assert_eq!(default_run_timeout(48000.0, 64), MIN_RUN_TIMEOUT);
+        assert_eq!(default_run_timeout(0.0, 64), MIN_RUN_TIMEOUT);
+    }
+}
@@ -0,0 +1,112 @@
+use std::ptr::NonNull;
+
+/// Values that ports stay connected to while the owner is moved and the
+/// values are read and written.
+///
+/// The pointers of the ports and every access of the owner derive from the
+/// pointer of `Box::into_raw`. A `Box` or a reference would instead claim
+/// unique access to the values when it is moved or used, which invalidates
+/// the pointers the instance holds.
+pub(crate) struct PortBuffer {
+    data: NonNull<f32>,
+    len: usize,
+}
+
+// The buffer owns its values like a `Box<[f32]>`, and writing them takes
+// `&mut self`.
+unsafe impl Send for PortBuffer {}
+unsafe impl Sync for PortBuffer {}
+
+impl PortBuffer {
+    pub(crate) fn new(values: Box<[f32]>) -> PortBuffer {
+        let len = values.len();
+        let data = Box::into_raw(values).cast::<f32>();
+        PortBuffer {
+            data: NonNull::new(data).unwrap_or_else(NonNull::dangling),
+            len,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The pointer to connect the port at `index` to.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub(crate) fn as_mut_ptr(&self, index: usize) -> *mut f32 {
+        assert!(index < self.len, "index {} out of bounds", index);
+        unsafe { self.data.as_ptr().add(index) }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<f32> {
+        if index < self.len {
+            Some(unsafe { self.data.as_ptr().add(index).read() })
+        } else {
+            None
+        }
+    }
+
+    /// The value at `index`.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub(crate) fn value(&self, index: usize) -> f32 {
+        unsafe { self.as_mut_ptr(index).read() }
+    }
+
+    /// Set the value at `index`. Returns `false` if it is out of bounds.
+    pub(crate) fn set(&mut self, index: usize, value: f32) -> bool {
+        if index < self.len {
+            unsafe { self.data.as_ptr().add(index).write(value) };
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.len).map(move |index| self.value(index))
+    }
+}
+
+impl Drop for PortBuffer {
+    fn drop(&mut self) {
+        let values = std::ptr::slice_from_raw_parts_mut(self.data.as_ptr(), self.len);
+        drop(unsafe { Box::from_raw(values) });
+    }
+}
+
+impl std::fmt::Debug for PortBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_buffer() {
+        let buffer = PortBuffer::new(vec![1.0, 2.0].into_boxed_slice());
+        let ptr = buffer.as_mut_ptr(1);
+        unsafe { ptr.write(3.0) };
+        assert_eq!(buffer.get(1), Some(3.0));
+        assert_eq!(buffer.get(2), None);
+
+        // The pointer stays valid when the buffer is moved and written.
+        let mut buffer = Box::new(buffer);
+        assert!(buffer.set(1, 4.0));
+        assert!(!buffer.set(2, 4.0));
+        assert_eq!(unsafe { ptr.read() }, 4.0);
+        assert_eq!(buffer.as_mut_ptr(1), ptr);
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), [1.0, 4.0]);
+        assert_eq!(format!("{:?}", buffer), "[1.0, 4.0]");
+
+        let empty = PortBuffer::new(Box::new([]));
+        assert_eq!(empty.len(), 0);
+        assert_eq!(empty.get(0), None);
+    }
+}
@@ -0,0 +1,541 @@
+use crate::info::{PluginInfo, PortInfo, PresetInfo};
+use crate::world::World;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const MAGIC: &[u8; 8] = b"LILVCAT\0";
+const VERSION: u32 = 1;
+const USER_MAGIC: &[u8; 8] = b"LILVTAG\0";
+const USER_VERSION: u32 = 1;
+
+/// Owned snapshots of the metadata of all the plugins in a world.
+///
+/// A catalog can be written to a file with `serialize_shared` and read back
+/// with `open_shared`, so a scanner process can load the world once and share
+/// the plugin data with host processes that never call into lilv. The file
+/// uses a compact binary format that is only meant to be read by the same
+/// version of this crate.
+///
+/// The catalog also holds the tags and favorites of the user, which `search`
+/// matches. They are kept by plugin URI, so they survive plugins that are
+/// uninstalled and installed again, and are saved to a separate file chosen
+/// by the host with `save_user_data`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PluginCatalog {
+    plugins: Vec<PluginInfo>,
+    // The sorted tags of each plugin URI that has tags.
+    tags: BTreeMap<String, Vec<String>>,
+    favorites: BTreeSet<String>,
+}
+
+impl PluginCatalog {
+    /// Create a catalog of `plugins`.
+    #[must_use]
+    pub fn new(plugins: Vec<PluginInfo>) -> PluginCatalog {
+        PluginCatalog {
+            plugins,
+            ..PluginCatalog::default()
+        }
+    }
+
+    /// Capture the metadata of the plugins in `world`. Plugins that fail
+    /// `Plugin::verify` are skipped.
+    #[must_use]
+    pub fn capture(world: &World) -> PluginCatalog {
+        let plugins = world
+            .plugins()
+            .iter()
+            .filter(crate::plugin::Plugin::verify)
+            .map(|plugin| PluginInfo::capture(&plugin))
+            .collect();
+        PluginCatalog::new(plugins)
+    }
+
+    /// The plugins in the catalog.
+    #[must_use]
+    pub fn plugins(&self) -> &[PluginInfo] {
+        &self.plugins
+    }
+
+    /// Get the plugin with the URI `uri`.
+    #[must_use]
+    pub fn plugin(&self, uri: &str) -> Option<&PluginInfo> {
+        self.plugins.iter().find(|plugin| plugin.uri == uri)
+    }
+
+    /// Set the tags of the plugin with the URI `uri`, replacing its previous
+    /// tags. Tags are trimmed, and empty and repeated tags are dropped, so
+    /// no tags remove the tags of the plugin.
+    pub fn set_tags<T: AsRef<str>>(&mut self, uri: &str, tags: impl IntoIterator<Item = T>) {
+        let tags: BTreeSet<String> = tags
+            .into_iter()
+            .map(|tag| tag.as_ref().trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        let tags: Vec<String> = tags.into_iter().collect();
+        if tags.is_empty() {
+            self.tags.remove(uri);
+        } else {
+            self.tags.insert(uri.to_string(), tags);
+        }
+    }
+
+    /// The sorted tags of the plugin with the URI `uri`.
+    #[must_use]
+    pub fn tags(&self, uri: &str) -> &[String] {
+        self.tags.get(uri).map_or(&[], Vec::as_slice)
+    }
+
+    /// Mark the plugin with the URI `uri` as a favorite, or unmark it.
+    pub fn set_favorite(&mut self, uri: &str, favorite: bool) {
+        if favorite {
+            self.favorites.insert(uri.to_string());
+        } else {
+            self.favorites.remove(uri);
+        }
+    }
+
+    /// Whether the plugin with the URI `uri` is a favorite.
+    #[must_use]
+    pub fn is_favorite(&self, uri: &str) -> bool {
+        self.favorites.contains(uri)
+    }
+
+    /// The plugins of the catalog that are favorites, in catalog order.
+    #[must_use]
+    pub fn favorites(&self) -> Vec<&PluginInfo> {
+        self.plugins
+            .iter()
+            .filter(|plugin| self.is_favorite(&plugin.uri))
+            .collect()
+    }
+
+    /// Find the plugins that match every word of `query`, with the
+    /// favorites first and otherwise in catalog order.
+    ///
+    /// A word matches if the name, URI, class label, author or a tag of the
+    /// plugin contains it, ignoring case. The word `tag:name` only matches
+    /// plugins with the tag `name`, and `is:favorite` only matches favorites.
+    /// An empty query matches every plugin.
+    #[must_use]
+    pub fn search(&self, query: &str) -> Vec<&PluginInfo> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let matches = |plugin: &PluginInfo| {
+            let tags = self.tags(&plugin.uri);
+            words.iter().all(|word| {
+                if word == "is:favorite" {
+                    return self.is_favorite(&plugin.uri);
+                }
+                if let Some(tag) = word.strip_prefix("tag:") {
+                    return tags.iter().any(|t| t.to_lowercase() == tag);
+                }
+                let contains = |field: &str| field.to_lowercase().contains(word.as_str());
+                let fields = [
+                    Some(plugin.name.as_str()),
+                    Some(plugin.uri.as_str()),
+                    plugin.class_label.as_deref(),
+                    plugin.author_name.as_deref(),
+                ];
+                fields.iter().flatten().any(|field| contains(field))
+                    || tags.iter().any(|tag| contains(tag))
+            })
+        };
+        let mut found: Vec<&PluginInfo> = self.plugins.iter().filter(|p| matches(p)).collect();
+        // The sort is stable, so the plugins keep their order otherwise.
+        found.sort_by_key(|plugin| !self.is_favorite(&plugin.uri));
+        found
+    }
+
+    /// Write the tags and favorites to the file at `path`, like
+    /// `serialize_shared` writes the catalog.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be written.
+    pub fn save_user_data(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = Writer(USER_MAGIC.to_vec());
+        writer.u32(USER_VERSION);
+        writer.len(self.tags.len())?;
+        for (uri, tags) in &self.tags {
+            writer.str(uri)?;
+            writer.strs(tags)?;
+        }
+        writer.len(self.favorites.len())?;
+        for uri in &self.favorites {
+            writer.str(uri)?;
+        }
+        write_replacing(path.as_ref(), &writer.0)
+    }
+
+    /// Replace the tags and favorites with the ones `save_user_data` wrote
+    /// to the file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be read, like an error of kind
+    /// `NotFound` before the first save, or an error of kind `InvalidData`
+    /// if it is not a file of this version. The tags and favorites are left
+    /// as they are on errors.
+    pub fn load_user_data(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+        if reader.take(USER_MAGIC.len())? != USER_MAGIC {
+            return Err(invalid("not a file of tags and favorites"));
+        }
+        let version = reader.u32()?;
+        if version != USER_VERSION {
+            return Err(invalid(&format!(
+                "unsupported tags and favorites version {}",
+                version
+            )));
+        }
+        let tags = reader.list(|reader| Ok((reader.str()?, reader.list(Reader::str)?)))?;
+        let favorites = reader.list(Reader::str)?;
+        if reader.offset != bytes.len() {
+            return Err(invalid("trailing data after the tags and favorites"));
+        }
+        self.tags.clear();
+        for (uri, tags) in tags {
+            self.set_tags(&uri, tags);
+        }
+        self.favorites = favorites.into_iter().collect();
+        Ok(())
+    }
+
+    /// Write the catalog to the file at `path`. The catalog is written to a
+    /// temporary file next to `path` first and then renamed, so processes
+    /// that open the file at the same time read either the old or the new
+    /// catalog. The tags and favorites are not written, see
+    /// `save_user_data`.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be written.
+    pub fn serialize_shared(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = Writer(MAGIC.to_vec());
+        writer.u32(VERSION);
+        writer.len(self.plugins.len())?;
+        for plugin in &self.plugins {
+            writer.plugin(plugin)?;
+        }
+        write_replacing(path.as_ref(), &writer.0)
+    }
+
+    /// Read a catalog written by `serialize_shared` from the file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be read, or an error of kind
+    /// `InvalidData` if it is not a catalog of this version.
+    pub fn open_shared(path: impl AsRef<Path>) -> std::io::Result<PluginCatalog> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a plugin catalog"));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported catalog version {}", version)));
+        }
+        let plugins = reader.list(Reader::plugin)?;
+        if reader.offset != bytes.len() {
+            return Err(invalid("trailing data after the catalog"));
+        }
+        Ok(PluginCatalog::new(plugins))
+    }
+}
+
+// Write `bytes` to a temporary file next to `path` and rename it to `path`.
+fn write_replacing(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    // The name is unique so writers in other processes or threads do not
+    // overwrite each other's temporary file.
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&temporary, bytes)?;
+    let result = std::fs::rename(&temporary, path);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
+
+// Integers and floats are written in little endian, strings and lists are
+// prefixed with their length and optional values with a byte that is 1 if
+// the value is present.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) -> std::io::Result<()> {
+        let len = u32::try_from(len).map_err(|_| invalid("catalog is too large"))?;
+        self.u32(len);
+        Ok(())
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) -> std::io::Result<()> {
+        self.len(value.len())?;
+        self.0.extend_from_slice(value.as_bytes());
+        Ok(())
+    }
+
+    fn strs(&mut self, values: &[String]) -> std::io::Result<()> {
+        self.len(values.len())?;
+        values.iter().try_for_each(|value| self.str(value))
+    }
+
+    fn option_str(&mut self, value: Option<&str>) -> std::io::Result<()> {
+        self.0.push(u8::from(value.is_some()));
+        value.map_or(Ok(()), |value| self.str(value))
+    }
+
+    fn option_f32(&mut self, value: Option<f32>) {
+        self.0.push(u8::from(value.is_some()));
+        if let Some(value) = value {
+            self.f32(value);
+        }
+    }
+
+    fn plugin(&mut self, plugin: &PluginInfo) -> std::io::Result<()> {
+        self.str(&plugin.uri)?;
+        self.str(&plugin.name)?;
+        self.option_str(plugin.class_uri.as_deref())?;
+        self.option_str(plugin.class_label.as_deref())?;
+        self.option_str(plugin.author_name.as_deref())?;
+        self.len(plugin.ports.len())?;
+        for port in &plugin.ports {
+            self.port(port)?;
+        }
+        self.len(plugin.presets.len())?;
+        for preset in &plugin.presets {
+            self.preset(preset)?;
+        }
+        Ok(())
+    }
+
+    fn port(&mut self, port: &PortInfo) -> std::io::Result<()> {
+        self.len(port.index)?;
+        self.str(&port.symbol)?;
+        self.str(&port.name)?;
+        self.strs(&port.classes)?;
+        self.strs(&port.properties)?;
+        self.option_f32(port.default);
+        self.option_f32(port.minimum);
+        self.option_f32(port.maximum);
+        self.option_str(port.unit_uri.as_deref())?;
+        self.option_str(port.unit_symbol.as_deref())?;
+        self.len(port.scale_points.len())?;
+        for (value, label) in &port.scale_points {
+            self.f32(*value);
+            self.str(label)?;
+        }
+        Ok(())
+    }
+
+    fn preset(&mut self, preset: &PresetInfo) -> std::io::Result<()> {
+        self.str(&preset.uri)?;
+        self.option_str(preset.label.as_deref())?;
+        self.option_str(preset.author.as_deref())?;
+        self.option_str(preset.bank.as_deref())?;
+        self.option_str(preset.comment.as_deref())?;
+        self.option_str(preset.modified.as_deref())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> std::io::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(len))
+            .ok_or_else(|| invalid("catalog is truncated"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn len(&mut self) -> std::io::Result<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn f32(&mut self) -> std::io::Result<f32> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn str(&mut self) -> std::io::Result<String> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string is not valid UTF-8"))
+    }
+
+    fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Reader<'a>) -> std::io::Result<T>,
+    ) -> std::io::Result<Option<T>> {
+        match self.take(1)?[0] {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            _ => Err(invalid("invalid optional value")),
+        }
+    }
+
+    // Lists are not preallocated from their length, since the length is
+    // read from the file.
+    fn list<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Reader<'a>) -> std::io::Result<T>,
+    ) -> std::io::Result<Vec<T>> {
+        let len = self.len()?;
+        let mut values = Vec::new();
+        for _ in 0..len {
+            values.push(read(self)?);
+        }
+        Ok(values)
+    }
+
+    fn plugin(&mut self) -> std::io::Result<PluginInfo> {
+        Ok(PluginInfo {
+            uri: self.str()?,
+            name: self.str()?,
+            class_uri: self.option(Reader::str)?,
+            class_label: self.option(Reader::str)?,
+            author_name: self.option(Reader::str)?,
+            ports: self.list(Reader::port)?,
+            presets: self.list(Reader::preset)?,
+        })
+    }
+
+    fn port(&mut self) -> std::io::Result<PortInfo> {
+        Ok(PortInfo {
+            index: self.len()?,
+            symbol: self.str()?,
+            name: self.str()?,
+            classes: self.list(Reader::str)?,
+            properties: self.list(Reader::str)?,
+            default: self.option(Reader::f32)?,
+            minimum: self.option(Reader::f32)?,
+            maximum: self.option(Reader::f32)?,
+            unit_uri: self.option(Reader::str)?,
+            unit_symbol: self.option(Reader::str)?,
+            scale_points: self.list(|reader| Ok((reader.f32()?, reader.str()?)))?,
+        })
+    }
+
+    fn preset(&mut self) -> std::io::Result<PresetInfo> {
+        Ok(PresetInfo {
+            uri: self.str()?,
+            label: self.option(Reader::str)?,
+            author: self.option(Reader::str)?,
+            bank: self.option(Reader::str)?,
+            comment: self.option(Reader::str)?,
+            modified: self.option(Reader::str)?,
+        })
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_catalog() {
+        let world = crate::testing::fixture_world().unwrap();
+        let catalog = PluginCatalog::capture(&world);
+        let amp = catalog.plugin(crate::testing::GAIN_URI).unwrap();
+        assert_eq!(amp.ports.len(), 3);
+
+        let dir = crate::testing::TempDir::new("shared-catalog").unwrap();
+        let path = dir.join("catalog.bin");
+        catalog.serialize_shared(&path).unwrap();
+        let shared = PluginCatalog::open_shared(&path).unwrap();
+        assert_eq!(shared, catalog);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let err = PluginCatalog::open_shared(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        std::fs::write(&path, b"LILVCAT\0\x02\0\0\0").unwrap();
+        let err = PluginCatalog::open_shared(&path).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported catalog version 2");
+    }
+
+    #[test]
+    fn test_tags_and_favorites() {
+        let world = crate::testing::fixture_world().unwrap();
+        let mut catalog = PluginCatalog::capture(&world);
+        let (gain, midi) = (crate::testing::GAIN_URI, crate::testing::MIDI_THROUGH_URI);
+        catalog.set_tags(gain, [" Mixing ", "mastering", "", "Mixing"]);
+        assert_eq!(catalog.tags(gain), ["Mixing", "mastering"]);
+        assert!(catalog.tags(midi).is_empty());
+        catalog.set_favorite(midi, true);
+        catalog.set_favorite("urn:lilv-rs:uninstalled", true);
+        assert!(catalog.is_favorite(midi));
+        let uris = |plugins: Vec<&PluginInfo>| -> Vec<String> {
+            plugins.iter().map(|plugin| plugin.uri.clone()).collect()
+        };
+        assert_eq!(uris(catalog.favorites()), [midi]);
+
+        // Favorites come first, and tags are matched like the other fields.
+        assert_eq!(
+            uris(catalog.search("fixture:")).first().map(String::as_str),
+            Some(midi)
+        );
+        assert_eq!(catalog.search("").len(), catalog.plugins().len());
+        assert_eq!(uris(catalog.search("MIX")), [gain]);
+        assert_eq!(uris(catalog.search("tag:mastering")), [gain]);
+        assert!(catalog.search("tag:master").is_empty());
+        assert!(catalog.search("gain is:favorite").is_empty());
+        assert_eq!(uris(catalog.search("is:favorite")), [midi]);
+
+        let dir = crate::testing::TempDir::new("catalog-user-data").unwrap();
+        let path = dir.join("user.bin");
+        let mut loaded = PluginCatalog::capture(&world);
+        let err = loaded.load_user_data(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        catalog.save_user_data(&path).unwrap();
+        loaded.load_user_data(&path).unwrap();
+        assert_eq!(loaded, catalog);
+        // The shared catalog does not hold the user data.
+        catalog.serialize_shared(dir.join("catalog.bin")).unwrap();
+        let shared = PluginCatalog::open_shared(dir.join("catalog.bin")).unwrap();
+        assert!(shared.tags(gain).is_empty());
+
+        catalog.set_tags(gain, Vec::<String>::new());
+        assert!(catalog.search("tag:mixing").is_empty());
+        std::fs::write(&path, b"LILVCAT\0").unwrap();
+        assert_eq!(
+            loaded.load_user_data(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(loaded.tags(gain), ["Mixing", "mastering"]);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::plugin::Plugin;
+use crate::port::camel_case_ident;
+use std::fmt::Write;
+
+/// Emit Rust source with an enum for each `lv2:enumeration` port of
+/// `plugin`, for hosts that hard-code support for specific plugins.
+///
+/// Each enum is named after the port symbol and has a variant for each
+/// scale point as named by `Port::enum_variants`, with `value` and
+/// `from_value` to convert from and to the port value. Symbols that have the
+/// same CamelCase name get a number like the variants do. Scale points with
+/// a value that is NaN or infinite are skipped, and so are ports without
+/// other scale points.
+#[must_use]
+pub fn port_enums(plugin: &Plugin) -> String {
+    let uri = plugin.uri().turtle_token();
+    let mut source = String::new();
+    let mut names: Vec<String> = Vec::new();
+    for port in plugin.iter_ports().filter(|port| port.is_enumeration()) {
+        let variants: Vec<_> = port
+            .enum_variants()
+            .into_iter()
+            .filter(|(value, _, _)| value.is_finite())
+            .collect();
+        let symbol = match port.symbol().as_ref().and_then(|s| s.as_str()) {
+            Some(symbol) => symbol.to_string(),
+            None => continue,
+        };
+        if variants.is_empty() {
+            continue;
+        }
+        let base = camel_case_ident(&symbol);
+        let mut name = base.clone();
+        let mut n = 1;
+        while names.contains(&name) {
+            n += 1;
+            name = format!("{}{}", base, n);
+        }
+        names.push(name.clone());
+        if !source.is_empty() {
+            source.push('\n');
+        }
+        let doc = format!("Values of the `{}` port of {}.", symbol, uri);
+        source.push_str(&enum_source(&name, &doc, &variants));
+    }
+    source
+}
+
+fn enum_source(name: &str, doc: &str, variants: &[(f32, String, String)]) -> String {
+    // Writing to a `String` can not fail.
+    let mut s = String::new();
+    let _ = writeln!(s, "/// {}", doc);
+    let _ = writeln!(s, "#[derive(Clone, Copy, Debug, PartialEq)]");
+    let _ = writeln!(s, "pub enum {} {{", name);
+    for (_, label, ident) in variants {
+        let _ = writeln!(s, "    /// {}", label.replace('\n', " "));
+        let _ = writeln!(s, "    {},", ident);
+    }
+    let _ = writeln!(s, "}}\n\nimpl {} {{", name);
+    let _ = writeln!(s, "    /// The port value of the variant.");
+    let _ = writeln!(s, "    pub fn value(self) -> f32 {{\n        match self {{");
+    for (value, _, ident) in variants {
+        let _ = writeln!(s, "            {}::{} => {:?},", name, ident, value);
+    }
+    let _ = writeln!(s, "        }}\n    }}\n");
+    let _ = writeln!(
+        s,
+        "    /// The variant with the port value `value`, if any."
+    );
+    let _ = writeln!(
+        s,
+        "    pub fn from_value(value: f32) -> Option<{}> {{",
+        name
+    );
+    for (value, _, ident) in variants {
+        let _ = writeln!(s, "        if value == {:?} {{", value);
+        let _ = writeln!(
+            s,
+            "            return Some({}::{});\n        }}",
+            name, ident
+        );
+    }
+    let _ = writeln!(s, "        None\n    }}\n}}");
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enum_source() {
+        let variants = vec![
+            (0.0, "Low pass".to_string(), "LowPass".to_string()),
+            (1.5, "High pass".to_string(), "HighPass".to_string()),
+        ];
+        let expected = "/// Values of the `mode` port.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Low pass
+    LowPass,
+    /// High pass
+    HighPass,
+}
+
+impl Mode {
+    /// The port value of the variant.
+    pub fn value(self) -> f32 {
+        match self {
+            Mode::LowPass => 0.0,
+            Mode::HighPass => 1.5,
+        }
+    }
+
+    /// The variant with the port value `value`, if any.
+    pub fn from_value(value: f32) -> Option<Mode> {
+        if value == 0.0 {
+            return Some(Mode::LowPass);
+        }
+        if value == 1.5 {
+            return Some(Mode::HighPass);
+        }
+        None
+    }
+}
+";
+        assert_eq!(
+            enum_source("Mode", "Values of the `mode` port.", &variants),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_port_enums_source() {
+        // `low_pass` and `lowPass` have the same CamelCase name, and the
+        // value of `Loud` does not fit into an `f32`.
+        let dir = crate::testing::TempDir::new("port-enums").unwrap();
+        let bundle = dir.join("enums.lv2");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(
+            bundle.join("manifest.ttl"),
+            "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+             @prefix rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#> .\n\
+             @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n\
+             <urn:lilv-rs:test:enums> a lv2:Plugin ;\n\
+             lv2:port [\n\
+             a lv2:InputPort , lv2:ControlPort ;\n\
+             lv2:index 0 ;\n\
+             lv2:symbol \"low_pass\" ;\n\
+             lv2:name \"Low pass\" ;\n\
+             lv2:portProperty lv2:enumeration ;\n\
+             lv2:scalePoint [ rdfs:label \"Off\" ; rdf:value 0.0 ] ,\n\
+             [ rdfs:label \"On\" ; rdf:value 1.0 ] ,\n\
+             [ rdfs:label \"Loud\" ; rdf:value 1.0e39 ]\n\
+             ] , [\n\
+             a lv2:InputPort , lv2:ControlPort ;\n\
+             lv2:index 1 ;\n\
+             lv2:symbol \"lowPass\" ;\n\
+             lv2:name \"Low pass 2\" ;\n\
+             lv2:portProperty lv2:enumeration ;\n\
+             lv2:scalePoint [ rdfs:label \"Steep\" ; rdf:value 2 ]\n\
+             ] .\n",
+        )
+        .unwrap();
+        let world = crate::World::new();
+        world.load_bundle_dir(&bundle).unwrap();
+        let uri = world.new_uri("urn:lilv-rs:test:enums").unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let expected = "/// Values of the `low_pass` port of <urn:lilv-rs:test:enums>.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LowPass {
+    /// Off
+    Off,
+    /// On
+    On,
+}
+
+impl LowPass {
+    /// The port value of the variant.
+    pub fn value(self) -> f32 {
+        match self {
+            LowPass::Off => 0.0,
+            LowPass::On => 1.0,
+        }
+    }
+
+    /// The variant with the port value `value`, if any.
+    pub fn from_value(value: f32) -> Option<LowPass> {
+        if value == 0.0 {
+            return Some(LowPass::Off);
+        }
+        if value == 1.0 {
+            return Some(LowPass::On);
+        }
+        None
+    }
+}
+
+/// Values of the `lowPass` port of <urn:lilv-rs:test:enums>.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LowPass2 {
+    /// Steep
+    Steep,
+}
+
+impl LowPass2 {
+    /// The port value of the variant.
+    pub fn value(self) -> f32 {
+        match self {
+            LowPass2::Steep => 2.0,
+        }
+    }
+
+    /// The variant with the port value `value`, if any.
+    pub fn from_value(value: f32) -> Option<LowPass2> {
+        if value == 2.0 {
+            return Some(LowPass2::Steep);
+        }
+        None
+    }
+}
+";
+        assert_eq!(port_enums(&plugin), expected);
+    }
+
+    #[test]
+    fn test_port_enums() {
+        let world = crate::testing::fixture_world().unwrap();
+        for plugin in world.plugins() {
+            let source = port_enums(&plugin);
+            let enums = plugin
+                .iter_ports()
+                .filter(|port| port.is_enumeration() && !port.enum_variants().is_empty())
+                .count();
+            assert_eq!(source.matches("pub enum ").count(), enums);
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::graph::Chain;
+use crate::host::Host;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Drives a chain from interleaved buffers of any number of frames, like the
+/// buffers of an audio device.
+///
+/// The input is collected into blocks of `Chain::block_length` frames and
+/// the chain is run once per full block, which delays the output by one
+/// block, see `latency`. Channel `n` of the device is channel `n` of the
+/// chain. Device channels the chain does not have are silent, and chain
+/// channels the device does not have are dropped.
+pub struct BlockRunner {
+    chain: Chain,
+    input_channels: usize,
+    output_channels: usize,
+    // The number of frames written to the inputs of the chain, which is also
+    // the number of frames read from its outputs.
+    position: usize,
+}
+
+impl BlockRunner {
+    /// Drive `chain` from buffers with `input_channels` and
+    /// `output_channels` interleaved channels.
+    #[must_use]
+    pub fn new(chain: Chain, input_channels: usize, output_channels: usize) -> BlockRunner {
+        BlockRunner {
+            chain,
+            input_channels,
+            output_channels,
+            position: 0,
+        }
+    }
+
+    /// The number of frames the output is delayed by, which is one block
+    /// plus the latency of the chain.
+    #[must_use]
+    pub fn latency(&self) -> usize {
+        self.chain.block_length() + self.chain.total_latency()
+    }
+
+    /// The chain, to schedule events or create a `Chain::param_queue`.
+    pub fn chain_mut(&mut self) -> &mut Chain {
+        &mut self.chain
+    }
+
+    /// Stop driving the chain and return it.
+    #[must_use]
+    pub fn into_chain(self) -> Chain {
+        self.chain
+    }
+
+    /// Feed the interleaved `input` to the chain and fill the interleaved
+    /// `output`, processing the chain each time a block is full. Frames that
+    /// are missing from `input` are silent. This does not allocate, so it is
+    /// real-time safe.
+    ///
+    /// # Safety
+    /// Running calls the plugins' code, which itself may be unsafe.
+    pub unsafe fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let block_length = self.chain.block_length();
+        let frames = output.len() / self.output_channels.max(1);
+        if block_length == 0 {
+            output.fill(0.0);
+            return;
+        }
+        let mut offset = 0;
+        while offset < frames {
+            let len = (block_length - self.position).min(frames - offset);
+            let block = self.position..self.position + len;
+            for channel in 0..self.chain.audio_inputs() {
+                let buffer = match self.chain.input_mut(channel) {
+                    Some(buffer) => &mut buffer[block.clone()],
+                    None => continue,
+                };
+                for (frame, sample) in buffer.iter_mut().enumerate() {
+                    let index = (offset + frame) * self.input_channels + channel;
+                    *sample = match input.get(index) {
+                        Some(sample) if channel < self.input_channels => *sample,
+                        _ => 0.0,
+                    };
+                }
+            }
+            for channel in 0..self.output_channels {
+                let buffer = self.chain.output(channel).map(|b| &b[block.clone()]);
+                for frame in 0..len {
+                    let index = (offset + frame) * self.output_channels + channel;
+                    output[index] = buffer.map_or(0.0, |buffer| buffer[frame]);
+                }
+            }
+            offset += len;
+            self.position += len;
+            if self.position == block_length {
+                unsafe { self.chain.process(block_length) };
+                self.position = 0;
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for BlockRunner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockRunner")
+            .field("chain", &self.chain)
+            .field("input_channels", &self.input_channels)
+            .field("output_channels", &self.output_channels)
+            .finish()
+    }
+}
+
+/// The reason a chain could not be run with cpal.
+#[derive(Debug)]
+pub enum CpalError {
+    /// The system has no default output device.
+    NoOutputDevice,
+    /// The configuration of a device could not be read.
+    Config(cpal::DefaultStreamConfigError),
+    /// A stream could not be created, like when the device does not support
+    /// the sample rate of the host or samples of type `f32`.
+    Build(cpal::BuildStreamError),
+    /// A stream could not be started.
+    Play(cpal::PlayStreamError),
+}
+
+impl std::fmt::Display for CpalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpalError::NoOutputDevice => write!(f, "there is no default output device"),
+            CpalError::Config(err) => write!(f, "failed to read the device configuration: {}", err),
+            CpalError::Build(err) => write!(f, "failed to create a stream: {}", err),
+            CpalError::Play(err) => write!(f, "failed to start a stream: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CpalError {}
+
+/// A chain that runs on the default devices of cpal, until it is dropped.
+pub struct CpalRunner {
+    // The streams stop when they are dropped.
+    _input: Option<cpal::Stream>,
+    _output: cpal::Stream,
+    latency: usize,
+}
+
+/// Run `chain` on the default input and output devices of the system, at
+/// the sample rate of `host`, for systems without JACK.
+///
+/// The output callback runs the chain with a `BlockRunner`, so the devices
+/// may use any buffer size. The input is passed to it through a ring buffer
+/// of four blocks, and input that is not there in time is silent. Chains
+/// without inputs do not open the input device.
+///
+/// # Safety
+/// Running calls the plugins' code, which itself may be unsafe. The chain
+/// must have been instantiated with `host`, and the ports the chain does not
+/// connect must be connected.
+///
+/// # Errors
+/// Returns an error if there is no output device or a stream could not be
+/// started.
+pub unsafe fn run(chain: Chain, host: &Host) -> Result<CpalRunner, CpalError> {
+    let system = cpal::default_host();
+    let output_device = system
+        .default_output_device()
+        .ok_or(CpalError::NoOutputDevice)?;
+    let config = |channels| cpal::StreamConfig {
+        channels,
+        sample_rate: cpal::SampleRate(host.sample_rate().round() as u32),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let output_channels = output_device
+        .default_output_config()
+        .map_err(CpalError::Config)?
+        .channels();
+    let input_device = system
+        .default_input_device()
+        .filter(|_| chain.audio_inputs() > 0);
+    let input_channels = match &input_device {
+        Some(device) => device
+            .default_input_config()
+            .map_err(CpalError::Config)?
+            .channels(),
+        None => 0,
+    };
+
+    let capacity = 4 * chain.block_length() * usize::from(input_channels);
+    let ring = Arc::new(SampleRing::new(capacity));
+    let input = match input_device {
+        Some(device) => {
+            let ring = Arc::clone(&ring);
+            let stream = device
+                .build_input_stream(
+                    &config(input_channels),
+                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                        ring.push(data);
+                    },
+                    |_| {},
+                    None,
+                )
+                .map_err(CpalError::Build)?;
+            Some(stream)
+        }
+        None => None,
+    };
+
+    let mut runner = BlockRunner::new(
+        chain,
+        usize::from(input_channels),
+        usize::from(output_channels),
+    );
+    let latency = runner.latency();
+    // The input of a callback, which is allocated before the stream starts.
+    let mut scratch = vec![0.0; capacity];
+    let output = output_device
+        .build_output_stream(
+            &config(output_channels),
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let frames = data.len() / usize::from(output_channels.max(1));
+                let wanted = (frames * usize::from(input_channels)).min(scratch.len());
+                let read = ring.pop(&mut scratch[..wanted]);
+                scratch[read..wanted].fill(0.0);
+                unsafe { runner.process(&scratch[..wanted], data) };
+            },
+            |_| {},
+            None,
+        )
+        .map_err(CpalError::Build)?;
+    if let Some(input) = &input {
+        input.play().map_err(CpalError::Play)?;
+    }
+    output.play().map_err(CpalError::Play)?;
+    Ok(CpalRunner {
+        _input: input,
+        _output: output,
+        latency,
+    })
+}
+
+impl CpalRunner {
+    /// The latency of the chain and its block in frames, without the latency
+    /// of the devices.
+    #[must_use]
+    pub fn latency(&self) -> usize {
+        self.latency
+    }
+}
+
+impl std::fmt::Debug for CpalRunner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CpalRunner")
+            .field("latency", &self.latency)
+            .finish()
+    }
+}
+
+// A single producer, single consumer queue of samples between the input and
+// the output callback. Samples that do not fit are dropped.
+struct SampleRing {
+    samples: Box<[AtomicU32]>,
+    // Only the input callback writes `head`, and only the output callback
+    // writes `tail`.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl SampleRing {
+    fn new(capacity: usize) -> SampleRing {
+        SampleRing {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, samples: &[f32]) {
+        let head = self.head.load(Ordering::Relaxed);
+        let free = self.samples.len() - head.wrapping_sub(self.tail.load(Ordering::Acquire));
+        for (n, sample) in samples.iter().take(free).enumerate() {
+            let slot = &self.samples[head.wrapping_add(n) % self.samples.len()];
+            slot.store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.head.store(
+            head.wrapping_add(samples.len().min(free)),
+            Ordering::Release,
+        );
+    }
+
+    // Returns the number of samples read into `out`.
+    fn pop(&self, out: &mut [f32]) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let available = self.head.load(Ordering::Acquire).wrapping_sub(tail);
+        let read = available.min(out.len());
+        for (n, sample) in out[..read].iter_mut().enumerate() {
+            let slot = &self.samples[tail.wrapping_add(n) % self.samples.len()];
+            *sample = f32::from_bits(slot.load(Ordering::Relaxed));
+        }
+        self.tail.store(tail.wrapping_add(read), Ordering::Release);
+        read
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Gain;
+
+    #[test]
+    fn test_block_runner() {
+        let mut chain = Chain::new(4);
+        chain
+            .push_native("gain", Box::new(Gain::new(1, 2.0)))
+            .unwrap();
+        // A mono chain on a device with two input and two output channels.
+        let mut runner = BlockRunner::new(chain, 2, 2);
+        assert_eq!(runner.latency(), 4);
+        let input: Vec<f32> = (1..=6).flat_map(|n| [n as f32, -1.0]).collect();
+        let mut output = vec![9.0; 12];
+        unsafe { runner.process(&input, &mut output) };
+        // The first block comes out after 4 frames.
+        assert_eq!(
+            output,
+            [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 4.0, 0.0]
+        );
+        let mut output = vec![9.0; 4];
+        unsafe { runner.process(&[], &mut output) };
+        assert_eq!(output, [6.0, 0.0, 8.0, 0.0]);
+    }
+
+    #[test]
+    fn test_sample_ring() {
+        let ring = SampleRing::new(3);
+        ring.push(&[1.0, 2.0]);
+        let mut out = [0.0; 2];
+        assert_eq!(ring.pop(&mut out[..1]), 1);
+        assert_eq!(out[0], 1.0);
+        ring.push(&[3.0, 4.0, 5.0]);
+        let mut out = [0.0; 4];
+        assert_eq!(ring.pop(&mut out), 3);
+        assert_eq!(out[..3], [2.0, 3.0, 4.0]);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::atom::JsonError;
+use std::ffi::NulError;
+use std::path::PathBuf;
+
+/// An error returned by functions that pass data to or load data from lilv.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A string contained an interior NUL byte and could not be passed to
+    /// lilv. Contains the (lossily converted) string.
+    InteriorNul(String),
+    /// A path could not be represented in the encoding lilv expects on this
+    /// platform.
+    NonUnicodePath(PathBuf),
+    /// The data for a resource could not be loaded. Contains the URI of the
+    /// resource.
+    ResourceNotLoaded(String),
+    /// No state was found for a resource, like a preset. Contains the URI of
+    /// the resource.
+    StateNotFound(String),
+    /// A state could not be saved. Contains the path of the file.
+    StateNotSaved(PathBuf),
+    /// A string was not valid Turtle.
+    InvalidTurtle(ParseError),
+    /// A string was not a valid absolute URI. Contains the string.
+    InvalidUri(String),
+    /// A property of a `PortableState` was not valid atom JSON.
+    InvalidAtom(JsonError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InteriorNul(s) => write!(f, "string contains an interior NUL byte: {:?}", s),
+            Error::NonUnicodePath(p) => write!(f, "path is not valid unicode: {:?}", p),
+            Error::ResourceNotLoaded(uri) => write!(f, "failed to load resource {}", uri),
+            Error::StateNotFound(uri) => write!(f, "no state found for {}", uri),
+            Error::StateNotSaved(path) => write!(f, "failed to save state to {:?}", path),
+            Error::InvalidTurtle(err) => write!(f, "invalid Turtle: {}", err),
+            Error::InvalidUri(uri) => write!(f, "invalid URI: {:?}", uri),
+            Error::InvalidAtom(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The first error found in a Turtle document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// The line of the error, starting at 1, or 0 if it is not known.
+    pub line: usize,
+    /// The column of the error, starting at 1, or 0 if it is not known.
+    pub column: usize,
+    /// A description of the error.
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<NulError> for Error {
+    fn from(err: NulError) -> Error {
+        Error::InteriorNul(String::from_utf8_lossy(&err.into_vec()).into_owned())
+    }
+}
@@ -0,0 +1,330 @@
+use crate::ffi::lv2::{RawEvent, RawEventBuffer};
+use lv2_raw::atom::{LV2Atom, LV2AtomEvent, LV2AtomSequence, LV2AtomSequenceBody};
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::mem::size_of;
+
+/// A buffer for a `PortKind::Event` port of the deprecated LV2 event
+/// extension.
+///
+/// Event ports can not be connected to atom sequences. Connect them to
+/// `as_mut_ptr` instead, and copy events between the buffer and the atom
+/// sequences of the host with `copy_from_sequence` and `copy_to_sequence`.
+///
+/// Event types are numbered by the host through the uri-map feature, unlike
+/// URIDs, so the copy functions take a function to convert between the two.
+/// Type 0 is reserved by the extension for reference counted events, which
+/// are not supported.
+pub struct EventBuffer {
+    // Boxed so the address stays the same when the buffer is moved.
+    header: Box<RawEventBuffer>,
+    data: Vec<u64>,
+}
+
+unsafe impl Send for EventBuffer {}
+
+/// An event in an `EventBuffer`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Event<'a> {
+    /// The time of the event in frames from the start of the block.
+    pub frames: u32,
+    /// The type of the event, as numbered by the uri-map feature.
+    pub event_type: u16,
+    /// The data of the event.
+    pub data: &'a [u8],
+}
+
+impl EventBuffer {
+    /// Create an empty buffer that can hold `capacity` bytes of events.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(capacity: usize) -> EventBuffer {
+        let mut data = vec![0_u64; capacity.min(u32::MAX as usize) / 8];
+        let header = Box::new(RawEventBuffer {
+            data: data.as_mut_ptr().cast(),
+            header_size: size_of::<RawEventBuffer>() as u16,
+            // Time stamps in audio frames.
+            stamp_type: 0,
+            event_count: 0,
+            capacity: (data.len() * 8) as u32,
+            size: 0,
+        });
+        EventBuffer { header, data }
+    }
+
+    /// The pointer to connect to the event port with
+    /// `Instance::connect_port_mut`. It stays valid until the buffer is
+    /// dropped.
+    pub fn as_mut_ptr(&mut self) -> *mut c_void {
+        (&mut *self.header as *mut RawEventBuffer).cast()
+    }
+
+    /// Remove all events. Input buffers must be cleared or refilled before
+    /// each `run`, and output buffers must be cleared before each `run`.
+    pub fn clear(&mut self) {
+        self.header.event_count = 0;
+        self.header.size = 0;
+    }
+
+    /// The number of events in the buffer.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.header.event_count as usize
+    }
+
+    /// Returns `true` if the buffer has no events.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.header.event_count == 0
+    }
+
+    /// Append an event. Events must be appended in time order. Returns
+    /// `false` if the event does not fit into the buffer.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn push(&mut self, frames: u32, event_type: u16, data: &[u8]) -> bool {
+        let size = match u16::try_from(data.len()) {
+            Ok(size) => size,
+            Err(_) => return false,
+        };
+        let offset = self.header.size as usize;
+        let total = pad(size_of::<RawEvent>() + data.len());
+        if offset + total > self.bytes().len() {
+            return false;
+        }
+        let event = RawEvent {
+            frames,
+            subframes: 0,
+            event_type,
+            size,
+        };
+        let bytes = self.bytes_mut();
+        unsafe {
+            std::ptr::write_unaligned(bytes.as_mut_ptr().add(offset).cast(), event);
+        }
+        let start = offset + size_of::<RawEvent>();
+        bytes[start..start + data.len()].copy_from_slice(data);
+        self.header.event_count += 1;
+        self.header.size = (offset + total) as u32;
+        true
+    }
+
+    /// Iterate over the events in the buffer, including the events written by
+    /// the plugin to an output port.
+    #[must_use]
+    pub fn iter(&self) -> EventIter<'_> {
+        let bytes = self.bytes();
+        EventIter {
+            bytes: &bytes[..(self.header.size as usize).min(bytes.len())],
+            remaining: self.header.event_count,
+        }
+    }
+
+    /// Replace the events in the buffer with the events of `sequence`.
+    ///
+    /// The time stamps of the sequence must be in frames, which is the default
+    /// for `run`. Events are converted with `event_type`, which gets the URID
+    /// of the type of an atom event. Events for which it returns `None`, and
+    /// events that do not fit into the buffer, are skipped. Returns the
+    /// number of events that were copied.
+    ///
+    /// # Safety
+    /// `sequence` must point to a valid atom sequence.
+    pub unsafe fn copy_from_sequence(
+        &mut self,
+        sequence: *const LV2AtomSequence,
+        mut event_type: impl FnMut(u32) -> Option<u16>,
+    ) -> usize {
+        self.clear();
+        let (body, size) = unsafe {
+            (
+                sequence.cast::<u8>().add(size_of::<LV2Atom>()),
+                (*sequence).atom.size as usize,
+            )
+        };
+        let mut offset = size_of::<LV2AtomSequenceBody>();
+        let mut copied = 0;
+        while offset + size_of::<LV2AtomEvent>() <= size {
+            let (time, mytype, data) = unsafe {
+                let event = body.add(offset).cast::<LV2AtomEvent>();
+                let data = std::slice::from_raw_parts(
+                    event.cast::<u8>().add(size_of::<LV2AtomEvent>()),
+                    (*event).body.size as usize,
+                );
+                ((*event).time_in_frames, (*event).body.mytype, data)
+            };
+            let frames = u32::try_from(time).ok();
+            let converted = event_type(mytype).filter(|t| *t != 0);
+            if let (Some(frames), Some(converted)) = (frames, converted) {
+                if self.push(frames, converted, data) {
+                    copied += 1;
+                }
+            }
+            offset += pad(size_of::<LV2AtomEvent>() + data.len());
+        }
+        copied
+    }
+
+    /// Replace the events of `sequence` with the events in the buffer.
+    ///
+    /// `capacity` is the size of the memory `sequence` points to in bytes.
+    /// Event types are converted to URIDs with `event_type`. Events for which
+    /// it returns `None`, and events of type 0, are skipped. Events stop being
+    /// copied once the sequence is full. The type and the time unit of the
+    /// sequence are left unchanged. Returns the number of events that were
+    /// copied.
+    ///
+    /// # Safety
+    /// `sequence` must point to `capacity` bytes that are aligned to 8 bytes.
+    #[allow(clippy::cast_possible_truncation)]
+    pub unsafe fn copy_to_sequence(
+        &self,
+        sequence: *mut LV2AtomSequence,
+        capacity: usize,
+        mut event_type: impl FnMut(u16) -> Option<u32>,
+    ) -> usize {
+        let body = unsafe { sequence.cast::<u8>().add(size_of::<LV2Atom>()) };
+        let mut size = size_of::<LV2AtomSequenceBody>();
+        let mut copied = 0;
+        for event in self.iter() {
+            let converted = match Some(event.event_type)
+                .filter(|t| *t != 0)
+                .and_then(&mut event_type)
+            {
+                Some(converted) => converted,
+                None => continue,
+            };
+            let total = pad(size_of::<LV2AtomEvent>() + event.data.len());
+            if size_of::<LV2Atom>() + size + total > capacity {
+                break;
+            }
+            unsafe {
+                let out = body.add(size).cast::<LV2AtomEvent>();
+                (*out).time_in_frames = i64::from(event.frames);
+                (*out).body = LV2Atom {
+                    size: event.data.len() as u32,
+                    mytype: converted,
+                };
+                std::ptr::copy_nonoverlapping(
+                    event.data.as_ptr(),
+                    out.cast::<u8>().add(size_of::<LV2AtomEvent>()),
+                    event.data.len(),
+                );
+            }
+            size += total;
+            copied += 1;
+        }
+        unsafe { (*sequence).atom.size = size as u32 };
+        copied
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr().cast(), self.data.len() * 8) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(self.data.as_mut_ptr().cast(), self.data.len() * 8)
+        }
+    }
+}
+
+impl std::fmt::Debug for EventBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over the events in an `EventBuffer`.
+#[derive(Clone, Debug)]
+pub struct EventIter<'a> {
+    bytes: &'a [u8],
+    remaining: u32,
+}
+
+impl<'a> Iterator for EventIter<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Event<'a>> {
+        if self.remaining == 0 || self.bytes.len() < size_of::<RawEvent>() {
+            return None;
+        }
+        self.remaining -= 1;
+        let event: RawEvent = unsafe { std::ptr::read_unaligned(self.bytes.as_ptr().cast()) };
+        let end = (size_of::<RawEvent>() + event.size as usize).min(self.bytes.len());
+        let data = &self.bytes[size_of::<RawEvent>()..end];
+        self.bytes = &self.bytes[pad(end).min(self.bytes.len())..];
+        Some(Event {
+            frames: event.frames,
+            event_type: event.event_type,
+            data,
+        })
+    }
+}
+
+// Events of both extensions are aligned to 8 bytes.
+fn pad(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_iter() {
+        let mut buffer = EventBuffer::new(64);
+        assert!(buffer.push(0, 1, &[0x90, 60, 100]));
+        assert!(buffer.push(16, 1, &[0x80, 60, 0]));
+        assert!(!buffer.push(32, 1, &[0; 64]));
+        assert_eq!(buffer.len(), 2);
+        let events: Vec<_> = buffer.iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    frames: 0,
+                    event_type: 1,
+                    data: &[0x90, 60, 100]
+                },
+                Event {
+                    frames: 16,
+                    event_type: 1,
+                    data: &[0x80, 60, 0]
+                },
+            ]
+        );
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_sequence_round_trip() {
+        const MIDI_URID: u32 = 7;
+        let mut buffer = EventBuffer::new(256);
+        buffer.push(3, 1, &[0x90, 60, 100]);
+        buffer.push(5, 2, &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        buffer.push(9, 1, &[0x80, 60, 0]);
+
+        let mut memory = vec![0_u64; 32];
+        let sequence = memory.as_mut_ptr().cast::<LV2AtomSequence>();
+        let to_urid = |t| if t == 1 { Some(MIDI_URID) } else { None };
+        let written = unsafe { buffer.copy_to_sequence(sequence, memory.len() * 8, to_urid) };
+        assert_eq!(written, 2);
+
+        let mut copy = EventBuffer::new(256);
+        let to_type = |urid| if urid == MIDI_URID { Some(1) } else { None };
+        assert_eq!(unsafe { copy.copy_from_sequence(sequence, to_type) }, 2);
+        let frames: Vec<_> = copy.iter().map(|e| (e.frames, e.data.to_vec())).collect();
+        assert_eq!(
+            frames,
+            vec![(3, vec![0x90, 60, 100]), (9, vec![0x80, 60, 0])]
+        );
+
+        // A full sequence stops at the last event that fits.
+        let mut small = vec![0_u64; 5];
+        let sequence = small.as_mut_ptr().cast::<LV2AtomSequence>();
+        let written = unsafe { buffer.copy_to_sequence(sequence, small.len() * 8, to_urid) };
+        assert_eq!(written, 1);
+    }
+}
@@ -0,0 +1,447 @@
+use crate::error::Error;
+use crate::port::PortKind;
+use crate::ui::UISupportQuality;
+use lv2_raw::{LV2Feature, LV2Urid};
+use std::ffi::{CStr, CString};
+use std::fmt::Debug;
+use std::os::raw::{c_char, c_void};
+
+const LV2_BUF_SIZE_BOUNDED_BLOCK_LENGTH: &str =
+    "http://lv2plug.in/ns/ext/buf-size#boundedBlockLength";
+pub(crate) const LV2_BUF_SIZE_FIXED_BLOCK_LENGTH: &str =
+    "http://lv2plug.in/ns/ext/buf-size#fixedBlockLength";
+pub(crate) const LV2_BUF_SIZE_MAX_BLOCK_LENGTH: &str =
+    "http://lv2plug.in/ns/ext/buf-size#maxBlockLength";
+pub(crate) const LV2_BUF_SIZE_MIN_BLOCK_LENGTH: &str =
+    "http://lv2plug.in/ns/ext/buf-size#minBlockLength";
+pub(crate) const LV2_BUF_SIZE_NOMINAL_BLOCK_LENGTH: &str =
+    "http://lv2plug.in/ns/ext/buf-size#nominalBlockLength";
+pub(crate) const LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH: &str =
+    "http://lv2plug.in/ns/ext/buf-size#powerOf2BlockLength";
+pub(crate) const LV2_OPTIONS_OPTIONS: &str = "http://lv2plug.in/ns/ext/options#options";
+pub(crate) const LV2_URID_MAP: &str = "http://lv2plug.in/ns/ext/urid#map";
+pub(crate) const LV2_URID_UNMAP: &str = "http://lv2plug.in/ns/ext/urid#unmap";
+pub(crate) const LV2_WORKER_SCHEDULE: &str = "http://lv2plug.in/ns/ext/worker#schedule";
+
+/// The data of the `urid:unmap` feature, `LV2_URID_Unmap` in C.
+///
+/// `lv2_raw` only defines the map feature, `LV2UridMap`.
+#[repr(C)]
+#[allow(clippy::module_name_repetitions)]
+pub struct LV2UridUnmap {
+    /// Opaque pointer to host data, passed to `unmap`.
+    pub handle: *mut c_void,
+    /// Get the URI of a URID, or NULL if the URID is not mapped.
+    pub unmap: extern "C" fn(handle: *mut c_void, urid: LV2Urid) -> *const c_char,
+}
+
+/// A set of features that a host provides to plugins.
+///
+/// The set owns the feature URIs. Feature data is not owned and must be kept
+/// alive by the host for as long as any instance created with the set.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Default)]
+pub struct FeatureSet {
+    // The URIs are kept alive here since `features` points into them.
+    uris: Vec<CString>,
+    features: Vec<LV2Feature>,
+}
+
+unsafe impl Send for FeatureSet {}
+unsafe impl Sync for FeatureSet {}
+
+impl FeatureSet {
+    /// Create a new empty feature set.
+    #[must_use]
+    pub fn new() -> FeatureSet {
+        FeatureSet::default()
+    }
+
+    /// Add a feature that does not require any data, like
+    /// `http://lv2plug.in/ns/lv2core#isLive`.
+    ///
+    /// # Errors
+    /// Returns an error if `uri` contains a NUL byte.
+    pub fn with_feature(self, uri: &str) -> Result<FeatureSet, Error> {
+        unsafe { self.with_feature_data(uri, std::ptr::null_mut()) }
+    }
+
+    /// Add a feature with data. If the feature already exists, its data is
+    /// replaced.
+    ///
+    /// # Safety
+    /// `data` must point to valid data for the feature as specified by the
+    /// extension that defines `uri`. `data` must stay valid for as long as any
+    /// plugin instance that was created with this feature set.
+    ///
+    /// # Errors
+    /// Returns an error if `uri` contains a NUL byte.
+    pub unsafe fn with_feature_data(
+        mut self,
+        uri: &str,
+        data: *mut c_void,
+    ) -> Result<FeatureSet, Error> {
+        if let Some(index) = self.index_of(uri) {
+            self.features[index].data = data;
+            return Ok(self);
+        }
+        self.push(CString::new(uri)?, data);
+        Ok(self)
+    }
+
+    /// Returns `true` if the set contains a feature with the given URI.
+    #[must_use]
+    pub fn contains(&self, uri: &str) -> bool {
+        self.index_of(uri).is_some()
+    }
+
+    /// An iterator over the URIs of all the features in the set.
+    pub fn uris(&self) -> impl '_ + Iterator<Item = &str> {
+        self.uris.iter().filter_map(|uri| uri.to_str().ok())
+    }
+
+    /// An iterator over all the features. This can be passed directly to
+    /// `Plugin::instantiate`.
+    pub fn iter(&self) -> impl '_ + Iterator<Item = &LV2Feature> {
+        self.features.iter()
+    }
+
+    /// The number of features in the set.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    /// Returns `true` if the set contains no features.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    /// The features as a NULL terminated array, like lilv expects them.
+    pub(crate) fn as_ptrs(&self) -> Vec<*const LV2Feature> {
+        self.features
+            .iter()
+            .map(|f| f as *const LV2Feature)
+            .chain(std::iter::once(std::ptr::null()))
+            .collect()
+    }
+
+    // Add a feature whose URI is known to be valid.
+    fn push(&mut self, uri: CString, data: *mut c_void) {
+        self.features.push(LV2Feature {
+            uri: uri.as_ptr(),
+            data,
+        });
+        self.uris.push(uri);
+    }
+
+    fn index_of(&self, uri: &str) -> Option<usize> {
+        self.uris
+            .iter()
+            .position(|u| u.as_bytes() == uri.as_bytes())
+    }
+}
+
+impl Debug for FeatureSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.uris
+                    .iter()
+                    .map(CString::as_c_str)
+                    .map(CStr::to_string_lossy),
+            )
+            .finish()
+    }
+}
+
+/// What a host supports besides the features in a `FeatureSet`, like the
+/// types of ports it can connect. See `Plugins::supported_by`.
+///
+/// The capabilities also decide which features of a `FeatureSet` are passed
+/// to plugins. `Plugin::instantiate_with` leaves out the `buf-size` features
+/// that contradict the block length of the host, and adds the ones that
+/// follow from `with_fixed_block_length`. The worker and options features
+/// are passed when they are provided; declaring them with `with_worker` and
+/// `with_options` lets `Plugin::is_supported_by` count them for a
+/// `FeatureSet` without their data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostCaps {
+    port_kinds: Vec<PortKind>,
+    ui_types: Vec<String>,
+    worker: bool,
+    options: bool,
+    fixed_block_length: Option<usize>,
+}
+
+impl HostCaps {
+    /// Create the capabilities of a host that connects audio and control
+    /// ports, without UIs, worker, options or a fixed block length.
+    #[must_use]
+    pub fn new() -> HostCaps {
+        HostCaps {
+            port_kinds: vec![PortKind::Audio, PortKind::Control],
+            ui_types: Vec::new(),
+            worker: false,
+            options: false,
+            fixed_block_length: None,
+        }
+    }
+
+    /// Add a type of port that the host can connect.
+    #[must_use]
+    pub fn with_port_kind(mut self, kind: PortKind) -> HostCaps {
+        if !self.port_kinds.contains(&kind) {
+            self.port_kinds.push(kind);
+        }
+        self
+    }
+
+    /// Add a type of UI that the host can embed, like
+    /// `http://lv2plug.in/ns/extensions/ui#X11UI`.
+    #[must_use]
+    pub fn with_ui_type(mut self, uri: &str) -> HostCaps {
+        if !self.supports_ui_type(uri) {
+            self.ui_types.push(uri.to_string());
+        }
+        self
+    }
+
+    /// Declare that the host runs a worker and provides `worker:schedule`.
+    #[must_use]
+    pub fn with_worker(mut self) -> HostCaps {
+        self.worker = true;
+        self
+    }
+
+    /// Declare that the host provides `opts:options`.
+    #[must_use]
+    pub fn with_options(mut self) -> HostCaps {
+        self.options = true;
+        self
+    }
+
+    /// Declare that the host always runs plugins with `block_length` frames.
+    #[must_use]
+    pub fn with_fixed_block_length(mut self, block_length: usize) -> HostCaps {
+        self.fixed_block_length = Some(block_length);
+        self
+    }
+
+    /// Returns `true` if the host can connect ports of type `kind`.
+    #[must_use]
+    pub fn supports_port_kind(&self, kind: &PortKind) -> bool {
+        self.port_kinds.contains(kind)
+    }
+
+    /// Returns `true` if the host can embed UIs of the type `uri`.
+    #[must_use]
+    pub fn supports_ui_type(&self, uri: &str) -> bool {
+        self.ui_types.iter().any(|ui_type| ui_type == uri)
+    }
+
+    /// Rank a UI of type `ui_type` for `container`, for
+    /// `Uis::best_supported`. UIs of the type of the container rank highest,
+    /// other types the host can embed rank lower and the rest are not
+    /// supported.
+    #[must_use]
+    pub fn ui_quality(&self, container: &str, ui_type: &str) -> UISupportQuality {
+        if !self.supports_ui_type(ui_type) {
+            UISupportQuality(0)
+        } else if ui_type == container {
+            UISupportQuality(2)
+        } else {
+            UISupportQuality(1)
+        }
+    }
+
+    /// The fixed block length of the host, if it declared one.
+    #[must_use]
+    pub fn fixed_block_length(&self) -> Option<usize> {
+        self.fixed_block_length
+    }
+
+    /// The features of `provided` that are compatible with the capabilities,
+    /// and the `buf-size` features that follow from them. Feature data is
+    /// shared with `provided`, so the same rules apply to its lifetime.
+    #[must_use]
+    pub fn assemble(&self, provided: &FeatureSet) -> FeatureSet {
+        let mut assembled = FeatureSet::new();
+        for (uri, feature) in provided.uris.iter().zip(&provided.features) {
+            let pruned = uri
+                .to_str()
+                .is_ok_and(|uri| self.prune_reason(uri).is_some());
+            if !pruned {
+                assembled.push(uri.clone(), feature.data);
+            }
+        }
+        if let Some(block_length) = self.fixed_block_length {
+            let mut implied = vec![
+                LV2_BUF_SIZE_BOUNDED_BLOCK_LENGTH,
+                LV2_BUF_SIZE_FIXED_BLOCK_LENGTH,
+            ];
+            if block_length.is_power_of_two() {
+                implied.push(LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH);
+            }
+            for uri in implied {
+                // The URIs of the constants contain no NUL bytes.
+                if let (false, Ok(uri)) = (assembled.contains(uri), CString::new(uri)) {
+                    assembled.push(uri, std::ptr::null_mut());
+                }
+            }
+        }
+        assembled
+    }
+
+    /// Returns `true` if the host declared that it provides the feature
+    /// `uri`, like `worker:schedule` with `with_worker`.
+    #[must_use]
+    pub fn declares(&self, uri: &str) -> bool {
+        match uri {
+            LV2_WORKER_SCHEDULE => self.worker,
+            LV2_OPTIONS_OPTIONS => self.options,
+            _ => false,
+        }
+    }
+
+    /// Why `assemble` leaves out the feature `uri`, or `None` if it is kept
+    /// when it is provided.
+    #[must_use]
+    pub fn prune_reason(&self, uri: &str) -> Option<String> {
+        match (uri, self.fixed_block_length) {
+            (
+                LV2_BUF_SIZE_BOUNDED_BLOCK_LENGTH
+                | LV2_BUF_SIZE_FIXED_BLOCK_LENGTH
+                | LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH,
+                None,
+            ) => Some("the host has no fixed block length".to_string()),
+            (LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH, Some(len)) if !len.is_power_of_two() => Some(
+                format!("the fixed block length {} is not a power of two", len),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl Default for HostCaps {
+    fn default() -> HostCaps {
+        HostCaps::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_set() {
+        let mut data = 0_u32;
+        let features = FeatureSet::new()
+            .with_feature("http://lv2plug.in/ns/lv2core#isLive")
+            .unwrap()
+            .with_feature("http://lv2plug.in/ns/lv2core#isLive")
+            .unwrap();
+        let features = unsafe {
+            features.with_feature_data(
+                "http://lv2plug.in/ns/ext/urid#map",
+                (&mut data as *mut u32).cast(),
+            )
+        }
+        .unwrap();
+        assert_eq!(features.len(), 2);
+        assert!(features.contains("http://lv2plug.in/ns/lv2core#isLive"));
+        assert!(features.contains("http://lv2plug.in/ns/ext/urid#map"));
+        assert!(!features.contains("http://lv2plug.in/ns/ext/worker#schedule"));
+        assert_eq!(
+            features.uris().collect::<Vec<_>>(),
+            vec![
+                "http://lv2plug.in/ns/lv2core#isLive",
+                "http://lv2plug.in/ns/ext/urid#map"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_caps() {
+        let caps = HostCaps::new();
+        assert!(caps.supports_port_kind(&PortKind::Audio));
+        assert!(!caps.supports_port_kind(&PortKind::Atom));
+        let caps = caps
+            .with_port_kind(PortKind::Atom)
+            .with_port_kind(PortKind::Atom);
+        assert!(caps.supports_port_kind(&PortKind::Atom));
+        assert_eq!(caps.port_kinds.len(), 3);
+
+        let caps = HostCaps::new()
+            .with_ui_type("urn:ui:x11")
+            .with_ui_type("urn:ui:x11");
+        assert_eq!(caps.ui_types.len(), 1);
+        assert_eq!(
+            caps.ui_quality("urn:ui:x11", "urn:ui:x11"),
+            UISupportQuality(2)
+        );
+        assert_eq!(
+            caps.ui_quality("urn:ui:gtk", "urn:ui:x11"),
+            UISupportQuality(1)
+        );
+        assert_eq!(
+            caps.ui_quality("urn:ui:x11", "urn:ui:gtk"),
+            UISupportQuality(0)
+        );
+    }
+
+    #[test]
+    fn test_assemble() {
+        let mut worker = 0_u32;
+        let provided = unsafe {
+            FeatureSet::new()
+                .with_feature("http://lv2plug.in/ns/ext/urid#map")
+                .unwrap()
+                .with_feature_data(LV2_WORKER_SCHEDULE, (&mut worker as *mut u32).cast())
+                .unwrap()
+                .with_feature(LV2_BUF_SIZE_FIXED_BLOCK_LENGTH)
+                .unwrap()
+        };
+        let assembled = HostCaps::new().assemble(&provided);
+        assert_eq!(
+            assembled.uris().collect::<Vec<_>>(),
+            vec!["http://lv2plug.in/ns/ext/urid#map", LV2_WORKER_SCHEDULE]
+        );
+        assert_eq!(
+            HostCaps::new()
+                .prune_reason(LV2_BUF_SIZE_FIXED_BLOCK_LENGTH)
+                .as_deref(),
+            Some("the host has no fixed block length")
+        );
+        assert!(!HostCaps::new().declares(LV2_WORKER_SCHEDULE));
+        assert!(HostCaps::new().with_worker().declares(LV2_WORKER_SCHEDULE));
+
+        let caps = HostCaps::new().with_worker().with_fixed_block_length(64);
+        let assembled = caps.assemble(&provided);
+        assert_eq!(assembled.len(), 5);
+        assert!(assembled.contains(LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH));
+        let data = assembled
+            .uris()
+            .zip(assembled.iter())
+            .find(|(uri, _)| *uri == LV2_WORKER_SCHEDULE)
+            .map(|(_, feature)| feature.data);
+        assert_eq!(data, Some((&mut worker as *mut u32).cast()));
+        let caps = caps.with_fixed_block_length(100);
+        let assembled = caps.assemble(&provided);
+        assert!(!assembled.contains(LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH));
+        assert_eq!(
+            caps.prune_reason(LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH)
+                .as_deref(),
+            Some("the fixed block length 100 is not a power of two")
+        );
+    }
+
+    #[test]
+    fn test_feature_set_interior_nul() {
+        assert_eq!(
+            FeatureSet::new().with_feature("a\0b").unwrap_err(),
+            Error::InteriorNul("a\0b".to_string())
+        );
+    }
+}
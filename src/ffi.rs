@@ -0,0 +1,268 @@
+// The C declarations that lilv-sys and lv2_raw do not provide. The safe
+// wrappers around them are in the modules that use them, so the raw types and
+// functions do not leave the crate.
+
+// Serd, the Turtle library of lilv.
+pub(crate) mod serd {
+    use std::os::raw::{c_char, c_uint, c_void};
+
+    // The parts of the Serd API that lilv does not expose, to read Turtle
+    // without a world. `SerdStatus`, `SerdSyntax` and `SerdType` are C
+    // enums.
+    #[repr(C)]
+    pub(crate) struct SerdNode {
+        pub(crate) buf: *const u8,
+        pub(crate) n_bytes: usize,
+        pub(crate) n_chars: usize,
+        pub(crate) flags: u32,
+        pub(crate) kind: c_uint,
+    }
+
+    #[repr(C)]
+    pub(crate) struct SerdError {
+        pub(crate) status: c_uint,
+        pub(crate) filename: *const u8,
+        pub(crate) line: c_uint,
+        pub(crate) col: c_uint,
+        pub(crate) fmt: *const c_char,
+        pub(crate) args: *mut c_void,
+    }
+
+    pub(crate) type SerdErrorSink = unsafe extern "C" fn(*mut c_void, *const SerdError) -> c_uint;
+    pub(crate) type SerdBaseSink = unsafe extern "C" fn(*mut c_void, *const SerdNode) -> c_uint;
+    pub(crate) type SerdPrefixSink =
+        unsafe extern "C" fn(*mut c_void, *const SerdNode, *const SerdNode) -> c_uint;
+    pub(crate) type SerdStatementSink = unsafe extern "C" fn(
+        *mut c_void,
+        u32,
+        *const SerdNode,
+        *const SerdNode,
+        *const SerdNode,
+        *const SerdNode,
+        *const SerdNode,
+        *const SerdNode,
+    ) -> c_uint;
+
+    pub(crate) const SERD_SUCCESS: c_uint = 0;
+    pub(crate) const SERD_ERR_UNKNOWN: c_uint = 2;
+    pub(crate) const SERD_TURTLE: c_uint = 1;
+    pub(crate) const SERD_URI: c_uint = 2;
+    pub(crate) const SERD_CURIE: c_uint = 3;
+    pub(crate) const SERD_BLANK: c_uint = 4;
+
+    extern "C" {
+        pub(crate) fn serd_reader_new(
+            syntax: c_uint,
+            handle: *mut c_void,
+            free_handle: Option<unsafe extern "C" fn(*mut c_void)>,
+            base_sink: Option<SerdBaseSink>,
+            prefix_sink: Option<SerdPrefixSink>,
+            statement_sink: Option<SerdStatementSink>,
+            end_sink: *const c_void,
+        ) -> *mut c_void;
+        pub(crate) fn serd_reader_set_error_sink(
+            reader: *mut c_void,
+            sink: SerdErrorSink,
+            handle: *mut c_void,
+        );
+        pub(crate) fn serd_reader_read_string(reader: *mut c_void, utf8: *const u8) -> c_uint;
+        pub(crate) fn serd_reader_free(reader: *mut c_void);
+        pub(crate) fn serd_strerror(status: c_uint) -> *const u8;
+        pub(crate) fn serd_env_new(base_uri: *const SerdNode) -> *mut c_void;
+        pub(crate) fn serd_env_free(env: *mut c_void);
+        pub(crate) fn serd_env_set_base_uri(env: *mut c_void, uri: *const SerdNode) -> c_uint;
+        pub(crate) fn serd_env_set_prefix(
+            env: *mut c_void,
+            name: *const SerdNode,
+            uri: *const SerdNode,
+        ) -> c_uint;
+        pub(crate) fn serd_env_expand_node(env: *const c_void, node: *const SerdNode) -> SerdNode;
+        pub(crate) fn serd_node_new_file_uri(
+            path: *const u8,
+            hostname: *const u8,
+            out: *mut c_void,
+            escape: bool,
+        ) -> SerdNode;
+        pub(crate) fn serd_node_free(node: *mut SerdNode);
+        // Needed for strings returned by lilv that were actually allocated in
+        // Serd, like the path of `lilv_node_get_path`.
+        pub(crate) fn serd_free(ptr: *mut c_void);
+    }
+}
+
+// The LV2 extensions that lv2_raw does not define.
+pub(crate) mod lv2 {
+    use lv2_raw::core::LV2Handle;
+    use lv2_raw::{LV2Feature, LV2Urid};
+    use std::os::raw::{c_char, c_void};
+
+    pub(crate) const LV2_STATE_INTERFACE: &str = "http://lv2plug.in/ns/ext/state#interface";
+    pub(crate) const LV2_STATE_MAP_PATH: &str = "http://lv2plug.in/ns/ext/state#mapPath";
+    #[cfg(feature = "testing")]
+    pub(crate) const LV2_STATE_FREE_PATH: &str = "http://lv2plug.in/ns/ext/state#freePath";
+    #[cfg(feature = "testing")]
+    pub(crate) const LV2_STATE_MAKE_PATH: &str = "http://lv2plug.in/ns/ext/state#makePath";
+
+    // LV2_Options_Option.
+    #[repr(C)]
+    pub(crate) struct OptionsOption {
+        pub(crate) context: u32,
+        pub(crate) subject: u32,
+        pub(crate) key: LV2Urid,
+        pub(crate) size: u32,
+        pub(crate) value_type: LV2Urid,
+        pub(crate) value: *const c_void,
+    }
+
+    // The header of an LV2_Event_Buffer from the deprecated event extension.
+    #[repr(C)]
+    pub(crate) struct RawEventBuffer {
+        pub(crate) data: *mut u8,
+        pub(crate) header_size: u16,
+        pub(crate) stamp_type: u16,
+        pub(crate) event_count: u32,
+        pub(crate) capacity: u32,
+        pub(crate) size: u32,
+    }
+
+    // The header of an LV2_Event, followed by its data.
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    pub(crate) struct RawEvent {
+        pub(crate) frames: u32,
+        pub(crate) subframes: u32,
+        pub(crate) event_type: u16,
+        pub(crate) size: u16,
+    }
+
+    pub(crate) type Store =
+        unsafe extern "C" fn(*mut c_void, u32, *const c_void, usize, u32, u32) -> u32;
+    pub(crate) type Retrieve =
+        unsafe extern "C" fn(*mut c_void, u32, *mut usize, *mut u32, *mut u32) -> *const c_void;
+
+    // LV2_State_Interface.
+    #[repr(C)]
+    pub(crate) struct StateInterface {
+        pub(crate) save: unsafe extern "C" fn(
+            LV2Handle,
+            Store,
+            *mut c_void,
+            u32,
+            *const *const LV2Feature,
+        ) -> u32,
+        pub(crate) restore: unsafe extern "C" fn(
+            LV2Handle,
+            Retrieve,
+            *mut c_void,
+            u32,
+            *const *const LV2Feature,
+        ) -> u32,
+    }
+
+    // LV2_State_Map_Path.
+    #[repr(C)]
+    pub(crate) struct MapPath {
+        pub(crate) handle: *mut c_void,
+        pub(crate) abstract_path: extern "C" fn(*mut c_void, *const c_char) -> *mut c_char,
+        pub(crate) absolute_path: extern "C" fn(*mut c_void, *const c_char) -> *mut c_char,
+    }
+
+    // LV2_State_Make_Path.
+    #[cfg(feature = "testing")]
+    #[repr(C)]
+    pub(crate) struct MakePath {
+        pub(crate) handle: *mut c_void,
+        pub(crate) path: extern "C" fn(*mut c_void, *const c_char) -> *mut c_char,
+    }
+
+    // LV2_State_Free_Path.
+    #[cfg(feature = "testing")]
+    #[repr(C)]
+    pub(crate) struct FreePath {
+        pub(crate) handle: *mut c_void,
+        pub(crate) free_path: extern "C" fn(*mut c_void, *mut c_char),
+    }
+
+    #[cfg(feature = "testing")]
+    pub(crate) type Respond = extern "C" fn(*mut c_void, u32, *const c_void) -> u32;
+
+    // LV2_Worker_Schedule.
+    #[cfg(feature = "testing")]
+    #[repr(C)]
+    pub(crate) struct WorkerSchedule {
+        pub(crate) handle: *mut c_void,
+        pub(crate) schedule_work: extern "C" fn(*mut c_void, u32, *const c_void) -> u32,
+    }
+
+    // LV2_Worker_Interface.
+    #[cfg(feature = "testing")]
+    #[repr(C)]
+    pub(crate) struct WorkerInterface {
+        pub(crate) work:
+            unsafe extern "C" fn(LV2Handle, Respond, *mut c_void, u32, *const c_void) -> u32,
+        pub(crate) work_response: unsafe extern "C" fn(LV2Handle, u32, *const c_void) -> u32,
+        pub(crate) end_run: Option<unsafe extern "C" fn(LV2Handle) -> u32>,
+    }
+
+    // LV2_WORKER_SUCCESS and LV2_WORKER_ERR_UNKNOWN.
+    #[cfg(feature = "testing")]
+    pub(crate) const WORKER_SUCCESS: u32 = 0;
+    #[cfg(feature = "testing")]
+    pub(crate) const WORKER_ERR_UNKNOWN: u32 = 1;
+}
+
+// JACK, which the `jack` feature links to.
+#[cfg(feature = "jack")]
+pub(crate) mod jack {
+    use std::os::raw::{c_char, c_int, c_ulong, c_void};
+
+    // The parts of the JACK API a client needs. `jack_options_t` and
+    // `jack_status_t` are C enums.
+    #[repr(C)]
+    pub(crate) struct JackMidiEvent {
+        pub(crate) time: u32,
+        pub(crate) size: usize,
+        pub(crate) buffer: *mut u8,
+    }
+
+    pub(crate) type JackProcessCallback = unsafe extern "C" fn(u32, *mut c_void) -> c_int;
+
+    pub(crate) const JACK_NO_START_SERVER: c_int = 0x01;
+    pub(crate) const JACK_PORT_IS_INPUT: c_ulong = 0x1;
+    pub(crate) const JACK_PORT_IS_OUTPUT: c_ulong = 0x2;
+    pub(crate) const JACK_DEFAULT_AUDIO_TYPE: &[u8] = b"32 bit float mono audio\0";
+    pub(crate) const JACK_DEFAULT_MIDI_TYPE: &[u8] = b"8 bit raw midi\0";
+
+    // The build script links the library it finds with pkg-config.
+    extern "C" {
+        pub(crate) fn jack_client_open(
+            client_name: *const c_char,
+            options: c_int,
+            status: *mut c_int,
+            ...
+        ) -> *mut c_void;
+        pub(crate) fn jack_client_close(client: *mut c_void) -> c_int;
+        pub(crate) fn jack_activate(client: *mut c_void) -> c_int;
+        pub(crate) fn jack_deactivate(client: *mut c_void) -> c_int;
+        pub(crate) fn jack_get_sample_rate(client: *mut c_void) -> u32;
+        pub(crate) fn jack_set_process_callback(
+            client: *mut c_void,
+            callback: JackProcessCallback,
+            arg: *mut c_void,
+        ) -> c_int;
+        pub(crate) fn jack_port_register(
+            client: *mut c_void,
+            port_name: *const c_char,
+            port_type: *const c_char,
+            flags: c_ulong,
+            buffer_size: c_ulong,
+        ) -> *mut c_void;
+        pub(crate) fn jack_port_get_buffer(port: *mut c_void, frames: u32) -> *mut c_void;
+        pub(crate) fn jack_midi_get_event_count(port_buffer: *mut c_void) -> u32;
+        pub(crate) fn jack_midi_event_get(
+            event: *mut JackMidiEvent,
+            port_buffer: *mut c_void,
+            index: u32,
+        ) -> c_int;
+    }
+}
@@ -0,0 +1,3754 @@
+use crate::atom::{Json, Parser};
+use crate::automation::{ControlRamp, ParamReceiver, ParamSender};
+use crate::buffer::PortBuffer;
+use crate::error::Error;
+use crate::feature::{FeatureSet, HostCaps};
+use crate::host::Host;
+use crate::instance::{ActiveInstance, Instance, InstantiateError};
+use crate::node::Node;
+use crate::plugin::Plugin;
+use crate::port::{Port, PortDirection, PortKind};
+use crate::state::{State, StateDirs, StateFlags};
+use crate::world::World;
+use lv2_raw::LV2UridMap;
+use parking_lot::{Mutex, MutexGuard};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::path::Path;
+use std::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU64, AtomicUsize};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// A description of a chain of plugins, which can be checked with `validate`
+/// before any instance is created.
+///
+/// The plugins are run in order. Each audio output of a plugin feeds the
+/// audio input with the same number of the next plugin, so the number of
+/// channels must line up. `connections` adds connections that do not follow
+/// the order, like a control output that modulates a later plugin or the
+/// sidechain of a compressor. Sidechain inputs (`lv2:isSideChain`) are not
+/// fed by the previous plugin, and must be connected unless they are
+/// `lv2:connectionOptional`.
+///
+/// With the `serde` feature, the spec can be read from a configuration file,
+/// like a pedalboard in TOML or JSON, and created with `instantiate`.
+/// `connections` and `parameters` may be left out of the file.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainSpec {
+    /// The plugins of the chain in processing order.
+    pub plugins: Vec<PluginSpec>,
+    /// The connections besides the audio of consecutive plugins.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub connections: Vec<ConnectionSpec>,
+}
+
+/// A plugin in a `ChainSpec`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PluginSpec {
+    /// The name of the plugin in the chain, which connections refer to.
+    pub id: String,
+    /// The URI of the plugin.
+    pub uri: String,
+    /// The values of control input ports by symbol. Other control ports keep
+    /// their default values.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub parameters: BTreeMap<String, f32>,
+}
+
+/// A connection from output ports to input ports in a `ChainSpec`.
+///
+/// Both ends select the same number of ports, which are connected in order,
+/// like the left and right channels of two port groups.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionSpec {
+    /// The output ports.
+    pub from: Endpoint,
+    /// The input ports.
+    pub to: Endpoint,
+}
+
+/// The ports of a plugin at one end of a `ConnectionSpec`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Endpoint {
+    /// The id of the plugin.
+    pub plugin: String,
+    /// The ports of the plugin.
+    pub ports: PortSelector,
+}
+
+/// How an `Endpoint` selects ports. Except for `Symbol`, only the ports with
+/// the direction of the end are selected, in the order of their indices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PortSelector {
+    /// The port with the symbol.
+    Symbol(String),
+    /// The ports with the `lv2:designation` URI, like
+    /// `http://lv2plug.in/ns/ext/port-groups#left`.
+    Designation(String),
+    /// The ports of the `pg:group` with the URI or `lv2:symbol`.
+    Group(String),
+    /// The audio ports that are `lv2:isSideChain`.
+    Sidechain,
+}
+
+impl From<PortRef> for Endpoint {
+    /// The port with the symbol of `port`.
+    fn from(port: PortRef) -> Endpoint {
+        Endpoint {
+            plugin: port.plugin,
+            ports: PortSelector::Symbol(port.port),
+        }
+    }
+}
+
+/// A port of a plugin in a `ChainSpec`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortRef {
+    /// The id of the plugin.
+    pub plugin: String,
+    /// The symbol of the port.
+    pub port: String,
+}
+
+/// A problem found by `ChainSpec::validate` or `ChainSpec::instantiate`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainProblem {
+    /// Several plugins have the same id.
+    DuplicateId(String),
+    /// The plugin `uri` is not in the world.
+    UnknownPlugin {
+        /// The id of the plugin.
+        id: String,
+        /// The URI of the plugin.
+        uri: String,
+    },
+    /// A connection refers to an id that is not in the chain.
+    UnknownId(String),
+    /// A connection refers to a port the plugin does not have.
+    UnknownPort(PortRef),
+    /// The end of a connection selects no ports.
+    NoPorts(Endpoint),
+    /// The ends of a connection select different numbers of ports.
+    ConnectionChannels {
+        /// The output ports.
+        from: Endpoint,
+        /// The number of output ports.
+        outputs: usize,
+        /// The input ports.
+        to: Endpoint,
+        /// The number of input ports.
+        inputs: usize,
+    },
+    /// A sidechain input that is not `lv2:connectionOptional` is not the end
+    /// of any connection.
+    UnconnectedSidechain(PortRef),
+    /// A connection starts at an input or ends at an output.
+    WrongDirection(PortRef),
+    /// A connection joins ports that carry different types of data.
+    IncompatiblePorts {
+        /// The output port.
+        from: PortRef,
+        /// The type of the output port.
+        from_kind: PortKind,
+        /// The input port.
+        to: PortRef,
+        /// The type of the input port.
+        to_kind: PortKind,
+    },
+    /// A plugin has a different number of audio outputs than the next plugin
+    /// has audio inputs.
+    ChannelMismatch {
+        /// The id of the plugin.
+        from: String,
+        /// The number of audio outputs of the plugin.
+        outputs: usize,
+        /// The id of the next plugin.
+        to: String,
+        /// The number of audio inputs of the next plugin.
+        inputs: usize,
+    },
+    /// A parameter is not a control input port of the plugin.
+    InvalidParameter(PortRef),
+    /// A plugin could not be instantiated.
+    InstantiateFailed {
+        /// The id of the plugin.
+        id: String,
+        /// Why the plugin could not be instantiated.
+        error: InstantiateError,
+    },
+    /// A native node has more audio channels than a `Chain` supports.
+    TooManyChannels {
+        /// The id of the node.
+        id: String,
+        /// The number of audio inputs or outputs of the node.
+        channels: usize,
+    },
+    /// The state of a plugin in a session could not be loaded.
+    InvalidState {
+        /// The id of the plugin.
+        id: String,
+        /// Why the state could not be loaded.
+        error: Error,
+    },
+    /// A plugin of a `Multiply` does not have exactly one audio input and
+    /// one audio output.
+    NotMono {
+        /// The URI of the plugin.
+        uri: String,
+        /// The number of audio inputs of the plugin.
+        inputs: usize,
+        /// The number of audio outputs of the plugin.
+        outputs: usize,
+    },
+    /// A chain has a different number of audio channels than a
+    /// `TypedChain` of it.
+    ChannelCount {
+        /// The number of audio inputs of the chain.
+        inputs: usize,
+        /// The number of audio outputs of the chain.
+        outputs: usize,
+        /// The number of audio inputs of the `TypedChain`.
+        expected_inputs: usize,
+        /// The number of audio outputs of the `TypedChain`.
+        expected_outputs: usize,
+    },
+}
+
+impl std::fmt::Display for ChainProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainProblem::DuplicateId(id) => write!(f, "several plugins have the id {}", id),
+            ChainProblem::UnknownPlugin { id, uri } => {
+                write!(f, "{}: plugin {} was not found", id, uri)
+            }
+            ChainProblem::UnknownId(id) => write!(f, "no plugin has the id {}", id),
+            ChainProblem::UnknownPort(port) => write!(f, "there is no port {}", port),
+            ChainProblem::NoPorts(endpoint) => write!(f, "there are no ports {}", endpoint),
+            ChainProblem::ConnectionChannels {
+                from,
+                outputs,
+                to,
+                inputs,
+            } => write!(
+                f,
+                "can not connect {} outputs {} to {} inputs {}",
+                outputs, from, inputs, to
+            ),
+            ChainProblem::UnconnectedSidechain(port) => {
+                write!(f, "the sidechain input {} is not connected", port)
+            }
+            ChainProblem::WrongDirection(port) => {
+                write!(f, "{} has the wrong direction for the connection", port)
+            }
+            ChainProblem::IncompatiblePorts {
+                from,
+                from_kind,
+                to,
+                to_kind,
+            } => write!(
+                f,
+                "can not connect {} ({:?}) to {} ({:?})",
+                from, from_kind, to, to_kind
+            ),
+            ChainProblem::ChannelMismatch {
+                from,
+                outputs,
+                to,
+                inputs,
+            } => write!(
+                f,
+                "{} has {} audio outputs but {} has {} audio inputs",
+                from, outputs, to, inputs
+            ),
+            ChainProblem::InvalidParameter(port) => {
+                write!(f, "{} is not a control input port", port)
+            }
+            ChainProblem::InstantiateFailed { id, error } => write!(f, "{}: {}", id, error),
+            ChainProblem::TooManyChannels { id, channels } => write!(
+                f,
+                "{} has {} audio channels but a chain supports at most {}",
+                id, channels, MAX_NATIVE_CHANNELS
+            ),
+            ChainProblem::InvalidState { id, error } => {
+                write!(f, "{}: invalid state: {}", id, error)
+            }
+            ChainProblem::NotMono {
+                uri,
+                inputs,
+                outputs,
+            } => write!(
+                f,
+                "{} has {} audio inputs and {} audio outputs but must have one of each",
+                uri, inputs, outputs
+            ),
+            ChainProblem::ChannelCount {
+                inputs,
+                outputs,
+                expected_inputs,
+                expected_outputs,
+            } => write!(
+                f,
+                "the chain has {} audio inputs and {} audio outputs but {} and {} were expected",
+                inputs, outputs, expected_inputs, expected_outputs
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for PortRef {
+    /// Formats the port like `id:symbol`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.plugin, self.port)
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    /// Formats the ports like `id:symbol`, `id:group(symbol)`,
+    /// `id:designation(uri)` or `id:sidechain`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.ports {
+            PortSelector::Symbol(symbol) => write!(f, "{}:{}", self.plugin, symbol),
+            PortSelector::Designation(uri) => write!(f, "{}:designation({})", self.plugin, uri),
+            PortSelector::Group(group) => write!(f, "{}:group({})", self.plugin, group),
+            PortSelector::Sidechain => write!(f, "{}:sidechain", self.plugin),
+        }
+    }
+}
+
+impl ChainSpec {
+    /// Check that the plugins of the chain exist in `world`, that the
+    /// connections join outputs and inputs of compatible types, that the
+    /// parameters are control inputs, that the sidechain inputs are
+    /// connected, and that the audio channels of consecutive plugins line
+    /// up.
+    ///
+    /// # Errors
+    /// Returns all the problems that were found.
+    pub fn validate(&self, world: &World) -> Result<(), Vec<ChainProblem>> {
+        let mut problems = Vec::new();
+        let mut plugins: Vec<(&str, Option<Plugin>)> = Vec::new();
+        for spec in &self.plugins {
+            if plugins.iter().any(|(id, _)| *id == spec.id) {
+                problems.push(ChainProblem::DuplicateId(spec.id.clone()));
+            }
+            let plugin = world
+                .new_uri(&spec.uri)
+                .ok()
+                .and_then(|uri| world.plugins().plugin(&uri));
+            if plugin.is_none() {
+                problems.push(ChainProblem::UnknownPlugin {
+                    id: spec.id.clone(),
+                    uri: spec.uri.clone(),
+                });
+            }
+            if let Some(plugin) = &plugin {
+                for symbol in spec.parameters.keys() {
+                    let port = world
+                        .new_string(symbol)
+                        .ok()
+                        .and_then(|symbol| plugin.port_by_symbol(&symbol));
+                    let is_control_input = matches!(port, Some(port)
+                        if port.kind() == PortKind::Control
+                            && port.direction() == Some(PortDirection::Input));
+                    if !is_control_input {
+                        problems.push(ChainProblem::InvalidParameter(PortRef {
+                            plugin: spec.id.clone(),
+                            port: symbol.clone(),
+                        }));
+                    }
+                }
+            }
+            plugins.push((&spec.id, plugin));
+        }
+
+        let is_sidechain = |port: &Port| {
+            port.kind() == PortKind::Audio
+                && port.has_property(&world.new_static_uri(LV2_CORE_IS_SIDE_CHAIN))
+        };
+        for pair in plugins.windows(2) {
+            if let [(from, Some(a)), (to, Some(b))] = pair {
+                let outputs = a.port_summary().audio_out;
+                let inputs = b
+                    .iter_ports()
+                    .filter(|port| {
+                        port.kind() == PortKind::Audio
+                            && port.direction() == Some(PortDirection::Input)
+                            && !is_sidechain(port)
+                    })
+                    .count();
+                if outputs != inputs {
+                    problems.push(ChainProblem::ChannelMismatch {
+                        from: from.to_string(),
+                        outputs,
+                        to: to.to_string(),
+                        inputs,
+                    });
+                }
+            }
+        }
+
+        let ports = |endpoint: &Endpoint, direction: PortDirection, problems: &mut Vec<_>| {
+            let plugin = match plugins.iter().find(|(id, _)| *id == endpoint.plugin) {
+                Some((_, plugin)) => plugin.as_ref()?,
+                None => {
+                    problems.push(ChainProblem::UnknownId(endpoint.plugin.clone()));
+                    return None;
+                }
+            };
+            let ports: Vec<Port> = match &endpoint.ports {
+                PortSelector::Symbol(symbol) => {
+                    let port_ref = PortRef {
+                        plugin: endpoint.plugin.clone(),
+                        port: symbol.clone(),
+                    };
+                    let port = world
+                        .new_string(symbol)
+                        .ok()
+                        .and_then(|symbol| plugin.port_by_symbol(&symbol));
+                    match port {
+                        Some(port) if port.direction() == Some(direction) => vec![port],
+                        Some(_) => {
+                            problems.push(ChainProblem::WrongDirection(port_ref));
+                            return None;
+                        }
+                        None => {
+                            problems.push(ChainProblem::UnknownPort(port_ref));
+                            return None;
+                        }
+                    }
+                }
+                selector => plugin
+                    .iter_ports()
+                    .filter(|port| port.direction() == Some(direction))
+                    .filter(|port| match selector {
+                        PortSelector::Designation(uri) => {
+                            let designation = world.new_static_uri(LV2_CORE_DESIGNATION);
+                            port.get::<Node>(&designation)
+                                .as_ref()
+                                .and_then(Node::as_uri)
+                                == Some(uri.as_str())
+                        }
+                        PortSelector::Group(group) => {
+                            let group_node = world.new_static_uri(LV2_PORT_GROUPS_GROUP);
+                            let symbol = world.new_static_uri(LV2_CORE_SYMBOL);
+                            port.get::<Node>(&group_node).is_some_and(|node| {
+                                node.as_uri() == Some(group.as_str())
+                                    || world
+                                        .get(Some(&node), Some(&symbol), None)
+                                        .as_ref()
+                                        .and_then(Node::as_str)
+                                        == Some(group.as_str())
+                            })
+                        }
+                        PortSelector::Sidechain => is_sidechain(port),
+                        PortSelector::Symbol(_) => false,
+                    })
+                    .collect(),
+            };
+            if ports.is_empty() {
+                problems.push(ChainProblem::NoPorts(endpoint.clone()));
+                return None;
+            }
+            Some(ports)
+        };
+        let port_ref = |id: &str, port: &Port| PortRef {
+            plugin: id.to_string(),
+            port: port
+                .symbol()
+                .as_ref()
+                .and_then(Node::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        };
+        // The inputs that are the end of a valid connection, by id and index.
+        let mut connected: Vec<(&str, usize)> = Vec::new();
+        for connection in &self.connections {
+            let from = ports(&connection.from, PortDirection::Output, &mut problems);
+            let to = ports(&connection.to, PortDirection::Input, &mut problems);
+            let (from, to) = match (from, to) {
+                (Some(from), Some(to)) => (from, to),
+                _ => continue,
+            };
+            if from.len() != to.len() {
+                problems.push(ChainProblem::ConnectionChannels {
+                    from: connection.from.clone(),
+                    outputs: from.len(),
+                    to: connection.to.clone(),
+                    inputs: to.len(),
+                });
+                continue;
+            }
+            for (output, input) in from.iter().zip(&to) {
+                let (from_kind, to_kind) = (output.kind(), input.kind());
+                if compatible(&from_kind, &to_kind) {
+                    connected.push((&connection.to.plugin, input.index()));
+                } else {
+                    problems.push(ChainProblem::IncompatiblePorts {
+                        from: port_ref(&connection.from.plugin, output),
+                        from_kind,
+                        to: port_ref(&connection.to.plugin, input),
+                        to_kind,
+                    });
+                }
+            }
+        }
+        for (id, plugin) in &plugins {
+            let plugin = match plugin {
+                Some(plugin) => plugin,
+                None => continue,
+            };
+            for port in plugin.iter_ports() {
+                let required = port.direction() == Some(PortDirection::Input)
+                    && is_sidechain(&port)
+                    && !port.is_connection_optional();
+                if required && !connected.contains(&(*id, port.index())) {
+                    problems.push(ChainProblem::UnconnectedSidechain(port_ref(id, &port)));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Validate the chain and instantiate each of its plugins with
+    /// `features`. The control ports of each instance are connected to
+    /// values owned by the `ChainInstance`, set to the parameters of the spec
+    /// or the default values of the ports. Audio and other ports are left for
+    /// the host to connect.
+    ///
+    /// # Errors
+    /// Returns the problems found by `validate`, or the plugins that could
+    /// not be instantiated.
+    pub fn instantiate(
+        &self,
+        world: &World,
+        sample_rate: f64,
+        features: &FeatureSet,
+    ) -> Result<Vec<ChainInstance>, Vec<ChainProblem>> {
+        self.validate(world)?;
+        let mut instances = Vec::with_capacity(self.plugins.len());
+        let mut problems = Vec::new();
+        for spec in &self.plugins {
+            // `validate` checked that the plugin exists.
+            let plugin = match world
+                .new_uri(&spec.uri)
+                .ok()
+                .and_then(|uri| world.plugins().plugin(&uri))
+            {
+                Some(plugin) => plugin,
+                None => continue,
+            };
+            match ChainInstance::new(world, spec, plugin, sample_rate, features) {
+                Ok(instance) => instances.push(instance),
+                Err(error) => problems.push(ChainProblem::InstantiateFailed {
+                    id: spec.id.clone(),
+                    error,
+                }),
+            }
+        }
+        if problems.is_empty() {
+            Ok(instances)
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// A plugin of a chain that was created by `ChainSpec::instantiate`.
+pub struct ChainInstance {
+    /// The id of the plugin in the chain.
+    pub id: String,
+    /// The plugin.
+    pub plugin: Plugin,
+    /// The instance, with its control ports connected.
+    pub instance: Instance,
+    // The value of each control port, by index. The buffer keeps its address
+    // since the control ports are connected to it.
+    controls: PortBuffer,
+}
+
+impl ChainInstance {
+    fn new(
+        world: &World,
+        spec: &PluginSpec,
+        plugin: Plugin,
+        sample_rate: f64,
+        features: &FeatureSet,
+    ) -> Result<ChainInstance, InstantiateError> {
+        let mut controls: Box<[f32]> = plugin
+            .port_ranges_float()
+            .iter()
+            .map(|range| {
+                if range.default.is_nan() {
+                    0.0
+                } else {
+                    range.default
+                }
+            })
+            .collect();
+        for (symbol, value) in &spec.parameters {
+            let port = world
+                .new_string(symbol)
+                .ok()
+                .and_then(|symbol| plugin.port_by_symbol(&symbol));
+            if let Some(port) = port {
+                controls[port.index()] = *value;
+            }
+        }
+        let controls = PortBuffer::new(controls);
+        let mut instance = plugin.instantiate_with(features, &chain_caps(), sample_rate)?;
+        for port in plugin.iter_ports() {
+            if port.kind() == PortKind::Control {
+                let index = port.index();
+                unsafe { instance.connect_port_mut(index, controls.as_mut_ptr(index)) };
+            }
+        }
+        Ok(ChainInstance {
+            id: spec.id.clone(),
+            plugin,
+            instance,
+            controls,
+        })
+    }
+
+    /// The value of the control port at `index`, which is the value the
+    /// plugin wrote for control outputs.
+    #[must_use]
+    pub fn control(&self, index: usize) -> Option<f32> {
+        self.controls.get(index)
+    }
+
+    /// Set the control input port at `index`. Returns `false` if there is no
+    /// such port.
+    pub fn set_control(&mut self, index: usize, value: f32) -> bool {
+        self.controls.set(index, value)
+    }
+
+    /// Instantiate the plugin again with `features` and copy the control
+    /// values and the state of the instance into the new instance, like for
+    /// another voice or for comparing changes with the original.
+    ///
+    /// The state is copied in memory, so files the plugin refers to are
+    /// shared by both instances. `map` is passed to `State::new_from_instance`
+    /// and `State::restore`.
+    ///
+    /// # Safety
+    /// Saving and restoring the state calls the plugin's code, which itself
+    /// may be unsafe.
+    ///
+    /// # Errors
+    /// Returns `ChainProblem::InvalidState` if the state could not be copied
+    /// and `ChainProblem::InstantiateFailed` if the plugin could not be
+    /// instantiated.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn duplicate(
+        &self,
+        sample_rate: f64,
+        features: &FeatureSet,
+        map: &LV2UridMap,
+    ) -> Result<ChainInstance, ChainProblem> {
+        let invalid_state = |error| ChainProblem::InvalidState {
+            id: self.id.clone(),
+            error,
+        };
+        let port_value = |port: &Port| {
+            if port.kind() == PortKind::Control && port.direction() == Some(PortDirection::Input) {
+                self.controls.get(port.index())
+            } else {
+                None
+            }
+        };
+        let state = unsafe {
+            State::new_from_instance(
+                &self.plugin,
+                &self.instance,
+                map,
+                &StateDirs::default(),
+                port_value,
+                StateFlags::POD,
+                features,
+            )
+        }
+        .map_err(invalid_state)?
+        .ok_or_else(|| {
+            invalid_state(Error::StateNotFound(
+                self.plugin.uri().as_uri().unwrap_or_default().to_string(),
+            ))
+        })?;
+        let mut controls = PortBuffer::new(self.controls.iter().collect());
+        let mut instance = self
+            .plugin
+            .instantiate_with(features, &chain_caps(), sample_rate)
+            .map_err(|error| ChainProblem::InstantiateFailed {
+                id: self.id.clone(),
+                error,
+            })?;
+        for port in self.plugin.iter_ports() {
+            if port.kind() == PortKind::Control {
+                let index = port.index();
+                unsafe { instance.connect_port_mut(index, controls.as_mut_ptr(index)) };
+            }
+        }
+        let world = World {
+            life: self.plugin.life.clone(),
+        };
+        let set_value = |symbol: &str, value: f32| {
+            let port = world
+                .new_string(symbol)
+                .ok()
+                .and_then(|symbol| self.plugin.port_by_symbol(&symbol));
+            if let Some(port) = port {
+                controls.set(port.index(), value);
+            }
+        };
+        unsafe { state.restore(&mut instance, map, set_value, StateFlags::POD, features) };
+        Ok(ChainInstance {
+            id: self.id.clone(),
+            plugin: self.plugin.clone(),
+            instance,
+            controls,
+        })
+    }
+}
+
+impl std::fmt::Debug for ChainInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChainInstance")
+            .field("id", &self.id)
+            .field("plugin", &self.plugin)
+            .field("controls", &self.controls)
+            .finish()
+    }
+}
+
+// The chain passes on the features it is given, and the caller connects
+// the ports the chain does not, so no port kind rules a plugin out.
+fn chain_caps() -> HostCaps {
+    HostCaps::new()
+        .with_port_kind(PortKind::Cv)
+        .with_port_kind(PortKind::Atom)
+        .with_port_kind(PortKind::Event)
+}
+
+// Audio and CV ports are both buffers of `f32` samples, so they can be
+// connected to each other.
+fn compatible(from: &PortKind, to: &PortKind) -> bool {
+    let signal = |kind: &PortKind| matches!(kind, PortKind::Audio | PortKind::Cv);
+    from == to || (signal(from) && signal(to))
+}
+
+/// A change to a chain at a specific frame. See `EventQueue`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainEvent {
+    /// Apply the preset `preset` to the plugin `plugin`.
+    ApplyPreset {
+        /// The id of the plugin.
+        plugin: String,
+        /// The URI of the preset.
+        preset: String,
+    },
+    /// Set a control input port.
+    SetParam {
+        /// The port.
+        port: PortRef,
+        /// The new value.
+        value: f32,
+    },
+    /// Bypass a plugin or stop bypassing it.
+    Bypass {
+        /// The id of the plugin.
+        plugin: String,
+        /// `true` to bypass the plugin.
+        bypassed: bool,
+    },
+}
+
+/// Events scheduled at frames of a chain, for sample accurate automation and
+/// program changes from sequencers.
+///
+/// The process loop splits each block at `next_time` and applies the events
+/// from `pop_due` before processing the frames after them. Events at the
+/// same frame are popped in the order they were scheduled. Scheduling may
+/// allocate, so the queue should be created `with_capacity` for use on the
+/// audio thread.
+#[derive(Debug, Default)]
+pub struct EventQueue {
+    events: BinaryHeap<Reverse<Scheduled>>,
+    scheduled: u64,
+}
+
+#[derive(Debug)]
+struct Scheduled {
+    time: u64,
+    // The number of events scheduled before, which orders events at the
+    // same time.
+    order: u64,
+    event: ChainEvent,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Scheduled) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Scheduled) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Scheduled) -> Ordering {
+        (self.time, self.order).cmp(&(other.time, other.order))
+    }
+}
+
+impl EventQueue {
+    /// Create an empty queue.
+    #[must_use]
+    pub fn new() -> EventQueue {
+        EventQueue::default()
+    }
+
+    /// Create an empty queue with room for `capacity` events.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> EventQueue {
+        EventQueue {
+            events: BinaryHeap::with_capacity(capacity),
+            scheduled: 0,
+        }
+    }
+
+    /// Schedule `event` at the frame `time`.
+    pub fn schedule(&mut self, time: u64, event: ChainEvent) {
+        self.events.push(Reverse(Scheduled {
+            time,
+            order: self.scheduled,
+            event,
+        }));
+        self.scheduled += 1;
+    }
+
+    /// The frame of the earliest event, or `None` if the queue is empty.
+    #[must_use]
+    pub fn next_time(&self) -> Option<u64> {
+        self.events.peek().map(|event| event.0.time)
+    }
+
+    /// Remove the earliest event if it is before the frame `until`, and
+    /// return it with its frame.
+    pub fn pop_due(&mut self, until: u64) -> Option<(u64, ChainEvent)> {
+        if self.next_time()? >= until {
+            return None;
+        }
+        let Reverse(scheduled) = self.events.pop()?;
+        Some((scheduled.time, scheduled.event))
+    }
+
+    /// The number of scheduled events.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns `true` if no events are scheduled.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Remove all the events, like when the transport jumps.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+// The most audio inputs or outputs of a native node in a `Chain`, which
+// passes the channels to the node in arrays on the stack so processing does
+// not allocate.
+const MAX_NATIVE_CHANNELS: usize = 32;
+const LV2_CORE_FREE_WHEELING: &str = "http://lv2plug.in/ns/lv2core#freeWheeling";
+const LV2_CORE_INPUT_PORT: &str = "http://lv2plug.in/ns/lv2core#InputPort";
+const LV2_CORE_DESIGNATION: &str = "http://lv2plug.in/ns/lv2core#designation";
+const LV2_CORE_IS_SIDE_CHAIN: &str = "http://lv2plug.in/ns/lv2core#isSideChain";
+const LV2_CORE_SYMBOL: &str = "http://lv2plug.in/ns/lv2core#symbol";
+const LV2_PORT_GROUPS_GROUP: &str = "http://lv2plug.in/ns/ext/port-groups#group";
+
+/// A linear chain of plugins and native nodes that owns the audio buffers
+/// between them.
+///
+/// Each node reads the audio outputs of the node before it, and the first
+/// node reads the inputs of the chain, which are written with `input_mut`.
+/// The outputs of the last node are the outputs of the chain. The audio and
+/// control ports of plugins are connected by the chain. Other ports, like
+/// atom ports, are left for the host to connect through `plugin_mut`.
+///
+/// `process` applies the `ChainEvent`s of `events_mut` at their frame, so
+/// automation is sample accurate.
+///
+/// # Example
+/// ```no_run
+/// # use lilv::graph::{Chain, ChainSpec, Gain, PluginSpec};
+/// let host = lilv::host::Host::new(44100.0, 512);
+/// let world = lilv::World::with_load_all();
+/// let spec = ChainSpec {
+///     plugins: vec![PluginSpec {
+///         id: "amp".to_string(),
+///         uri: "http://lv2plug.in/plugins/eg-amp".to_string(),
+///         parameters: Default::default(),
+///     }],
+///     connections: Vec::new(),
+/// };
+/// let mut chain = Chain::new(host.block_length());
+/// for instance in spec.instantiate(&world, 44100.0, host.features()).unwrap() {
+///     unsafe { chain.push_plugin(instance).unwrap() };
+/// }
+/// chain.push_native("trim", Box::new(Gain::from_db(1, -3.0))).unwrap();
+/// chain.input_mut(0).unwrap().fill(0.5);
+/// unsafe { chain.process(host.block_length()) };
+/// let _output = chain.output(0).unwrap();
+/// ```
+pub struct Chain {
+    nodes: Vec<ChainNode>,
+    // The buffers of the inputs of the chain, with `block_length` frames
+    // each. Like the outputs of the nodes, they keep their address since
+    // plugins are connected to them.
+    inputs: Box<[Box<[f32]>]>,
+    block_length: usize,
+    events: EventQueue,
+    time: u64,
+    // The control changes of other threads, see `param_queue`.
+    params: Option<ParamReceiver>,
+}
+
+struct ChainNode {
+    id: String,
+    processor: Processor,
+    outputs: Box<[Box<[f32]>]>,
+    bypassed: bool,
+}
+
+enum Processor {
+    Plugin(Box<PluginNode>),
+    Native(Box<dyn NativeNode>),
+}
+
+struct PluginNode {
+    plugin: Plugin,
+    instance: ActiveInstance,
+    // The value of each control port, by index, which the control ports are
+    // connected to.
+    controls: PortBuffer,
+    audio_inputs: Vec<usize>,
+    audio_outputs: Vec<usize>,
+    // The symbol and index of each control input, for `ChainEvent::SetParam`.
+    control_inputs: Vec<(String, usize)>,
+    // The index of the latency port, which is connected to `controls`.
+    latency_port: Option<usize>,
+    // The index of the control input designated `lv2:freeWheeling`.
+    freewheel_port: Option<usize>,
+}
+
+impl Chain {
+    /// Create an empty chain that processes up to `block_length` frames at
+    /// a time.
+    #[must_use]
+    pub fn new(block_length: usize) -> Chain {
+        Chain {
+            nodes: Vec::new(),
+            inputs: Box::new([]),
+            block_length,
+            events: EventQueue::new(),
+            time: 0,
+            params: None,
+        }
+    }
+
+    /// Activate `instance` and append it to the chain.
+    ///
+    /// Connections of the `ChainSpec` other than the audio of consecutive
+    /// plugins are not made.
+    ///
+    /// # Safety
+    /// Activating calls the plugin's code, which itself may be unsafe.
+    ///
+    /// # Errors
+    /// Returns an error if the id is already in the chain or if the audio
+    /// inputs of the plugin do not match the outputs of the last node.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn push_plugin(&mut self, instance: ChainInstance) -> Result<(), ChainProblem> {
+        let ChainInstance {
+            id,
+            plugin,
+            instance,
+            controls,
+        } = instance;
+        let mut audio_inputs = Vec::new();
+        let mut audio_outputs = Vec::new();
+        let mut control_inputs = Vec::new();
+        for port in plugin.iter_ports() {
+            match (port.kind(), port.direction()) {
+                (PortKind::Audio, Some(PortDirection::Input)) => audio_inputs.push(port.index()),
+                (PortKind::Audio, Some(PortDirection::Output)) => audio_outputs.push(port.index()),
+                (PortKind::Control, Some(PortDirection::Input)) => {
+                    if let Some(symbol) = port.symbol().as_ref().and_then(|s| s.as_str()) {
+                        control_inputs.push((symbol.to_string(), port.index()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.check(&id, audio_inputs.len())?;
+        let outputs = audio_outputs.len();
+        let world = World {
+            life: plugin.life.clone(),
+        };
+        let freewheel_port = world
+            .new_uri(LV2_CORE_INPUT_PORT)
+            .ok()
+            .zip(world.new_uri(LV2_CORE_FREE_WHEELING).ok())
+            .and_then(|(input, designation)| plugin.port_by_designation(Some(&input), &designation))
+            .filter(|port| port.kind() == PortKind::Control)
+            .map(|port| port.index());
+        let node = PluginNode {
+            latency_port: plugin.latency_port_index(),
+            freewheel_port,
+            plugin,
+            instance: unsafe { instance.activate() },
+            controls,
+            audio_inputs,
+            audio_outputs,
+            control_inputs,
+        };
+        self.push(id, Processor::Plugin(Box::new(node)), outputs);
+        Ok(())
+    }
+
+    /// Append the native node `node` with the id `id` to the chain.
+    ///
+    /// # Errors
+    /// Returns an error if the id is already in the chain, if the audio
+    /// inputs of the node do not match the outputs of the last node or if
+    /// the node has more than 32 inputs or outputs.
+    #[allow(clippy::result_large_err)]
+    pub fn push_native(
+        &mut self,
+        id: impl Into<String>,
+        node: Box<dyn NativeNode>,
+    ) -> Result<(), ChainProblem> {
+        let id = id.into();
+        let channels = node.audio_inputs().max(node.audio_outputs());
+        if channels > MAX_NATIVE_CHANNELS {
+            return Err(ChainProblem::TooManyChannels { id, channels });
+        }
+        self.check(&id, node.audio_inputs())?;
+        let outputs = node.audio_outputs();
+        self.push(id, Processor::Native(node), outputs);
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check(&self, id: &str, inputs: usize) -> Result<(), ChainProblem> {
+        if self.nodes.iter().any(|node| node.id == id) {
+            return Err(ChainProblem::DuplicateId(id.to_string()));
+        }
+        match self.nodes.last() {
+            Some(last) if last.outputs.len() != inputs => Err(ChainProblem::ChannelMismatch {
+                from: last.id.clone(),
+                outputs: last.outputs.len(),
+                to: id.to_string(),
+                inputs,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn push(&mut self, id: String, processor: Processor, outputs: usize) {
+        if self.nodes.is_empty() {
+            let inputs = match &processor {
+                Processor::Plugin(node) => node.audio_inputs.len(),
+                Processor::Native(node) => node.audio_inputs(),
+            };
+            self.inputs = buffers(inputs, self.block_length);
+        }
+        self.nodes.push(ChainNode {
+            id,
+            processor,
+            outputs: buffers(outputs, self.block_length),
+            bypassed: false,
+        });
+    }
+
+    /// The most frames `process` can process at a time.
+    #[must_use]
+    pub fn block_length(&self) -> usize {
+        self.block_length
+    }
+
+    /// The number of audio inputs of the chain, which are the inputs of the
+    /// first node.
+    #[must_use]
+    pub fn audio_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// The number of audio outputs of the chain, which are the outputs of the
+    /// last node, or its inputs if it is empty.
+    #[must_use]
+    pub fn audio_outputs(&self) -> usize {
+        self.last_outputs().len()
+    }
+
+    /// The ids of the nodes in processing order.
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.nodes.iter().map(|node| node.id.as_str())
+    }
+
+    /// The buffer of the audio input `channel`, with `block_length` frames,
+    /// which is read by the next `process`.
+    pub fn input_mut(&mut self, channel: usize) -> Option<&mut [f32]> {
+        self.inputs.get_mut(channel).map(|buffer| &mut buffer[..])
+    }
+
+    /// The buffer of the audio output `channel`, with `block_length` frames.
+    /// The first frames are the ones written by the last `process`.
+    #[must_use]
+    pub fn output(&self, channel: usize) -> Option<&[f32]> {
+        self.last_outputs().get(channel).map(|buffer| &buffer[..])
+    }
+
+    fn last_outputs(&self) -> &[Box<[f32]>] {
+        self.nodes.last().map_or(&self.inputs, |node| &node.outputs)
+    }
+
+    /// The latency of the chain in frames, which is the sum of the latencies
+    /// of the nodes that are not bypassed. Plugins report their latency when
+    /// they run, so it is 0 before the first block.
+    #[must_use]
+    pub fn total_latency(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| !node.bypassed)
+            .map(|node| match &node.processor {
+                Processor::Plugin(plugin) => plugin.latency(),
+                Processor::Native(native) => native.latency(),
+            })
+            .sum()
+    }
+
+    /// The plugin of the node `id`, or `None` if it is a native node.
+    #[must_use]
+    pub fn plugin(&self, id: &str) -> Option<&Plugin> {
+        self.nodes
+            .iter()
+            .find(|node| node.id == id)
+            .and_then(|node| match &node.processor {
+                Processor::Plugin(plugin) => Some(&plugin.plugin),
+                Processor::Native(_) => None,
+            })
+    }
+
+    /// The instance of the plugin `id`, to connect ports the chain does not
+    /// connect.
+    pub fn plugin_mut(&mut self, id: &str) -> Option<&mut ActiveInstance> {
+        self.nodes
+            .iter_mut()
+            .find(|node| node.id == id)
+            .and_then(|node| match &mut node.processor {
+                Processor::Plugin(plugin) => Some(&mut plugin.instance),
+                Processor::Native(_) => None,
+            })
+    }
+
+    /// The control input ports of the plugins of the chain.
+    #[must_use]
+    pub fn control_inputs(&self) -> Vec<PortRef> {
+        let mut ports = Vec::new();
+        for node in &self.nodes {
+            if let Processor::Plugin(plugin) = &node.processor {
+                ports.extend(plugin.control_inputs.iter().map(|(symbol, _)| PortRef {
+                    plugin: node.id.clone(),
+                    port: symbol.clone(),
+                }));
+            }
+        }
+        ports
+    }
+
+    /// The value of the control input `symbol` of the plugin `plugin`.
+    #[must_use]
+    pub fn control(&self, plugin: &str, symbol: &str) -> Option<f32> {
+        let node = self.nodes.iter().find(|node| node.id == plugin)?;
+        match &node.processor {
+            Processor::Plugin(plugin) => {
+                let (_, index) = plugin
+                    .control_inputs
+                    .iter()
+                    .find(|(input, _)| input == symbol)?;
+                plugin.controls.get(*index)
+            }
+            Processor::Native(_) => None,
+        }
+    }
+
+    /// The index of the control input `symbol` of the plugin `plugin` for
+    /// the senders of `param_queue`. Indices change when plugins are added.
+    #[must_use]
+    pub fn control_index(&self, plugin: &str, symbol: &str) -> Option<usize> {
+        let mut offset = 0;
+        for node in &self.nodes {
+            if let Processor::Plugin(node_plugin) = &node.processor {
+                if node.id == plugin {
+                    let (_, index) = node_plugin
+                        .control_inputs
+                        .iter()
+                        .find(|(input, _)| input == symbol)?;
+                    return Some(offset + index);
+                }
+                offset += node_plugin.controls.len();
+            }
+        }
+        None
+    }
+
+    /// Create a queue of up to `capacity` control changes for other threads,
+    /// like a network or UI thread, which `process` applies at the start of
+    /// each block. The changes are sent to the indices of `control_index`.
+    /// This replaces the queue of an earlier call.
+    pub fn param_queue(&mut self, capacity: usize) -> ParamSender {
+        let (sender, receiver) = crate::automation::param_queue(capacity);
+        self.params = Some(receiver);
+        sender
+    }
+
+    /// Returns `true` if the node `id` is bypassed, see
+    /// `ChainEvent::Bypass`.
+    #[must_use]
+    pub fn is_bypassed(&self, id: &str) -> bool {
+        self.nodes.iter().any(|node| node.id == id && node.bypassed)
+    }
+
+    /// The events to apply while processing. Their frames count from the
+    /// first frame the chain processed, see `time`.
+    pub fn events_mut(&mut self) -> &mut EventQueue {
+        &mut self.events
+    }
+
+    /// The number of frames the chain processed.
+    #[must_use]
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Process `frames` frames from the inputs into the outputs.
+    ///
+    /// The block is split at the frames of the scheduled events, which are
+    /// applied before the frames after them, and events that are due before
+    /// the block are applied at its start. `ChainEvent::Bypass` passes the
+    /// inputs of a node through to its outputs, and `ChainEvent::SetParam`
+    /// sets a control input of a plugin. `ChainEvent::ApplyPreset` is
+    /// dropped, since loading a state is not real-time safe. The changes of
+    /// `param_queue` are applied first. Processing does not allocate.
+    ///
+    /// # Panics
+    /// Panics if `frames` is more than `block_length`.
+    ///
+    /// # Safety
+    /// Running calls the plugin's code, which itself may be unsafe. The ports
+    /// the chain does not connect must be connected.
+    #[allow(clippy::cast_possible_truncation)]
+    pub unsafe fn process(&mut self, frames: usize) {
+        assert!(
+            frames <= self.block_length,
+            "{} frames do not fit into blocks of {} frames",
+            frames,
+            self.block_length
+        );
+        if let Some(params) = &mut self.params {
+            while let Some((index, value)) = params.pop() {
+                set_indexed_control(&mut self.nodes, index, value);
+            }
+        }
+        let mut offset = 0;
+        while offset < frames {
+            let now = self.time + offset as u64;
+            while let Some((_, event)) = self.events.pop_due(now + 1) {
+                self.apply(&event);
+            }
+            let end = self
+                .events
+                .next_time()
+                .map_or(frames, |time| ((time - self.time) as usize).min(frames));
+            unsafe { self.run(offset, end - offset) };
+            offset = end;
+        }
+        self.time += frames as u64;
+    }
+
+    fn apply(&mut self, event: &ChainEvent) {
+        match event {
+            ChainEvent::Bypass { plugin, bypassed } => {
+                if let Some(node) = self.nodes.iter_mut().find(|node| node.id == *plugin) {
+                    node.bypassed = *bypassed;
+                }
+            }
+            ChainEvent::SetParam { port, value } => {
+                let node = self.nodes.iter_mut().find(|node| node.id == port.plugin);
+                if let Some(Processor::Plugin(node)) = node.map(|node| &mut node.processor) {
+                    let index = node
+                        .control_inputs
+                        .iter()
+                        .find(|(symbol, _)| *symbol == port.port)
+                        .map(|(_, index)| *index);
+                    if let Some(index) = index {
+                        node.controls.set(index, *value);
+                    }
+                }
+            }
+            ChainEvent::ApplyPreset { .. } => {}
+        }
+    }
+
+    /// Render `inputs` into `outputs` offline, as fast as the plugins run,
+    /// like for batch processing or tests. Returns the latency that was
+    /// compensated.
+    ///
+    /// The control inputs designated `lv2:freeWheeling` are set to 1 while
+    /// rendering, so plugins know they do not run in real time, and set back
+    /// afterwards. The first frames of the output are dropped by the latency
+    /// of the chain after the first block, so the outputs line up with the
+    /// inputs. Silence is rendered after the end of the inputs, so outputs
+    /// that are longer than the inputs receive the tail, like of a reverb.
+    /// Missing input channels are silent, and all the outputs are rendered to
+    /// the length of the shortest one.
+    ///
+    /// # Safety
+    /// Running calls the plugin's code, which itself may be unsafe. The ports
+    /// the chain does not connect must be connected.
+    pub unsafe fn render(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) -> usize {
+        let frames = outputs.iter().map(|output| output.len()).min().unwrap_or(0);
+        if self.block_length == 0 || frames == 0 {
+            return 0;
+        }
+        let freewheel = self.set_freewheel(1.0);
+        let (mut read, mut written) = (0, 0);
+        let mut latency = None;
+        // The frames of the latency that are left to drop.
+        let mut skip = 0;
+        while written < frames {
+            let len = self.block_length;
+            for (channel, buffer) in self.inputs.iter_mut().enumerate() {
+                let input = inputs.get(channel).map_or(&[][..], |input| input);
+                let available = input.len().saturating_sub(read).min(len);
+                buffer[..available].copy_from_slice(&input[read..read + available]);
+                buffer[available..len].fill(0.0);
+            }
+            unsafe { self.process(len) };
+            read += len;
+            if latency.is_none() {
+                skip = self.total_latency();
+                latency = Some(skip);
+            }
+            let dropped = skip.min(len);
+            skip -= dropped;
+            let count = (len - dropped).min(frames - written);
+            for (output, buffer) in outputs.iter_mut().zip(self.last_outputs()) {
+                output[written..written + count].copy_from_slice(&buffer[dropped..dropped + count]);
+            }
+            written += count;
+        }
+        self.restore_freewheel(&freewheel);
+        latency.unwrap_or(0)
+    }
+
+    // Set the freewheel ports to `value` and return their values before.
+    fn set_freewheel(&mut self, value: f32) -> Vec<Option<f32>> {
+        self.nodes
+            .iter_mut()
+            .map(|node| match &mut node.processor {
+                Processor::Plugin(plugin) => plugin.freewheel_port.map(|index| {
+                    let previous = plugin.controls.value(index);
+                    plugin.controls.set(index, value);
+                    previous
+                }),
+                Processor::Native(_) => None,
+            })
+            .collect()
+    }
+
+    fn restore_freewheel(&mut self, values: &[Option<f32>]) {
+        for (node, value) in self.nodes.iter_mut().zip(values) {
+            if let (Processor::Plugin(plugin), Some(value)) = (&mut node.processor, value) {
+                if let Some(index) = plugin.freewheel_port {
+                    plugin.controls.set(index, *value);
+                }
+            }
+        }
+    }
+
+    // Run every node for `len` frames starting at `offset`.
+    unsafe fn run(&mut self, offset: usize, len: usize) {
+        for index in 0..self.nodes.len() {
+            let (before, rest) = self.nodes.split_at_mut(index);
+            let inputs = before.last().map_or(&self.inputs, |node| &node.outputs);
+            unsafe { rest[0].run(inputs, offset, len) };
+        }
+    }
+}
+
+// Set the control input at `index`, which counts the controls of the plugins
+// in order, see `Chain::control_index`.
+fn set_indexed_control(nodes: &mut [ChainNode], mut index: usize, value: f32) {
+    for node in nodes {
+        if let Processor::Plugin(plugin) = &mut node.processor {
+            if index < plugin.controls.len() {
+                if plugin
+                    .control_inputs
+                    .iter()
+                    .any(|(_, input)| *input == index)
+                {
+                    plugin.controls.set(index, value);
+                }
+                return;
+            }
+            index -= plugin.controls.len();
+        }
+    }
+}
+
+impl PluginNode {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn latency(&self) -> usize {
+        let latency = self
+            .latency_port
+            .map_or(0.0, |index| self.controls.value(index));
+        if latency.is_finite() {
+            latency.max(0.0).round() as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl ChainNode {
+    unsafe fn run(&mut self, inputs: &[Box<[f32]>], offset: usize, len: usize) {
+        let range = offset..offset + len;
+        if self.bypassed {
+            for (channel, output) in self.outputs.iter_mut().enumerate() {
+                match inputs.get(channel) {
+                    Some(input) => output[range.clone()].copy_from_slice(&input[range.clone()]),
+                    None => output[range.clone()].fill(0.0),
+                }
+            }
+            return;
+        }
+        match &mut self.processor {
+            Processor::Plugin(node) => {
+                let instance = node.instance.instance_mut();
+                for (port, input) in node.audio_inputs.iter().zip(inputs) {
+                    unsafe { instance.connect_port(*port, input[offset..].as_ptr()) };
+                }
+                for (port, output) in node.audio_outputs.iter().zip(self.outputs.iter_mut()) {
+                    unsafe { instance.connect_port_mut(*port, output[offset..].as_mut_ptr()) };
+                }
+                unsafe { node.instance.run(len) };
+            }
+            Processor::Native(node) => {
+                let mut inputs = inputs.iter().map(|input| &input[range.clone()]);
+                let inputs: [&[f32]; MAX_NATIVE_CHANNELS] =
+                    std::array::from_fn(|_| inputs.next().unwrap_or_default());
+                let mut outputs = self.outputs.iter_mut();
+                let mut outputs: [&mut [f32]; MAX_NATIVE_CHANNELS] =
+                    std::array::from_fn(|_| match outputs.next() {
+                        Some(output) => &mut output[range.clone()],
+                        None => &mut [],
+                    });
+                let (ins, outs) = (node.audio_inputs(), node.audio_outputs());
+                node.process(&inputs[..ins], &mut outputs[..outs]);
+            }
+        }
+    }
+}
+
+// `count` buffers of `len` silent frames.
+fn buffers(count: usize, len: usize) -> Box<[Box<[f32]>]> {
+    (0..count)
+        .map(|_| vec![0.0; len].into_boxed_slice())
+        .collect()
+}
+
+// The subject of the states in a session.
+const SESSION_STATE_URI: &str = "urn:lilv-rs:session:state";
+
+/// The reason a session could not be saved or loaded with
+/// `Chain::save_session` or `Chain::load_session`.
+#[derive(Debug)]
+pub enum SessionError {
+    /// The session file could not be read or written.
+    Io(std::io::Error),
+    /// The file is not a session. Contains a description of the problem.
+    Invalid(String),
+    /// Lilv could not save the state of a plugin. Contains the id of the
+    /// plugin.
+    StateNotSaved(String),
+    /// The state of a plugin could not be passed to lilv.
+    State {
+        /// The id of the plugin.
+        id: String,
+        /// Why the state could not be saved.
+        error: Error,
+    },
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::Io(err) => write!(f, "failed to access the session: {}", err),
+            SessionError::Invalid(message) => write!(f, "invalid session: {}", message),
+            SessionError::StateNotSaved(id) => write!(f, "{}: failed to save the state", id),
+            SessionError::State { id, error } => write!(f, "{}: {}", id, error),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+/// A chain loaded by `Chain::load_session`.
+#[derive(Debug)]
+pub struct LoadedSession {
+    /// The plugins of the session that could be loaded, in their order.
+    pub chain: Chain,
+    /// The plugins that could not be loaded, like plugins that are not
+    /// installed.
+    pub problems: Vec<ChainProblem>,
+}
+
+impl Chain {
+    /// Save the plugins of the chain to the file `path`, with their URIs,
+    /// whether they are bypassed and their states as Turtle.
+    ///
+    /// The session is a JSON document. The plugins are connected in the order
+    /// of the chain, so that order is all that is stored of the connections.
+    /// Native nodes are not saved and have to be added again after loading.
+    /// Files the plugins refer to are copied or linked into the directory of
+    /// `path`, see `StateDirs`.
+    ///
+    /// # Safety
+    /// Saving calls the plugins' code, which itself may be unsafe. `host`
+    /// must be the host the plugins were instantiated with, and the chain
+    /// must not be processing.
+    ///
+    /// # Errors
+    /// Returns an error if the state of a plugin could not be saved or the
+    /// file could not be written.
+    pub unsafe fn save_session(&self, path: &Path, host: &Host) -> Result<(), SessionError> {
+        let dirs = StateDirs {
+            save_dir: path.parent().map(Path::to_path_buf),
+            ..StateDirs::default()
+        };
+        let mut plugins = Vec::new();
+        for node in &self.nodes {
+            let plugin = match &node.processor {
+                Processor::Plugin(plugin) => plugin,
+                Processor::Native(_) => continue,
+            };
+            let state_error = |error| SessionError::State {
+                id: node.id.clone(),
+                error,
+            };
+            let (controls, inputs) = (&plugin.controls, &plugin.control_inputs);
+            let port_value = |port: &Port| {
+                let index = port.index();
+                if inputs.iter().any(|(_, input)| *input == index) {
+                    controls.get(index)
+                } else {
+                    None
+                }
+            };
+            let state = unsafe {
+                State::new_from_instance(
+                    &plugin.plugin,
+                    plugin.instance.instance(),
+                    host.urid_map(),
+                    &dirs,
+                    port_value,
+                    StateFlags::POD | StateFlags::PORTABLE,
+                    host.features(),
+                )
+            }
+            .map_err(state_error)?
+            .ok_or_else(|| SessionError::StateNotSaved(node.id.clone()))?;
+            let world = World {
+                life: plugin.plugin.life.clone(),
+            };
+            let turtle = state
+                .save_to_string(
+                    &world,
+                    host.urid_map(),
+                    host.urid_unmap(),
+                    SESSION_STATE_URI,
+                )
+                .map_err(state_error)?
+                .ok_or_else(|| SessionError::StateNotSaved(node.id.clone()))?;
+            let uri = plugin.plugin.uri();
+            plugins.push(Json::object(vec![
+                ("id", Json::String(node.id.clone())),
+                (
+                    "uri",
+                    Json::String(uri.as_uri().unwrap_or_default().to_string()),
+                ),
+                ("bypassed", Json::Bool(node.bypassed)),
+                ("state", Json::String(turtle)),
+            ]));
+        }
+        let mut session = String::new();
+        Json::object(vec![
+            ("version", Json::Number("1".to_string())),
+            ("plugins", Json::Array(plugins)),
+        ])
+        .write(&mut session);
+        session.push('\n');
+        std::fs::write(path, session).map_err(SessionError::Io)
+    }
+
+    /// Load a session saved by `save_session` into a chain that processes
+    /// blocks of `host.block_length()` frames.
+    ///
+    /// Each plugin is instantiated with `host`, restored from its state and
+    /// activated. Plugins that could not be loaded are left out and reported
+    /// in `LoadedSession::problems`, along with plugins that do not fit
+    /// after them.
+    ///
+    /// # Safety
+    /// Instantiating, restoring and activating calls the plugins' code, which
+    /// itself may be unsafe.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be read or is not a session.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn load_session(
+        world: &World,
+        path: &Path,
+        host: &Host,
+    ) -> Result<LoadedSession, SessionError> {
+        let session = std::fs::read_to_string(path).map_err(SessionError::Io)?;
+        let session = Parser::parse(&session).map_err(|err| SessionError::Invalid(err.0))?;
+        match session.field("version") {
+            Some(Json::Number(version)) if version == "1" => {}
+            _ => return Err(SessionError::Invalid("unknown version".to_string())),
+        }
+        let plugins = match session.field("plugins") {
+            Some(Json::Array(plugins)) => plugins,
+            _ => return Err(SessionError::Invalid("no list of plugins".to_string())),
+        };
+        let mut chain = Chain::new(host.block_length());
+        let mut problems = Vec::new();
+        for plugin in plugins {
+            let text = |key| match plugin.field(key) {
+                Some(Json::String(text)) => Some(text.as_str()),
+                _ => None,
+            };
+            let (id, uri, turtle) = match (text("id"), text("uri"), text("state")) {
+                (Some(id), Some(uri), Some(turtle)) => (id, uri, turtle),
+                _ => {
+                    let message = "a plugin has no id, URI or state".to_string();
+                    return Err(SessionError::Invalid(message));
+                }
+            };
+            let loaded = unsafe { load_plugin(world, id, uri, turtle, host) }
+                .and_then(|instance| unsafe { chain.push_plugin(instance) });
+            match loaded {
+                Ok(()) => {
+                    if let Some(node) = chain.nodes.last_mut() {
+                        node.bypassed = matches!(plugin.field("bypassed"), Some(Json::Bool(true)));
+                    }
+                }
+                Err(problem) => problems.push(problem),
+            }
+        }
+        Ok(LoadedSession { chain, problems })
+    }
+}
+
+// Instantiate the plugin of a session and restore it from its state.
+#[allow(clippy::result_large_err)]
+unsafe fn load_plugin(
+    world: &World,
+    id: &str,
+    uri: &str,
+    turtle: &str,
+    host: &Host,
+) -> Result<ChainInstance, ChainProblem> {
+    let plugin = world
+        .new_uri(uri)
+        .ok()
+        .and_then(|node| world.plugins().plugin(&node))
+        .ok_or_else(|| ChainProblem::UnknownPlugin {
+            id: id.to_string(),
+            uri: uri.to_string(),
+        })?;
+    let state = State::new_from_string(world, host.urid_map(), turtle).map_err(|error| {
+        ChainProblem::InvalidState {
+            id: id.to_string(),
+            error,
+        }
+    })?;
+    let spec = PluginSpec {
+        id: id.to_string(),
+        uri: uri.to_string(),
+        parameters: BTreeMap::new(),
+    };
+    let ChainInstance {
+        id,
+        plugin,
+        mut instance,
+        mut controls,
+    } = ChainInstance::new(world, &spec, plugin, host.sample_rate(), host.features()).map_err(
+        |error| ChainProblem::InstantiateFailed {
+            id: id.to_string(),
+            error,
+        },
+    )?;
+    let set_value = |symbol: &str, value: f32| {
+        let port = world
+            .new_string(symbol)
+            .ok()
+            .and_then(|symbol| plugin.port_by_symbol(&symbol));
+        if let Some(port) = port {
+            controls.set(port.index(), value);
+        }
+    };
+    unsafe {
+        state.restore(
+            &mut instance,
+            host.urid_map(),
+            set_value,
+            StateFlags::empty(),
+            host.features(),
+        );
+    }
+    Ok(ChainInstance {
+        id,
+        plugin,
+        instance,
+        controls,
+    })
+}
+
+impl std::fmt::Debug for Chain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Chain")
+            .field("ids", &self.ids().collect::<Vec<_>>())
+            .field("block_length", &self.block_length)
+            .field("time", &self.time)
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+/// A chain with `IN` audio inputs and `OUT` audio outputs, so the channels
+/// the host passes to `process` are checked at compile time instead of in
+/// every block.
+///
+/// The channels are checked once by `new`. The chain can not be changed
+/// while it is wrapped, since nodes would change its channels.
+///
+/// # Example
+/// ```no_run
+/// use lilv::graph::{Chain, Gain, TypedChain};
+///
+/// let mut chain = Chain::new(256);
+/// chain.push_native("trim", Box::new(Gain::from_db(2, -3.0))).unwrap();
+/// let mut stereo = TypedChain::<2, 2>::new(chain).unwrap();
+/// let (left, right) = ([0.5; 64], [0.5; 64]);
+/// let (mut out_left, mut out_right) = ([0.0; 64], [0.0; 64]);
+/// unsafe { stereo.process(&[&left, &right], &mut [&mut out_left, &mut out_right]) };
+/// ```
+pub struct TypedChain<const IN: usize, const OUT: usize> {
+    chain: Chain,
+}
+
+impl<const IN: usize, const OUT: usize> TypedChain<IN, OUT> {
+    /// Wrap `chain`.
+    ///
+    /// # Errors
+    /// Returns an error if the chain does not have `IN` audio inputs and
+    /// `OUT` audio outputs.
+    #[allow(clippy::result_large_err)]
+    pub fn new(chain: Chain) -> Result<TypedChain<IN, OUT>, ChainProblem> {
+        if chain.audio_inputs() != IN || chain.audio_outputs() != OUT {
+            return Err(ChainProblem::ChannelCount {
+                inputs: chain.audio_inputs(),
+                outputs: chain.audio_outputs(),
+                expected_inputs: IN,
+                expected_outputs: OUT,
+            });
+        }
+        Ok(TypedChain { chain })
+    }
+
+    /// The wrapped chain.
+    #[must_use]
+    pub fn chain(&self) -> &Chain {
+        &self.chain
+    }
+
+    /// The events of the chain, see `Chain::events_mut`.
+    pub fn events_mut(&mut self) -> &mut EventQueue {
+        self.chain.events_mut()
+    }
+
+    /// A queue for control changes from other threads, see
+    /// `Chain::param_queue`.
+    pub fn param_queue(&mut self, capacity: usize) -> ParamSender {
+        self.chain.param_queue(capacity)
+    }
+
+    /// Unwrap the chain.
+    #[must_use]
+    pub fn into_chain(self) -> Chain {
+        self.chain
+    }
+
+    /// Process `inputs` into `outputs`, in blocks of up to `block_length`
+    /// frames. All the channels must have the same number of frames, and
+    /// frames beyond the shortest channel are left as they are. A chain with
+    /// a `block_length` of 0 processes nothing.
+    ///
+    /// # Safety
+    /// Running calls the plugin's code, which itself may be unsafe. The ports
+    /// the chain does not connect must be connected.
+    pub unsafe fn process(&mut self, inputs: &[&[f32]; IN], outputs: &mut [&mut [f32]; OUT]) {
+        let frames = inputs
+            .iter()
+            .map(|input| input.len())
+            .chain(outputs.iter().map(|output| output.len()))
+            .min()
+            .unwrap_or(0);
+        let block_length = self.chain.block_length;
+        if block_length == 0 {
+            return;
+        }
+        let mut offset = 0;
+        while offset < frames {
+            let len = block_length.min(frames - offset);
+            for (input, buffer) in inputs.iter().zip(self.chain.inputs.iter_mut()) {
+                buffer[..len].copy_from_slice(&input[offset..offset + len]);
+            }
+            unsafe { self.chain.process(len) };
+            for (output, buffer) in outputs.iter_mut().zip(self.chain.last_outputs()) {
+                output[offset..offset + len].copy_from_slice(&buffer[..len]);
+            }
+            offset += len;
+        }
+    }
+}
+
+impl<const IN: usize, const OUT: usize> std::fmt::Debug for TypedChain<IN, OUT> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedChain")
+            .field("chain", &self.chain)
+            .finish()
+    }
+}
+
+/// Wraps an instance with a dry/wet mix and a bypass that crossfades, since
+/// most plugins have no bypass of their own.
+///
+/// The dry signal is delayed by the latency the plugin reports on its
+/// `Plugin::latency_port_index` port, up to `max_latency` frames, so it
+/// lines up with the wet signal. The plugin keeps running while it is
+/// bypassed, so it picks up where the input is when it is enabled again.
+pub struct Bypass {
+    instance: ActiveInstance,
+    audio_inputs: Vec<usize>,
+    audio_outputs: Vec<usize>,
+    // The latency port is connected to the value, which keeps its address.
+    latency: Option<PortBuffer>,
+    // The dry signal of each output channel.
+    delays: Vec<DelayLine>,
+    wet: ControlRamp,
+    // The wet gain of each frame of the block.
+    fade: Box<[f32]>,
+    fade_frames: usize,
+    mix: f32,
+    bypassed: bool,
+}
+
+impl Bypass {
+    /// Wrap `instance`, an instance of `plugin`, to process blocks of up to
+    /// `max_block_length` frames. Changes of the mix and the bypass fade over
+    /// `fade_frames` frames.
+    ///
+    /// The latency port is connected to a value owned by the wrapper. The
+    /// other control ports must be connected through `instance_mut`.
+    #[must_use]
+    pub fn new(
+        plugin: &Plugin,
+        mut instance: ActiveInstance,
+        max_block_length: usize,
+        max_latency: usize,
+        fade_frames: usize,
+    ) -> Bypass {
+        let mut audio_inputs = Vec::new();
+        let mut audio_outputs = Vec::new();
+        for port in plugin.iter_ports() {
+            match (port.kind(), port.direction()) {
+                (PortKind::Audio, Some(PortDirection::Input)) => audio_inputs.push(port.index()),
+                (PortKind::Audio, Some(PortDirection::Output)) => audio_outputs.push(port.index()),
+                _ => {}
+            }
+        }
+        let latency = plugin.latency_port_index().map(|index| {
+            let latency = PortBuffer::new(Box::new([0.0]));
+            unsafe {
+                instance
+                    .instance_mut()
+                    .connect_port_mut(index, latency.as_mut_ptr(0))
+            };
+            latency
+        });
+        Bypass {
+            instance,
+            delays: (0..audio_outputs.len())
+                .map(|_| DelayLine::new(max_latency))
+                .collect(),
+            audio_inputs,
+            audio_outputs,
+            latency,
+            wet: ControlRamp::new(1.0),
+            fade: vec![0.0; max_block_length].into_boxed_slice(),
+            fade_frames,
+            mix: 1.0,
+            bypassed: false,
+        }
+    }
+
+    /// The wrapped instance, to connect its control ports.
+    pub fn instance_mut(&mut self) -> &mut ActiveInstance {
+        &mut self.instance
+    }
+
+    /// The latency the plugin reported in the last block, which is the delay
+    /// of the dry signal.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn latency(&self) -> usize {
+        let latency = self
+            .latency
+            .as_ref()
+            .map_or(0.0, |latency| latency.value(0));
+        let max = self.delays.first().map_or(0, DelayLine::max_delay);
+        (latency.max(0.0).round() as usize).min(max)
+    }
+
+    /// The share of the processed signal in the output, from 0 for only the
+    /// dry signal to 1 for only the processed signal.
+    #[must_use]
+    pub fn mix(&self) -> f32 {
+        self.mix
+    }
+
+    /// Fade to the mix `mix`, from 0 for only the dry signal to 1 for only
+    /// the processed signal.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+        self.fade_to_target();
+    }
+
+    /// Returns `true` if the plugin is bypassed.
+    #[must_use]
+    pub fn is_bypassed(&self) -> bool {
+        self.bypassed
+    }
+
+    /// Fade to the dry signal, or back to the mix.
+    pub fn set_bypassed(&mut self, bypassed: bool) {
+        self.bypassed = bypassed;
+        self.fade_to_target();
+    }
+
+    fn fade_to_target(&mut self) {
+        let target = if self.bypassed { 0.0 } else { self.mix };
+        self.wet.set_target(target, self.fade_frames);
+    }
+
+    /// Run the plugin on `inputs` and mix its outputs in `outputs` with the
+    /// delayed inputs. There must be a channel for each audio input and
+    /// output of the plugin, and all the channels must have the same number
+    /// of frames. Output channels without an input channel have a silent dry
+    /// signal. This does not allocate, so it is real-time safe.
+    ///
+    /// # Panics
+    /// Panics if the channels have more frames than the maximum block length.
+    ///
+    /// # Safety
+    /// Running calls the plugin's code, which itself may be unsafe. The other
+    /// ports of the plugin must be connected.
+    pub unsafe fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        let frames = outputs
+            .first()
+            .map(|output| output.len())
+            .or_else(|| inputs.first().map(|input| input.len()))
+            .unwrap_or(0);
+        let instance = self.instance.instance_mut();
+        for (port, input) in self.audio_inputs.iter().zip(inputs) {
+            unsafe { instance.connect_port(*port, input.as_ptr()) };
+        }
+        for (port, output) in self.audio_outputs.iter().zip(outputs.iter_mut()) {
+            unsafe { instance.connect_port_mut(*port, output.as_mut_ptr()) };
+        }
+        unsafe { self.instance.run(frames) };
+
+        let latency = self.latency();
+        let fade = &mut self.fade[..frames];
+        self.wet.fill(fade);
+        for (channel, (output, delay)) in outputs.iter_mut().zip(&mut self.delays).enumerate() {
+            let input = inputs.get(channel);
+            for (frame, (out, wet)) in output.iter_mut().zip(fade.iter()).enumerate() {
+                let dry = delay.delay(input.map_or(0.0, |input| input[frame]), latency);
+                *out = *out * wet + dry * (1.0 - wet);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Bypass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Bypass")
+            .field("instance", &self.instance)
+            .field("latency", &self.latency())
+            .field("mix", &self.mix)
+            .field("bypassed", &self.bypassed)
+            .finish()
+    }
+}
+
+/// Processes multichannel audio with a copy of a mono plugin for each
+/// channel, like a mono equalizer on a stereo track.
+///
+/// The control inputs of all the copies are connected to the same values,
+/// so a parameter change or a restored state applies to every channel. The
+/// control outputs are kept for each copy. Ports other than audio and
+/// control ports are left for the host to connect through `instance_mut`.
+pub struct Multiply {
+    instances: Vec<ActiveInstance>,
+    audio_input: usize,
+    audio_output: usize,
+    // The value of each control input, by index, shared by the copies.
+    controls: PortBuffer,
+    // The value of each control output of each copy, by index.
+    control_outputs: Box<[PortBuffer]>,
+    // The symbol and index of each control input, for restoring states.
+    control_inputs: Vec<(String, usize)>,
+}
+
+impl Multiply {
+    /// Instantiate and activate `channels` copies of `plugin` with
+    /// `features`. The control inputs are set to the default values of the
+    /// ports.
+    ///
+    /// # Safety
+    /// Activating calls the plugin's code, which itself may be unsafe.
+    ///
+    /// # Errors
+    /// Returns an error if the plugin is not mono or a copy could not be
+    /// instantiated.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn new(
+        plugin: &Plugin,
+        channels: usize,
+        sample_rate: f64,
+        features: &FeatureSet,
+    ) -> Result<Multiply, ChainProblem> {
+        let mut audio_inputs = Vec::new();
+        let mut audio_outputs = Vec::new();
+        let mut control_inputs = Vec::new();
+        let mut control_output_ports = Vec::new();
+        for port in plugin.iter_ports() {
+            match (port.kind(), port.direction()) {
+                (PortKind::Audio, Some(PortDirection::Input)) => audio_inputs.push(port.index()),
+                (PortKind::Audio, Some(PortDirection::Output)) => audio_outputs.push(port.index()),
+                (PortKind::Control, Some(PortDirection::Input)) => {
+                    if let Some(symbol) = port.symbol().as_ref().and_then(|s| s.as_str()) {
+                        control_inputs.push((symbol.to_string(), port.index()));
+                    }
+                }
+                (PortKind::Control, Some(PortDirection::Output)) => {
+                    control_output_ports.push(port.index());
+                }
+                _ => {}
+            }
+        }
+        let (audio_input, audio_output) = match (&audio_inputs[..], &audio_outputs[..]) {
+            ([input], [output]) => (*input, *output),
+            _ => {
+                return Err(ChainProblem::NotMono {
+                    uri: plugin.uri().as_uri().unwrap_or_default().to_string(),
+                    inputs: audio_inputs.len(),
+                    outputs: audio_outputs.len(),
+                })
+            }
+        };
+        let controls = PortBuffer::new(
+            plugin
+                .port_ranges_float()
+                .iter()
+                .map(|range| {
+                    if range.default.is_nan() {
+                        0.0
+                    } else {
+                        range.default
+                    }
+                })
+                .collect(),
+        );
+        let control_outputs: Box<[PortBuffer]> = (0..channels)
+            .map(|_| PortBuffer::new(vec![0.0; controls.len()].into_boxed_slice()))
+            .collect();
+        let mut instances = Vec::with_capacity(channels);
+        for outputs in control_outputs.iter() {
+            let mut instance = plugin
+                .instantiate_with(features, &chain_caps(), sample_rate)
+                .map_err(|error| ChainProblem::InstantiateFailed {
+                    id: plugin.uri().as_uri().unwrap_or_default().to_string(),
+                    error,
+                })?;
+            for (_, index) in &control_inputs {
+                unsafe { instance.connect_port_mut(*index, controls.as_mut_ptr(*index)) };
+            }
+            for index in &control_output_ports {
+                unsafe { instance.connect_port_mut(*index, outputs.as_mut_ptr(*index)) };
+            }
+            instances.push(unsafe { instance.activate() });
+        }
+        Ok(Multiply {
+            instances,
+            audio_input,
+            audio_output,
+            controls,
+            control_outputs,
+            control_inputs,
+        })
+    }
+
+    /// The number of channels, which is the number of copies.
+    #[must_use]
+    pub fn channels(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// The copy of the plugin that processes `channel`.
+    pub fn instance_mut(&mut self, channel: usize) -> Option<&mut ActiveInstance> {
+        self.instances.get_mut(channel)
+    }
+
+    /// The value of the control input port at `index`.
+    #[must_use]
+    pub fn control(&self, index: usize) -> Option<f32> {
+        self.control_inputs
+            .iter()
+            .find(|(_, input)| *input == index)
+            .and_then(|_| self.controls.get(index))
+    }
+
+    /// Set the control input port at `index` of every copy. Returns `false`
+    /// if there is no such port.
+    pub fn set_control(&mut self, index: usize, value: f32) -> bool {
+        if self.control_inputs.iter().any(|(_, input)| *input == index) {
+            self.controls.set(index, value)
+        } else {
+            false
+        }
+    }
+
+    /// The value the copy of `channel` wrote to the control output port at
+    /// `index`.
+    #[must_use]
+    pub fn control_output(&self, channel: usize, index: usize) -> Option<f32> {
+        self.control_outputs.get(channel)?.get(index)
+    }
+
+    /// Restore every copy from `state` and set the control inputs to the
+    /// port values of the state. See `State::restore` for `map`, `flags` and
+    /// `features`.
+    ///
+    /// # Safety
+    /// Restoring calls the plugin's code, which itself may be unsafe. The
+    /// state must be a state of the plugin and the copies must not be
+    /// running.
+    pub unsafe fn restore(
+        &mut self,
+        state: &State,
+        map: &LV2UridMap,
+        flags: StateFlags,
+        features: &FeatureSet,
+    ) {
+        let (controls, control_inputs) = (&mut self.controls, &self.control_inputs);
+        for instance in &mut self.instances {
+            let set_value = |symbol: &str, value: f32| {
+                if let Some((_, index)) = control_inputs.iter().find(|(s, _)| s == symbol) {
+                    controls.set(*index, value);
+                }
+            };
+            unsafe { state.restore(instance.instance_mut(), map, set_value, flags, features) };
+        }
+    }
+
+    /// Process each channel of `inputs` with its copy into the same channel
+    /// of `outputs`. There must be a channel of each for every copy, and all
+    /// the channels must have the same number of frames. This does not
+    /// allocate, so it is real-time safe.
+    ///
+    /// # Safety
+    /// Running calls the plugin's code, which itself may be unsafe. The other
+    /// ports of the plugin must be connected.
+    pub unsafe fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        let (input_port, output_port) = (self.audio_input, self.audio_output);
+        let channels = self.instances.iter_mut().zip(inputs).zip(outputs);
+        for ((instance, input), output) in channels {
+            let frames = input.len().min(output.len());
+            unsafe {
+                instance
+                    .instance_mut()
+                    .connect_port(input_port, input.as_ptr());
+                instance
+                    .instance_mut()
+                    .connect_port_mut(output_port, output.as_mut_ptr());
+                instance.run(frames);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Multiply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Multiply")
+            .field("instances", &self.instances)
+            .field("controls", &self.controls)
+            .finish()
+    }
+}
+
+/// Options for `ParallelChains`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParallelOptions {
+    /// The number of worker threads, besides the thread that calls
+    /// `ParallelChains::process`, which processes chains too. With 0 all the
+    /// chains are processed by the calling thread.
+    pub threads: usize,
+    /// How many times a worker checks for a new cycle before it parks. Workers
+    /// that spin start sooner, but keep a core busy between cycles.
+    pub spin: u32,
+    /// The largest difference between the latencies of the chains that is
+    /// compensated, in frames.
+    pub max_latency: usize,
+}
+
+impl Default for ParallelOptions {
+    /// A worker for each core but one, which spin for a short while.
+    fn default() -> ParallelOptions {
+        let cores = std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get);
+        ParallelOptions {
+            threads: cores - 1,
+            spin: 10_000,
+            max_latency: 8192,
+        }
+    }
+}
+
+/// Runs independent chains in parallel, like the tracks of a mix, and sums
+/// their outputs.
+///
+/// The worker threads are started when the chains are created and kept for
+/// each cycle. In a cycle the threads take the next chain that is left until
+/// all the chains are processed, so a thread that finishes a short chain
+/// goes on with another one. Channel `n` of the output is the sum of
+/// channel `n` of each chain, added in the order of the chains so the result
+/// does not depend on which thread processed which chain.
+///
+/// Chains with less latency than the others are delayed by the difference
+/// before they are summed, so the chains stay aligned. The sum has the
+/// latency of the chain with the most latency, see `latency`.
+pub struct ParallelChains {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+    outputs: Box<[Box<[f32]>]>,
+    // The delay of each output channel of each chain.
+    delays: Vec<Vec<DelayLine>>,
+    max_latency: usize,
+    block_length: usize,
+}
+
+// The state of a cycle that the workers share.
+struct Shared {
+    // A chain is only locked by the thread that took it, or by the owner
+    // between cycles, so the locks are never waited for.
+    chains: Box<[Mutex<Chain>]>,
+    // The index of the next chain to take.
+    next: AtomicUsize,
+    // The number of chains that were processed in the cycle.
+    done: AtomicUsize,
+    // Incremented to start a cycle.
+    epoch: AtomicU64,
+    frames: AtomicUsize,
+    stop: AtomicBool,
+    spin: u32,
+}
+
+impl Shared {
+    // Process the chains that are left in the cycle.
+    fn work(&self) {
+        loop {
+            let index = self.next.fetch_add(1, atomic::Ordering::AcqRel);
+            let chain = match self.chains.get(index) {
+                Some(chain) => chain,
+                None => return,
+            };
+            // Read after taking the chain, since a worker that was late for
+            // the last cycle can take a chain of the next one.
+            let frames = self.frames.load(atomic::Ordering::Acquire);
+            // A panic leaves the chain half processed, but must not stop the
+            // cycle from finishing.
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+                chain.lock().process(frames);
+            }));
+            self.done.fetch_add(1, atomic::Ordering::AcqRel);
+        }
+    }
+
+    fn run_worker(&self) {
+        let mut epoch = 0;
+        loop {
+            let mut spins = 0;
+            while self.epoch.load(atomic::Ordering::Acquire) == epoch {
+                if spins < self.spin {
+                    spins += 1;
+                    std::hint::spin_loop();
+                } else {
+                    // Parking can wake up spuriously, so the epoch is checked
+                    // again.
+                    std::thread::park();
+                }
+            }
+            if self.stop.load(atomic::Ordering::Acquire) {
+                return;
+            }
+            epoch = self.epoch.load(atomic::Ordering::Acquire);
+            self.work();
+        }
+    }
+}
+
+impl ParallelChains {
+    /// Run `chains` with the threads of `options`. The chains must all have
+    /// the same block length, which is the block length of the result.
+    ///
+    /// # Panics
+    /// Panics if the chains have different block lengths or a thread can not
+    /// be started.
+    #[must_use]
+    pub fn new(chains: Vec<Chain>, options: &ParallelOptions) -> ParallelChains {
+        let block_length = chains.first().map_or(0, Chain::block_length);
+        assert!(
+            chains
+                .iter()
+                .all(|chain| chain.block_length() == block_length),
+            "the chains have different block lengths"
+        );
+        let channels = chains.iter().map(Chain::audio_outputs).max().unwrap_or(0);
+        let delays = chains
+            .iter()
+            .map(|chain| {
+                (0..chain.audio_outputs())
+                    .map(|_| DelayLine::new(options.max_latency))
+                    .collect()
+            })
+            .collect();
+        let shared = Arc::new(Shared {
+            chains: chains.into_iter().map(Mutex::new).collect(),
+            next: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            epoch: AtomicU64::new(0),
+            frames: AtomicUsize::new(0),
+            stop: AtomicBool::new(false),
+            spin: options.spin,
+        });
+        let workers = (0..options.threads)
+            .map(|n| {
+                let shared = Arc::clone(&shared);
+                std::thread::Builder::new()
+                    .name(format!("lilv-chains-{}", n))
+                    .spawn(move || shared.run_worker())
+                    .unwrap()
+            })
+            .collect();
+        ParallelChains {
+            shared,
+            workers,
+            outputs: buffers(channels, block_length),
+            delays,
+            max_latency: options.max_latency,
+            block_length,
+        }
+    }
+
+    /// The latency of the sum in frames, which is the largest latency of the
+    /// chains, up to the largest latency that is compensated.
+    #[must_use]
+    pub fn latency(&self) -> usize {
+        self.shared
+            .chains
+            .iter()
+            .map(|chain| chain.lock().total_latency())
+            .max()
+            .unwrap_or(0)
+            .min(self.max_latency)
+    }
+
+    /// The number of chains.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.shared.chains.len()
+    }
+
+    /// Returns `true` if there are no chains.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.shared.chains.is_empty()
+    }
+
+    /// The chain at `index`, to fill its inputs or schedule its events
+    /// between cycles.
+    pub fn chain_mut(&mut self, index: usize) -> Option<MutexGuard<'_, Chain>> {
+        self.shared.chains.get(index).map(Mutex::lock)
+    }
+
+    /// The summed channel `channel` of the last cycle, with `block_length`
+    /// frames.
+    #[must_use]
+    pub fn output(&self, channel: usize) -> Option<&[f32]> {
+        self.outputs.get(channel).map(|output| &output[..])
+    }
+
+    /// Process `frames` frames of every chain and sum their outputs.
+    ///
+    /// The calling thread processes chains like the workers and waits for
+    /// the workers by spinning, and does not allocate. It locks each chain
+    /// to sum its outputs only after the workers are done with it, so the
+    /// locks are never contended.
+    /// A chain that panics does not stop the cycle, but its outputs are
+    /// summed as they are.
+    ///
+    /// # Panics
+    /// Panics if `frames` is more than the block length.
+    ///
+    /// # Safety
+    /// Running calls the plugins' code, which itself may be unsafe. The
+    /// plugins run on the worker threads, and the ports the chains do not
+    /// connect must be connected.
+    pub unsafe fn process(&mut self, frames: usize) {
+        assert!(
+            frames <= self.block_length,
+            "{} frames do not fit into blocks of {} frames",
+            frames,
+            self.block_length
+        );
+        let shared = &*self.shared;
+        shared.frames.store(frames, atomic::Ordering::Release);
+        // `done` is reset first: a worker that is late for the last cycle can
+        // take a chain as soon as `next` is reset, and must not have its
+        // count wiped.
+        shared.done.store(0, atomic::Ordering::Release);
+        shared.next.store(0, atomic::Ordering::Release);
+        shared.epoch.fetch_add(1, atomic::Ordering::AcqRel);
+        for worker in &self.workers {
+            worker.thread().unpark();
+        }
+        shared.work();
+        while shared.done.load(atomic::Ordering::Acquire) < shared.chains.len() {
+            std::hint::spin_loop();
+        }
+
+        for output in self.outputs.iter_mut() {
+            output[..frames].fill(0.0);
+        }
+        let latency = self.latency();
+        for (chain, delays) in shared.chains.iter().zip(&mut self.delays) {
+            let chain = chain.lock();
+            let delay = latency - chain.total_latency().min(latency);
+            for (channel, (output, line)) in self.outputs.iter_mut().zip(delays).enumerate() {
+                if let Some(source) = chain.output(channel) {
+                    for (sum, sample) in output[..frames].iter_mut().zip(source) {
+                        *sum += line.delay(*sample, delay);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ParallelChains {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, atomic::Ordering::Release);
+        self.shared.epoch.fetch_add(1, atomic::Ordering::AcqRel);
+        for worker in self.workers.drain(..) {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
+
+impl std::fmt::Debug for ParallelChains {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParallelChains")
+            .field("chains", &self.len())
+            .field("threads", &self.workers.len())
+            .field("block_length", &self.block_length)
+            .finish()
+    }
+}
+
+// A ring buffer that delays a signal by up to `max_delay` frames.
+struct DelayLine {
+    buffer: Box<[f32]>,
+    position: usize,
+}
+
+impl DelayLine {
+    fn new(max_delay: usize) -> DelayLine {
+        DelayLine {
+            buffer: vec![0.0; max_delay + 1].into_boxed_slice(),
+            position: 0,
+        }
+    }
+
+    fn max_delay(&self) -> usize {
+        self.buffer.len() - 1
+    }
+
+    // Push `sample` and return the sample from `delay` frames ago.
+    fn delay(&mut self, sample: f32, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[self.position] = sample;
+        let read = (self.position + len - delay.min(len - 1)) % len;
+        self.position = (self.position + 1) % len;
+        self.buffer[read]
+    }
+}
+
+/// A node of a chain that is implemented in Rust instead of by a plugin,
+/// for simple routing that should not depend on utility plugins being
+/// installed.
+///
+/// `process` is called from the audio thread and does not allocate. All the
+/// channels must have the same number of frames. Channels beyond
+/// `audio_inputs` and `audio_outputs` are ignored, and missing input
+/// channels are treated as silence.
+pub trait NativeNode: Send {
+    /// The number of audio input channels.
+    fn audio_inputs(&self) -> usize;
+    /// The number of audio output channels.
+    fn audio_outputs(&self) -> usize;
+    /// Process `inputs` into `outputs`.
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]);
+    /// The number of frames the outputs are delayed by, for
+    /// `Chain::total_latency`.
+    fn latency(&self) -> usize {
+        0
+    }
+}
+
+/// Multiplies each channel by a gain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gain {
+    channels: usize,
+    /// The linear gain.
+    pub gain: f32,
+}
+
+impl Gain {
+    /// Create a node for `channels` channels with a linear `gain`.
+    #[must_use]
+    pub fn new(channels: usize, gain: f32) -> Gain {
+        Gain { channels, gain }
+    }
+
+    /// Create a node for `channels` channels with a gain in decibels, like
+    /// a trim.
+    #[must_use]
+    pub fn from_db(channels: usize, db: f32) -> Gain {
+        Gain::new(channels, 10.0_f32.powf(db / 20.0))
+    }
+}
+
+impl NativeNode for Gain {
+    fn audio_inputs(&self) -> usize {
+        self.channels
+    }
+
+    fn audio_outputs(&self) -> usize {
+        self.channels
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        for (channel, output) in outputs.iter_mut().take(self.channels).enumerate() {
+            match inputs.get(channel) {
+                Some(input) => {
+                    for (out, sample) in output.iter_mut().zip(input.iter()) {
+                        *out = sample * self.gain;
+                    }
+                }
+                None => output.fill(0.0),
+            }
+        }
+    }
+}
+
+/// Sums its input channels into one output channel, each with its own gain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mixer {
+    /// The linear gain of each input channel.
+    pub gains: Vec<f32>,
+}
+
+impl Mixer {
+    /// Create a mixer of `inputs` channels with unity gain.
+    #[must_use]
+    pub fn new(inputs: usize) -> Mixer {
+        Mixer {
+            gains: vec![1.0; inputs],
+        }
+    }
+}
+
+impl NativeNode for Mixer {
+    fn audio_inputs(&self) -> usize {
+        self.gains.len()
+    }
+
+    fn audio_outputs(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        let output = match outputs.first_mut() {
+            Some(output) => output,
+            None => return,
+        };
+        output.fill(0.0);
+        for (input, gain) in inputs.iter().zip(&self.gains) {
+            for (out, sample) in output.iter_mut().zip(input.iter()) {
+                *out += sample * gain;
+            }
+        }
+    }
+}
+
+/// Copies its input channel to each of its output channels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Splitter {
+    outputs: usize,
+}
+
+impl Splitter {
+    /// Create a splitter with `outputs` output channels.
+    #[must_use]
+    pub fn new(outputs: usize) -> Splitter {
+        Splitter { outputs }
+    }
+}
+
+impl NativeNode for Splitter {
+    fn audio_inputs(&self) -> usize {
+        1
+    }
+
+    fn audio_outputs(&self) -> usize {
+        self.outputs
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        for output in outputs.iter_mut().take(self.outputs) {
+            match inputs.first() {
+                Some(input) => {
+                    let len = output.len().min(input.len());
+                    output[..len].copy_from_slice(&input[..len]);
+                }
+                None => output.fill(0.0),
+            }
+        }
+    }
+}
+
+/// Pans a mono channel to stereo with constant power.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Panner {
+    /// The position from -1 for left to 1 for right.
+    pub pan: f32,
+}
+
+impl Panner {
+    /// Create a panner at `pan`, from -1 for left to 1 for right.
+    #[must_use]
+    pub fn new(pan: f32) -> Panner {
+        Panner { pan }
+    }
+
+    /// The gains of the left and right channel.
+    #[must_use]
+    pub fn gains(&self) -> (f32, f32) {
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+        (angle.cos(), angle.sin())
+    }
+}
+
+impl NativeNode for Panner {
+    fn audio_inputs(&self) -> usize {
+        1
+    }
+
+    fn audio_outputs(&self) -> usize {
+        2
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        let (left, right) = self.gains();
+        for (output, gain) in outputs.iter_mut().zip([left, right]) {
+            match inputs.first() {
+                Some(input) => {
+                    for (out, sample) in output.iter_mut().zip(input.iter()) {
+                        *out = sample * gain;
+                    }
+                }
+                None => output.fill(0.0),
+            }
+        }
+    }
+}
+
+/// Delays each channel by a number of frames, like to line up a path with
+/// one that has more latency.
+pub struct Delay {
+    lines: Vec<DelayLine>,
+    delay: usize,
+}
+
+impl Delay {
+    /// Create a delay of `delay` frames for `channels` channels, which can be
+    /// set to at most `max_delay` frames.
+    #[must_use]
+    pub fn new(channels: usize, max_delay: usize, delay: usize) -> Delay {
+        Delay {
+            lines: (0..channels).map(|_| DelayLine::new(max_delay)).collect(),
+            delay: delay.min(max_delay),
+        }
+    }
+
+    /// The delay in frames.
+    #[must_use]
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    /// Set the delay to `delay` frames, limited to the maximum delay.
+    pub fn set_delay(&mut self, delay: usize) {
+        let max = self.lines.first().map_or(0, DelayLine::max_delay);
+        self.delay = delay.min(max);
+    }
+}
+
+impl std::fmt::Debug for Delay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Delay")
+            .field("channels", &self.lines.len())
+            .field("delay", &self.delay)
+            .finish()
+    }
+}
+
+impl NativeNode for Delay {
+    fn audio_inputs(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn audio_outputs(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        let delay = self.delay;
+        for (channel, (line, output)) in self.lines.iter_mut().zip(outputs).enumerate() {
+            let input = inputs.get(channel);
+            for (frame, out) in output.iter_mut().enumerate() {
+                *out = line.delay(input.map_or(0.0, |input| input[frame]), delay);
+            }
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.delay
+    }
+}
+
+/// A meter that measures the peak and RMS level of each channel of the blocks
+/// that pass through it.
+///
+/// The tap is run on the audio thread and the levels of the last block are
+/// read from a `MeterReadings`, usually on the GUI thread. Levels are stored
+/// in atomics, so neither side ever waits for the other.
+#[derive(Debug)]
+pub struct MeterTap {
+    levels: Arc<[ChannelLevels]>,
+}
+
+/// The levels measured by a `MeterTap`. Clones read the same tap.
+#[derive(Clone, Debug)]
+pub struct MeterReadings {
+    levels: Arc<[ChannelLevels]>,
+}
+
+// The levels are stored as the bits of `f32`s.
+#[derive(Debug, Default)]
+struct ChannelLevels {
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+impl MeterTap {
+    /// Create a tap for `channels` channels. The levels are 0 until the
+    /// first block is measured.
+    #[must_use]
+    pub fn new(channels: usize) -> MeterTap {
+        MeterTap {
+            levels: (0..channels).map(|_| ChannelLevels::default()).collect(),
+        }
+    }
+
+    /// The number of channels of the tap.
+    #[must_use]
+    pub fn channels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Get the readings of the tap, which can be sent to another thread.
+    #[must_use]
+    pub fn readings(&self) -> MeterReadings {
+        MeterReadings {
+            levels: self.levels.clone(),
+        }
+    }
+
+    /// Measure a block with a slice of samples for each channel. Channels
+    /// beyond the channels of the tap are ignored, and missing channels read
+    /// as silent. This does not allocate, so it is real-time safe.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn measure(&mut self, block: &[&[f32]]) {
+        for (index, levels) in self.levels.iter().enumerate() {
+            let samples = block.get(index).copied().unwrap_or_default();
+            let peak = samples.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+            let rms = if samples.is_empty() {
+                0.0
+            } else {
+                let sum: f32 = samples.iter().map(|s| s * s).sum();
+                (sum / samples.len() as f32).sqrt()
+            };
+            levels.peak.store(peak.to_bits(), atomic::Ordering::Relaxed);
+            levels.rms.store(rms.to_bits(), atomic::Ordering::Relaxed);
+        }
+    }
+}
+
+impl MeterReadings {
+    /// The number of channels of the tap.
+    #[must_use]
+    pub fn channels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The largest absolute sample of `channel` in the last block.
+    #[must_use]
+    pub fn peak(&self, channel: usize) -> Option<f32> {
+        let levels = self.levels.get(channel)?;
+        Some(f32::from_bits(levels.peak.load(atomic::Ordering::Relaxed)))
+    }
+
+    /// The RMS level of `channel` in the last block.
+    #[must_use]
+    pub fn rms(&self, channel: usize) -> Option<f32> {
+        let levels = self.levels.get(channel)?;
+        Some(f32::from_bits(levels.rms.load(atomic::Ordering::Relaxed)))
+    }
+}
+
+impl NativeNode for MeterTap {
+    fn audio_inputs(&self) -> usize {
+        self.channels()
+    }
+
+    fn audio_outputs(&self) -> usize {
+        self.channels()
+    }
+
+    /// Pass `inputs` through to `outputs` and measure them.
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        for (index, output) in outputs.iter_mut().enumerate() {
+            match inputs.get(index) {
+                Some(input) => {
+                    let len = output.len().min(input.len());
+                    output[..len].copy_from_slice(&input[..len]);
+                }
+                None => output.fill(0.0),
+            }
+        }
+        self.measure(inputs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(id: &str, uri: &str) -> PluginSpec {
+        PluginSpec {
+            id: id.to_string(),
+            uri: uri.to_string(),
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    fn port(plugin: &str, port: &str) -> PortRef {
+        PortRef {
+            plugin: plugin.to_string(),
+            port: port.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let world = crate::testing::fixture_world().unwrap();
+        let amp = crate::testing::GAIN_URI;
+        let mut spec = ChainSpec {
+            plugins: vec![plugin("a", amp), plugin("b", amp)],
+            connections: Vec::new(),
+        };
+        assert_eq!(spec.validate(&world), Ok(()));
+
+        spec.plugins.push(plugin("b", "urn:lilv-rs:missing"));
+        spec.connections = vec![
+            ConnectionSpec {
+                from: port("a", "out").into(),
+                to: port("b", "gain").into(),
+            },
+            ConnectionSpec {
+                from: port("a", "in").into(),
+                to: port("c", "in").into(),
+            },
+            ConnectionSpec {
+                from: port("a", "nope").into(),
+                to: port("b", "in").into(),
+            },
+        ];
+        assert_eq!(
+            spec.validate(&world),
+            Err(vec![
+                ChainProblem::DuplicateId("b".to_string()),
+                ChainProblem::UnknownPlugin {
+                    id: "b".to_string(),
+                    uri: "urn:lilv-rs:missing".to_string(),
+                },
+                ChainProblem::IncompatiblePorts {
+                    from: port("a", "out"),
+                    from_kind: PortKind::Audio,
+                    to: port("b", "gain"),
+                    to_kind: PortKind::Control,
+                },
+                ChainProblem::WrongDirection(port("a", "in")),
+                ChainProblem::UnknownId("c".to_string()),
+                ChainProblem::UnknownPort(port("a", "nope")),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_sidechain() {
+        let world = crate::testing::fixture_world().unwrap();
+        // Validating only needs the data of the plugin, not its library.
+        let dir = crate::testing::TempDir::new("sidechain").unwrap();
+        let bundle = dir.join("sidechain.lv2");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(bundle.join("manifest.ttl"), SIDECHAIN_TTL).unwrap();
+        world.load_bundle_dir(&bundle).unwrap();
+        let endpoint = |plugin: &str, ports| Endpoint {
+            plugin: plugin.to_string(),
+            ports,
+        };
+        let connection = |to| ConnectionSpec {
+            from: port("amp", "out").into(),
+            to: endpoint("comp", to),
+        };
+        let mut spec = ChainSpec {
+            plugins: vec![
+                plugin("amp", crate::testing::GAIN_URI),
+                plugin("comp", "urn:lilv-rs:test:sidechain"),
+            ],
+            connections: Vec::new(),
+        };
+        // The sidechain inputs are not fed by the previous plugin.
+        assert_eq!(
+            spec.validate(&world),
+            Err(vec![
+                ChainProblem::UnconnectedSidechain(port("comp", "key_l")),
+                ChainProblem::UnconnectedSidechain(port("comp", "key_r")),
+            ])
+        );
+
+        let left = "http://lv2plug.in/ns/ext/port-groups#left";
+        let right = "http://lv2plug.in/ns/ext/port-groups#right";
+        spec.connections = vec![
+            connection(PortSelector::Designation(left.to_string())),
+            connection(PortSelector::Designation(right.to_string())),
+        ];
+        assert_eq!(spec.validate(&world), Ok(()));
+
+        let group = "urn:lilv-rs:test:sidechain#key";
+        spec.connections = vec![
+            connection(PortSelector::Sidechain),
+            connection(PortSelector::Group(group.to_string())),
+            connection(PortSelector::Group("key".to_string())),
+            ConnectionSpec {
+                from: endpoint("amp", PortSelector::Designation(left.to_string())),
+                to: port("comp", "key_l").into(),
+            },
+        ];
+        let problems = spec.validate(&world).unwrap_err();
+        let mismatch = |to| ChainProblem::ConnectionChannels {
+            from: port("amp", "out").into(),
+            outputs: 1,
+            to: endpoint("comp", to),
+            inputs: 2,
+        };
+        assert_eq!(
+            problems,
+            vec![
+                mismatch(PortSelector::Sidechain),
+                mismatch(PortSelector::Group(group.to_string())),
+                mismatch(PortSelector::Group("key".to_string())),
+                ChainProblem::NoPorts(endpoint("amp", PortSelector::Designation(left.to_string()))),
+                ChainProblem::UnconnectedSidechain(port("comp", "key_l")),
+                ChainProblem::UnconnectedSidechain(port("comp", "key_r")),
+            ]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "can not connect 1 outputs amp:out to 2 inputs comp:sidechain"
+        );
+    }
+
+    const SIDECHAIN_TTL: &str = r#"
+@prefix lv2: <http://lv2plug.in/ns/lv2core#> .
+@prefix pg: <http://lv2plug.in/ns/ext/port-groups#> .
+
+<urn:lilv-rs:test:sidechain#key>
+    a pg:StereoGroup ;
+    lv2:symbol "key" .
+
+<urn:lilv-rs:test:sidechain>
+    a lv2:Plugin ;
+    lv2:binary <sidechain.so> ;
+    lv2:port [
+        a lv2:AudioPort , lv2:InputPort ;
+        lv2:index 0 ;
+        lv2:symbol "in" ;
+        lv2:name "In"
+    ] , [
+        a lv2:AudioPort , lv2:InputPort ;
+        lv2:index 1 ;
+        lv2:symbol "key_l" ;
+        lv2:name "Key Left" ;
+        lv2:portProperty lv2:isSideChain ;
+        lv2:designation pg:left ;
+        pg:group <urn:lilv-rs:test:sidechain#key>
+    ] , [
+        a lv2:AudioPort , lv2:InputPort ;
+        lv2:index 2 ;
+        lv2:symbol "key_r" ;
+        lv2:name "Key Right" ;
+        lv2:portProperty lv2:isSideChain ;
+        lv2:designation pg:right ;
+        pg:group <urn:lilv-rs:test:sidechain#key>
+    ] , [
+        a lv2:AudioPort , lv2:OutputPort ;
+        lv2:index 3 ;
+        lv2:symbol "out" ;
+        lv2:name "Out"
+    ] .
+"#;
+
+    #[test]
+    fn test_instantiate() {
+        let world = crate::testing::fixture_world().unwrap();
+        let mut amp = plugin("amp", crate::testing::GAIN_URI);
+        amp.parameters.insert("gain".to_string(), -6.0);
+        let mut spec = ChainSpec {
+            plugins: vec![amp, plugin("amp2", crate::testing::GAIN_URI)],
+            connections: Vec::new(),
+        };
+        let instances = spec
+            .instantiate(&world, 44100.0, &FeatureSet::new())
+            .unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].id, "amp");
+        assert_eq!(instances[0].control(0), Some(-6.0));
+        assert_eq!(instances[1].control(0), Some(0.0));
+        assert_eq!(instances[0].control(3), None);
+
+        spec.plugins[1].parameters.insert("out".to_string(), 1.0);
+        let problems = spec
+            .instantiate(&world, 44100.0, &FeatureSet::new())
+            .unwrap_err();
+        assert_eq!(
+            problems,
+            vec![ChainProblem::InvalidParameter(PortRef {
+                plugin: "amp2".to_string(),
+                port: "out".to_string(),
+            })]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "amp2:out is not a control input port"
+        );
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = Host::new(44100.0, 4);
+        let spec = ChainSpec {
+            plugins: vec![plugin("amp", crate::testing::GAIN_URI)],
+            connections: Vec::new(),
+        };
+        let mut instances = spec
+            .instantiate(&world, host.sample_rate(), host.features())
+            .unwrap();
+        let mut original = instances.remove(0);
+        assert!(original.set_control(0, -6.0));
+        let mut copy =
+            unsafe { original.duplicate(host.sample_rate(), host.features(), host.urid_map()) }
+                .unwrap();
+        assert_eq!(copy.id, "amp");
+        assert_eq!(copy.plugin.uri(), original.plugin.uri());
+        assert_eq!(copy.control(0), Some(-6.0));
+
+        // The copies run on their own.
+        assert!(copy.set_control(0, 0.0));
+        assert_eq!(original.control(0), Some(-6.0));
+        let run = |instance: ChainInstance| {
+            let input = [1.0_f32; 4];
+            let mut output = [0.0_f32; 4];
+            let mut active = unsafe { instance.instance.activate() };
+            unsafe {
+                active.instance_mut().connect_port(1, input.as_ptr());
+                active
+                    .instance_mut()
+                    .connect_port_mut(2, output.as_mut_ptr());
+                active.run(4);
+            }
+            output[3]
+        };
+        assert!((run(original) - 0.501).abs() < 1e-3);
+        assert!((run(copy) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_channel_mismatch() {
+        let world = crate::testing::fixture_world().unwrap();
+        let spec = ChainSpec {
+            plugins: vec![
+                plugin("amp", crate::testing::GAIN_URI),
+                plugin("metro", crate::testing::MIDI_THROUGH_URI),
+            ],
+            connections: Vec::new(),
+        };
+        let problems = spec.validate(&world).unwrap_err();
+        assert_eq!(
+            problems,
+            vec![ChainProblem::ChannelMismatch {
+                from: "amp".to_string(),
+                outputs: 1,
+                to: "metro".to_string(),
+                inputs: 0,
+            }]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "amp has 1 audio outputs but metro has 0 audio inputs"
+        );
+    }
+
+    #[test]
+    fn test_chain() {
+        let world = crate::testing::fixture_world().unwrap();
+        let mut amp = plugin("amp", crate::testing::GAIN_URI);
+        amp.parameters.insert("gain".to_string(), -6.0);
+        let spec = ChainSpec {
+            plugins: vec![amp],
+            connections: Vec::new(),
+        };
+        let mut chain = Chain::new(8);
+        for instance in spec
+            .instantiate(&world, 44100.0, &FeatureSet::new())
+            .unwrap()
+        {
+            unsafe { chain.push_plugin(instance).unwrap() };
+        }
+        chain
+            .push_native("double", Box::new(Gain::new(1, 2.0)))
+            .unwrap();
+        assert_eq!(
+            chain.push_native("double", Box::new(Gain::new(1, 1.0))),
+            Err(ChainProblem::DuplicateId("double".to_string()))
+        );
+        assert_eq!(
+            chain.push_native("mix", Box::new(Mixer::new(2))),
+            Err(ChainProblem::ChannelMismatch {
+                from: "double".to_string(),
+                outputs: 1,
+                to: "mix".to_string(),
+                inputs: 2,
+            })
+        );
+        assert_eq!(
+            chain.push_native("split", Box::new(Splitter::new(33))),
+            Err(ChainProblem::TooManyChannels {
+                id: "split".to_string(),
+                channels: 33,
+            })
+        );
+        assert_eq!(chain.ids().collect::<Vec<_>>(), vec!["amp", "double"]);
+        assert_eq!((chain.audio_inputs(), chain.audio_outputs()), (1, 1));
+
+        let set = |plugin: &str, bypassed| ChainEvent::Bypass {
+            plugin: plugin.to_string(),
+            bypassed,
+        };
+        chain.input_mut(0).unwrap().fill(1.0);
+        chain.events_mut().schedule(2, set("amp", true));
+        chain.events_mut().schedule(
+            4,
+            ChainEvent::SetParam {
+                port: port("amp", "gain"),
+                value: 6.0,
+            },
+        );
+        chain.events_mut().schedule(6, set("amp", false));
+        unsafe { chain.process(8) };
+        let output = chain.output(0).unwrap();
+        let expected = [1.002, 1.002, 2.0, 2.0, 2.0, 2.0, 3.991, 3.991];
+        for (sample, expected) in output.iter().zip(&expected) {
+            assert!((sample - expected).abs() < 1e-3, "{:?}", output);
+        }
+        assert_eq!(chain.time(), 8);
+        assert!(chain.events_mut().is_empty());
+        assert!(!chain.is_bypassed("amp"));
+
+        // Events that are due before the block are applied at its start.
+        chain.events_mut().schedule(0, set("double", true));
+        unsafe { chain.process(4) };
+        assert!(chain.is_bypassed("double"));
+        assert!((chain.output(0).unwrap()[0] - 1.995).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_param_queue() {
+        let world = crate::testing::fixture_world().unwrap();
+        let spec = ChainSpec {
+            plugins: vec![
+                plugin("first", crate::testing::GAIN_URI),
+                plugin("second", crate::testing::GAIN_URI),
+            ],
+            connections: Vec::new(),
+        };
+        let mut chain = Chain::new(4);
+        for instance in spec
+            .instantiate(&world, 44100.0, &FeatureSet::new())
+            .unwrap()
+        {
+            unsafe { chain.push_plugin(instance).unwrap() };
+        }
+        assert_eq!(
+            chain.control_inputs(),
+            vec![port("first", "gain"), port("second", "gain")]
+        );
+        // The gain fixture has 3 ports, so the second plugin starts at 3.
+        assert_eq!(chain.control_index("second", "gain"), Some(3));
+        assert_eq!(chain.control_index("second", "in"), None);
+        assert_eq!(chain.control_index("third", "gain"), None);
+
+        let mut sender = chain.param_queue(4);
+        assert!(sender.push(3, -6.0));
+        assert!(sender.push(1, 5.0));
+        assert_eq!(chain.control("second", "gain"), Some(0.0));
+        unsafe { chain.process(4) };
+        assert_eq!(chain.control("second", "gain"), Some(-6.0));
+        assert_eq!(chain.control("first", "gain"), Some(0.0));
+    }
+
+    #[test]
+    fn test_session() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = Host::new(44100.0, 4);
+        let mut amp = plugin("amp", crate::testing::GAIN_URI);
+        amp.parameters.insert("gain".to_string(), -6.0);
+        let spec = ChainSpec {
+            plugins: vec![amp, plugin("boost", crate::testing::GAIN_URI)],
+            connections: Vec::new(),
+        };
+        let mut chain = Chain::new(4);
+        for instance in spec
+            .instantiate(&world, host.sample_rate(), host.features())
+            .unwrap()
+        {
+            unsafe { chain.push_plugin(instance).unwrap() };
+        }
+        chain
+            .push_native("double", Box::new(Gain::new(1, 2.0)))
+            .unwrap();
+        chain.events_mut().schedule(
+            0,
+            ChainEvent::Bypass {
+                plugin: "boost".to_string(),
+                bypassed: true,
+            },
+        );
+        unsafe { chain.process(4) };
+
+        let temp = crate::testing::TempDir::new("session").unwrap();
+        let path = temp.join("session.json");
+        unsafe { chain.save_session(&path, &host).unwrap() };
+        let loaded = unsafe { Chain::load_session(&world, &path, &host) }.unwrap();
+        assert_eq!(loaded.problems, Vec::new());
+        let mut chain = loaded.chain;
+        assert_eq!(chain.ids().collect::<Vec<_>>(), vec!["amp", "boost"]);
+        assert!(chain.is_bypassed("boost"));
+        chain.input_mut(0).unwrap().fill(1.0);
+        unsafe { chain.process(4) };
+        assert!(chain
+            .output(0)
+            .unwrap()
+            .iter()
+            .all(|s| (s - 0.501).abs() < 1e-3));
+
+        // Plugins that are not installed are reported and left out.
+        let session = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(
+            &path,
+            session.replacen(crate::testing::GAIN_URI, "urn:missing", 1),
+        )
+        .unwrap();
+        let loaded = unsafe { Chain::load_session(&world, &path, &host) }.unwrap();
+        assert_eq!(
+            loaded.problems,
+            vec![ChainProblem::UnknownPlugin {
+                id: "amp".to_string(),
+                uri: "urn:missing".to_string(),
+            }]
+        );
+        assert_eq!(loaded.chain.ids().collect::<Vec<_>>(), vec!["boost"]);
+
+        std::fs::write(&path, "{\"version\": 2}").unwrap();
+        let invalid = unsafe { Chain::load_session(&world, &path, &host) };
+        assert!(matches!(invalid, Err(SessionError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_bypass() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let instance = unsafe { plugin.instantiate(44100.0, []).unwrap().activate() };
+        let mut bypass = Bypass::new(&plugin, instance, 8, 16, 4);
+        let mut gain = -6.0_f32;
+        unsafe {
+            bypass
+                .instance_mut()
+                .instance_mut()
+                .connect_port_mut(0, &mut gain)
+        };
+        assert_eq!(bypass.latency(), 0);
+
+        let input = [1.0; 8];
+        let mut output = [0.0; 8];
+        let mut process = |bypass: &mut Bypass| {
+            unsafe { bypass.process(&[&input], &mut [&mut output]) };
+            output
+        };
+        let wet = 0.501;
+        assert!(process(&mut bypass).iter().all(|s| (s - wet).abs() < 1e-3));
+
+        // The bypass fades to the dry signal over 4 frames.
+        bypass.set_bypassed(true);
+        let faded = process(&mut bypass);
+        for (frame, sample) in faded.iter().enumerate() {
+            let share = (1.0 - 0.25 * (frame + 1) as f32).max(0.0);
+            let expected = wet * share + (1.0 - share);
+            assert!((sample - expected).abs() < 1e-3, "{:?}", faded);
+        }
+        assert!(bypass.is_bypassed());
+
+        bypass.set_mix(0.5);
+        assert_eq!(process(&mut bypass), [1.0; 8]);
+        bypass.set_bypassed(false);
+        let mixed = process(&mut bypass);
+        assert!((mixed[7] - (wet + 1.0) / 2.0).abs() < 1e-3, "{:?}", mixed);
+    }
+
+    #[test]
+    fn test_multiply() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = crate::testing::TestHost::new(44100.0, 512);
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let mut multiply = unsafe { Multiply::new(&plugin, 2, 44100.0, host.features()) }.unwrap();
+        assert_eq!(multiply.channels(), 2);
+        assert_eq!(multiply.control(0), Some(0.0));
+        assert!(multiply.set_control(0, -6.0));
+        assert!(!multiply.set_control(1, 0.0));
+
+        let (left, right) = ([1.0; 4], [2.0; 4]);
+        let (mut left_out, mut right_out) = ([0.0; 4], [0.0; 4]);
+        unsafe { multiply.process(&[&left, &right], &mut [&mut left_out, &mut right_out]) };
+        assert!(left_out.iter().all(|s| (s - 0.501).abs() < 1e-3));
+        assert!(right_out.iter().all(|s| (s - 1.002).abs() < 1e-3));
+
+        let portable = crate::state::PortableState {
+            plugin_uri: crate::testing::GAIN_URI.to_string(),
+            label: None,
+            port_values: vec![("gain".to_string(), 6.0)],
+            properties: Vec::new(),
+        };
+        let state = State::new_from_string(&world, host.urid_map(), &portable.to_turtle()).unwrap();
+        unsafe {
+            multiply.restore(
+                &state,
+                host.urid_map(),
+                StateFlags::empty(),
+                host.features(),
+            )
+        };
+        assert_eq!(multiply.control(0), Some(6.0));
+        unsafe { multiply.process(&[&left, &right], &mut [&mut left_out, &mut right_out]) };
+        assert!(right_out.iter().all(|s| (s - 3.991).abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_not_mono() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = crate::testing::TestHost::new(44100.0, 512);
+        let uri = world.new_uri(crate::testing::MIDI_THROUGH_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let error = unsafe { Multiply::new(&plugin, 2, 44100.0, host.features()) }.unwrap_err();
+        assert_eq!(
+            error,
+            ChainProblem::NotMono {
+                uri: crate::testing::MIDI_THROUGH_URI.to_string(),
+                inputs: 0,
+                outputs: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parallel_chains() {
+        let chains = (1..=5)
+            .map(|n| {
+                let mut chain = Chain::new(4);
+                chain
+                    .push_native("gain", Box::new(Gain::new(2, n as f32)))
+                    .unwrap();
+                chain
+            })
+            .collect();
+        let options = ParallelOptions {
+            threads: 2,
+            spin: 100,
+            max_latency: 0,
+        };
+        let mut parallel = ParallelChains::new(chains, &options);
+        assert_eq!(parallel.len(), 5);
+        for cycle in 0..50 {
+            for index in 0..parallel.len() {
+                let mut chain = parallel.chain_mut(index).unwrap();
+                chain.input_mut(0).unwrap().fill(1.0);
+                chain.input_mut(1).unwrap().fill(cycle as f32);
+            }
+            unsafe { parallel.process(4) };
+            assert_eq!(parallel.output(0).unwrap(), [15.0; 4]);
+            assert_eq!(parallel.output(1).unwrap(), [15.0 * cycle as f32; 4]);
+        }
+        assert!(parallel.output(2).is_none());
+
+        // Without workers the calling thread processes every chain.
+        let mut chain = Chain::new(4);
+        chain
+            .push_native("gain", Box::new(Gain::new(1, 2.0)))
+            .unwrap();
+        let options = ParallelOptions {
+            threads: 0,
+            spin: 0,
+            max_latency: 4,
+        };
+        let mut parallel = ParallelChains::new(vec![chain], &options);
+        parallel
+            .chain_mut(0)
+            .unwrap()
+            .input_mut(0)
+            .unwrap()
+            .fill(1.0);
+        unsafe { parallel.process(2) };
+        assert_eq!(parallel.output(0).unwrap()[..2], [2.0; 2]);
+    }
+
+    #[test]
+    fn test_parallel_chains_stress() {
+        // More workers than chains, so workers are often late for a cycle.
+        let chains = (1..=2)
+            .map(|n| {
+                let mut chain = Chain::new(1);
+                chain
+                    .push_native("gain", Box::new(Gain::new(1, n as f32)))
+                    .unwrap();
+                chain
+            })
+            .collect();
+        let options = ParallelOptions {
+            threads: 6,
+            spin: 10,
+            max_latency: 0,
+        };
+        let mut parallel = ParallelChains::new(chains, &options);
+        for cycle in 0..5000 {
+            for index in 0..parallel.len() {
+                let mut chain = parallel.chain_mut(index).unwrap();
+                chain.input_mut(0).unwrap().fill(cycle as f32);
+            }
+            unsafe { parallel.process(1) };
+            assert_eq!(parallel.output(0).unwrap(), [3.0 * cycle as f32]);
+        }
+    }
+
+    #[test]
+    fn test_typed_chain() {
+        let mut chain = Chain::new(4);
+        chain
+            .push_native("double", Box::new(Gain::new(1, 2.0)))
+            .unwrap();
+        // Longer than a block.
+        let input = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut output = [0.0; 6];
+        let mut typed = TypedChain::<1, 1>::new(chain).unwrap();
+        unsafe { typed.process(&[&input], &mut [&mut output]) };
+        assert_eq!(output, [2.0, 4.0, 6.0, 8.0, 10.0, 12.0]);
+        assert_eq!(typed.chain().time(), 6);
+
+        let error = TypedChain::<2, 2>::new(typed.into_chain()).unwrap_err();
+        assert_eq!(
+            error,
+            ChainProblem::ChannelCount {
+                inputs: 1,
+                outputs: 1,
+                expected_inputs: 2,
+                expected_outputs: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut delay = Delay::new(1, 8, 2);
+        let mut output = [9.0; 4];
+        delay.process(&[&[1.0, 2.0, 3.0, 4.0]], &mut [&mut output]);
+        assert_eq!(output, [0.0, 0.0, 1.0, 2.0]);
+        delay.set_delay(100);
+        assert_eq!(delay.delay(), 8);
+
+        let mut delayed = Chain::new(4);
+        delayed
+            .push_native("delay", Box::new(Delay::new(1, 8, 2)))
+            .unwrap();
+        delayed
+            .push_native("more", Box::new(Delay::new(1, 8, 1)))
+            .unwrap();
+        assert_eq!(delayed.total_latency(), 3);
+        delayed.events_mut().schedule(
+            0,
+            ChainEvent::Bypass {
+                plugin: "more".to_string(),
+                bypassed: true,
+            },
+        );
+        unsafe { delayed.process(4) };
+        assert_eq!(delayed.total_latency(), 2);
+
+        let mut direct = Chain::new(4);
+        direct
+            .push_native("gain", Box::new(Gain::new(1, 1.0)))
+            .unwrap();
+        let options = ParallelOptions {
+            threads: 1,
+            spin: 100,
+            max_latency: 8,
+        };
+        let mut parallel = ParallelChains::new(vec![delayed, direct], &options);
+        assert_eq!(parallel.latency(), 2);
+        for index in 0..2 {
+            let mut chain = parallel.chain_mut(index).unwrap();
+            chain
+                .input_mut(0)
+                .unwrap()
+                .copy_from_slice(&[1.0, 0.0, 0.0, 0.0]);
+        }
+        unsafe { parallel.process(4) };
+        // The impulse of the direct chain is delayed to line up.
+        assert_eq!(parallel.output(0).unwrap(), [0.0, 0.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut chain = Chain::new(4);
+        chain
+            .push_native("delay", Box::new(Delay::new(1, 8, 3)))
+            .unwrap();
+        chain
+            .push_native("double", Box::new(Gain::new(1, 2.0)))
+            .unwrap();
+        let input = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        // Two more frames of tail than the input.
+        let mut output = [9.0; 8];
+        let latency = unsafe { chain.render(&[&input], &mut [&mut output]) };
+        assert_eq!(latency, 3);
+        assert_eq!(output, [2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 0.0, 0.0]);
+
+        let world = crate::testing::fixture_world().unwrap();
+        let mut amp = plugin("amp", crate::testing::GAIN_URI);
+        amp.parameters.insert("gain".to_string(), -6.0);
+        let spec = ChainSpec {
+            plugins: vec![amp],
+            connections: Vec::new(),
+        };
+        let mut chain = Chain::new(4);
+        for instance in spec
+            .instantiate(&world, 44100.0, &FeatureSet::new())
+            .unwrap()
+        {
+            unsafe { chain.push_plugin(instance).unwrap() };
+        }
+        let mut output = [0.0; 5];
+        assert_eq!(unsafe { chain.render(&[&[1.0; 5]], &mut [&mut output]) }, 0);
+        assert!(output.iter().all(|out| (out - 0.501).abs() < 1e-3));
+        assert_eq!(unsafe { chain.render(&[], &mut []) }, 0);
+    }
+
+    #[test]
+    fn test_delay_line() {
+        let mut line = DelayLine::new(2);
+        let delayed: Vec<f32> = [1.0, 2.0, 3.0, 4.0]
+            .iter()
+            .map(|s| line.delay(*s, 2))
+            .collect();
+        assert_eq!(delayed, [0.0, 0.0, 1.0, 2.0]);
+        assert_eq!(line.delay(5.0, 0), 5.0);
+        // Delays are limited to the maximum delay.
+        assert_eq!(line.delay(6.0, 8), 4.0);
+    }
+
+    #[test]
+    fn test_native_nodes() {
+        let input = [1.0, -0.5];
+        let mut left = [9.0; 2];
+        let mut right = [9.0; 2];
+
+        let mut gain = Gain::from_db(1, 20.0);
+        gain.process(&[&input], &mut [&mut left]);
+        assert_eq!(left, [10.0, -5.0]);
+        gain.process(&[], &mut [&mut left]);
+        assert_eq!(left, [0.0; 2]);
+
+        let mut mixer = Mixer::new(2);
+        mixer.gains[1] = 0.5;
+        mixer.process(&[&input, &[2.0, 2.0]], &mut [&mut left]);
+        assert_eq!(left, [2.0, 0.5]);
+
+        let mut splitter = Splitter::new(2);
+        assert_eq!((splitter.audio_inputs(), splitter.audio_outputs()), (1, 2));
+        splitter.process(&[&input], &mut [&mut left, &mut right]);
+        assert_eq!((left, right), (input, input));
+
+        let mut panner = Panner::new(-1.0);
+        panner.process(&[&input], &mut [&mut left, &mut right]);
+        assert_eq!(left, input);
+        assert!(right.iter().all(|s| s.abs() < 1e-6));
+        let (l, r) = Panner::new(0.0).gains();
+        assert!((l - r).abs() < 1e-6 && (l * l + r * r - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_event_queue() {
+        let bypass = |bypassed| ChainEvent::Bypass {
+            plugin: "amp".to_string(),
+            bypassed,
+        };
+        let set = |value| ChainEvent::SetParam {
+            port: port("amp", "gain"),
+            value,
+        };
+        let mut queue = EventQueue::with_capacity(4);
+        queue.schedule(600, set(1.0));
+        queue.schedule(100, bypass(true));
+        queue.schedule(600, set(2.0));
+        queue.schedule(100, bypass(false));
+        assert_eq!(queue.next_time(), Some(100));
+        assert_eq!(queue.pop_due(100), None);
+        assert_eq!(queue.pop_due(512), Some((100, bypass(true))));
+        assert_eq!(queue.pop_due(512), Some((100, bypass(false))));
+        assert_eq!(queue.pop_due(512), None);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop_due(1024), Some((600, set(1.0))));
+        assert_eq!(queue.pop_due(1024), Some((600, set(2.0))));
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_time(), None);
+    }
+
+    #[test]
+    fn test_meter_tap() {
+        let mut tap = MeterTap::new(2);
+        let readings = tap.readings();
+        assert_eq!(readings.channels(), 2);
+        assert_eq!(readings.peak(0), Some(0.0));
+        assert_eq!(readings.peak(2), None);
+
+        let square = [0.5, -0.5, 0.5, -0.5];
+        let impulse = [0.0, -1.0, 0.0, 0.0];
+        std::thread::spawn(move || tap.measure(&[&square, &impulse]))
+            .join()
+            .unwrap();
+        assert_eq!(readings.peak(0), Some(0.5));
+        assert_eq!(readings.rms(0), Some(0.5));
+        assert_eq!(readings.peak(1), Some(1.0));
+        assert_eq!(readings.rms(1), Some(0.5));
+    }
+}
@@ -0,0 +1,313 @@
+use crate::atom::{ATOM_FLOAT, ATOM_INT};
+use crate::feature::{
+    FeatureSet, HostCaps, LV2UridUnmap, LV2_BUF_SIZE_MAX_BLOCK_LENGTH,
+    LV2_BUF_SIZE_MIN_BLOCK_LENGTH, LV2_BUF_SIZE_NOMINAL_BLOCK_LENGTH, LV2_OPTIONS_OPTIONS,
+    LV2_URID_MAP, LV2_URID_UNMAP,
+};
+use crate::ffi::lv2::OptionsOption;
+use crate::port::PortKind;
+use lv2_raw::{LV2Urid, LV2UridMap};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+
+const LV2_PARAMETERS_SAMPLE_RATE: &str = "http://lv2plug.in/ns/ext/parameters#sampleRate";
+
+/// A minimal host that provides the features most plugins need.
+///
+/// The host provides `urid:map`, `urid:unmap` and `options:options` with the
+/// sample rate and the block lengths. Mapping takes a lock, so the features
+/// are not real-time safe.
+///
+/// # Example
+/// ```no_run
+/// let host = lilv::host::Host::new(44100.0, 512);
+/// let world = lilv::World::with_load_all();
+/// let uri = world.new_uri("http://lv2plug.in/plugins/eg-amp").unwrap();
+/// let plugin = world.plugins().plugin(&uri).unwrap();
+/// let _instance = unsafe { plugin.instantiate(host.sample_rate(), host.features().iter()) };
+/// ```
+pub struct Host {
+    // The feature data is boxed so it keeps its address when the host is
+    // moved.
+    urids: Box<Mutex<Urids>>,
+    map: Box<LV2UridMap>,
+    unmap: Box<LV2UridUnmap>,
+    _options: Box<[OptionsOption]>,
+    values: Box<OptionValues>,
+    features: FeatureSet,
+    sample_rate: f64,
+}
+
+unsafe impl Send for Host {}
+unsafe impl Sync for Host {}
+
+#[derive(Default)]
+struct Urids {
+    by_uri: HashMap<CString, LV2Urid>,
+    by_urid: HashMap<LV2Urid, CString>,
+    // Maps the URIs that are not mapped yet instead of the next URID, like
+    // the map of another process.
+    source: Option<Box<dyn UridSource>>,
+}
+
+// A map that `Urids` asks for URIDs it does not know, so the URIDs of a host
+// match another map.
+pub(crate) trait UridSource: Send {
+    // The URID of `uri`, or 0 if it could not be mapped.
+    fn map(&mut self, uri: &CStr) -> LV2Urid;
+    // The URI of `urid`, or `None` if it is not mapped.
+    fn unmap(&mut self, urid: LV2Urid) -> Option<CString>;
+}
+
+struct OptionValues {
+    sample_rate: f32,
+    block_length: i32,
+}
+
+// Called from C so they must not unwind. Panics are reported as the URID 0,
+// which means the URI could not be mapped, and as an unknown URID.
+
+extern "C" fn map_uri(handle: *mut c_void, uri: *const c_char) -> LV2Urid {
+    let urids = unsafe { &*handle.cast::<Mutex<Urids>>() };
+    let uri = unsafe { CStr::from_ptr(uri) };
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| urids.lock().map(uri))).unwrap_or(0)
+}
+
+extern "C" fn unmap_urid(handle: *mut c_void, urid: LV2Urid) -> *const c_char {
+    let urids = unsafe { &*handle.cast::<Mutex<Urids>>() };
+    // The strings are never removed, so the pointer outlives the lock.
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        urids
+            .lock()
+            .unmap(urid)
+            .map_or(std::ptr::null(), CStr::as_ptr)
+    }))
+    .unwrap_or(std::ptr::null())
+}
+
+impl Urids {
+    #[allow(clippy::cast_possible_truncation)]
+    fn map(&mut self, uri: &CStr) -> LV2Urid {
+        if let Some(urid) = self.by_uri.get(uri) {
+            return *urid;
+        }
+        let urid = match &mut self.source {
+            Some(source) => source.map(uri),
+            None => self.by_urid.len() as LV2Urid + 1,
+        };
+        if urid != 0 {
+            self.by_urid.insert(urid, uri.to_owned());
+            self.by_uri.insert(uri.to_owned(), urid);
+        }
+        urid
+    }
+
+    // The strings are never removed or replaced, and their bytes stay in
+    // place when the maps grow.
+    fn unmap(&mut self, urid: LV2Urid) -> Option<&CStr> {
+        if !self.by_urid.contains_key(&urid) {
+            let uri = self.source.as_mut()?.unmap(urid)?;
+            self.by_uri.insert(uri.clone(), urid);
+            self.by_urid.insert(urid, uri);
+        }
+        self.by_urid.get(&urid).map(CString::as_c_str)
+    }
+}
+
+impl Host {
+    /// Create a host that runs at `sample_rate` with blocks of
+    /// `block_length` frames.
+    ///
+    /// # Panics
+    /// Panics if `block_length` does not fit into an `i32`.
+    #[must_use]
+    pub fn new(sample_rate: f64, block_length: usize) -> Host {
+        Host::with_urids(sample_rate, block_length, Urids::default())
+    }
+
+    // A host that maps URIs with `source`, so its URIDs are the URIDs of
+    // `source`.
+    #[cfg(all(feature = "bridge", unix))]
+    pub(crate) fn with_urid_source(
+        sample_rate: f64,
+        block_length: usize,
+        source: Box<dyn UridSource>,
+    ) -> Host {
+        let urids = Urids {
+            source: Some(source),
+            ..Urids::default()
+        };
+        Host::with_urids(sample_rate, block_length, urids)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn with_urids(sample_rate: f64, block_length: usize, urids: Urids) -> Host {
+        let mut urids = Box::new(Mutex::new(urids));
+        let handle = (&mut *urids as *mut Mutex<Urids>).cast();
+        let mut map = Box::new(LV2UridMap {
+            handle,
+            map: map_uri,
+        });
+        let mut unmap = Box::new(LV2UridUnmap {
+            handle,
+            unmap: unmap_urid,
+        });
+        let values = Box::new(OptionValues {
+            sample_rate: sample_rate as f32,
+            block_length: i32::try_from(block_length).unwrap(),
+        });
+        let mut urid = |uri: &str| urids.get_mut().map(&CString::new(uri).unwrap());
+        let float = urid(ATOM_FLOAT);
+        let int = urid(ATOM_INT);
+        let option = |key, value_type, value: *const c_void| OptionsOption {
+            // LV2_OPTIONS_INSTANCE, the options apply to the instance.
+            context: 0,
+            subject: 0,
+            key,
+            size: 4,
+            value_type,
+            value,
+        };
+        let sample_rate_ptr = (&values.sample_rate as *const f32).cast();
+        let block_length_ptr = (&values.block_length as *const i32).cast();
+        let options: Box<[OptionsOption]> = vec![
+            option(urid(LV2_PARAMETERS_SAMPLE_RATE), float, sample_rate_ptr),
+            option(urid(LV2_BUF_SIZE_MIN_BLOCK_LENGTH), int, block_length_ptr),
+            option(urid(LV2_BUF_SIZE_MAX_BLOCK_LENGTH), int, block_length_ptr),
+            option(
+                urid(LV2_BUF_SIZE_NOMINAL_BLOCK_LENGTH),
+                int,
+                block_length_ptr,
+            ),
+            // The options are terminated with a zeroed option.
+            option(0, 0, std::ptr::null()),
+        ]
+        .into_boxed_slice();
+        let features = unsafe {
+            FeatureSet::new()
+                .with_feature_data(LV2_URID_MAP, (&mut *map as *mut LV2UridMap).cast())
+                .and_then(|f| {
+                    f.with_feature_data(LV2_URID_UNMAP, (&mut *unmap as *mut LV2UridUnmap).cast())
+                })
+                .and_then(|f| {
+                    f.with_feature_data(LV2_OPTIONS_OPTIONS, options.as_ptr() as *mut c_void)
+                })
+        }
+        .unwrap();
+        Host {
+            urids,
+            map,
+            unmap,
+            _options: options,
+            values,
+            features,
+            sample_rate,
+        }
+    }
+
+    /// The features of the host. They can be passed to
+    /// `Plugin::instantiate` with `features().iter()` and stay valid until
+    /// the host is dropped.
+    #[must_use]
+    pub fn features(&self) -> &FeatureSet {
+        &self.features
+    }
+
+    /// The capabilities of the host to pass to `Plugin::instantiate_with`
+    /// with `features`. The host connects audio, control, CV and atom ports
+    /// and provides `opts:options`.
+    #[must_use]
+    pub fn caps(&self) -> HostCaps {
+        HostCaps::new()
+            .with_port_kind(PortKind::Cv)
+            .with_port_kind(PortKind::Atom)
+            .with_options()
+    }
+
+    /// The URID map of the host, like for `World::load_preset`.
+    #[must_use]
+    pub fn urid_map(&self) -> &LV2UridMap {
+        &self.map
+    }
+
+    /// The URID unmap of the host, like for `State::save`.
+    #[must_use]
+    pub fn urid_unmap(&self) -> &LV2UridUnmap {
+        &self.unmap
+    }
+
+    /// The sample rate of the host.
+    #[must_use]
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// The block length of the host in frames.
+    #[must_use]
+    #[allow(clippy::cast_sign_loss)]
+    pub fn block_length(&self) -> usize {
+        self.values.block_length as usize
+    }
+
+    /// Map `uri` to a URID like the plugins of the host do.
+    ///
+    /// # Panics
+    /// Panics if `uri` contains a NUL byte.
+    #[must_use]
+    pub fn map(&self, uri: &str) -> LV2Urid {
+        self.urids.lock().map(&CString::new(uri).unwrap())
+    }
+
+    /// The URI of `urid`, or `None` if it was not mapped.
+    #[must_use]
+    pub fn unmap(&self, urid: LV2Urid) -> Option<String> {
+        self.urids
+            .lock()
+            .unmap(urid)
+            .map(|uri| uri.to_string_lossy().into_owned())
+    }
+}
+
+impl std::fmt::Debug for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Host")
+            .field("sample_rate", &self.sample_rate)
+            .field("block_length", &self.values.block_length)
+            .field("features", &self.features)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host() {
+        let host = Host::new(48000.0, 256);
+        assert!(host.features().contains(LV2_URID_MAP));
+        assert!(host.features().contains(LV2_URID_UNMAP));
+        assert!(host.features().contains(LV2_OPTIONS_OPTIONS));
+        assert_eq!(host.block_length(), 256);
+
+        let urid = host.map("urn:lilv-rs:test");
+        assert_ne!(urid, 0);
+        assert_eq!(host.map("urn:lilv-rs:test"), urid);
+        assert_eq!(host.unmap(urid).as_deref(), Some("urn:lilv-rs:test"));
+        assert_eq!(host.unmap(0), None);
+
+        // The features reach the same map as the host.
+        let host = Box::new(host);
+        let feature = host
+            .features()
+            .iter()
+            .find(|f| unsafe { CStr::from_ptr(f.uri) }.to_str() == Ok(LV2_URID_MAP))
+            .unwrap();
+        let map = unsafe { &*feature.data.cast::<LV2UridMap>() };
+        let uri = CString::new("urn:lilv-rs:test").unwrap();
+        assert_eq!((map.map)(map.handle, uri.as_ptr()), urid);
+    }
+}
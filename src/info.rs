@@ -0,0 +1,456 @@
+use crate::node::Node;
+use crate::plugin::Plugin;
+use crate::port::{Port, PortDirection, PortKind, LV2_CORE_ENUMERATION};
+use crate::world::{World, LV2_PRESETS_PRESET};
+
+const DCTERMS_CREATOR: &str = "http://purl.org/dc/terms/creator";
+const DCTERMS_MODIFIED: &str = "http://purl.org/dc/terms/modified";
+const LV2_CORE: &str = "http://lv2plug.in/ns/lv2core#";
+const LV2_CORE_DEFAULT: &str = "http://lv2plug.in/ns/lv2core#default";
+const LV2_CORE_INTEGER: &str = "http://lv2plug.in/ns/lv2core#integer";
+const LV2_CORE_MAXIMUM: &str = "http://lv2plug.in/ns/lv2core#maximum";
+const LV2_CORE_MINIMUM: &str = "http://lv2plug.in/ns/lv2core#minimum";
+const LV2_CORE_PORT_PROPERTY: &str = "http://lv2plug.in/ns/lv2core#portProperty";
+const LV2_CORE_TOGGLED: &str = "http://lv2plug.in/ns/lv2core#toggled";
+const LV2_PATCH_WRITABLE: &str = "http://lv2plug.in/ns/ext/patch#writable";
+const LV2_PORT_GROUPS_GROUP: &str = "http://lv2plug.in/ns/ext/port-groups#group";
+const LV2_PORT_PROPS_LOGARITHMIC: &str = "http://lv2plug.in/ns/ext/port-props#logarithmic";
+const LV2_PRESET_BANK: &str = "http://lv2plug.in/ns/ext/presets#bank";
+pub(crate) const RDFS_COMMENT: &str = "http://www.w3.org/2000/01/rdf-schema#comment";
+const LV2_UNITS_UNIT: &str = "http://lv2plug.in/ns/extensions/units#unit";
+const LV2_UNITS_SYMBOL: &str = "http://lv2plug.in/ns/extensions/units#symbol";
+const RDFS_LABEL: &str = "http://www.w3.org/2000/01/rdf-schema#label";
+const RDFS_RANGE: &str = "http://www.w3.org/2000/01/rdf-schema#range";
+
+/// An owned snapshot of the metadata of a plugin.
+///
+/// Unlike `Plugin`, the snapshot does not keep the world alive and reading it
+/// does not call into lilv. This makes it suitable for GUIs that need to keep
+/// plugin data around or read it from another thread.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PluginInfo {
+    /// The URI of the plugin.
+    pub uri: String,
+    /// The human readable name of the plugin.
+    pub name: String,
+    /// The URI of the plugin class, like `lv2:ReverbPlugin`.
+    pub class_uri: Option<String>,
+    /// The label of the plugin class, like "Reverb".
+    pub class_label: Option<String>,
+    /// The name of the author.
+    pub author_name: Option<String>,
+    /// The ports of the plugin, ordered by index.
+    pub ports: Vec<PortInfo>,
+    /// The presets that are known for the plugin.
+    pub presets: Vec<PresetInfo>,
+}
+
+/// An owned snapshot of the metadata of a port.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortInfo {
+    /// The index of the port.
+    pub index: usize,
+    /// The symbol of the port.
+    pub symbol: String,
+    /// The human readable name of the port.
+    pub name: String,
+    /// The URIs of the classes of the port, like `lv2:InputPort`.
+    pub classes: Vec<String>,
+    /// The URIs of the properties of the port, like `lv2:toggled`.
+    pub properties: Vec<String>,
+    /// The default value of the port.
+    pub default: Option<f32>,
+    /// The minimum value of the port.
+    pub minimum: Option<f32>,
+    /// The maximum value of the port.
+    pub maximum: Option<f32>,
+    /// The URI of the unit of the port, like `units:db`.
+    pub unit_uri: Option<String>,
+    /// The symbol of the unit of the port, like "dB".
+    pub unit_symbol: Option<String>,
+    /// The scale points of the port as `(value, label)` ordered by value.
+    pub scale_points: Vec<(f32, String)>,
+}
+
+/// An owned snapshot of a preset.
+///
+/// The metadata is read from the data in the world, without creating the
+/// state of the preset, so it is fast enough for browsers with thousands of
+/// presets. Metadata that is only in the file of the preset is missing until
+/// the preset is loaded with `World::load_resource`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresetInfo {
+    /// The URI of the preset.
+    pub uri: String,
+    /// The label of the preset if it is known without loading the preset.
+    pub label: Option<String>,
+    /// The author of the preset (`dcterms:creator`).
+    pub author: Option<String>,
+    /// The label of the bank of the preset (`pset:bank`), or its URI if it
+    /// has no label.
+    pub bank: Option<String>,
+    /// The description of the preset (`rdfs:comment`).
+    pub comment: Option<String>,
+    /// When the preset was last modified (`dcterms:modified`), usually an
+    /// ISO 8601 date and time.
+    pub modified: Option<String>,
+}
+
+/// A control that a generic plugin UI shows, which is an input control
+/// port or a writable patch parameter. See `Plugin::control_model`.
+#[allow(clippy::module_name_repetitions)]
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlInfo {
+    /// The index of the port, or `None` for a parameter.
+    pub port_index: Option<usize>,
+    /// The symbol of the port, or the URI of the parameter.
+    pub key: String,
+    /// The human readable name of the control.
+    pub label: String,
+    /// The URI of the type of the parameter (`rdfs:range`), like
+    /// `atom:Path`. `None` for ports, which are always `f32`.
+    pub value_type: Option<String>,
+    /// The default value.
+    pub default: Option<f32>,
+    /// The minimum value.
+    pub minimum: Option<f32>,
+    /// The maximum value.
+    pub maximum: Option<f32>,
+    /// The URI of the unit, like `units:db`.
+    pub unit_uri: Option<String>,
+    /// The symbol of the unit, like "dB".
+    pub unit_symbol: Option<String>,
+    /// The scale points as `(value, label)` ordered by value.
+    pub scale_points: Vec<(f32, String)>,
+    /// The control should be shown on a logarithmic scale.
+    pub logarithmic: bool,
+    /// The value is an integer.
+    pub integer: bool,
+    /// The value is on or off.
+    pub toggled: bool,
+    /// The value is always one of the scale points.
+    pub enumeration: bool,
+    /// The label of the group of the control (`pg:group`), or its URI if it
+    /// has no label.
+    pub group: Option<String>,
+}
+
+impl PluginInfo {
+    /// Capture all the metadata of `plugin` that a GUI typically needs.
+    ///
+    /// # Panics
+    /// May panic if `plugin.verify()` returns false.
+    #[must_use]
+    pub fn capture(plugin: &Plugin) -> PluginInfo {
+        let world = World {
+            life: plugin.life.clone(),
+        };
+        let class = plugin.class();
+        PluginInfo {
+            uri: uri_string(&plugin.uri()),
+            name: lossy_string(&plugin.name()),
+            class_uri: class.uri().as_ref().map(uri_string),
+            class_label: Some(lossy_string(&class.label())),
+            author_name: plugin.author_name().as_ref().map(lossy_string),
+            ports: plugin
+                .iter_ports()
+                .map(|port| PortInfo::capture(&world, &port))
+                .collect(),
+            presets: PresetInfo::capture_all(&world, plugin),
+        }
+    }
+
+    /// Get the port with the given symbol.
+    #[must_use]
+    pub fn port_by_symbol(&self, symbol: &str) -> Option<&PortInfo> {
+        self.ports.iter().find(|port| port.symbol == symbol)
+    }
+}
+
+impl PortInfo {
+    fn capture(world: &World, port: &Port) -> PortInfo {
+        let range = port.range();
+        let unit: Option<Node> = port.get(&world.new_static_uri(LV2_UNITS_UNIT));
+        let unit_symbol = unit.as_ref().and_then(|unit| {
+            let predicate = world.new_static_uri(LV2_UNITS_SYMBOL);
+            world.get(Some(unit), Some(&predicate), None)
+        });
+        PortInfo {
+            index: port.index(),
+            symbol: port.symbol().as_ref().map(lossy_string).unwrap_or_default(),
+            name: port.name().as_ref().map(lossy_string).unwrap_or_default(),
+            classes: port.classes().iter().map(|n| uri_string(&n)).collect(),
+            properties: port.properties().iter().map(|n| uri_string(&n)).collect(),
+            default: range.default.as_ref().and_then(node_to_f32),
+            minimum: range.minimum.as_ref().and_then(node_to_f32),
+            maximum: range.maximum.as_ref().and_then(node_to_f32),
+            unit_uri: unit.as_ref().and_then(Node::as_uri).map(str::to_string),
+            unit_symbol: unit_symbol.as_ref().map(lossy_string),
+            scale_points: port
+                .scale_point_map()
+                .into_iter()
+                .map(|(value, label)| (value.0, label))
+                .collect(),
+        }
+    }
+
+    /// Returns `true` if the port has the given class. `class` may be a full
+    /// URI or a name in the LV2 core namespace, like `"InputPort"`.
+    #[must_use]
+    pub fn is_a(&self, class: &str) -> bool {
+        self.classes.iter().any(|c| matches_core_uri(c, class))
+    }
+
+    /// Returns `true` if the port has the given property. `property` may be a
+    /// full URI or a name in the LV2 core namespace, like `"toggled"`.
+    #[must_use]
+    pub fn has_property(&self, property: &str) -> bool {
+        self.properties
+            .iter()
+            .any(|p| matches_core_uri(p, property))
+    }
+}
+
+impl PresetInfo {
+    /// Capture the metadata of the preset `preset`.
+    #[must_use]
+    pub fn capture(world: &World, preset: &Node) -> PresetInfo {
+        let value = |predicate: &'static str| {
+            let predicate = world.new_static_uri(predicate);
+            world.get(Some(preset), Some(&predicate), None)
+        };
+        let label = |node: &Node| {
+            let predicate = world.new_static_uri(RDFS_LABEL);
+            world.get(Some(node), Some(&predicate), None)
+        };
+        PresetInfo {
+            uri: uri_string(preset),
+            label: label(preset).as_ref().map(lossy_string),
+            author: value(DCTERMS_CREATOR).as_ref().map(lossy_string),
+            bank: value(LV2_PRESET_BANK).map(|bank| label_or_uri(world, &bank)),
+            comment: value(RDFS_COMMENT).as_ref().map(lossy_string),
+            modified: value(DCTERMS_MODIFIED).as_ref().map(lossy_string),
+        }
+    }
+
+    fn capture_all(world: &World, plugin: &Plugin) -> Vec<PresetInfo> {
+        let preset_class = world.new_static_uri(LV2_PRESETS_PRESET);
+        plugin
+            .related(Some(&preset_class))
+            .map(|presets| {
+                presets
+                    .iter()
+                    .map(|preset| PresetInfo::capture(world, &preset))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl ControlInfo {
+    pub(crate) fn capture_all(plugin: &Plugin) -> Vec<ControlInfo> {
+        let world = World {
+            life: plugin.life.clone(),
+        };
+        let mut controls: Vec<ControlInfo> = plugin
+            .iter_ports()
+            .filter(|port| {
+                port.kind() == PortKind::Control && port.direction() == Some(PortDirection::Input)
+            })
+            .map(|port| ControlInfo::from_port(&world, &port))
+            .collect();
+        let parameters = plugin.value(&world.new_static_uri(LV2_PATCH_WRITABLE));
+        controls.extend(
+            parameters
+                .iter()
+                .map(|parameter| ControlInfo::from_parameter(&world, &parameter)),
+        );
+        controls
+    }
+
+    fn from_port(world: &World, port: &Port) -> ControlInfo {
+        let group: Option<Node> = port.get(&world.new_static_uri(LV2_PORT_GROUPS_GROUP));
+        let port = PortInfo::capture(world, port);
+        ControlInfo {
+            port_index: Some(port.index),
+            logarithmic: port.has_property(LV2_PORT_PROPS_LOGARITHMIC),
+            integer: port.has_property(LV2_CORE_INTEGER),
+            toggled: port.has_property(LV2_CORE_TOGGLED),
+            enumeration: port.has_property(LV2_CORE_ENUMERATION),
+            group: group.map(|group| label_or_uri(world, &group)),
+            key: port.symbol,
+            label: port.name,
+            value_type: None,
+            default: port.default,
+            minimum: port.minimum,
+            maximum: port.maximum,
+            unit_uri: port.unit_uri,
+            unit_symbol: port.unit_symbol,
+            scale_points: port.scale_points,
+        }
+    }
+
+    fn from_parameter(world: &World, parameter: &Node) -> ControlInfo {
+        let value = |predicate: &'static str| {
+            let predicate = world.new_static_uri(predicate);
+            world.get(Some(parameter), Some(&predicate), None)
+        };
+        let has_property = |property: &'static str| {
+            let predicate = world.new_static_uri(LV2_CORE_PORT_PROPERTY);
+            let property = world.new_static_uri(property);
+            world.ask(Some(parameter), Some(&predicate), Some(&property))
+        };
+        let unit = value(LV2_UNITS_UNIT);
+        let unit_symbol = unit.as_ref().and_then(|unit| {
+            let predicate = world.new_static_uri(LV2_UNITS_SYMBOL);
+            world.get(Some(unit), Some(&predicate), None)
+        });
+        ControlInfo {
+            port_index: None,
+            key: uri_string(parameter),
+            label: label_or_uri(world, parameter),
+            value_type: value(RDFS_RANGE).as_ref().map(uri_string),
+            default: value(LV2_CORE_DEFAULT).as_ref().and_then(node_to_f32),
+            minimum: value(LV2_CORE_MINIMUM).as_ref().and_then(node_to_f32),
+            maximum: value(LV2_CORE_MAXIMUM).as_ref().and_then(node_to_f32),
+            unit_uri: unit.as_ref().and_then(Node::as_uri).map(str::to_string),
+            unit_symbol: unit_symbol.as_ref().map(lossy_string),
+            scale_points: Vec::new(),
+            logarithmic: has_property(LV2_PORT_PROPS_LOGARITHMIC),
+            integer: has_property(LV2_CORE_INTEGER),
+            toggled: has_property(LV2_CORE_TOGGLED),
+            enumeration: has_property(LV2_CORE_ENUMERATION),
+            group: value(LV2_PORT_GROUPS_GROUP).map(|group| label_or_uri(world, &group)),
+        }
+    }
+}
+
+fn matches_core_uri(uri: &str, name: &str) -> bool {
+    uri == name || uri.strip_prefix(LV2_CORE) == Some(name)
+}
+
+// The `rdfs:label` of `node`, or its URI if it has no label.
+fn label_or_uri(world: &World, node: &Node) -> String {
+    let predicate = world.new_static_uri(RDFS_LABEL);
+    world
+        .get(Some(node), Some(&predicate), None)
+        .as_ref()
+        .map_or_else(|| uri_string(node), lossy_string)
+}
+
+fn uri_string(node: &Node) -> String {
+    node.as_uri()
+        .map_or_else(|| node.turtle_token(), str::to_string)
+}
+
+fn lossy_string(node: &Node) -> String {
+    node.as_str()
+        .map_or_else(|| node.turtle_token(), str::to_string)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn node_to_f32(node: &Node) -> Option<f32> {
+    node.as_float().or_else(|| node.as_int().map(|v| v as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync_static<T: Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_capture() {
+        assert_send_sync_static::<PluginInfo>();
+
+        let info = {
+            let world = crate::testing::fixture_world().unwrap();
+            let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+            let plugin = world.plugins().plugin(&uri).unwrap();
+            PluginInfo::capture(&plugin)
+        };
+        assert_eq!(info.uri, crate::testing::GAIN_URI);
+        assert_eq!(info.name, "Fixture Gain");
+        assert_eq!(info.ports.len(), 3);
+        assert_eq!(
+            info.presets,
+            vec![PresetInfo {
+                uri: format!("{}#loud", crate::testing::GAIN_URI),
+                label: Some("Loud".to_string()),
+                author: None,
+                bank: None,
+                comment: None,
+                modified: None,
+            }]
+        );
+
+        let gain = info.port_by_symbol("gain").unwrap();
+        assert_eq!(gain.index, 0);
+        assert!(gain.is_a("InputPort"));
+        assert!(gain.is_a("http://lv2plug.in/ns/lv2core#ControlPort"));
+        assert_eq!(gain.default, Some(0.0));
+        assert_eq!(gain.minimum, Some(-90.0));
+        assert_eq!(gain.maximum, Some(24.0));
+        assert_eq!(
+            gain.unit_uri.as_deref(),
+            Some("http://lv2plug.in/ns/extensions/units#db")
+        );
+    }
+
+    #[test]
+    fn test_control_model() {
+        let world = crate::testing::fixture_world().unwrap();
+        let gain = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let controls = world.plugins().plugin(&gain).unwrap().control_model();
+        assert_eq!(controls.len(), 1);
+        let gain = &controls[0];
+        assert_eq!((gain.port_index, gain.key.as_str()), (Some(0), "gain"));
+        assert_eq!(gain.label, "Gain");
+        assert_eq!((gain.minimum, gain.maximum), (Some(-90.0), Some(24.0)));
+        assert_eq!(gain.group.as_deref(), Some("Level"));
+        assert!(!gain.logarithmic && !gain.toggled);
+
+        let sampler = world.new_uri(crate::testing::SAMPLER_URI).unwrap();
+        let controls = world.plugins().plugin(&sampler).unwrap().control_model();
+        assert_eq!(
+            controls,
+            vec![ControlInfo {
+                port_index: None,
+                key: format!("{}#sample", crate::testing::SAMPLER_URI),
+                label: "Sample".to_string(),
+                value_type: Some("http://lv2plug.in/ns/ext/atom#Path".to_string()),
+                default: None,
+                minimum: None,
+                maximum: None,
+                unit_uri: None,
+                unit_symbol: None,
+                scale_points: Vec::new(),
+                logarithmic: false,
+                integer: false,
+                toggled: false,
+                enumeration: false,
+                group: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_preset_metadata() {
+        let world = crate::testing::fixture_world().unwrap();
+        let preset = world
+            .new_uri(&format!("{}#loud", crate::testing::GAIN_URI))
+            .unwrap();
+        assert_eq!(PresetInfo::capture(&world, &preset).comment, None);
+        world.load_resource(&preset).unwrap();
+        let info = PresetInfo::capture(&world, &preset);
+        assert_eq!(info.label.as_deref(), Some("Loud"));
+        assert_eq!(info.author.as_deref(), Some("lilv-rs"));
+        assert_eq!(info.bank.as_deref(), Some("Factory"));
+        assert_eq!(info.comment.as_deref(), Some("Boosts the input by 6 dB."));
+        assert_eq!(info.modified.as_deref(), Some("2024-01-01T00:00:00Z"));
+    }
+}
@@ -1,14 +1,51 @@
+use crate::automation::ParamReceiver;
+use crate::feature::FeatureSet;
+use crate::port::PortKind;
+use crate::state::{State, StateFlags};
 use lilv_sys as lib;
 use lv2_raw::core::LV2Descriptor;
 use lv2_raw::core::LV2Handle;
+use lv2_raw::LV2UridMap;
+use parking_lot::Mutex;
+use std::cell::UnsafeCell;
 use std::convert::TryFrom;
 use std::ffi::CStr;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread::Thread;
+use std::time::Duration;
 
 /// An LV2 plugin instance.
+///
+/// The instance tracks which ports are connected, so running an
+/// `ActiveInstance` with a port that must be connected but is not, or that
+/// was marked with `poison_port`, panics in debug builds. Ports that are
+/// `lv2:connectionOptional` are not checked.
+///
+/// The instance also tracks whether the plugin is activated, so the plugin is
+/// never activated or deactivated twice in a row, even if the instances of
+/// two `ActiveInstance`s are swapped through `instance_mut`. An instance that
+/// is dropped while active is deactivated first.
 #[allow(clippy::module_name_repetitions)]
 pub struct Instance {
     pub(crate) inner: NonNull<lib::LilvInstanceImpl>,
+    // For each port, `true` if it must be connected before `run`.
+    required: Vec<bool>,
+    connections: Vec<Connection>,
+    // For each port, the kind of the port if it is an output of `f32`
+    // values, and the buffer it is connected to.
+    outputs: Vec<Option<PortKind>>,
+    buffers: Vec<*mut f32>,
+    active: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Connection {
+    None,
+    Connected,
+    // Connected to a buffer that is no longer valid.
+    Poisoned,
 }
 
 /// An LV2 plugin instance that has been activated and is ready to process data.
@@ -19,7 +56,97 @@ pub struct ActiveInstance {
 
 unsafe impl Send for Instance {}
 
+/// The reason a plugin could not be instantiated.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstantiateError {
+    /// The plugin requires features that were not provided. Contains the URIs
+    /// of the missing features.
+    MissingFeatures(Vec<String>),
+    /// The plugin has ports that must be connected but that the host can not
+    /// connect. Contains the symbols of the ports and their types.
+    UnsupportedPorts(Vec<String>),
+    /// The plugin requires features that were provided but left out because
+    /// they contradict the `HostCaps` of the host. Contains the URIs of the
+    /// features and why they were left out.
+    PrunedFeatures(Vec<String>),
+    /// Lilv or the plugin failed to create the instance.
+    Failed,
+}
+
+impl std::fmt::Display for InstantiateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstantiateError::MissingFeatures(uris) => {
+                write!(f, "missing required features: {}", uris.join(", "))
+            }
+            InstantiateError::UnsupportedPorts(ports) => {
+                write!(f, "unsupported ports: {}", ports.join(", "))
+            }
+            InstantiateError::PrunedFeatures(uris) => {
+                write!(f, "required features left out: {}", uris.join(", "))
+            }
+            InstantiateError::Failed => write!(f, "failed to instantiate plugin"),
+        }
+    }
+}
+
+impl std::error::Error for InstantiateError {}
+
+/// An output value that is NaN or infinite, found by
+/// `ActiveInstance::run_checked`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NonFiniteOutput {
+    /// The URI of the plugin.
+    pub plugin: Option<String>,
+    /// The index of the output port.
+    pub port_index: usize,
+    /// The frame of the value, which is 0 for control ports.
+    pub frame: usize,
+    /// The value.
+    pub value: f32,
+}
+
+impl std::fmt::Display for NonFiniteOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} wrote {} to port {} at frame {}",
+            self.plugin.as_deref().unwrap_or("plugin"),
+            self.value,
+            self.port_index,
+            self.frame
+        )
+    }
+}
+
+impl std::error::Error for NonFiniteOutput {}
+
 impl Instance {
+    pub(crate) fn new(
+        inner: NonNull<lib::LilvInstanceImpl>,
+        required: Vec<bool>,
+        outputs: Vec<Option<PortKind>>,
+    ) -> Instance {
+        let connections = vec![Connection::None; required.len()];
+        let buffers = vec![std::ptr::null_mut(); outputs.len()];
+        Instance {
+            inner,
+            required,
+            connections,
+            outputs,
+            buffers,
+            active: false,
+        }
+    }
+
+    /// Returns `true` if the plugin is activated, like the instance of an
+    /// `ActiveInstance`.
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
     /// Returns the URI of the plugin for the instance.
     /// This is a globally unique string for the plugin.
     #[must_use]
@@ -61,12 +188,24 @@ impl Instance {
     /// data when run() is called. Data present at the time of the
     /// `connect_port()` call MUST NOT be considered meaningful.
     ///
+    /// Connecting `data` to NULL marks the port as not connected.
+    ///
     /// # Safety
     /// Connecting a port calls a plugin's code, which itself may be unsafe.
     pub unsafe fn connect_port_mut<T>(&mut self, port_index: usize, data: *mut T) {
         match u32::try_from(port_index) {
-            Ok(port_index) => {
-                lib::lilv_instance_connect_port(self.inner.as_ptr(), port_index, data.cast())
+            Ok(index) => {
+                unsafe { lib::lilv_instance_connect_port(self.inner.as_ptr(), index, data.cast()) };
+                if let Some(connection) = self.connections.get_mut(port_index) {
+                    *connection = if data.is_null() {
+                        Connection::None
+                    } else {
+                        Connection::Connected
+                    };
+                }
+                if let Some(buffer) = self.buffers.get_mut(port_index) {
+                    *buffer = data.cast();
+                }
             }
             Err(e) => debug_assert!(false, "port_index is too large: {}", e),
         }
@@ -84,19 +223,106 @@ impl Instance {
     /// # Safety
     /// Connecting a port calls a plugin's code, which itself may be unsafe.
     pub unsafe fn connect_port<T>(&mut self, port_index: usize, data: *const T) {
-        self.connect_port_mut(port_index, data as *mut T);
+        unsafe { self.connect_port_mut(port_index, data as *mut T) };
+    }
+
+    /// Mark a port as connected to a buffer that is no longer valid, like a
+    /// buffer that was freed while the port was still connected to it.
+    ///
+    /// Running the instance panics in debug builds until the port is
+    /// connected again, instead of letting the plugin access freed memory.
+    pub fn poison_port(&mut self, port_index: usize) {
+        if let Some(connection) = self.connections.get_mut(port_index) {
+            *connection = Connection::Poisoned;
+        }
+    }
+
+    /// The indices of the ports that must be connected before `run` but are
+    /// not connected, or are poisoned.
+    #[must_use]
+    pub fn unconnected_ports(&self) -> Vec<usize> {
+        self.unconnected().collect()
+    }
+
+    /// Check that every port that must be connected before `run` is
+    /// connected, like `Plugin::check_features` does for features.
+    ///
+    /// # Errors
+    /// Returns the indices of the ports that are not connected or are
+    /// poisoned, see `unconnected_ports`.
+    pub fn verify_complete(&self) -> Result<(), Vec<usize>> {
+        let unconnected = self.unconnected_ports();
+        if unconnected.is_empty() {
+            Ok(())
+        } else {
+            Err(unconnected)
+        }
+    }
+
+    /// Fill the buffers of the connected audio, control and CV outputs with
+    /// zeros, so a plugin that does not write all of its outputs produces
+    /// silence instead of stale data.
+    ///
+    /// # Safety
+    /// The buffers of the audio and CV outputs must be valid for
+    /// `sample_count` frames.
+    pub unsafe fn clear_outputs(&mut self, sample_count: usize) {
+        for (_, buffer, len) in self.output_buffers(sample_count) {
+            unsafe { std::ptr::write_bytes(buffer, 0, len) };
+        }
+    }
+
+    // The index, buffer and number of values of the connected outputs of
+    // `f32` values.
+    fn output_buffers(
+        &self,
+        sample_count: usize,
+    ) -> impl '_ + Iterator<Item = (usize, *mut f32, usize)> {
+        let ports = self
+            .outputs
+            .iter()
+            .zip(&self.buffers)
+            .zip(&self.connections);
+        ports
+            .enumerate()
+            .filter_map(move |(index, ((kind, buffer), connection))| {
+                let len = match kind {
+                    Some(PortKind::Control) => 1,
+                    Some(_) => sample_count,
+                    None => return None,
+                };
+                (*connection == Connection::Connected).then_some((index, *buffer, len))
+            })
+    }
+
+    fn unconnected(&self) -> impl '_ + Iterator<Item = usize> {
+        self.required
+            .iter()
+            .zip(&self.connections)
+            .enumerate()
+            .filter(|(_, (required, connection))| {
+                **required && **connection != Connection::Connected
+            })
+            .map(|(index, _)| index)
     }
 
     /// Activate a plugin instance.
     ///
     /// This resets all state information in the plugin except for port
-    /// connections.
+    /// connections. The plugin is not activated again if it is already
+    /// active, see `is_active`.
     ///
     /// # Safety
     /// Calling external code may be unsafe.
     #[must_use]
-    pub unsafe fn activate(self) -> ActiveInstance {
-        lib::lilv_instance_activate(self.inner.as_ptr());
+    pub unsafe fn activate(mut self) -> ActiveInstance {
+        if self.active {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(uri = ?self.uri(), "instance is already active");
+        } else {
+            unsafe { lib::lilv_instance_activate(self.inner.as_ptr()) };
+            self.active = true;
+        }
         ActiveInstance { inner: self }
     }
 
@@ -111,9 +337,9 @@ impl Instance {
     #[must_use]
     pub unsafe fn extension_data<T>(&self, uri: &str) -> Option<NonNull<T>> {
         let uri = std::ffi::CString::new(uri).ok()?;
-        NonNull::new(
-            lib::lilv_instance_get_extension_data(self.inner.as_ptr(), uri.as_ptr().cast()) as _,
-        )
+        NonNull::new(unsafe {
+            lib::lilv_instance_get_extension_data(self.inner.as_ptr(), uri.as_ptr().cast()) as _
+        })
     }
 
     /// Get the raw descriptor for the plugin.
@@ -128,10 +354,42 @@ impl Instance {
     pub fn handle(&self) -> LV2Handle {
         unsafe { lib::lilv_instance_get_handle(self.inner.as_ptr()) }
     }
+
+    fn deactivate_impl(&mut self) {
+        if !self.active {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(uri = ?self.uri(), "instance is not active");
+            return;
+        }
+        unsafe {
+            let inner = self.inner.as_ref();
+            if let Some(deactivate_fn) = (*inner.lv2_descriptor).deactivate {
+                deactivate_fn(inner.lv2_handle);
+            }
+        }
+        self.active = false;
+    }
+}
+
+impl std::fmt::Debug for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Instance")
+            .field("uri", &self.uri())
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for ActiveInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ActiveInstance")
+            .field("uri", &self.instance().uri())
+            .finish()
+    }
 }
 
 impl Drop for Instance {
     fn drop(&mut self) {
+        self.deactivate_impl();
         unsafe { lib::lilv_instance_free(self.inner.as_ptr().cast()) };
     }
 }
@@ -139,33 +397,92 @@ impl Drop for Instance {
 impl ActiveInstance {
     /// Run the plugin instance for `sample_count` frames.
     ///
+    /// # Panics
+    /// Panics in debug builds if a port that must be connected is not
+    /// connected or is poisoned, see `Instance::unconnected_ports`.
+    ///
     /// # Safety
     /// Calling external code may be unsafe.
     #[allow(clippy::cast_possible_truncation)]
     pub unsafe fn run(&mut self, sample_count: usize) {
-        let sample_count = match u32::try_from(sample_count) {
-            Ok(sample_count) => sample_count,
-            Err(_) => u32::MAX,
-        };
-        lib::lilv_instance_run(self.instance().inner.as_ptr(), sample_count);
+        #[cfg(debug_assertions)]
+        if let Some(port) = self.inner.unconnected().next() {
+            panic!(
+                "port {} of {:?} is not connected to a valid buffer",
+                port,
+                self.inner.uri()
+            );
+        }
+        let sample_count = u32::try_from(sample_count).unwrap_or(u32::MAX);
+        unsafe { lib::lilv_instance_run(self.instance().inner.as_ptr(), sample_count) };
+    }
+
+    /// Apply the changes queued in `params` to `controls` and run the
+    /// instance for `sample_count` frames.
+    ///
+    /// `controls` are the buffers the control ports are connected to, indexed
+    /// by port index. See `ParamReceiver::apply`.
+    ///
+    /// # Safety
+    /// Same as `run`.
+    pub unsafe fn run_with_params(
+        &mut self,
+        sample_count: usize,
+        params: &mut ParamReceiver,
+        controls: &mut [f32],
+    ) {
+        params.apply(controls);
+        unsafe { self.run(sample_count) };
+    }
+
+    /// Run the plugin instance for `sample_count` frames and check that the
+    /// audio, control and CV outputs are finite, for debugging plugins that
+    /// misbehave.
+    ///
+    /// # Errors
+    /// Returns the first output value that is NaN or infinite.
+    ///
+    /// # Safety
+    /// Calling external code may be unsafe. The buffers of the audio and CV
+    /// outputs must be valid for `sample_count` frames.
+    pub unsafe fn run_checked(&mut self, sample_count: usize) -> Result<(), NonFiniteOutput> {
+        unsafe { self.run(sample_count) };
+        for (port_index, buffer, len) in self.inner.output_buffers(sample_count) {
+            let values = unsafe { std::slice::from_raw_parts(buffer, len) };
+            if let Some((frame, value)) = values.iter().enumerate().find(|(_, v)| !v.is_finite()) {
+                return Err(NonFiniteOutput {
+                    plugin: self.inner.uri().map(str::to_string),
+                    port_index,
+                    frame,
+                    value: *value,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the plugin instance for `sample_count` frames with denormal numbers
+    /// flushed to zero. See `DenormalGuard`.
+    ///
+    /// # Safety
+    /// Calling external code may be unsafe.
+    pub unsafe fn run_ftz(&mut self, sample_count: usize) {
+        let _guard = DenormalGuard::new();
+        unsafe { self.run(sample_count) };
     }
 
     /// Deactivate the plugin instance.
     ///
     /// Note: This will reset all state information except for port connections.
+    /// The plugin is not deactivated again if it is not active, see
+    /// `Instance::is_active`.
     ///
     /// # Safety
     /// Calling external code may be unsafe.
     #[must_use]
-    pub unsafe fn deactivate(self) -> Option<Instance> {
-        let mut active_instance = self;
-        let instance = active_instance
-            .deactive_impl()
-            .map(|i| Instance { inner: i })?;
-        // Prevent running deactivate twice since we manually called the drop
-        // side-effects with `deactivate_impl`..
-        std::mem::forget(active_instance);
-        Some(instance)
+    pub unsafe fn deactivate(mut self) -> Instance {
+        self.inner.deactivate_impl();
+        self.inner
     }
 
     /// Get the underlying instance.
@@ -180,39 +497,383 @@ impl ActiveInstance {
     pub fn instance_mut(&mut self) -> &mut Instance {
         &mut self.inner
     }
+}
 
-    fn deactive_impl(&mut self) -> Option<NonNull<lib::LilvInstanceImpl>> {
-        let deactivate_fn = unsafe { (*self.inner.inner.as_ref().lv2_descriptor).deactivate }?;
-        unsafe { deactivate_fn(self.inner.inner.as_ref().lv2_handle) };
-        Some(self.inner.inner)
+/// Flushes denormal numbers to zero on the current thread until dropped.
+///
+/// Denormal numbers are very slow to process on most CPUs and many plugins
+/// produce them when their output decays towards silence. On x86 this sets the
+/// FTZ and DAZ flags and restores the previous flags when dropped. On other
+/// architectures this does nothing.
+#[derive(Debug)]
+pub struct DenormalGuard {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    previous: u32,
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod mxcsr {
+    // Flush To Zero and Denormals Are Zero.
+    pub const FTZ_DAZ: u32 = 0x8040;
+
+    pub fn get() -> u32 {
+        let mut csr = 0_u32;
+        unsafe {
+            std::arch::asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack, preserves_flags));
+        }
+        csr
+    }
+
+    pub fn set(csr: u32) {
+        unsafe {
+            std::arch::asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack, readonly, preserves_flags));
+        }
     }
 }
 
-impl Drop for ActiveInstance {
+impl DenormalGuard {
+    /// Start flushing denormal numbers to zero.
+    #[must_use]
+    pub fn new() -> DenormalGuard {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            let previous = mxcsr::get();
+            mxcsr::set(previous | mxcsr::FTZ_DAZ);
+            DenormalGuard { previous }
+        }
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        DenormalGuard {}
+    }
+}
+
+impl Default for DenormalGuard {
+    fn default() -> DenormalGuard {
+        DenormalGuard::new()
+    }
+}
+
+impl Drop for DenormalGuard {
     fn drop(&mut self) {
-        self.deactive_impl();
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        mxcsr::set(self.previous);
+    }
+}
+
+/// An instance that the audio thread runs while other threads restore
+/// states to it, like presets.
+///
+/// Restoring a state calls into lilv and the plugin, which allocate and may
+/// touch files, and a plugin must not be restored while it runs. The audio
+/// thread runs the instance with `try_run`, which never waits, and
+/// `apply_state_rt_safe` restores a state on the thread that calls it. The
+/// two coordinate through a lock-free flag: the audio thread fades the
+/// output out over `fade_frames`, hands the instance over while the state is
+/// restored, skips the blocks until it is done and fades the output back
+/// in. Clones share the instance.
+#[derive(Clone)]
+pub struct SharedInstance {
+    shared: Arc<Shared>,
+}
+
+// The phases of `Shared::phase`. The restoring thread sets `FADE_OUT`, the
+// audio thread sets `SILENT` once the output is faded out, and the restoring
+// thread sets `FADE_IN` when the state is restored.
+const IDLE: u8 = 0;
+const FADE_OUT: u8 = 1;
+const SILENT: u8 = 2;
+const FADE_IN: u8 = 3;
+
+// How long a restoring thread parks before it checks the phase again, in case
+// the audio thread could not wake it.
+const WAKE_INTERVAL: Duration = Duration::from_millis(5);
+
+struct Shared {
+    phase: AtomicU8,
+    // The gain of the output as `f32` bits, which only the audio thread uses.
+    gain: AtomicU32,
+    fade_frames: usize,
+    // Only one state is restored at a time.
+    restoring: Mutex<()>,
+    // The thread that waits for the audio thread to hand the instance over.
+    waiter: Mutex<Option<Thread>>,
+    instance: UnsafeCell<ActiveInstance>,
+}
+
+// The instance is only used by the audio thread outside of `SILENT`, and by
+// the restoring thread in it.
+unsafe impl Sync for Shared {}
+
+/// The gain ramp that `SharedInstance::try_run` asks the host to apply to
+/// the outputs of a block, from `from` at the first frame to `to` after the
+/// last one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fade {
+    /// The gain at the start of the block.
+    pub from: f32,
+    /// The gain at the end of the block.
+    pub to: f32,
+}
+
+impl Fade {
+    /// Returns `true` if the fade does not change the output.
+    #[must_use]
+    pub fn is_unity(&self) -> bool {
+        self.from == 1.0 && self.to == 1.0
+    }
+
+    /// Apply the fade to the samples of a block.
+    pub fn apply(&self, samples: &mut [f32]) {
+        if self.is_unity() {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let step = (self.to - self.from) / samples.len().max(1) as f32;
+        for (frame, sample) in samples.iter_mut().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let gain = self.from + step * frame as f32;
+            *sample *= gain;
+        }
+    }
+}
+
+impl SharedInstance {
+    /// Share `instance`, fading its output out and in over `fade_frames`
+    /// when a state is restored. With 0 frames the output is cut.
+    #[must_use]
+    pub fn new(instance: ActiveInstance, fade_frames: usize) -> SharedInstance {
+        SharedInstance {
+            shared: Arc::new(Shared {
+                phase: AtomicU8::new(IDLE),
+                gain: AtomicU32::new(1.0_f32.to_bits()),
+                fade_frames,
+                restoring: Mutex::new(()),
+                waiter: Mutex::new(None),
+                instance: UnsafeCell::new(instance),
+            }),
+        }
+    }
+
+    /// Call `run` with the instance to process a block of `sample_count`
+    /// frames, unless a state is being restored. Returns the result of `run`
+    /// and the fade the host must apply to the outputs of the block, or
+    /// `None` if `run` was not called and the host should clear the outputs.
+    /// This does not wait or allocate, so it is meant for the audio thread,
+    /// which must keep calling it for `apply_state_rt_safe` to return.
+    pub fn try_run<R>(
+        &self,
+        sample_count: usize,
+        run: impl FnOnce(&mut ActiveInstance) -> R,
+    ) -> Option<(R, Fade)> {
+        let shared = &*self.shared;
+        let phase = shared.phase.load(Ordering::Acquire);
+        let from = f32::from_bits(shared.gain.load(Ordering::Relaxed));
+        match phase {
+            SILENT => return None,
+            FADE_OUT if from == 0.0 || shared.fade_frames == 0 => {
+                self.hand_over();
+                return None;
+            }
+            _ => {}
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let step = sample_count as f32 / shared.fade_frames as f32;
+        let to = match phase {
+            FADE_OUT => (from - step).max(0.0),
+            _ => (from + step).min(1.0),
+        };
+        let result = run(unsafe { &mut *shared.instance.get() });
+        shared.gain.store(to.to_bits(), Ordering::Relaxed);
+        if phase == FADE_IN && to == 1.0 {
+            // A new restore may have started, which keeps its phase.
+            let _ =
+                shared
+                    .phase
+                    .compare_exchange(FADE_IN, IDLE, Ordering::AcqRel, Ordering::Relaxed);
+        }
+        Some((result, Fade { from, to }))
+    }
+
+    /// Restore the instance from `state`, like `State::restore`, on the
+    /// calling thread, which must not be the audio thread. This waits until
+    /// the audio thread faded the output out in `try_run` and hands the
+    /// instance over, without spinning. `set_value` is called while the
+    /// audio thread skips the instance, so it can set the values the control
+    /// ports are connected to.
+    ///
+    /// # Safety
+    /// Restoring calls the plugin's code, which itself may be unsafe. The
+    /// instance must be an instance of the plugin of the state.
+    pub unsafe fn apply_state_rt_safe(
+        &self,
+        state: &State,
+        map: &LV2UridMap,
+        set_value: impl FnMut(&str, f32),
+        flags: StateFlags,
+        features: &FeatureSet,
+    ) {
+        let shared = &*self.shared;
+        let _restoring = shared.restoring.lock();
+        *shared.waiter.lock() = Some(std::thread::current());
+        shared.phase.store(FADE_OUT, Ordering::Release);
+        while shared.phase.load(Ordering::Acquire) != SILENT {
+            std::thread::park_timeout(WAKE_INTERVAL);
+        }
+        *shared.waiter.lock() = None;
+        let instance = unsafe { &mut *shared.instance.get() };
+        // The phase is set back even if the plugin panics.
+        struct FadeIn<'a>(&'a AtomicU8);
+        impl Drop for FadeIn<'_> {
+            fn drop(&mut self) {
+                self.0.store(FADE_IN, Ordering::Release);
+            }
+        }
+        let _fade_in = FadeIn(&shared.phase);
+        unsafe { state.restore(instance.instance_mut(), map, set_value, flags, features) };
+    }
+
+    /// The instance, or the shared instance back if it has other clones.
+    ///
+    /// # Errors
+    /// Returns `self` if there are other clones.
+    pub fn try_unwrap(self) -> Result<ActiveInstance, SharedInstance> {
+        match Arc::try_unwrap(self.shared) {
+            Ok(shared) => Ok(shared.instance.into_inner()),
+            Err(shared) => Err(SharedInstance { shared }),
+        }
+    }
+
+    // Called by the audio thread once the output is faded out.
+    fn hand_over(&self) {
+        let shared = &*self.shared;
+        shared.phase.store(SILENT, Ordering::Release);
+        // The restoring thread also wakes up on its own, so a waiter that is
+        // being set is not waited for.
+        if let Some(waiter) = shared.waiter.try_lock() {
+            if let Some(thread) = &*waiter {
+                thread.unpark();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for SharedInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedInstance")
+            .field("phase", &self.shared.phase.load(Ordering::Relaxed))
+            .field("fade_frames", &self.shared.fade_frames)
+            .finish()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{Fade, SharedInstance};
+    use crate::state::StateFlags;
+    use crate::testing::TestHost;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fade() {
+        let mut samples = [1.0; 4];
+        Fade { from: 1.0, to: 0.0 }.apply(&mut samples);
+        assert_eq!(samples, [1.0, 0.75, 0.5, 0.25]);
+        assert!(Fade { from: 1.0, to: 1.0 }.is_unity());
+    }
+
+    #[test]
+    fn test_shared_instance() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = TestHost::new(44100.0, 512);
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let instance = unsafe { plugin.instantiate(host.sample_rate(), host.features().iter()) };
+        let shared = SharedInstance::new(unsafe { instance.unwrap().activate() }, 128);
+        let preset = world
+            .new_uri(&format!("{}#loud", crate::testing::GAIN_URI))
+            .unwrap();
+        let state = world.load_preset(&preset, host.urid_map()).unwrap();
+        assert_eq!(
+            shared.try_run(64, |_| 1),
+            Some((1, Fade { from: 1.0, to: 1.0 }))
+        );
+
+        // An audio thread that runs blocks of 64 frames until it is stopped.
+        let stop = Arc::new(AtomicBool::new(false));
+        let audio = {
+            let (shared, stop) = (shared.clone(), Arc::clone(&stop));
+            std::thread::spawn(move || {
+                let mut fades = Vec::new();
+                while !stop.load(Ordering::Acquire) {
+                    fades.push(shared.try_run(64, |_| ()).map(|(_, fade)| fade));
+                    std::thread::sleep(std::time::Duration::from_micros(100));
+                }
+                fades
+            })
+        };
+        let mut values = Vec::new();
+        unsafe {
+            shared.apply_state_rt_safe(
+                &state,
+                host.urid_map(),
+                |symbol, value| values.push((symbol.to_string(), value)),
+                StateFlags::default(),
+                host.features(),
+            );
+        }
+        assert_eq!(values, vec![("gain".to_string(), 6.0)]);
+        // Wait for the output to fade back in.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        stop.store(true, Ordering::Release);
+        let fades = audio.join().unwrap();
+        let fades: Vec<Option<Fade>> = fades
+            .into_iter()
+            .filter(|fade| !matches!(fade, Some(fade) if fade.is_unity()))
+            .collect();
+        let skipped = fades.iter().position(Option::is_none).unwrap();
+        assert_eq!(
+            fades[..skipped],
+            [
+                Some(Fade { from: 1.0, to: 0.5 }),
+                Some(Fade { from: 0.5, to: 0.0 })
+            ]
+        );
+        assert!(fades[skipped..].ends_with(&[
+            Some(Fade { from: 0.0, to: 0.5 }),
+            Some(Fade { from: 0.5, to: 1.0 })
+        ]));
+        assert!(shared.try_unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_denormal_guard() {
+        let denormal = || std::hint::black_box(f32::MIN_POSITIVE) / std::hint::black_box(4.0);
+        assert_ne!(denormal(), 0.0);
+        {
+            let _guard = super::DenormalGuard::new();
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            assert_eq!(denormal(), 0.0);
+        }
+        assert_ne!(denormal(), 0.0);
+    }
+
     #[test]
     fn test_can_run_plugin() {
-        let world = crate::World::with_load_all();
-        // This is the only plugin that doesn't require a feature.
-        // Most require at least URID Map.
-        let uri = world.new_uri("http://lv2plug.in/plugins/eg-amp");
+        let world = crate::testing::fixture_world().unwrap();
+        // The gain fixture does not require any feature.
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
         let plugin = world
             .plugins()
             .plugin(&uri)
             .unwrap_or_else(|| panic!("Could not find plugin {:?}", uri));
         let uri = plugin.uri().as_uri().unwrap_or("").to_string();
         let mut instance = unsafe {
-            plugin.instantiate(44100.0, []).unwrap_or_else(|| {
+            plugin.instantiate(44100.0, []).unwrap_or_else(|err| {
                 panic!(
-                    "failed to instantiate {} which has required features {:?}",
+                    "failed to instantiate {} which has required features {:?}: {}",
                     uri,
-                    plugin.required_features()
+                    plugin.required_features(),
+                    err
                 )
             })
         };
@@ -234,4 +895,92 @@ mod tests {
             active_instance.run(1);
         }
     }
+
+    #[test]
+    fn test_connection_tracking() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let mut instance = unsafe { plugin.instantiate(44100.0, []).unwrap() };
+        assert_eq!(instance.unconnected_ports(), vec![0, 1, 2]);
+
+        let gain = 0.0_f32;
+        let input = [0.0_f32; 4];
+        let mut output = [0.0_f32; 4];
+        unsafe {
+            instance.connect_port(0, &gain);
+            instance.connect_port(1, input.as_ptr());
+            instance.connect_port_mut(2, output.as_mut_ptr());
+        }
+        assert_eq!(instance.verify_complete(), Ok(()));
+        instance.poison_port(2);
+        assert_eq!(instance.verify_complete(), Err(vec![2]));
+        assert_eq!(instance.unconnected_ports(), vec![2]);
+        unsafe { instance.connect_port_mut(2, output.as_mut_ptr()) };
+
+        assert!(!instance.is_active());
+        let mut active = unsafe { instance.activate() };
+        assert!(active.instance().is_active());
+        unsafe { active.run(4) };
+        let mut instance = unsafe { active.deactivate() };
+        assert!(!instance.is_active());
+        unsafe { instance.connect_port::<f32>(0, std::ptr::null()) };
+        assert_eq!(instance.unconnected_ports(), vec![0]);
+    }
+
+    #[test]
+    fn test_run_checked() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let mut instance = unsafe { plugin.instantiate(44100.0, []).unwrap() };
+        let gain = 0.0_f32;
+        let mut input = [0.5_f32; 4];
+        let input_ptr = input.as_mut_ptr();
+        let mut output = [1.0_f32; 4];
+        unsafe {
+            instance.connect_port(0, &gain);
+            instance.connect_port(1, input_ptr);
+            instance.connect_port_mut(2, output.as_mut_ptr());
+            instance.clear_outputs(4);
+        }
+        assert_eq!(output, [0.0; 4]);
+
+        let mut active = unsafe { instance.activate() };
+        assert_eq!(unsafe { active.run_checked(4) }, Ok(()));
+        unsafe { *input_ptr.add(2) = f32::INFINITY };
+        let err = unsafe { active.run_checked(4) }.unwrap_err();
+        assert_eq!(err.plugin.as_deref(), Some(crate::testing::GAIN_URI));
+        assert_eq!((err.port_index, err.frame), (2, 2));
+        assert!(err.value.is_infinite());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not connected")]
+    fn test_run_unconnected() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let instance = unsafe { plugin.instantiate(44100.0, []).unwrap() };
+        let mut active = unsafe { instance.activate() };
+        unsafe { active.run(1) };
+    }
+
+    #[test]
+    fn test_instantiate_missing_features() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::SAMPLER_URI).unwrap();
+        let plugin = world
+            .plugins()
+            .plugin(&uri)
+            .unwrap_or_else(|| panic!("Could not find plugin {:?}", uri));
+        match unsafe { plugin.instantiate(44100.0, []) } {
+            Err(super::InstantiateError::MissingFeatures(missing)) => {
+                assert!(missing.contains(&"http://lv2plug.in/ns/ext/urid#map".to_string()));
+            }
+            Err(err) => panic!("Unexpected error: {}", err),
+            Ok(_) => panic!("Instantiated a plugin without its required features."),
+        }
+    }
 }
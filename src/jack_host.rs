@@ -0,0 +1,390 @@
+use crate::atom::ATOM_SEQUENCE;
+use crate::ffi::jack::{
+    jack_activate, jack_client_close, jack_client_open, jack_deactivate, jack_get_sample_rate,
+    jack_midi_event_get, jack_midi_get_event_count, jack_port_get_buffer, jack_port_register,
+    jack_set_process_callback, JackMidiEvent, JACK_DEFAULT_AUDIO_TYPE, JACK_DEFAULT_MIDI_TYPE,
+    JACK_NO_START_SERVER, JACK_PORT_IS_INPUT, JACK_PORT_IS_OUTPUT,
+};
+use crate::graph::Chain;
+use crate::host::Host;
+use crate::midi::MIDI_EVENT;
+use crate::port::{PortDirection, PortKind};
+use crate::world::World;
+use lv2_raw::LV2Urid;
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_void};
+
+// The size of the atom sequence of each MIDI input port in bytes.
+const MIDI_CAPACITY: usize = 8192;
+
+/// The reason a chain could not be run as a JACK client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JackError {
+    /// The client could not be opened, usually because no JACK server is
+    /// running. Contains the `jack_status_t` JACK reported.
+    ClientNotOpened(i32),
+    /// JACK runs at a different sample rate than the host the chain was
+    /// instantiated with.
+    SampleRateMismatch {
+        /// The sample rate of JACK.
+        jack: u32,
+        /// The sample rate of the host.
+        host: f64,
+    },
+    /// A port could not be registered. Contains the name of the port.
+    PortNotRegistered(String),
+    /// The client could not be activated.
+    NotActivated,
+}
+
+impl std::fmt::Display for JackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JackError::ClientNotOpened(status) => {
+                write!(f, "failed to open a JACK client, status {:#x}", status)
+            }
+            JackError::SampleRateMismatch { jack, host } => {
+                write!(f, "JACK runs at {} Hz but the plugins at {} Hz", jack, host)
+            }
+            JackError::PortNotRegistered(name) => {
+                write!(f, "failed to register the JACK port {}", name)
+            }
+            JackError::NotActivated => write!(f, "failed to activate the JACK client"),
+        }
+    }
+}
+
+impl std::error::Error for JackError {}
+
+/// A chain that runs as a JACK client, until it is dropped or closed.
+pub struct JackClient {
+    client: *mut c_void,
+    // Boxed so the process callback can keep a pointer to it.
+    process: Option<Box<Process>>,
+}
+
+unsafe impl Send for JackClient {}
+
+// The state of the process callback.
+struct Process {
+    chain: Chain,
+    inputs: Vec<*mut c_void>,
+    outputs: Vec<*mut c_void>,
+    midi_in: Option<*mut c_void>,
+    // The atom sequences of the MIDI inputs of the plugins, which they are
+    // connected to.
+    sequences: Vec<Box<[u64]>>,
+    sequence_type: LV2Urid,
+    midi_type: LV2Urid,
+}
+
+/// Run `chain` as the JACK client `name`.
+///
+/// The client has an audio input port `in_<n>` for each input of the chain
+/// and an audio output port `out_<n>` for each output. If plugins of the
+/// chain have atom inputs that support MIDI, their atom ports are connected
+/// to sequences that are filled with the events of the JACK MIDI input port
+/// `midi_in`. The process callback copies the JACK buffers to the chain and
+/// calls `Chain::process`, in several blocks if JACK's buffer is longer than
+/// the block length of the chain. The ports are not connected to other
+/// clients.
+///
+/// # Safety
+/// Running calls the plugins' code, which itself may be unsafe. The chain
+/// must have been instantiated with `host`, and ports other than the ones
+/// above must be connected.
+///
+/// # Errors
+/// Returns an error if the client could not be opened or activated, or if JACK
+/// does not run at the sample rate of `host`.
+pub unsafe fn run(name: &str, mut chain: Chain, host: &Host) -> Result<JackClient, JackError> {
+    let c_name = CString::new(name).map_err(|_| JackError::ClientNotOpened(0))?;
+    let mut status = 0;
+    let client = unsafe { jack_client_open(c_name.as_ptr(), JACK_NO_START_SERVER, &mut status) };
+    if client.is_null() {
+        return Err(JackError::ClientNotOpened(status));
+    }
+    let mut jack = JackClient {
+        client,
+        process: None,
+    };
+    let rate = unsafe { jack_get_sample_rate(client) };
+    if (f64::from(rate) - host.sample_rate()).abs() > f64::EPSILON {
+        return Err(JackError::SampleRateMismatch {
+            jack: rate,
+            host: host.sample_rate(),
+        });
+    }
+    let register = |name: String, port_type: &[u8], flags| {
+        let c_name = CString::new(name.as_str()).ok();
+        let port = c_name.map_or(std::ptr::null_mut(), |c_name| unsafe {
+            jack_port_register(client, c_name.as_ptr(), port_type.as_ptr().cast(), flags, 0)
+        });
+        if port.is_null() {
+            Err(JackError::PortNotRegistered(name))
+        } else {
+            Ok(port)
+        }
+    };
+    let inputs = (1..=chain.audio_inputs())
+        .map(|n| {
+            register(
+                format!("in_{}", n),
+                JACK_DEFAULT_AUDIO_TYPE,
+                JACK_PORT_IS_INPUT,
+            )
+        })
+        .collect::<Result<_, _>>()?;
+    let outputs = (1..=chain.audio_outputs())
+        .map(|n| {
+            register(
+                format!("out_{}", n),
+                JACK_DEFAULT_AUDIO_TYPE,
+                JACK_PORT_IS_OUTPUT,
+            )
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut midi_ports = Vec::new();
+    for id in chain.ids() {
+        if let Some(plugin) = chain.plugin(id) {
+            let world = World {
+                life: plugin.life.clone(),
+            };
+            let midi_event = world.new_uri(MIDI_EVENT).ok();
+            let supports_midi = |port: &crate::port::Port| {
+                port.kind() == PortKind::Atom
+                    && port.direction() == Some(PortDirection::Input)
+                    && midi_event
+                        .as_ref()
+                        .is_some_and(|event| port.supports_event(event))
+            };
+            for port in plugin.iter_ports().filter(supports_midi) {
+                midi_ports.push((id.to_string(), port.index()));
+            }
+        }
+    }
+    let mut sequences = Vec::with_capacity(midi_ports.len());
+    for (id, index) in &midi_ports {
+        let mut sequence = vec![0_u64; MIDI_CAPACITY / 8].into_boxed_slice();
+        if let Some(instance) = chain.plugin_mut(id) {
+            unsafe {
+                instance
+                    .instance_mut()
+                    .connect_port_mut(*index, sequence.as_mut_ptr());
+            }
+        }
+        sequences.push(sequence);
+    }
+    let midi_in = if sequences.is_empty() {
+        None
+    } else {
+        let name = "midi_in".to_string();
+        Some(register(name, JACK_DEFAULT_MIDI_TYPE, JACK_PORT_IS_INPUT)?)
+    };
+
+    let mut process = Box::new(Process {
+        chain,
+        inputs,
+        outputs,
+        midi_in,
+        sequences,
+        sequence_type: host.map(ATOM_SEQUENCE),
+        midi_type: host.map(MIDI_EVENT),
+    });
+    let arg = (&mut *process as *mut Process).cast();
+    jack.process = Some(process);
+    let activated = unsafe {
+        jack_set_process_callback(client, process_callback, arg) == 0 && jack_activate(client) == 0
+    };
+    if activated {
+        Ok(jack)
+    } else {
+        Err(JackError::NotActivated)
+    }
+}
+
+impl JackClient {
+    /// Stop the client and return the chain.
+    #[must_use]
+    pub fn close(mut self) -> Chain {
+        self.stop();
+        // `run` always sets the process before returning the client.
+        self.process.take().map(|process| process.chain).unwrap()
+    }
+
+    fn stop(&mut self) {
+        if !self.client.is_null() {
+            unsafe {
+                jack_deactivate(self.client);
+                jack_client_close(self.client);
+            }
+            self.client = std::ptr::null_mut();
+        }
+    }
+}
+
+impl Drop for JackClient {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl std::fmt::Debug for JackClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JackClient")
+            .field("chain", &self.process.as_ref().map(|p| &p.chain))
+            .finish()
+    }
+}
+
+// Called from C so it must not unwind. A panic is reported to JACK as an
+// error, which stops the client.
+unsafe extern "C" fn process_callback(frames: u32, arg: *mut c_void) -> c_int {
+    let process = unsafe { &mut *arg.cast::<Process>() };
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+        process.run(frames);
+    }))
+    .map_or(1, |()| 0)
+}
+
+impl Process {
+    unsafe fn run(&mut self, frames: u32) {
+        let block_length = u32::try_from(self.chain.block_length()).unwrap_or(u32::MAX);
+        if block_length == 0 {
+            return;
+        }
+        let midi = self
+            .midi_in
+            .map(|port| unsafe { jack_port_get_buffer(port, frames) });
+        let mut offset = 0;
+        while offset < frames {
+            let len = (frames - offset).min(block_length);
+            for (channel, port) in self.inputs.iter().enumerate() {
+                let buffer = unsafe { jack_buffer(*port, frames, offset, len) };
+                if let Some(input) = self.chain.input_mut(channel) {
+                    input[..buffer.len()].copy_from_slice(buffer);
+                }
+            }
+            if let Some(midi) = midi {
+                let count = unsafe { jack_midi_get_event_count(midi) };
+                let events = (0..count).filter_map(|index| {
+                    let mut event = JackMidiEvent {
+                        time: 0,
+                        size: 0,
+                        buffer: std::ptr::null_mut(),
+                    };
+                    if unsafe { jack_midi_event_get(&mut event, midi, index) } != 0 {
+                        return None;
+                    }
+                    let time = event.time.checked_sub(offset).filter(|time| *time < len)?;
+                    let data = unsafe { std::slice::from_raw_parts(event.buffer, event.size) };
+                    Some((time, data))
+                });
+                let types = (self.sequence_type, self.midi_type);
+                if let Some((sequence, others)) = self.sequences.split_first_mut() {
+                    write_sequence(sequence, types, events);
+                    for other in others {
+                        other.copy_from_slice(sequence);
+                    }
+                }
+            }
+            unsafe { self.chain.process(len as usize) };
+            for (channel, port) in self.outputs.iter().enumerate() {
+                let buffer = unsafe { jack_buffer_mut(*port, frames, offset, len) };
+                match self.chain.output(channel) {
+                    Some(output) => buffer.copy_from_slice(&output[..buffer.len()]),
+                    None => buffer.fill(0.0),
+                }
+            }
+            offset += len;
+        }
+    }
+}
+
+// The frames `offset..offset + len` of the buffer of `port`.
+unsafe fn jack_buffer<'a>(port: *mut c_void, frames: u32, offset: u32, len: u32) -> &'a [f32] {
+    unsafe {
+        let buffer = jack_port_get_buffer(port, frames).cast::<f32>();
+        std::slice::from_raw_parts(buffer.add(offset as usize), len as usize)
+    }
+}
+
+unsafe fn jack_buffer_mut<'a>(
+    port: *mut c_void,
+    frames: u32,
+    offset: u32,
+    len: u32,
+) -> &'a mut [f32] {
+    unsafe {
+        let buffer = jack_port_get_buffer(port, frames).cast::<f32>();
+        std::slice::from_raw_parts_mut(buffer.add(offset as usize), len as usize)
+    }
+}
+
+// Replace the contents of the atom sequence `sequence` with the MIDI events
+// `events`, given as their frame and data. `types` are the URIDs of
+// `atom:Sequence` and `midi:MidiEvent`. Events that do not fit are dropped.
+#[allow(clippy::cast_possible_truncation)]
+fn write_sequence<'a>(
+    sequence: &mut [u64],
+    (sequence_type, midi_type): (LV2Urid, LV2Urid),
+    events: impl Iterator<Item = (u32, &'a [u8])>,
+) {
+    // The atom header and the sequence body header are 8 bytes each.
+    let mut words = 2;
+    for (time, data) in events {
+        let data_words = data.len().div_ceil(8);
+        if words + 2 + data_words > sequence.len() {
+            break;
+        }
+        sequence[words] = u64::from(time);
+        sequence[words + 1] = pack(data.len() as u32, midi_type);
+        for (word, chunk) in sequence[words + 2..].iter_mut().zip(data.chunks(8)) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_ne_bytes(bytes);
+        }
+        words += 2 + data_words;
+    }
+    // The size of the atom counts the body of the sequence, without its atom
+    // header.
+    sequence[0] = pack((words as u32 - 1) * 8, sequence_type);
+    // The time unit 0 means frames.
+    sequence[1] = 0;
+}
+
+// Two 32 bit fields in the order they have in memory.
+fn pack(first: u32, second: u32) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&first.to_ne_bytes());
+    bytes[4..].copy_from_slice(&second.to_ne_bytes());
+    u64::from_ne_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_sequence() {
+        let mut sequence = [9; 8];
+        let events = vec![(3, &[0x90_u8, 60, 100][..]), (5, &[0x80, 60, 0][..])];
+        write_sequence(&mut sequence, (1, 2), events.into_iter());
+        assert_eq!(sequence[0], pack(6 * 8 + 8, 1));
+        assert_eq!(sequence[1], 0);
+        assert_eq!(sequence[2], 3);
+        assert_eq!(sequence[3], pack(3, 2));
+        assert_eq!(
+            sequence[4],
+            u64::from_ne_bytes([0x90, 60, 100, 0, 0, 0, 0, 0])
+        );
+        assert_eq!(sequence[5], 5);
+
+        // Events that do not fit are dropped.
+        let events = vec![(0, &[0_u8; 40][..])];
+        write_sequence(&mut sequence, (1, 2), events.into_iter());
+        assert_eq!(sequence[0], pack(8, 1));
+    }
+}
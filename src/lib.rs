@@ -1,15 +1,89 @@
+#![deny(unsafe_op_in_unsafe_fn)]
+
+#[macro_use]
+mod trace;
+
+/// Contains test signal analysis of plugin instances, like impulse responses.
+pub mod analysis;
+/// Contains conversions between atoms and JSON.
+pub mod atom;
+/// Contains helpers to automate control ports.
+pub mod automation;
+/// Contains an adapter to run plugins with a fixed block length.
+pub mod block;
+/// Contains plugin instances that run in a separate process. Requires the
+/// `bridge` feature and a Unix system.
+#[cfg(all(feature = "bridge", unix))]
+pub mod bridge;
+/// Contains a catalog of plugin metadata that can be shared between processes.
+pub mod catalog;
+/// Contains Rust source generation for plugin ports. Requires the `codegen`
+/// feature.
+#[cfg(feature = "codegen")]
+pub mod codegen;
+/// Contains a runner for chains on the default audio devices. Requires the
+/// `cpal` feature.
+#[cfg(feature = "cpal")]
+pub mod cpal_host;
+/// Contains an adapter for ports of the deprecated LV2 event extension.
+pub mod event;
+/// Contains functionality for features that hosts provide to plugins.
+pub mod feature;
+/// Contains chains of plugins, their descriptions and nodes implemented in Rust.
+pub mod graph;
+/// Contains a minimal host with the features most plugins need.
+pub mod host;
+/// Contains owned snapshots of plugin metadata.
+pub mod info;
 /// Contains functionality for plugin instances that process data.
 pub mod instance;
+/// Contains a runner for chains as JACK clients. Requires the `jack`
+/// feature and the JACK library.
+#[cfg(feature = "jack")]
+pub mod jack_host;
+/// Contains MIDI event transforms and a mapping of program changes to presets.
+pub mod midi;
 /// Contains functionality for nodes. Nodes are used to represent metadata.
 pub mod node;
+/// Contains a server for the controls of a chain over OSC. Requires the `osc`
+/// feature.
+#[cfg(feature = "osc")]
+pub mod osc;
+/// Contains a cache of the patch parameters of plugin instances.
+pub mod patch;
 /// Contains functionality to describe and instantiate plugins.
 pub mod plugin;
 /// Contains port to describe IO for plugins.
 pub mod port;
+/// Contains payloads of the protocols that send port values to UIs.
+pub mod protocol;
+/// Contains a wrapper that runs a chain at a different sample rate. Requires
+/// the `rubato` feature.
+#[cfg(feature = "rubato")]
+pub mod resample;
+/// Contains functionality to discover LV2 bundles.
+pub mod scan;
+/// Contains functionality for plugin state and presets.
+pub mod state;
+/// Contains LV2 fixture bundles and a minimal host for tests. Requires the
+/// `testing` feature.
+#[cfg(feature = "testing")]
+pub mod testing;
+/// Contains a terminal editor for the controls of a plugin. Requires the `tui`
+/// feature.
+#[cfg(feature = "tui")]
+pub mod tui;
 /// Contains data about plugin UIs.
 pub mod ui;
 
+mod buffer;
+mod error;
+mod ffi;
+mod path;
+mod string;
+mod turtle;
 mod world;
 
+pub use error::{Error, ParseError};
 pub use lilv_sys as sys;
-pub use world::World;
+pub use world::{Uris, World, WorldStats};
@@ -0,0 +1,599 @@
+use crate::atom::children;
+use lv2_raw::atom::{LV2Atom, LV2AtomEvent, LV2AtomSequence, LV2AtomSequenceBody};
+use lv2_raw::LV2Urid;
+use std::convert::TryFrom;
+use std::mem::size_of;
+
+/// The URI of MIDI events in atom sequences.
+pub const MIDI_EVENT: &str = "http://lv2plug.in/ns/ext/midi#MidiEvent";
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const POLY_PRESSURE: u8 = 0xa0;
+const CONTROL_CHANGE: u8 = 0xb0;
+const PROGRAM_CHANGE: u8 = 0xc0;
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+
+/// A transform of MIDI messages, like the MIDI events of an atom sequence
+/// before it is passed to an instrument. See `transform_sequence`.
+///
+/// Transforms are combined with `then`, or at run time with a `Vec` of boxed
+/// transforms, which are applied in order.
+pub trait MidiTransform {
+    /// Transform `message` in place. Returns `false` if the message should be
+    /// dropped.
+    fn transform(&mut self, message: &mut [u8]) -> bool;
+
+    /// A transform that applies `self`, then `next` to the messages that
+    /// were not dropped.
+    fn then<T: MidiTransform>(self, next: T) -> Then<Self, T>
+    where
+        Self: Sized,
+    {
+        Then {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+/// Two transforms applied after each other. See `MidiTransform::then`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Then<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: MidiTransform, B: MidiTransform> MidiTransform for Then<A, B> {
+    fn transform(&mut self, message: &mut [u8]) -> bool {
+        self.first.transform(message) && self.second.transform(message)
+    }
+}
+
+impl<T: MidiTransform + ?Sized> MidiTransform for Box<T> {
+    fn transform(&mut self, message: &mut [u8]) -> bool {
+        (**self).transform(message)
+    }
+}
+
+impl<T: MidiTransform> MidiTransform for Vec<T> {
+    fn transform(&mut self, message: &mut [u8]) -> bool {
+        self.iter_mut()
+            .all(|transform| transform.transform(message))
+    }
+}
+
+/// Passes the channel messages of some channels and drops the others.
+/// System messages always pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelFilter {
+    // A bit for each of the 16 channels.
+    channels: u16,
+}
+
+impl ChannelFilter {
+    /// Create a filter that passes the channels in `channels`, from 0 to 15.
+    /// Larger channels are ignored.
+    pub fn new(channels: impl IntoIterator<Item = u8>) -> ChannelFilter {
+        let channels = channels
+            .into_iter()
+            .filter(|channel| *channel < 16)
+            .fold(0, |mask, channel| mask | 1 << channel);
+        ChannelFilter { channels }
+    }
+
+    /// Returns `true` if the filter passes `channel`.
+    #[must_use]
+    pub fn contains(&self, channel: u8) -> bool {
+        channel < 16 && self.channels & 1 << channel != 0
+    }
+}
+
+impl MidiTransform for ChannelFilter {
+    fn transform(&mut self, message: &mut [u8]) -> bool {
+        match channel_message(message) {
+            Some((_, channel)) => self.contains(channel),
+            None => true,
+        }
+    }
+}
+
+/// Shifts the notes of note on, note off and polyphonic pressure messages.
+/// Notes that are shifted out of the MIDI range are dropped.
+///
+/// Changing `semitones` while notes are held leaves them hanging, since their
+/// note off messages are shifted to other notes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Transpose {
+    /// The number of semitones to shift the notes by.
+    pub semitones: i8,
+}
+
+impl Transpose {
+    /// Create a transform that shifts notes by `semitones`.
+    #[must_use]
+    pub fn new(semitones: i8) -> Transpose {
+        Transpose { semitones }
+    }
+}
+
+impl MidiTransform for Transpose {
+    fn transform(&mut self, message: &mut [u8]) -> bool {
+        match (channel_message(message), message.get_mut(1)) {
+            (Some((NOTE_OFF | NOTE_ON | POLY_PRESSURE, _)), Some(note)) => {
+                match u8::try_from(i16::from(*note) + i16::from(self.semitones)) {
+                    Ok(shifted) if shifted < 128 => {
+                        *note = shifted;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Maps the velocities of note on messages with a table. Note on messages
+/// with a velocity of 0 are note off messages and are left unchanged, and
+/// velocities are never mapped to 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VelocityCurve {
+    table: [u8; 128],
+}
+
+impl VelocityCurve {
+    /// Create a curve that maps each velocity with `curve`. Results are
+    /// clamped to 1 to 127.
+    pub fn from_fn(mut curve: impl FnMut(u8) -> u8) -> VelocityCurve {
+        let mut table = [0; 128];
+        for (velocity, mapped) in (0..).zip(&mut table) {
+            *mapped = curve(velocity).clamp(1, 127);
+        }
+        VelocityCurve { table }
+    }
+
+    /// Create a curve that raises the velocity, scaled to 0 to 1, to
+    /// `exponent`. Exponents larger than 1 make soft notes softer, smaller
+    /// exponents make them louder.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn power(exponent: f32) -> VelocityCurve {
+        VelocityCurve::from_fn(|velocity| {
+            (127.0 * (f32::from(velocity) / 127.0).powf(exponent)).round() as u8
+        })
+    }
+
+    /// Create a curve that maps all velocities to `velocity`.
+    #[must_use]
+    pub fn fixed(velocity: u8) -> VelocityCurve {
+        VelocityCurve::from_fn(|_| velocity)
+    }
+
+    /// The velocity that `velocity` is mapped to.
+    #[must_use]
+    pub fn map(&self, velocity: u8) -> u8 {
+        self.table[usize::from(velocity & 0x7f)]
+    }
+}
+
+impl MidiTransform for VelocityCurve {
+    fn transform(&mut self, message: &mut [u8]) -> bool {
+        if let (Some((NOTE_ON, _)), Some(velocity)) = (channel_message(message), message.get_mut(2))
+        {
+            if *velocity != 0 {
+                *velocity = self.map(*velocity);
+            }
+        }
+        true
+    }
+}
+
+/// Apply `transform` to the events of type `midi_event` in `sequence`, like
+/// the buffer of an atom input port before the instance is run. Dropped
+/// events are removed from the sequence and other events are left unchanged.
+/// Returns the number of events that were removed.
+///
+/// Only the `capacity` bytes of the buffer are read, so a sequence whose
+/// size is larger than the buffer is cut off. An event that does not fit
+/// into the sequence ends it and is cut off with the events after it.
+///
+/// # Safety
+/// `sequence` must point to an atom sequence in a buffer of `capacity`
+/// bytes.
+#[allow(clippy::cast_possible_truncation)]
+pub unsafe fn transform_sequence(
+    sequence: *mut LV2AtomSequence,
+    capacity: usize,
+    midi_event: LV2Urid,
+    transform: &mut (impl MidiTransform + ?Sized),
+) -> usize {
+    if capacity < size_of::<LV2AtomSequence>() {
+        return 0;
+    }
+    let (body, size) = unsafe {
+        (
+            sequence.cast::<u8>().add(size_of::<LV2Atom>()),
+            ((*sequence).atom.size as usize).min(capacity - size_of::<LV2Atom>()),
+        )
+    };
+    let mut read = size_of::<LV2AtomSequenceBody>();
+    let mut write = read;
+    let mut removed = 0;
+    while read + size_of::<LV2AtomEvent>() <= size {
+        let event = unsafe { body.add(read).cast::<LV2AtomEvent>() };
+        let (event_type, len) = unsafe { ((*event).body.mytype, (*event).body.size as usize) };
+        if len > size - read - size_of::<LV2AtomEvent>() {
+            break;
+        }
+        let total = pad(size_of::<LV2AtomEvent>() + len);
+        let keep = event_type != midi_event || {
+            let message = unsafe {
+                std::slice::from_raw_parts_mut(
+                    event.cast::<u8>().add(size_of::<LV2AtomEvent>()),
+                    len,
+                )
+            };
+            transform.transform(message)
+        };
+        if keep {
+            if write != read {
+                unsafe { std::ptr::copy(body.add(read), body.add(write), total.min(size - read)) };
+            }
+            write += total;
+        } else {
+            removed += 1;
+        }
+        read += total;
+    }
+    unsafe { (*sequence).atom.size = write.min(size) as u32 };
+    removed
+}
+
+// The kind and the channel of a channel message.
+fn channel_message(message: &[u8]) -> Option<(u8, u8)> {
+    let status = *message.first()?;
+    (NOTE_OFF..0xf0)
+        .contains(&status)
+        .then_some((status & 0xf0, status & 0x0f))
+}
+
+// Atom events are aligned to 8 bytes.
+fn pad(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+/// A preset that is selected by a MIDI program change in a bank. See
+/// `PresetMapper`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProgramMapping {
+    /// The bank, from the bank select MSB (CC 0) and LSB (CC 32) like
+    /// `msb << 7 | lsb`.
+    pub bank: u16,
+    /// The program number, from 0 to 127.
+    pub program: u8,
+    /// The URI of the preset.
+    pub preset: String,
+}
+
+/// Maps MIDI bank select and program change messages to presets.
+///
+/// The mapper follows the bank select controllers of each channel and looks
+/// up the preset of each program change. Applying the preset calls into lilv
+/// and the plugin, so the host should pass the URI to a thread that loads
+/// the preset with `World::load_preset` and applies it to an
+/// `instance::SharedInstance` with `SharedInstance::apply_state_rt_safe`. Keep `mappings` with the
+/// session to restore the table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PresetMapper {
+    mappings: Vec<ProgramMapping>,
+    channel: Option<u8>,
+    // The bank select MSB and LSB of each channel.
+    banks: [(u8, u8); 16],
+}
+
+impl PresetMapper {
+    /// Create a mapper without mappings that listens on all channels.
+    #[must_use]
+    pub fn new() -> PresetMapper {
+        PresetMapper::default()
+    }
+
+    /// Create a mapper with the table from `mappings`.
+    #[must_use]
+    pub fn from_mappings(mappings: Vec<ProgramMapping>) -> PresetMapper {
+        PresetMapper {
+            mappings,
+            ..PresetMapper::default()
+        }
+    }
+
+    /// Only listen to messages on `channel`, from 0 to 15, or on all channels
+    /// if `channel` is `None`.
+    pub fn set_channel(&mut self, channel: Option<u8>) {
+        self.channel = channel;
+    }
+
+    /// Select `preset` with `program` in `bank`, replacing the preset that
+    /// was mapped there before.
+    pub fn map(&mut self, bank: u16, program: u8, preset: impl Into<String>) {
+        let preset = preset.into();
+        match self.position(bank, program) {
+            Some(index) => self.mappings[index].preset = preset,
+            None => self.mappings.push(ProgramMapping {
+                bank,
+                program,
+                preset,
+            }),
+        }
+    }
+
+    /// Remove the mapping of `program` in `bank`. Returns `false` if there is
+    /// no such mapping.
+    pub fn unmap(&mut self, bank: u16, program: u8) -> bool {
+        match self.position(bank, program) {
+            Some(index) => {
+                self.mappings.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The URI of the preset of `program` in `bank`.
+    #[must_use]
+    pub fn preset(&self, bank: u16, program: u8) -> Option<&str> {
+        let index = self.position(bank, program)?;
+        Some(&self.mappings[index].preset)
+    }
+
+    /// The table of the mapper, in the order the mappings were added.
+    #[must_use]
+    pub fn mappings(&self) -> &[ProgramMapping] {
+        &self.mappings
+    }
+
+    /// Handle the MIDI message `message`. Returns the URI of the preset to
+    /// apply if it is a program change with a mapping.
+    pub fn handle(&mut self, message: &[u8]) -> Option<&str> {
+        let index = self.select(message)?;
+        Some(&self.mappings[index].preset)
+    }
+
+    // Handle `message` and return the index of the mapping it selects.
+    fn select(&mut self, message: &[u8]) -> Option<usize> {
+        let status = *message.first()?;
+        let channel = status & 0x0f;
+        if matches!(self.channel, Some(c) if c != channel) {
+            return None;
+        }
+        let bank = &mut self.banks[usize::from(channel)];
+        match (status & 0xf0, message.get(1), message.get(2)) {
+            (CONTROL_CHANGE, Some(&BANK_SELECT_MSB), Some(value)) => bank.0 = value & 0x7f,
+            (CONTROL_CHANGE, Some(&BANK_SELECT_LSB), Some(value)) => bank.1 = value & 0x7f,
+            (PROGRAM_CHANGE, Some(program), _) => {
+                let bank = (u16::from(bank.0) << 7) | u16::from(bank.1);
+                return self.position(bank, program & 0x7f);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Handle the MIDI events of type `midi_event` in `sequence`, like the
+    /// buffer of an atom input port before the instance is run. Returns the
+    /// URI of the preset of the last mapped program change.
+    ///
+    /// `capacity` is the size of the buffer of `sequence` in bytes. A
+    /// sequence with a size that does not fit into it is only read up to the
+    /// end of the buffer, like events that do not fit into the sequence.
+    ///
+    /// # Safety
+    /// `sequence` must point to `capacity` readable bytes, which start with
+    /// an atom sequence header if `capacity` is large enough for one.
+    pub unsafe fn handle_sequence(
+        &mut self,
+        sequence: *const LV2AtomSequence,
+        capacity: usize,
+        midi_event: LV2Urid,
+    ) -> Option<&str> {
+        let size = capacity.checked_sub(size_of::<LV2Atom>())?;
+        let body = unsafe {
+            std::slice::from_raw_parts(
+                sequence.cast::<u8>().add(size_of::<LV2Atom>()),
+                ((*sequence).atom.size as usize).min(size),
+            )
+        };
+        let mut selected = None;
+        for (_, event_type, event) in children(body, 8, 8) {
+            if event_type == midi_event {
+                selected = self.select(event).or(selected);
+            }
+        }
+        Some(&self.mappings[selected?].preset)
+    }
+
+    fn position(&self, bank: u16, program: u8) -> Option<usize> {
+        self.mappings
+            .iter()
+            .position(|mapping| mapping.bank == bank && mapping.program == program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom::from_json;
+    use crate::testing::TestHost;
+
+    #[test]
+    fn test_transforms() {
+        let mut filter = ChannelFilter::new([0, 9, 16]);
+        assert!(filter.contains(9) && !filter.contains(1) && !filter.contains(16));
+        assert!(filter.transform(&mut [0x99, 36, 100]));
+        assert!(!filter.transform(&mut [0x91, 36, 100]));
+        assert!(filter.transform(&mut [0xf8]));
+
+        let mut transpose = Transpose::new(12);
+        let mut message = [0x90, 60, 100];
+        assert!(transpose.transform(&mut message));
+        assert_eq!(message, [0x90, 72, 100]);
+        assert!(!transpose.transform(&mut [0x80, 120, 0]));
+        assert!(transpose.transform(&mut [0xb0, 120, 0]));
+
+        let soft = VelocityCurve::power(2.0);
+        assert_eq!((soft.map(1), soft.map(64), soft.map(127)), (1, 32, 127));
+        let mut loud = VelocityCurve::fixed(127);
+        let mut message = [0x90, 60, 0];
+        assert!(loud.transform(&mut message));
+        assert_eq!(message[2], 0);
+
+        let mut chain = ChannelFilter::new([0])
+            .then(Transpose::new(-60))
+            .then(VelocityCurve::fixed(200));
+        let mut message = [0x90, 64, 10];
+        assert!(chain.transform(&mut message));
+        assert_eq!(message, [0x90, 4, 127]);
+        assert!(!chain.transform(&mut [0x90, 59, 10]));
+        let mut boxed: Vec<Box<dyn MidiTransform>> = vec![Box::new(Transpose::new(1))];
+        assert!(!boxed.transform(&mut [0x90, 127, 10]));
+    }
+
+    #[test]
+    fn test_transform_sequence() {
+        let host = TestHost::new(44100.0, 512);
+        let mut words = from_json(
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": [
+                {"time": 0, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "903c64"}},
+                {"time": 4, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "913c64"}},
+                {"time": 8, "value": {"type": "http://lv2plug.in/ns/ext/atom#Int", "value": 7}},
+                {"time": 16, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "803c00"}}]}"#,
+            host.urid_map(),
+        )
+        .unwrap();
+        let sequence = words.as_mut_ptr().cast::<LV2AtomSequence>();
+        let midi_event = host.map(MIDI_EVENT);
+        let mut transform = ChannelFilter::new([0]).then(Transpose::new(2));
+        let capacity = 8 * words.len();
+        let removed = unsafe { transform_sequence(sequence, capacity, midi_event, &mut transform) };
+        assert_eq!(removed, 1);
+        let size = unsafe { (*sequence).atom.size } as usize;
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        let body = &bytes[size_of::<LV2Atom>()..size_of::<LV2Atom>() + size];
+        let events: Vec<(LV2Urid, &[u8])> = children(body, 8, 8)
+            .map(|(_, event_type, event)| (event_type, event))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (midi_event, &[0x90, 62, 100][..]),
+                (
+                    host.map("http://lv2plug.in/ns/ext/atom#Int"),
+                    &7_i32.to_ne_bytes()[..]
+                ),
+                (midi_event, &[0x80, 62, 0][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transform_sequence_bounds() {
+        let host = TestHost::new(44100.0, 512);
+        let mut words = from_json(
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": [
+                {"time": 0, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "903c64"}},
+                {"time": 4, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "803c00"}}]}"#,
+            host.urid_map(),
+        )
+        .unwrap();
+        let capacity = 8 * words.len();
+        let sequence = words.as_mut_ptr().cast::<LV2AtomSequence>();
+        let midi_event = host.map(MIDI_EVENT);
+        // The header and the sequence body take 2 words and each event 3, so
+        // the size of the second event is in the 7th word.
+        let event_size = unsafe { words.as_mut_ptr().add(6).cast::<u32>() };
+        unsafe { event_size.write(1000) };
+        let mut transform = Transpose::new(2);
+        let removed = unsafe { transform_sequence(sequence, capacity, midi_event, &mut transform) };
+        assert_eq!(removed, 0);
+        assert_eq!(unsafe { (*sequence).atom.size }, 8 + 24);
+        assert_eq!(words[4].to_ne_bytes()[..3], [0x90, 62, 100]);
+
+        // A sequence that is larger than its buffer only reaches the end of
+        // the buffer.
+        unsafe {
+            event_size.write(3);
+            (*sequence).atom.size = 1000;
+        }
+        let removed = unsafe { transform_sequence(sequence, capacity, midi_event, &mut transform) };
+        assert_eq!(removed, 0);
+        assert_eq!(unsafe { (*sequence).atom.size }, 8 + 2 * 24);
+        assert_eq!(words[4].to_ne_bytes()[..3], [0x90, 64, 100]);
+        assert_eq!(words[7].to_ne_bytes()[..3], [0x80, 62, 0]);
+        assert_eq!(
+            unsafe { transform_sequence(sequence, 8, midi_event, &mut transform) },
+            0
+        );
+    }
+
+    #[test]
+    fn test_preset_mapper() {
+        let mut mapper = PresetMapper::new();
+        mapper.map(0, 0, "urn:preset:clean");
+        mapper.map(0, 1, "urn:preset:old");
+        mapper.map(0, 1, "urn:preset:crunch");
+        mapper.map(129, 5, "urn:preset:lead");
+        assert_eq!(mapper.mappings().len(), 3);
+        assert_eq!(mapper.preset(0, 1), Some("urn:preset:crunch"));
+
+        assert_eq!(mapper.handle(&[0xc0, 1]), Some("urn:preset:crunch"));
+        assert_eq!(mapper.handle(&[0xc0, 5]), None);
+        assert_eq!(mapper.handle(&[0xb0, 0, 1]), None);
+        assert_eq!(mapper.handle(&[0xb0, 32, 1]), None);
+        assert_eq!(mapper.handle(&[0xc0, 5]), Some("urn:preset:lead"));
+        // The bank of channel 2 was not changed.
+        assert_eq!(mapper.handle(&[0xc1, 0]), Some("urn:preset:clean"));
+
+        mapper.set_channel(Some(1));
+        assert_eq!(mapper.handle(&[0xc0, 0]), None);
+        assert!(mapper.unmap(0, 0));
+        assert!(!mapper.unmap(0, 0));
+        assert_eq!(mapper.handle(&[0xc1, 0]), None);
+
+        let restored = PresetMapper::from_mappings(mapper.mappings().to_vec());
+        assert_eq!(restored.preset(129, 5), Some("urn:preset:lead"));
+    }
+
+    #[test]
+    fn test_handle_sequence() {
+        let host = TestHost::new(44100.0, 512);
+        let words = from_json(
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": [
+                {"time": 0, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "c001"}},
+                {"time": 8, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "903c64"}},
+                {"time": 16, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "c000"}},
+                {"time": 24, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "c07f"}}]}"#,
+            host.urid_map(),
+        )
+        .unwrap();
+        let sequence = words.as_ptr().cast::<LV2AtomSequence>();
+        let midi_event = host.map(MIDI_EVENT);
+        let mut mapper = PresetMapper::new();
+        mapper.map(0, 0, "urn:preset:clean");
+        mapper.map(0, 1, "urn:preset:crunch");
+        let capacity = words.len() * size_of::<u64>();
+        let preset = unsafe { mapper.handle_sequence(sequence, capacity, midi_event) };
+        assert_eq!(preset, Some("urn:preset:clean"));
+        let preset = unsafe { mapper.handle_sequence(sequence, capacity, midi_event + 1) };
+        assert_eq!(preset, None);
+        // A buffer that ends after the second event only has the first
+        // program change.
+        let capacity = size_of::<LV2Atom>() + size_of::<LV2AtomSequenceBody>() + 2 * 24;
+        let preset = unsafe { mapper.handle_sequence(sequence, capacity, midi_event) };
+        assert_eq!(preset, Some("urn:preset:crunch"));
+        let preset = unsafe { mapper.handle_sequence(sequence, 4, midi_event) };
+        assert_eq!(preset, None);
+    }
+}
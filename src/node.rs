@@ -1,20 +1,23 @@
+use crate::string::LilvString;
 use crate::world::Life;
 use lilv_sys as lib;
 use std::borrow::Borrow;
 use std::ffi::CStr;
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::ptr::NonNull;
 use std::sync::Arc;
 
-extern "C" {
-    // Needed for Node::get_path as it actually returns
-    // things allocated in Serd.
-    fn serd_free(ptr: *mut std::os::raw::c_void);
-}
-
 unsafe impl Send for Node {}
 unsafe impl Sync for Node {}
 
+/// A value of metadata, like a URI, a string or a number.
+///
+/// Lilv returns both nodes that the caller owns, like `Port::name`, and nodes
+/// owned by the world, like `Port::symbol`. A `Node` knows which one it holds
+/// and only frees the nodes it owns. Every node keeps the world alive, so
+/// borrowed nodes stay valid for as long as they are held. `Clone` always
+/// produces an owned copy.
 pub struct Node {
     pub(crate) inner: NonNull<lib::LilvNodeImpl>,
     pub(crate) borrowed: bool,
@@ -28,12 +31,9 @@ impl Node {
         let _life = self.life.inner.lock();
         let node = self.inner.as_ptr();
 
-        unsafe {
-            let raw = lib::lilv_node_get_turtle_token(node);
-            let formatted = CStr::from_ptr(raw).to_string_lossy().into_owned();
-            lib::lilv_free(raw.cast());
-            formatted
-        }
+        unsafe { LilvString::from_lilv(lib::lilv_node_get_turtle_token(node)) }
+            .map(|token| token.to_string_lossy())
+            .unwrap_or_default()
     }
 
     /// Returns whether the value is a URI (resource).
@@ -43,7 +43,8 @@ impl Node {
         unsafe { lib::lilv_node_is_uri(self.inner.as_ptr()) }
     }
 
-    /// Returns this value as a URI string or `None` if it is not a uri.
+    /// Returns this value as a URI string or `None` if it is not a uri or not
+    /// valid UTF-8.
     #[must_use]
     pub fn as_uri(&self) -> Option<&str> {
         if self.is_uri() {
@@ -65,7 +66,8 @@ impl Node {
         unsafe { lib::lilv_node_is_blank(self.inner.as_ptr()) }
     }
 
-    /// Returns this value as a blank node identifier.
+    /// Returns this value as a blank node identifier or `None` if it is not a
+    /// blank node or not valid UTF-8.
     #[must_use]
     pub fn as_blank(&self) -> Option<&str> {
         if self.is_blank() {
@@ -95,7 +97,10 @@ impl Node {
         unsafe { lib::lilv_node_is_string(self.inner.as_ptr()) }
     }
 
-    /// Return the value as a string or `None` if it is not a string.
+    /// Return the value as a string or `None` if it is not valid UTF-8.
+    ///
+    /// The string is borrowed from the node without copying, like the strings
+    /// returned by `as_uri` and `as_blank`.
     #[must_use]
     pub fn as_str(&self) -> Option<&str> {
         let _life = self.life.inner.lock();
@@ -113,25 +118,15 @@ impl Node {
         let _life = self.life.inner.lock();
         let node = self.inner.as_ptr();
         let mut raw_hostname = std::ptr::null_mut();
-        let raw_path = NonNull::new(unsafe { lib::lilv_node_get_path(node, &mut raw_hostname) })?;
-
-        let path = unsafe { CStr::from_ptr(raw_path.as_ptr()) }
-            .to_string_lossy()
-            .into_owned();
-
-        let hostname = if !raw_hostname.is_null() {
-            let hostname = unsafe { CStr::from_ptr(raw_hostname) }
-                .to_string_lossy()
-                .into_owned();
-            unsafe { serd_free(raw_hostname.cast()) };
-            hostname
-        } else {
-            "".to_string()
-        };
-
-        unsafe { serd_free(raw_path.as_ptr().cast()) };
-
-        Some((hostname, path))
+        // Both strings are allocated by Serd.
+        let path =
+            unsafe { LilvString::from_serd(lib::lilv_node_get_path(node, &mut raw_hostname)) };
+        let hostname = unsafe { LilvString::from_serd(raw_hostname) };
+
+        Some((
+            hostname.map(|h| h.to_string_lossy()).unwrap_or_default(),
+            path?.to_string_lossy(),
+        ))
     }
 
     /// Returns `true` if the value is a decimal literal.
@@ -213,10 +208,96 @@ impl Debug for Node {
     }
 }
 
+impl std::fmt::Display for Node {
+    /// Formats the URI, string or number held by the node.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.as_str() {
+            Some(s) => f.write_str(s),
+            None => f.write_str(&self.turtle_token()),
+        }
+    }
+}
+
 impl PartialEq for Node {
+    /// Nodes are compared with `lilv_node_equals`, except for floats, which
+    /// are compared by their bits with all NaNs equal to each other and
+    /// `-0.0` equal to `0.0`, so `Node` can be `Eq`.
     fn eq(&self, other: &Self) -> bool {
-        let _life = self.life.inner.lock();
-        unsafe { lib::lilv_node_equals(self.inner.as_ptr(), other.inner.as_ptr()) }
+        match (self.float_bits(), other.float_bits()) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => {
+                let _life = self.life.inner.lock();
+                unsafe { lib::lilv_node_equals(self.inner.as_ptr(), other.inner.as_ptr()) }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Node {}
+
+impl std::hash::Hash for Node {
+    /// Nodes are hashed by their kind and their value, which is all that
+    /// `eq` compares. Lilv compares numbers by value, so `1.0` and `1.00` are
+    /// equal and are hashed by value as well.
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+// What two equal nodes have in common. Lilv only compares nodes of the same
+// kind, numbers and booleans by value and other nodes by their interned
+// string, so equal nodes always have the same key.
+#[derive(Hash)]
+enum NodeKey<'a> {
+    Float(u32),
+    Int(i32),
+    Bool(bool),
+    Uri(&'a [u8]),
+    Blank(&'a [u8]),
+    Literal(&'a [u8]),
+}
+
+impl Node {
+    fn key(&self) -> NodeKey<'_> {
+        if let Some(bits) = self.float_bits() {
+            return NodeKey::Float(bits);
+        }
+        if let Some(value) = self.as_int() {
+            return NodeKey::Int(value);
+        }
+        if let Some(value) = self.as_bool() {
+            return NodeKey::Bool(value);
+        }
+        let (is_uri, is_blank) = (self.is_uri(), self.is_blank());
+        let string = {
+            let _life = self.life.inner.lock();
+            let string = unsafe { lib::lilv_node_as_string(self.inner.as_ptr()) };
+            if string.is_null() {
+                &[][..]
+            } else {
+                unsafe { CStr::from_ptr(string) }.to_bytes()
+            }
+        };
+        if is_uri {
+            NodeKey::Uri(string)
+        } else if is_blank {
+            NodeKey::Blank(string)
+        } else {
+            NodeKey::Literal(string)
+        }
+    }
+
+    // The bits of a float node, with one NaN and one zero.
+    fn float_bits(&self) -> Option<u32> {
+        let value = self.as_float()?;
+        Some(if value.is_nan() {
+            f32::NAN.to_bits()
+        } else if value == 0.0 {
+            0.0_f32.to_bits()
+        } else {
+            value.to_bits()
+        })
     }
 }
 
@@ -229,9 +310,95 @@ impl Drop for Node {
     }
 }
 
+/// A type that can be read from the value of a `Node`. This is used by the
+/// typed getters like `Plugin::get` and `Port::get`.
+///
+/// `String` accepts any string literal or URI, `bool` accepts boolean
+/// literals, `i32` accepts integer literals and `f32` accepts float and integer
+/// literals.
+pub trait FromNode: Sized {
+    /// Convert `node` or return `None` if it does not hold a value of this
+    /// type.
+    fn from_node(node: &Node) -> Option<Self>;
+
+    /// Convert an owned `node`. This avoids a copy when converting to `Node`.
+    fn from_owned_node(node: Node) -> Option<Self> {
+        Self::from_node(&node)
+    }
+}
+
+impl FromNode for Node {
+    fn from_node(node: &Node) -> Option<Node> {
+        Some(node.clone())
+    }
+
+    fn from_owned_node(node: Node) -> Option<Node> {
+        Some(node)
+    }
+}
+
+impl FromNode for String {
+    fn from_node(node: &Node) -> Option<String> {
+        if node.is_uri() {
+            node.as_uri().map(str::to_string)
+        } else if node.is_string() {
+            node.as_str().map(str::to_string)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromNode for bool {
+    fn from_node(node: &Node) -> Option<bool> {
+        node.as_bool()
+    }
+}
+
+impl FromNode for i32 {
+    fn from_node(node: &Node) -> Option<i32> {
+        node.as_int()
+    }
+}
+
+impl FromNode for f32 {
+    #[allow(clippy::cast_precision_loss)]
+    fn from_node(node: &Node) -> Option<f32> {
+        node.as_float().or_else(|| node.as_int().map(|v| v as f32))
+    }
+}
+
 /// A collection of `Node`.
+///
+/// Iterating yields nodes that stay valid after the collection is dropped.
+/// Borrows from a node do not outlive the node:
+///
+/// ```compile_fail
+/// # let world = lilv::World::with_load_all();
+/// # let plugin = world.plugins().iter().next().unwrap();
+/// let name: &str = {
+///     let features = plugin.supported_features();
+///     let feature = features.iter().next().unwrap();
+///     feature.as_uri().unwrap()
+/// };
+/// # let _ = name;
+/// ```
+///
+/// and iterators borrowing the collection do not outlive it:
+///
+/// ```compile_fail
+/// # let world = lilv::World::with_load_all();
+/// # let plugin = world.plugins().iter().next().unwrap();
+/// let mut iter = {
+///     let features = plugin.supported_features();
+///     features.iter()
+/// };
+/// # let _ = iter.next();
+/// ```
 pub struct Nodes {
     pub(crate) inner: *const lib::LilvNodes,
+    // `true` if the collection is owned by lilv and must not be freed.
+    pub(crate) borrowed: bool,
     pub(crate) life: Arc<Life>,
 }
 
@@ -266,10 +433,49 @@ impl Nodes {
 
         Nodes {
             inner: unsafe { lib::lilv_nodes_merge(a, b) },
+            borrowed: false,
             life: self.life.clone(),
         }
     }
 
+    /// The URIs of the URI nodes. Other nodes are skipped.
+    #[must_use]
+    pub fn to_uris(&self) -> Vec<String> {
+        self.iter()
+            .filter_map(|node| node.as_uri().map(str::to_string))
+            .collect()
+    }
+
+    /// The values of the URI and string nodes, converted like
+    /// `String::from_node`. Other nodes are skipped.
+    #[must_use]
+    pub fn to_strings(&self) -> Vec<String> {
+        self.iter()
+            .filter_map(|node| String::from_node(&node))
+            .collect()
+    }
+
+    /// The local paths of the file URI nodes, like `to_uris` for
+    /// `Plugin::data_uris`. Other nodes, and file URIs of other hosts, are
+    /// skipped.
+    #[must_use]
+    pub fn to_paths(&self) -> Vec<PathBuf> {
+        self.iter()
+            .filter_map(|node| node.path())
+            .filter(|(hostname, _)| hostname.is_empty() || hostname == "localhost")
+            .map(|(_, path)| PathBuf::from(path))
+            .collect()
+    }
+
+    /// The nodes sorted by their Turtle token, which does not depend on the
+    /// order lilv stores them in.
+    #[must_use]
+    pub fn sorted(&self) -> Vec<Node> {
+        let mut nodes: Vec<Node> = self.iter().collect();
+        nodes.sort_by_cached_key(Node::turtle_token);
+        nodes
+    }
+
     /// An iterator over all the nodes.
     pub fn iter(&self) -> impl '_ + Iterator<Item = Node> {
         let _life = self.life.inner.lock();
@@ -281,6 +487,15 @@ impl Nodes {
     }
 }
 
+impl Drop for Nodes {
+    fn drop(&mut self) {
+        if !self.borrowed && !self.inner.is_null() {
+            let _life = self.life.inner.lock();
+            unsafe { lib::lilv_nodes_free(self.inner as *mut _) }
+        }
+    }
+}
+
 impl Debug for Nodes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_list().entries(self.iter()).finish()
@@ -319,13 +534,23 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         let _life = self.life.inner.lock();
-        let node = unsafe { lib::lilv_nodes_get(self.nodes.borrow().inner, self.inner) } as *mut _;
+        let nodes = self.nodes.borrow();
+        let node = unsafe { lib::lilv_nodes_get(nodes.inner, self.inner) };
+        if node.is_null() {
+            return None;
+        }
+        // Nodes in an owned collection are freed with it so they are copied
+        // to outlive the collection.
         let next = Some({
-            let ptr = NonNull::new(node)?;
+            let (ptr, borrowed) = if nodes.borrowed {
+                (node as *mut _, true)
+            } else {
+                (unsafe { lib::lilv_node_duplicate(node) }, false)
+            };
             let world = self.life.clone();
             Node {
-                inner: ptr,
-                borrowed: true,
+                inner: NonNull::new(ptr)?,
+                borrowed,
                 life: world,
             }
         });
@@ -360,6 +585,7 @@ mod tests {
         let world = World::new();
         let nodes = Nodes {
             inner: std::ptr::null(),
+            borrowed: false,
             life: world.life,
         };
         assert_eq!(nodes.count(), 0);
@@ -368,10 +594,153 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_invalid_utf8() {
+        let world = World::new();
+        let node = Node {
+            inner: NonNull::new(unsafe {
+                lib::lilv_new_string(world.as_ptr(), b"\xff\xfe\0".as_ptr().cast())
+            })
+            .unwrap(),
+            borrowed: false,
+            life: world.life.clone(),
+        };
+        assert_eq!(node.as_str(), None);
+        assert!(!node.turtle_token().is_empty());
+    }
+
+    #[test]
+    fn test_owned_and_borrowed() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let port = world
+            .plugins()
+            .plugin(&uri)
+            .unwrap_or_else(|| panic!("Could not find plugin {:?}", uri))
+            .port_by_index(0)
+            .unwrap();
+        let name = port.name().unwrap();
+        let symbol = port.symbol().unwrap();
+        assert!(!name.borrowed);
+        assert!(symbol.borrowed);
+
+        let owned_symbol = symbol.clone();
+        assert!(!owned_symbol.borrowed);
+        drop(symbol);
+        drop(port);
+        drop(world);
+        assert_eq!(owned_symbol.as_str(), Some("gain"));
+        assert_eq!(name.as_str(), Some("Gain"));
+    }
+
+    #[test]
+    fn test_nodes_outlive_collection() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let features: Vec<Node> = plugin.supported_features().into_iter().collect();
+        let classes: Vec<Node> = plugin.port_by_index(0).unwrap().classes().iter().collect();
+        assert!(features.iter().all(|n| !n.borrowed && n.is_uri()));
+        assert!(classes.iter().all(|n| n.borrowed && n.is_uri()));
+    }
+
+    #[test]
+    fn test_nodes_conversions() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let data = plugin.data_uris();
+        assert_eq!(data.to_uris().len(), data.count());
+        assert_eq!(data.to_strings(), data.to_uris());
+        let paths = data.to_paths();
+        assert_eq!(paths.len(), data.count());
+        assert!(paths.iter().any(|path| path.ends_with("gain.ttl")));
+        let sorted: Vec<String> = data.sorted().iter().map(Node::turtle_token).collect();
+        let mut expected: Vec<String> = data.iter().map(|node| node.turtle_token()).collect();
+        expected.sort();
+        assert_eq!(sorted, expected);
+
+        let name = world.new_uri("http://usefulinc.com/ns/doap#name").unwrap();
+        let names = plugin.value(&name);
+        assert!(names.to_uris().is_empty());
+        assert!(names.to_paths().is_empty());
+        assert!(names.to_strings().contains(&"Fixture Gain".to_string()));
+    }
+
+    #[test]
+    // The world lock does not affect the hash.
+    #[allow(clippy::mutable_key_type)]
+    fn test_hash() {
+        let world = crate::World::new();
+        let uri = world.new_uri("urn:lilv-rs:a").unwrap();
+        let mut set = std::collections::HashSet::new();
+        assert!(set.insert(uri.clone()));
+        assert!(!set.insert(world.new_uri("urn:lilv-rs:a").unwrap()));
+        assert!(set.insert(world.new_string("urn:lilv-rs:a").unwrap()));
+        assert!(set.insert(world.new_float(1.0)));
+        assert!(!set.insert(world.new_float(1.0)));
+        assert!(set.insert(world.new_int(1)));
+        assert!(set.insert(world.new_bool(true)));
+        assert_eq!(set.len(), 5);
+        assert!(set.contains(&uri));
+
+        // Floats are equal to themselves, even NaN, and zeros are equal.
+        let nan = world.new_float(f32::NAN);
+        assert_eq!(nan, nan.clone());
+        assert_eq!(nan, world.new_float(-f32::NAN));
+        assert_eq!(world.new_float(-0.0), world.new_float(0.0));
+        assert_ne!(world.new_float(1.0), world.new_int(1));
+        assert!(set.insert(nan));
+        assert!(!set.insert(world.new_float(f32::NAN)));
+        assert!(set.insert(world.new_float(0.0)));
+        assert!(!set.insert(world.new_float(-0.0)));
+    }
+
+    #[test]
+    fn test_equal_nodes_hash_equally() {
+        fn hash(node: &Node) -> u64 {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            node.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        // The nodes of the plugin are read from Turtle, so their literals are
+        // not written like the nodes that are made by the world.
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let port = plugin.port_by_index(0).unwrap();
+        let range = port.range();
+        let boost = port
+            .scale_points()
+            .iter()
+            .find(|point| point.label().as_str() == Some("Boost"))
+            .unwrap();
+        let pairs = [
+            (plugin.uri(), uri.clone()),
+            (plugin.name(), world.new_string("Fixture Gain").unwrap()),
+            (range.maximum.unwrap(), world.new_float(24.0)),
+            (range.default.unwrap(), world.new_float(-0.0)),
+            (boost.value(), world.new_int(6)),
+            (world.new_bool(true), world.new_bool(true)),
+            (world.new_float(f32::NAN), world.new_float(-f32::NAN)),
+        ];
+        for (a, b) in &pairs {
+            assert_eq!(a, b);
+            assert_eq!(hash(a), hash(b), "{:?} and {:?}", a, b);
+        }
+        assert_ne!(hash(&world.new_float(1.0)), hash(&world.new_int(1)));
+        assert_ne!(
+            hash(&uri),
+            hash(&world.new_string(crate::testing::GAIN_URI).unwrap())
+        );
+    }
+
     #[test]
     fn test_path() {
-        let world = crate::World::with_load_all();
-        let uri = world.new_uri("http://lv2plug.in/plugins/eg-amp");
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
         let plugin = world
             .plugins()
             .plugin(&uri)
@@ -381,6 +750,6 @@ mod tests {
             .path()
             .expect("Missing plugin library path.");
         assert_eq!(hostname, "");
-        assert!(path.ends_with("/eg-amp.lv2/amp.so"));
+        assert!(path.ends_with("/gain.lv2/gain.so"));
     }
 }
@@ -0,0 +1,264 @@
+use crate::automation::ParamSender;
+use crate::graph::Chain;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// How often the server thread checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// The number of control changes that can wait for the audio thread.
+const QUEUE_CAPACITY: usize = 256;
+
+// The largest packet that fits into a UDP datagram.
+const MAX_PACKET: usize = 65536;
+
+/// Receives OSC messages on a UDP socket and writes them to the control
+/// inputs of a chain, until it is dropped.
+pub struct OscServer {
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Serve the control inputs of `chain` over OSC on `address`, so a headless
+/// host can be controlled remotely.
+///
+/// The control input `symbol` of the plugin with the id `id` has the address
+/// `/<id>/<symbol>`. A message to it with a single `f`, `d` or `i` argument
+/// sets the control, and messages in bundles are applied right away
+/// regardless of their time tag. The values are passed to the audio thread
+/// through `Chain::param_queue`, which replaces an earlier queue of the
+/// chain, and are applied at the start of the next block. Other messages are
+/// ignored. `patch:Set` messages for patch parameters are not sent, since a
+/// chain does not connect the atom ports of its plugins.
+///
+/// # Errors
+/// Returns an error if the socket could not be bound.
+pub fn serve(chain: &mut Chain, address: impl ToSocketAddrs) -> io::Result<OscServer> {
+    let socket = UdpSocket::bind(address)?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    let address = socket.local_addr()?;
+    let controls: HashMap<String, usize> = chain
+        .control_inputs()
+        .into_iter()
+        .filter_map(|port| {
+            let index = chain.control_index(&port.plugin, &port.port)?;
+            Some((format!("/{}/{}", port.plugin, port.port), index))
+        })
+        .collect();
+    let sender = chain.param_queue(QUEUE_CAPACITY);
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = Arc::clone(&stop);
+        std::thread::Builder::new()
+            .name("lilv-osc".to_string())
+            .spawn(move || receive(&socket, &controls, sender, &stop))?
+    };
+    Ok(OscServer {
+        address,
+        stop,
+        thread: Some(thread),
+    })
+}
+
+impl OscServer {
+    /// The address the server receives on, with the port the system chose if
+    /// it was 0.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl std::fmt::Debug for OscServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OscServer")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+fn receive(
+    socket: &UdpSocket,
+    controls: &HashMap<String, usize>,
+    mut sender: ParamSender,
+    stop: &AtomicBool,
+) {
+    let mut packet = vec![0; MAX_PACKET];
+    while !stop.load(Ordering::Acquire) {
+        let len = match socket.recv(&mut packet) {
+            Ok(len) => len,
+            Err(err) if is_timeout(&err) => continue,
+            Err(_) => return,
+        };
+        parse_packet(&packet[..len], 0, &mut |address, value| {
+            if let Some(index) = controls.get(address) {
+                // A full queue drops the change, like a lost datagram.
+                sender.push(*index, value);
+            }
+        });
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+// Bundles can contain bundles, but not deeper than this.
+const MAX_BUNDLE_DEPTH: usize = 8;
+
+// Call `f` with the address and value of each message in `packet` that has a
+// single numeric argument. Malformed packets are ignored.
+#[allow(clippy::cast_possible_truncation)]
+fn parse_packet(packet: &[u8], depth: usize, f: &mut impl FnMut(&str, f32)) {
+    if let Some(mut elements) = packet.strip_prefix(b"#bundle\0") {
+        if depth >= MAX_BUNDLE_DEPTH || elements.len() < 8 {
+            return;
+        }
+        // Skip the time tag.
+        elements = &elements[8..];
+        while let Some((size, rest)) = read_u32(elements) {
+            let size = size as usize;
+            if size > rest.len() {
+                return;
+            }
+            parse_packet(&rest[..size], depth + 1, f);
+            elements = &rest[size..];
+        }
+        return;
+    }
+    let (address, rest) = match read_string(packet) {
+        Some(message) => message,
+        None => return,
+    };
+    let (tags, arguments) = match read_string(rest) {
+        Some(tags) => tags,
+        None => return,
+    };
+    let value = match (tags, read_u32(arguments)) {
+        (",f", Some((bits, _))) => f32::from_bits(bits),
+        (",i", Some((bits, _))) => bits as i32 as f32,
+        (",d", _) => match arguments.get(..8).map(<[u8; 8]>::try_from) {
+            Some(Ok(bytes)) => f64::from_be_bytes(bytes) as f32,
+            _ => return,
+        },
+        _ => return,
+    };
+    f(address, value);
+}
+
+// A string padded with NUL bytes to a multiple of 4 bytes, and the rest.
+fn read_string(bytes: &[u8]) -> Option<(&str, &[u8])> {
+    let len = bytes.iter().position(|b| *b == 0)?;
+    let padded = (len + 4) & !3;
+    let string = std::str::from_utf8(&bytes[..len]).ok()?;
+    Some((string, bytes.get(padded..)?))
+}
+
+// A big endian 32 bit integer, and the rest.
+fn read_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let value = u32::from_be_bytes(<[u8; 4]>::try_from(bytes.get(..4)?).ok()?);
+    Some((value, &bytes[4..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureSet;
+    use crate::graph::{ChainSpec, PluginSpec};
+
+    // An OSC message with a single argument, which is 4 bytes long unless it
+    // is a double.
+    fn message(address: &str, tag: char, argument: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        let string = |message: &mut Vec<u8>, s: &str| {
+            message.extend_from_slice(s.as_bytes());
+            message.resize((message.len() + 4) & !3, 0);
+        };
+        string(&mut message, address);
+        string(&mut message, &format!(",{}", tag));
+        message.extend_from_slice(argument);
+        message
+    }
+
+    fn parse(packet: &[u8]) -> Vec<(String, f32)> {
+        let mut messages = Vec::new();
+        parse_packet(packet, 0, &mut |address, value| {
+            messages.push((address.to_string(), value));
+        });
+        messages
+    }
+
+    #[test]
+    fn test_parse_packet() {
+        let float = message("/amp/gain", 'f', &(-6.0_f32).to_be_bytes());
+        assert_eq!(parse(&float), vec![("/amp/gain".to_string(), -6.0)]);
+        let int = message("/amp/gain", 'i', &(-3_i32).to_be_bytes());
+        assert_eq!(parse(&int), vec![("/amp/gain".to_string(), -3.0)]);
+        let double = message("/a", 'd', &0.5_f64.to_be_bytes());
+        assert_eq!(parse(&double), vec![("/a".to_string(), 0.5)]);
+        assert!(parse(&message("/a", 's', b"no\0\0")).is_empty());
+        assert!(parse(&float[..float.len() - 1]).is_empty());
+        assert!(parse(b"/a").is_empty());
+
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for element in &[&float, &int] {
+            bundle.extend_from_slice(&u32::try_from(element.len()).unwrap().to_be_bytes());
+            bundle.extend_from_slice(element);
+        }
+        assert_eq!(parse(&bundle).len(), 2);
+        // An element that is longer than the bundle is dropped.
+        bundle.extend_from_slice(&100_u32.to_be_bytes());
+        assert_eq!(parse(&bundle).len(), 2);
+    }
+
+    #[test]
+    fn test_serve() {
+        let world = crate::testing::fixture_world().unwrap();
+        let spec = ChainSpec {
+            plugins: vec![PluginSpec {
+                id: "amp".to_string(),
+                uri: crate::testing::GAIN_URI.to_string(),
+                parameters: Default::default(),
+            }],
+            connections: Vec::new(),
+        };
+        let mut chain = Chain::new(4);
+        for instance in spec
+            .instantiate(&world, 44100.0, &FeatureSet::new())
+            .unwrap()
+        {
+            unsafe { chain.push_plugin(instance).unwrap() };
+        }
+        let server = serve(&mut chain, "127.0.0.1:0").unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let float = message("/amp/gain", 'f', &(-6.0_f32).to_be_bytes());
+        client.send_to(&float, server.local_addr()).unwrap();
+        let start = std::time::Instant::now();
+        while chain.control("amp", "gain") != Some(-6.0) {
+            assert!(start.elapsed() < Duration::from_secs(5), "no OSC message");
+            std::thread::sleep(Duration::from_millis(10));
+            unsafe { chain.process(4) };
+        }
+        drop(server);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::atom::{children, read_u32, ATOM_BLANK, ATOM_OBJECT, ATOM_RESOURCE};
+use lv2_raw::atom::{LV2Atom, LV2AtomSequence};
+use lv2_raw::{LV2Urid, LV2UridMap};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::mem::size_of;
+
+const PATCH_BODY: &str = "http://lv2plug.in/ns/ext/patch#body";
+const PATCH_PROPERTY: &str = "http://lv2plug.in/ns/ext/patch#property";
+const PATCH_PUT: &str = "http://lv2plug.in/ns/ext/patch#Put";
+const PATCH_SET: &str = "http://lv2plug.in/ns/ext/patch#Set";
+const PATCH_VALUE: &str = "http://lv2plug.in/ns/ext/patch#value";
+
+/// The value of a parameter, an atom without its header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AtomValue {
+    /// The URID of the type of the atom.
+    pub atom_type: LV2Urid,
+    /// The body of the atom.
+    pub body: Vec<u8>,
+}
+
+/// The current values of the patch parameters of a plugin instance.
+///
+/// The store is updated with the `patch:Set` and `patch:Put` messages that
+/// the plugin sends on its atom outputs, so it follows changes that the
+/// plugin makes itself, like a file it loaded from its own UI. Callbacks
+/// added with `subscribe` are called for each value that changed.
+pub struct ParameterStore {
+    urids: Urids,
+    values: BTreeMap<LV2Urid, AtomValue>,
+    subscribers: Vec<(usize, Callback)>,
+    next_id: usize,
+}
+
+type Callback = Box<dyn FnMut(LV2Urid, &AtomValue) + Send>;
+
+struct Urids {
+    objects: [LV2Urid; 3],
+    body: LV2Urid,
+    property: LV2Urid,
+    put: LV2Urid,
+    set: LV2Urid,
+    value: LV2Urid,
+}
+
+impl ParameterStore {
+    /// Create an empty store. URIs are mapped with `map`, which must be the
+    /// map the instance was created with.
+    #[must_use]
+    pub fn new(map: &LV2UridMap) -> ParameterStore {
+        let map = |uri: &str| {
+            let uri = CString::new(uri).unwrap();
+            (map.map)(map.handle, uri.as_ptr())
+        };
+        ParameterStore {
+            urids: Urids {
+                objects: [map(ATOM_OBJECT), map(ATOM_BLANK), map(ATOM_RESOURCE)],
+                body: map(PATCH_BODY),
+                property: map(PATCH_PROPERTY),
+                put: map(PATCH_PUT),
+                set: map(PATCH_SET),
+                value: map(PATCH_VALUE),
+            },
+            values: BTreeMap::new(),
+            subscribers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// The current value of the parameter `property`, or `None` if the plugin
+    /// has not sent it yet.
+    #[must_use]
+    pub fn get(&self, property: LV2Urid) -> Option<&AtomValue> {
+        self.values.get(&property)
+    }
+
+    /// An iterator over the parameters and their values, ordered by URID.
+    pub fn iter(&self) -> impl '_ + Iterator<Item = (LV2Urid, &AtomValue)> {
+        self.values
+            .iter()
+            .map(|(property, value)| (*property, value))
+    }
+
+    /// Set the value of `property` without notifying the subscribers, like
+    /// when the host sent the value to the plugin itself.
+    pub fn insert(&mut self, property: LV2Urid, value: AtomValue) {
+        self.values.insert(property, value);
+    }
+
+    /// Call `callback` with the parameter and its new value each time a value
+    /// changes. Returns an id for `unsubscribe`.
+    pub fn subscribe(
+        &mut self,
+        callback: impl FnMut(LV2Urid, &AtomValue) + Send + 'static,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscribers.push((id, Box::new(callback)));
+        id
+    }
+
+    /// Remove the callback with `id`. Returns `false` if there is no such
+    /// callback.
+    pub fn unsubscribe(&mut self, id: usize) -> bool {
+        let len = self.subscribers.len();
+        self.subscribers.retain(|(other, _)| *other != id);
+        self.subscribers.len() != len
+    }
+
+    /// Apply a message that the plugin sent, an atom of type `atom_type`
+    /// with `body`. Messages other than `patch:Set` and `patch:Put` are
+    /// ignored. Returns the number of values that changed.
+    pub fn observe(&mut self, atom_type: LV2Urid, body: &[u8]) -> usize {
+        if !self.urids.objects.contains(&atom_type) || body.len() < 8 {
+            return 0;
+        }
+        let otype = read_u32(body, 4);
+        let mut changes = Vec::new();
+        if otype == self.urids.set {
+            let mut property = None;
+            let mut value = None;
+            for (header, value_type, child) in children(body, 8, 8) {
+                let key = read_u32(header, 0);
+                if key == self.urids.property && child.len() >= 4 {
+                    property = Some(read_u32(child, 0));
+                } else if key == self.urids.value {
+                    value = Some(AtomValue {
+                        atom_type: value_type,
+                        body: child.to_vec(),
+                    });
+                }
+            }
+            changes.extend(property.zip(value));
+        } else if otype == self.urids.put {
+            for (header, value_type, child) in children(body, 8, 8) {
+                if read_u32(header, 0) != self.urids.body
+                    || !self.urids.objects.contains(&value_type)
+                    || child.len() < 8
+                {
+                    continue;
+                }
+                changes.extend(children(child, 8, 8).map(|(header, value_type, value)| {
+                    let value = AtomValue {
+                        atom_type: value_type,
+                        body: value.to_vec(),
+                    };
+                    (read_u32(header, 0), value)
+                }));
+            }
+        }
+        let mut changed = 0;
+        for (property, value) in changes {
+            if self.values.get(&property) == Some(&value) {
+                continue;
+            }
+            for (_, callback) in &mut self.subscribers {
+                callback(property, &value);
+            }
+            self.values.insert(property, value);
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Apply the messages in the events of `sequence`, the buffer of an atom
+    /// output port after the instance was run. Returns the number of values
+    /// that changed.
+    ///
+    /// # Safety
+    /// `sequence` must point to a valid atom sequence.
+    pub unsafe fn observe_sequence(&mut self, sequence: *const LV2AtomSequence) -> usize {
+        let body = unsafe {
+            std::slice::from_raw_parts(
+                sequence.cast::<u8>().add(size_of::<LV2Atom>()),
+                (*sequence).atom.size as usize,
+            )
+        };
+        children(body, 8, 8)
+            .map(|(_, event_type, event)| self.observe(event_type, event))
+            .sum()
+    }
+}
+
+impl std::fmt::Debug for ParameterStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParameterStore")
+            .field("values", &self.values)
+            .field("subscribers", &self.subscribers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom::from_json;
+    use crate::testing::TestHost;
+    use std::sync::{Arc, Mutex};
+
+    fn message(host: &TestHost, json: &str) -> (LV2Urid, Vec<u8>) {
+        let words = from_json(json, host.urid_map()).unwrap();
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        let size = read_u32(&bytes, 0) as usize;
+        (read_u32(&bytes, 4), bytes[8..8 + size].to_vec())
+    }
+
+    #[test]
+    fn test_parameter_store() {
+        let host = TestHost::new(44100.0, 512);
+        let mut store = ParameterStore::new(host.urid_map());
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let id = store.subscribe({
+            let changes = changes.clone();
+            move |property, value| changes.lock().unwrap().push((property, value.clone()))
+        });
+        let set = message(
+            &host,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Object", "id": null,
+                "otype": "http://lv2plug.in/ns/ext/patch#Set", "properties": [
+                {"key": "http://lv2plug.in/ns/ext/patch#property",
+                 "value": {"type": "http://lv2plug.in/ns/ext/atom#URID", "value": "urn:gain"}},
+                {"key": "http://lv2plug.in/ns/ext/patch#value",
+                 "value": {"type": "http://lv2plug.in/ns/ext/atom#Float", "value": 0.5}}]}"#,
+        );
+        assert_eq!(store.observe(set.0, &set.1), 1);
+        assert_eq!(store.observe(set.0, &set.1), 0);
+        let gain = host.map("urn:gain");
+        let float = host.map("http://lv2plug.in/ns/ext/atom#Float");
+        let half = AtomValue {
+            atom_type: float,
+            body: 0.5_f32.to_ne_bytes().to_vec(),
+        };
+        assert_eq!(store.get(gain), Some(&half));
+        assert_eq!(*changes.lock().unwrap(), vec![(gain, half)]);
+
+        let put = message(
+            &host,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Object", "id": null,
+                "otype": "http://lv2plug.in/ns/ext/patch#Put", "properties": [
+                {"key": "http://lv2plug.in/ns/ext/patch#body",
+                 "value": {"type": "http://lv2plug.in/ns/ext/atom#Object", "id": null,
+                           "otype": null, "properties": [
+                    {"key": "urn:gain",
+                     "value": {"type": "http://lv2plug.in/ns/ext/atom#Float", "value": 1.0}},
+                    {"key": "urn:file",
+                     "value": {"type": "http://lv2plug.in/ns/ext/atom#Path", "value": "/a.wav"}}]}}]}"#,
+        );
+        assert!(store.unsubscribe(id));
+        assert!(!store.unsubscribe(id));
+        assert_eq!(store.observe(put.0, &put.1), 2);
+        assert_eq!(changes.lock().unwrap().len(), 1);
+        assert_eq!(store.iter().count(), 2);
+        assert_eq!(
+            store.get(host.map("urn:file")).map(|v| v.body.as_slice()),
+            Some(&b"/a.wav\0"[..])
+        );
+
+        let int = message(
+            &host,
+            r#"{"type": "http://lv2plug.in/ns/ext/atom#Int", "value": 1}"#,
+        );
+        assert_eq!(store.observe(int.0, &int.1), 0);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::error::Error;
+use std::ffi::CString;
+use std::path::Path;
+
+/// Convert a path into the string lilv expects.
+///
+/// On Unix, paths are passed as their raw bytes. On Windows, lilv takes UTF-8
+/// and converts it back to the UTF-16 of the wide file API, so the path is
+/// converted from UTF-16 and paths with unpaired surrogates are rejected.
+/// Elsewhere paths that are not valid Unicode are rejected.
+pub(crate) fn path_to_cstring(path: &Path) -> Result<CString, Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        let wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+        let utf8 =
+            String::from_utf16(&wide).map_err(|_| Error::NonUnicodePath(path.to_path_buf()))?;
+        Ok(CString::new(utf8)?)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let utf8 = path
+            .to_str()
+            .ok_or_else(|| Error::NonUnicodePath(path.to_path_buf()))?;
+        Ok(CString::new(utf8)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_to_cstring() {
+        let path = Path::new("/tmp/bundle.lv2/plugin.ttl");
+        assert_eq!(
+            path_to_cstring(path).unwrap().to_str(),
+            Ok("/tmp/bundle.lv2/plugin.ttl")
+        );
+        assert!(matches!(
+            path_to_cstring(Path::new("a\0b")),
+            Err(Error::InteriorNul(_))
+        ));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let path = Path::new(std::ffi::OsStr::from_bytes(b"/tmp/\xff.lv2"));
+            assert_eq!(path_to_cstring(path).unwrap().as_bytes(), b"/tmp/\xff.lv2");
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::ffi::OsStringExt;
+            let path = std::ffi::OsString::from_wide(&[0x61, 0xd800, 0x62]);
+            assert!(matches!(
+                path_to_cstring(Path::new(&path)),
+                Err(Error::NonUnicodePath(_))
+            ));
+        }
+    }
+}
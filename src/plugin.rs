@@ -1,19 +1,191 @@
-use crate::instance::Instance;
-use crate::node::{Node, Nodes};
-use crate::port::{FloatRanges, Port};
+use crate::feature::{
+    FeatureSet, HostCaps, LV2_BUF_SIZE_FIXED_BLOCK_LENGTH, LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH,
+};
+use crate::info::{ControlInfo, RDFS_COMMENT};
+use crate::instance::{Instance, InstantiateError};
+use crate::node::{FromNode, Node, Nodes};
+use crate::port::{FloatRanges, Port, PortDirection, PortKind};
 use crate::ui::Uis;
-use crate::world::Life;
+use crate::world::{Life, World};
 use lilv_sys as lib;
 use lv2_raw::LV2Feature;
 use std::borrow::Borrow;
 use std::convert::TryFrom;
+use std::ffi::CStr;
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::ptr::NonNull;
 use std::sync::Arc;
 
 unsafe impl Send for Plugin {}
 unsafe impl Sync for Plugin {}
 
+const LV2_CORE_HARD_RT_CAPABLE: &str = "http://lv2plug.in/ns/lv2core#hardRTCapable";
+const LV2_CORE_IN_PLACE_BROKEN: &str = "http://lv2plug.in/ns/lv2core#inPlaceBroken";
+const LV2_CORE_IS_LIVE: &str = "http://lv2plug.in/ns/lv2core#isLive";
+
+const DOAP_LICENSE: &str = "http://usefulinc.com/ns/doap#license";
+const LV2_CORE_DOCUMENTATION: &str = "http://lv2plug.in/ns/lv2core#documentation";
+const DCTERMS_REPLACES: &str = "http://purl.org/dc/terms/replaces";
+
+#[cfg(feature = "mod-ext")]
+const MOD_BRAND: &str = "http://moddevices.com/ns/mod#brand";
+#[cfg(feature = "mod-ext")]
+const MOD_LABEL: &str = "http://moddevices.com/ns/mod#label";
+#[cfg(feature = "mod-ext")]
+const MODGUI_GUI: &str = "http://moddevices.com/ns/modgui#gui";
+const LV2_CORE_MICRO_VERSION: &str = "http://lv2plug.in/ns/lv2core#microVersion";
+const LV2_CORE_MINOR_VERSION: &str = "http://lv2plug.in/ns/lv2core#minorVersion";
+
+// 64 bit FNV-1a. `std::hash::DefaultHasher` is not guaranteed to be stable
+// across Rust versions so it can not be used for hashes that are stored.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    // Each field is terminated so that ("ab", "c") and ("a", "bc") differ.
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().chain(std::iter::once(&0xff)) {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The documentation of a plugin. See `Plugin::documentation`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Documentation {
+    /// The text of the documentation.
+    pub text: String,
+    /// The detected markup of `text`.
+    pub format: DocumentationFormat,
+}
+
+/// The markup used by plugin documentation.
+///
+/// LV2 does not say how documentation is formatted, and in practice it may be
+/// plain text, Markdown or HTML. The format is guessed from the text.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DocumentationFormat {
+    /// Plain text.
+    Plain,
+    /// Markdown.
+    Markdown,
+    /// HTML (or XHTML).
+    Html,
+}
+
+impl Documentation {
+    fn new(text: &str) -> Documentation {
+        Documentation {
+            text: text.to_string(),
+            format: DocumentationFormat::detect(text),
+        }
+    }
+}
+
+impl DocumentationFormat {
+    /// Guess the format of `text`.
+    #[must_use]
+    pub fn detect(text: &str) -> DocumentationFormat {
+        let trimmed = text.trim_start();
+        if trimmed.starts_with('<') && text.contains("</") {
+            return DocumentationFormat::Html;
+        }
+        let is_markdown_line = |line: &str| {
+            let line = line.trim_start();
+            line.starts_with("# ")
+                || line.starts_with("## ")
+                || line.starts_with("* ")
+                || line.starts_with("- ")
+                || line.starts_with("```")
+        };
+        if text.lines().any(is_markdown_line) || (text.contains("](") && text.contains('[')) {
+            DocumentationFormat::Markdown
+        } else {
+            DocumentationFormat::Plain
+        }
+    }
+}
+
+/// The declared properties of a plugin that affect how a host should run it.
+/// See `Plugin::features_summary`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeaturesSummary {
+    /// The plugin is real-time safe: `run` does not allocate, lock or block.
+    pub hard_rt_capable: bool,
+    /// The plugin requires separate input and output buffers.
+    pub in_place_broken: bool,
+    /// The plugin must be run in real time with a live input.
+    pub is_live: bool,
+    /// The plugin must always be run with the same number of frames. See
+    /// `block::BlockAdapter`.
+    pub fixed_block_length: bool,
+    /// The plugin must be run with a power of 2 number of frames.
+    pub power_of_2_block_length: bool,
+    /// The index of the port the plugin reports its latency on.
+    pub latency_port_index: Option<usize>,
+}
+
+/// A port that a host connects before running an instance. See
+/// `Plugin::connection_plan`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortConnection {
+    /// The index of the port.
+    pub index: usize,
+    /// The type of data the port carries.
+    pub kind: PortKind,
+    /// The direction of the port.
+    pub direction: Option<PortDirection>,
+    /// The port is `lv2:connectionOptional` and the instance may be run while
+    /// it is not connected.
+    pub optional: bool,
+}
+
+/// The metadata of the MOD extensions of a plugin, which pedalboard hosts
+/// show. See `Plugin::mod_metadata`.
+#[cfg(feature = "mod-ext")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModMetadata {
+    /// A short name of the plugin for small displays (`mod:label`).
+    pub label: Option<String>,
+    /// A short name of the maker of the plugin (`mod:brand`).
+    pub brand: Option<String>,
+    /// The plugin has a MOD web GUI (`modgui:gui`).
+    pub has_modgui: bool,
+}
+
+/// The number of ports of a plugin by type and direction. See
+/// `Plugin::port_summary`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PortSummary {
+    /// The number of audio inputs.
+    pub audio_in: usize,
+    /// The number of audio outputs.
+    pub audio_out: usize,
+    /// The number of CV inputs.
+    pub cv_in: usize,
+    /// The number of CV outputs.
+    pub cv_out: usize,
+    /// The number of control inputs.
+    pub control_in: usize,
+    /// The number of control outputs.
+    pub control_out: usize,
+    /// The number of atom inputs.
+    pub atom_in: usize,
+    /// The number of atom outputs.
+    pub atom_out: usize,
+}
+
 /// Can be used to instantiave LV2 plugins.
 #[derive(Clone)]
 pub struct Plugin {
@@ -76,10 +248,41 @@ impl Plugin {
 
         Nodes {
             inner: unsafe { lib::lilv_plugin_get_data_uris(plugin) },
+            borrowed: true,
             life: self.life.clone(),
         }
     }
 
+    /// The local Turtle files that describe the plugin, which are the
+    /// manifest of its bundle followed by the files of `data_uris`.
+    ///
+    /// Data that is not in a local file is skipped. Packaging and validation
+    /// tools can use `missing_data_files` to check that a bundle is
+    /// complete.
+    #[must_use]
+    pub fn data_files(&self) -> Vec<PathBuf> {
+        let manifest = self
+            .bundle_uri()
+            .path()
+            .map(|(_, bundle)| PathBuf::from(bundle).join("manifest.ttl"));
+        let mut files: Vec<PathBuf> = manifest.into_iter().collect();
+        for file in self.data_uris().to_paths() {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// The files of `data_files` that do not exist.
+    #[must_use]
+    pub fn missing_data_files(&self) -> Vec<PathBuf> {
+        self.data_files()
+            .into_iter()
+            .filter(|file| !file.is_file())
+            .collect()
+    }
+
     /// The uri for the library.
     #[must_use]
     pub fn library_uri(&self) -> Option<Node> {
@@ -147,6 +350,7 @@ impl Plugin {
 
         Nodes {
             inner: unsafe { lib::lilv_plugin_get_value(plugin, predicate) },
+            borrowed: false,
             life: self.life.clone(),
         }
     }
@@ -168,7 +372,11 @@ impl Plugin {
         let plugin = self.inner.as_ptr();
         let inner = unsafe { lib::lilv_plugin_get_supported_features(plugin) };
         let world = self.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: false,
+            life: world,
+        }
     }
 
     /// The set of features that are required to instantiate the plugin.
@@ -178,7 +386,11 @@ impl Plugin {
         let plugin = self.inner.as_ptr();
         let inner = unsafe { lib::lilv_plugin_get_required_features(plugin) };
         let world = self.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: false,
+            life: world,
+        }
     }
 
     /// The set of features that are optional to instantiate the plugin.
@@ -188,7 +400,194 @@ impl Plugin {
         let plugin = self.inner.as_ptr();
         let inner = unsafe { lib::lilv_plugin_get_optional_features(plugin) };
         let world = self.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: false,
+            life: world,
+        }
+    }
+
+    /// Check that all the features required by the plugin are provided.
+    ///
+    /// # Errors
+    /// Returns the URIs of the required features that are missing from
+    /// `provided`.
+    pub fn check_features(&self, provided: &FeatureSet) -> Result<(), Vec<String>> {
+        let missing = self.missing_features(|uri| provided.contains(uri));
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    /// Returns `true` if the host can instantiate and run the plugin: it
+    /// provides or declares all the required features and can connect every
+    /// port that is not `lv2:connectionOptional`.
+    #[must_use]
+    pub fn is_supported_by(&self, features: &FeatureSet, caps: &HostCaps) -> bool {
+        self.check_caps(features, caps).is_ok()
+    }
+
+    /// Check that a host with `features` and `caps` can instantiate and run
+    /// the plugin. The features that `HostCaps::assemble` keeps count, and
+    /// the ones that `caps` declares.
+    ///
+    /// # Errors
+    /// Returns `InstantiateError::UnsupportedPorts` with the ports that must
+    /// be connected but have a type the host can not connect,
+    /// `InstantiateError::MissingFeatures` with the required features that
+    /// the host does not provide, or `InstantiateError::PrunedFeatures` with
+    /// the required features that `caps` leaves out and why.
+    pub fn check_caps(
+        &self,
+        features: &FeatureSet,
+        caps: &HostCaps,
+    ) -> Result<(), InstantiateError> {
+        let unsupported: Vec<String> = self
+            .iter_ports()
+            .filter(|port| !port.is_connection_optional())
+            .filter_map(|port| {
+                let kind = port.kind();
+                if caps.supports_port_kind(&kind) {
+                    return None;
+                }
+                let kind = match &kind {
+                    PortKind::Audio => "audio".to_string(),
+                    PortKind::Control => "control".to_string(),
+                    PortKind::Cv => "CV".to_string(),
+                    PortKind::Atom => "atom".to_string(),
+                    PortKind::Event => "event".to_string(),
+                    PortKind::Unknown(class) => class.turtle_token(),
+                };
+                let symbol = port.symbol();
+                let symbol = symbol.as_ref().and_then(Node::as_str).unwrap_or_default();
+                Some(format!("{} ({})", symbol, kind))
+            })
+            .collect();
+        if !unsupported.is_empty() {
+            return Err(InstantiateError::UnsupportedPorts(unsupported));
+        }
+        let assembled = caps.assemble(features);
+        let (pruned, missing): (Vec<String>, Vec<String>) = self
+            .missing_features(|uri| assembled.contains(uri) || caps.declares(uri))
+            .into_iter()
+            .partition(|uri| features.contains(uri));
+        if !missing.is_empty() {
+            return Err(InstantiateError::MissingFeatures(missing));
+        }
+        if !pruned.is_empty() {
+            let pruned = pruned
+                .into_iter()
+                .map(|uri| {
+                    let reason = caps.prune_reason(&uri).unwrap_or_default();
+                    format!("{} ({})", uri, reason)
+                })
+                .collect();
+            return Err(InstantiateError::PrunedFeatures(pruned));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the plugin can not process data in place, that is with
+    /// the same buffer connected to an input and an output port. Hosts must
+    /// connect separate buffers for such plugins.
+    #[must_use]
+    pub fn in_place_broken(&self) -> bool {
+        self.supports_feature_uri(LV2_CORE_IN_PLACE_BROKEN)
+    }
+
+    /// Summarize the declared properties of the plugin that affect how a host
+    /// should run it.
+    #[must_use]
+    pub fn features_summary(&self) -> FeaturesSummary {
+        let features: Vec<String> = self
+            .supported_features()
+            .iter()
+            .filter_map(|feature| feature.as_uri().map(str::to_string))
+            .collect();
+        let required: Vec<String> = self
+            .required_features()
+            .iter()
+            .filter_map(|feature| feature.as_uri().map(str::to_string))
+            .collect();
+        let has = |uri: &str| features.iter().any(|f| f == uri);
+        // A plugin that merely supports a block length constraint can still
+        // be run with any block length.
+        let requires = |uri: &str| required.iter().any(|f| f == uri);
+        FeaturesSummary {
+            hard_rt_capable: has(LV2_CORE_HARD_RT_CAPABLE),
+            in_place_broken: has(LV2_CORE_IN_PLACE_BROKEN),
+            is_live: has(LV2_CORE_IS_LIVE),
+            fixed_block_length: requires(LV2_BUF_SIZE_FIXED_BLOCK_LENGTH),
+            power_of_2_block_length: requires(LV2_BUF_SIZE_POWER_OF_2_BLOCK_LENGTH),
+            latency_port_index: self.latency_port_index(),
+        }
+    }
+
+    /// List the ports to connect before running an instance, in index order.
+    ///
+    /// Every port must be connected unless it is `optional`. Once the ports
+    /// are connected, `Instance::verify_complete` returns the ports that were
+    /// missed.
+    #[must_use]
+    pub fn connection_plan(&self) -> Vec<PortConnection> {
+        self.iter_ports()
+            .map(|port| PortConnection {
+                index: port.index(),
+                kind: port.kind(),
+                direction: port.direction(),
+                optional: port.is_connection_optional(),
+            })
+            .collect()
+    }
+
+    /// Describe the input control ports and writable patch parameters of the
+    /// plugin, ports first in index order, for hosts that generate a UI.
+    #[must_use]
+    pub fn control_model(&self) -> Vec<ControlInfo> {
+        ControlInfo::capture_all(self)
+    }
+
+    /// Get the metadata of the MOD extensions of the plugin.
+    #[cfg(feature = "mod-ext")]
+    #[must_use]
+    pub fn mod_metadata(&self) -> ModMetadata {
+        let world = self.world();
+        let string = |predicate: &'static str| {
+            self.first_value(&world.new_static_uri(predicate))
+                .and_then(|node| node.as_str().map(str::to_string))
+        };
+        ModMetadata {
+            label: string(MOD_LABEL),
+            brand: string(MOD_BRAND),
+            has_modgui: self
+                .first_value(&world.new_static_uri(MODGUI_GUI))
+                .is_some(),
+        }
+    }
+
+    /// Count the ports of the plugin by type and direction. Ports of other
+    /// types or without a direction are not counted.
+    #[must_use]
+    pub fn port_summary(&self) -> PortSummary {
+        let mut summary = PortSummary::default();
+        for port in self.iter_ports() {
+            let input = match port.direction() {
+                Some(PortDirection::Input) => true,
+                Some(PortDirection::Output) => false,
+                None => continue,
+            };
+            let (inputs, outputs) = match port.kind() {
+                PortKind::Audio => (&mut summary.audio_in, &mut summary.audio_out),
+                PortKind::Cv => (&mut summary.cv_in, &mut summary.cv_out),
+                PortKind::Control => (&mut summary.control_in, &mut summary.control_out),
+                PortKind::Atom => (&mut summary.atom_in, &mut summary.atom_out),
+                _ => continue,
+            };
+            *if input { inputs } else { outputs } += 1;
+        }
+        summary
     }
 
     /// Returns `true` if the plugin has extension data for `uri`.
@@ -210,7 +609,11 @@ impl Plugin {
         Some({
             let inner = unsafe { lib::lilv_plugin_get_extension_data(plugin) };
             let world = self.life.clone();
-            Nodes { inner, life: world }
+            Nodes {
+                inner,
+                borrowed: false,
+                life: world,
+            }
         })
     }
 
@@ -360,6 +763,80 @@ impl Plugin {
         })
     }
 
+    /// Get the first value of a property of the plugin converted to `T`, like
+    /// `Node`, `String` or `f32`.
+    ///
+    /// `None` is returned if there is no value or it can not be converted to
+    /// `T`.
+    #[must_use]
+    pub fn get<T: FromNode>(&self, predicate: &Node) -> Option<T> {
+        T::from_owned_node(self.first_value(predicate)?)
+    }
+
+    /// A hash of the identity of the plugin that is stable across runs and
+    /// machines.
+    ///
+    /// The hash combines the URI, `lv2:minorVersion`, `lv2:microVersion` and
+    /// the index, symbol and classes of every port. Session files can store it
+    /// to detect that a plugin changed since the session was saved.
+    #[must_use]
+    pub fn identity_hash(&self) -> u64 {
+        let world = self.world();
+        let minor: Option<i32> = self.get(&world.new_static_uri(LV2_CORE_MINOR_VERSION));
+        let micro: Option<i32> = self.get(&world.new_static_uri(LV2_CORE_MICRO_VERSION));
+
+        let mut hash = Fnv1a::default();
+        hash.write(self.uri().as_uri().unwrap_or_default().as_bytes());
+        hash.write(&minor.unwrap_or(0).to_le_bytes());
+        hash.write(&micro.unwrap_or(0).to_le_bytes());
+        for port in self.iter_ports() {
+            hash.write(&(port.index() as u64).to_le_bytes());
+            let symbol = port.symbol();
+            hash.write(
+                symbol
+                    .as_ref()
+                    .and_then(Node::as_str)
+                    .unwrap_or_default()
+                    .as_bytes(),
+            );
+            let mut classes: Vec<String> = port
+                .classes()
+                .iter()
+                .filter_map(|class| class.as_uri().map(str::to_string))
+                .collect();
+            classes.sort();
+            for class in classes {
+                hash.write(class.as_bytes());
+            }
+        }
+        hash.finish()
+    }
+
+    /// The URI of the license of the plugin, or of its project if the plugin
+    /// does not declare one.
+    #[must_use]
+    pub fn license(&self) -> Option<String> {
+        let world = self.world();
+        let license = world.new_static_uri(DOAP_LICENSE);
+        self.first_value(&license)
+            .or_else(|| world.get(Some(&self.project()?), Some(&license), None))
+            .and_then(|license| license.as_uri().map(str::to_string))
+    }
+
+    /// A short description of the plugin from `rdfs:comment`.
+    #[must_use]
+    pub fn comment(&self) -> Option<String> {
+        self.first_value(&self.world().new_static_uri(RDFS_COMMENT))
+            .and_then(|comment| comment.as_str().map(str::to_string))
+    }
+
+    /// The documentation of the plugin from `lv2:documentation`.
+    #[must_use]
+    pub fn documentation(&self) -> Option<Documentation> {
+        self.first_value(&self.world().new_static_uri(LV2_CORE_DOCUMENTATION))
+            .and_then(|doc| doc.as_str().map(Documentation::new))
+    }
+
     /// Get the project the plugin is a part of.
     ///
     /// More information about the project can be read with `World::find_nodes`.
@@ -438,6 +915,15 @@ impl Plugin {
         unsafe { lib::lilv_plugin_is_replaced(plugin) }
     }
 
+    /// The plugin that replaces this one with `dc:replaces`, if any.
+    ///
+    /// Like `is_replaced`, this only sees `dc:replaces` statements from the
+    /// manifest of the replacing plugin.
+    #[must_use]
+    pub fn replaced_by(&self) -> Option<Node> {
+        replacement(&self.world(), &self.uri())
+    }
+
     /// Get the resources related to plugin with lv2:appliesTo.
     ///
     /// Some plugin-related resources are not linked directly to the plugin with
@@ -459,7 +945,11 @@ impl Plugin {
         Some({
             let inner = unsafe { lib::lilv_plugin_get_related(plugin, plugin_type) };
             let world = self.life.clone();
-            Nodes { inner, life: world }
+            Nodes {
+                inner,
+                borrowed: false,
+                life: world,
+            }
         })
     }
 
@@ -478,28 +968,117 @@ impl Plugin {
 
     /// Instantiate a plugin.
     ///
+    /// The features required by the plugin are checked against `features`
+    /// before any plugin code is called.
+    ///
     /// # Safety
     /// Instantiating a plugin calls the plugin's code which itself may be
-    /// unsafe.
-    #[must_use]
-    pub unsafe fn instantiate<'a, FS>(&self, sample_rate: f64, features: FS) -> Option<Instance>
+    /// unsafe. All features must have a valid URI.
+    ///
+    /// # Errors
+    /// Returns `InstantiateError::MissingFeatures` if some required features
+    /// are not provided and `InstantiateError::Failed` if lilv or the plugin
+    /// could not create the instance. In the latter case, lilv may print more
+    /// details (like a library that could not be loaded) to stderr.
+    pub unsafe fn instantiate<'a, FS>(
+        &self,
+        sample_rate: f64,
+        features: FS,
+    ) -> Result<Instance, InstantiateError>
     where
         FS: IntoIterator<Item = &'a LV2Feature>,
     {
-        let _life = self.life.inner.lock();
-        let plugin = self.inner.as_ptr();
         let features_vec: Vec<*const LV2Feature> = features
             .into_iter()
             .map(|f| f as *const LV2Feature)
             .chain(std::iter::once(std::ptr::null()))
             .collect();
-        let inner = NonNull::new(lib::lilv_plugin_instantiate(
-            plugin,
-            sample_rate,
-            features_vec.as_ptr(),
-        ))?;
+        let provided: Vec<&CStr> = features_vec
+            .iter()
+            .filter_map(|f| unsafe { f.as_ref() })
+            .map(|f| unsafe { CStr::from_ptr(f.uri) })
+            .collect();
+        let missing =
+            self.missing_features(|uri| provided.iter().any(|p| p.to_bytes() == uri.as_bytes()));
+        if !missing.is_empty() {
+            return Err(InstantiateError::MissingFeatures(missing));
+        }
+
+        let required = self
+            .iter_ports()
+            .map(|port| !port.is_connection_optional())
+            .collect();
+        let outputs = self
+            .iter_ports()
+            .map(|port| match (port.direction(), port.kind()) {
+                (
+                    Some(PortDirection::Output),
+                    kind @ (PortKind::Audio | PortKind::Control | PortKind::Cv),
+                ) => Some(kind),
+                _ => None,
+            })
+            .collect();
 
-        Some(Instance { inner })
+        #[cfg(feature = "tracing")]
+        let uri = self.uri().to_string();
+        traced!("lilv::instantiate" (plugin = %uri, sample_rate) => {
+            let _life = self.life.inner.lock();
+            let plugin = self.inner.as_ptr();
+            let inner = NonNull::new(unsafe {
+                lib::lilv_plugin_instantiate(plugin, sample_rate, features_vec.as_ptr())
+            })
+            .ok_or(InstantiateError::Failed)?;
+
+            Ok(Instance::new(inner, required, outputs))
+        })
+    }
+
+    /// Instantiate a plugin with the features of a `FeatureSet` that are
+    /// compatible with `caps`, see `HostCaps::assemble`. Plugins that the
+    /// host can not run are refused before any plugin code is called.
+    ///
+    /// This is a safe version of `instantiate`. The feature data was already
+    /// vouched for by the unsafe `FeatureSet::with_feature_data`, which also
+    /// requires it to outlive the instance, and the plugin library is trusted
+    /// like any other library that gets loaded.
+    ///
+    /// # Errors
+    /// Same as `check_caps`, `InstantiateError::MissingFeatures` if a
+    /// required feature is only declared by `caps` but not in `features`, and
+    /// `InstantiateError::Failed` if the instance could not be created.
+    pub fn instantiate_with(
+        &self,
+        features: &FeatureSet,
+        caps: &HostCaps,
+        sample_rate: f64,
+    ) -> Result<Instance, InstantiateError> {
+        self.check_caps(features, caps)?;
+        let assembled = caps.assemble(features);
+        unsafe { self.instantiate(sample_rate, assembled.iter()) }
+    }
+
+    fn world(&self) -> World {
+        World {
+            life: self.life.clone(),
+        }
+    }
+
+    fn first_value(&self, predicate: &Node) -> Option<Node> {
+        self.value(predicate).into_iter().next()
+    }
+
+    fn supports_feature_uri(&self, uri: &str) -> bool {
+        self.supported_features()
+            .iter()
+            .any(|feature| feature.as_uri() == Some(uri))
+    }
+
+    fn missing_features(&self, is_provided: impl Fn(&str) -> bool) -> Vec<String> {
+        self.required_features()
+            .iter()
+            .filter_map(|feature| feature.as_uri().map(str::to_string))
+            .filter(|uri| !is_provided(uri))
+            .collect()
     }
 }
 
@@ -515,6 +1094,7 @@ impl Debug for Plugin {
             .field("optional_features", &self.optional_features())
             .field("ports_count", &self.ports_count())
             .field("has_latency", &self.has_latency())
+            .field("in_place_broken", &self.in_place_broken())
             .field("project", &self.project())
             .field("author_name", &self.author_name())
             .field("author_email", &self.author_email())
@@ -524,12 +1104,27 @@ impl Debug for Plugin {
     }
 }
 
+impl std::fmt::Display for Plugin {
+    /// Formats the name and URI of the plugin, like `Amp <http://...>`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} <{}>", self.name(), self.uri())
+    }
+}
+
 /// A collection of plugins.
 pub struct Plugins {
     pub(crate) life: Arc<Life>,
     pub(crate) ptr: *const lib::LilvPlugins,
 }
 
+impl Debug for Plugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugins")
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
 impl Plugins {
     /// An iterable over all the plugins in the world.
     pub fn iter(&self) -> impl '_ + Iterator<Item = Plugin> {
@@ -560,6 +1155,77 @@ impl Plugins {
         let size = unsafe { lib::lilv_plugins_size(self.ptr) };
         size as usize
     }
+
+    /// The plugins sorted by URI.
+    ///
+    /// The order of `iter` depends on how lilv stores the plugins, so use
+    /// this for output that must be reproducible, like snapshots or session
+    /// files.
+    #[must_use]
+    pub fn sorted_by_uri(&self) -> Vec<Plugin> {
+        let mut plugins: Vec<Plugin> = self.iter().collect();
+        plugins.sort_by_cached_key(|plugin| plugin.uri().turtle_token());
+        plugins
+    }
+
+    /// The plugins sorted by name, and by URI for plugins with the same name.
+    #[must_use]
+    pub fn sorted_by_name(&self) -> Vec<Plugin> {
+        let mut plugins: Vec<Plugin> = self.iter().collect();
+        plugins.sort_by_cached_key(|plugin| {
+            let name = plugin.name().as_str().unwrap_or_default().to_string();
+            (name, plugin.uri().turtle_token())
+        });
+        plugins
+    }
+
+    /// The plugins that the host can instantiate and run, see
+    /// `Plugin::is_supported_by`.
+    #[must_use]
+    pub fn supported_by(&self, features: &FeatureSet, caps: &HostCaps) -> Vec<Plugin> {
+        self.iter()
+            .filter(|plugin| plugin.is_supported_by(features, caps))
+            .collect()
+    }
+
+    /// Get the plugin to load in place of the plugin `uri`, which is the
+    /// newest loaded plugin in its chain of `dc:replaces` successors. This is
+    /// the plugin itself if it has not been replaced, and `None` if neither
+    /// the plugin nor a successor is loaded.
+    ///
+    /// Session loaders can use it to substitute plugins that were replaced
+    /// since the session was saved.
+    #[must_use]
+    pub fn resolve_replacement(&self, uri: &Node) -> Option<Plugin> {
+        let world = World {
+            life: self.life.clone(),
+        };
+        let mut resolved = self.plugin(uri);
+        let mut seen = vec![uri.turtle_token()];
+        let mut current = replacement(&world, uri);
+        while let Some(successor) = current {
+            let token = successor.turtle_token();
+            if seen.contains(&token) {
+                break;
+            }
+            seen.push(token);
+            if let Some(plugin) = self.plugin(&successor) {
+                resolved = Some(plugin);
+            }
+            current = replacement(&world, &successor);
+        }
+        resolved
+    }
+}
+
+// The subject of a `dc:replaces` statement with `uri` as the object, other
+// than `uri` itself.
+fn replacement(world: &World, uri: &Node) -> Option<Node> {
+    let replaces = world.new_static_uri(DCTERMS_REPLACES);
+    world
+        .find_nodes(None, &replaces, Some(uri))
+        .iter()
+        .find(|node| node != uri)
 }
 
 impl IntoIterator for Plugins {
@@ -718,12 +1384,25 @@ impl Debug for Class {
     }
 }
 
+impl std::fmt::Display for Class {
+    /// Formats the label of the class, like "Reverb".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
 /// A collection of plugin classes.
 pub struct Classes {
     pub(crate) inner: NonNull<lib::LilvPluginClasses>,
     pub(crate) life: Arc<Life>,
 }
 
+impl Debug for Classes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
 impl Classes {
     /// An iterable over all the plugin classes in the world.
     #[must_use]
@@ -772,7 +1451,6 @@ pub struct ClassIter {
 impl Iterator for ClassIter {
     type Item = Class;
 
-    #[must_use]
     fn next(&mut self) -> Option<Class> {
         let _life = self.life.inner.lock();
         let ptr = unsafe { lib::lilv_plugin_classes_get(self.classes, self.iter) };
@@ -794,6 +1472,11 @@ impl Iterator for ClassIter {
 
 #[cfg(test)]
 mod tests {
+    use crate::feature::{FeatureSet, HostCaps};
+    use crate::instance::InstantiateError;
+    use crate::node::Node;
+    use crate::plugin::{Documentation, DocumentationFormat, PortSummary};
+    use crate::port::{PortDirection, PortKind};
     use crate::world::World;
 
     #[test]
@@ -804,4 +1487,396 @@ mod tests {
             _ = format!("{:?}", plugin);
         }
     }
+
+    #[test]
+    fn test_check_features() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::SAMPLER_URI).unwrap();
+        let plugin = world
+            .plugins()
+            .plugin(&uri)
+            .unwrap_or_else(|| panic!("Could not find plugin {:?}", uri));
+        let missing = plugin.check_features(&FeatureSet::new()).unwrap_err();
+        assert!(missing.contains(&"http://lv2plug.in/ns/ext/urid#map".to_string()));
+
+        let features = missing
+            .iter()
+            .try_fold(FeatureSet::new(), |features, uri| {
+                features.with_feature(uri)
+            })
+            .unwrap();
+        assert_eq!(plugin.check_features(&features), Ok(()));
+    }
+
+    #[test]
+    fn test_supported_by() {
+        let world = crate::testing::fixture_world().unwrap();
+        let caps = HostCaps::new();
+        let supported: Vec<String> = world
+            .plugins()
+            .supported_by(&FeatureSet::new(), &caps)
+            .iter()
+            .map(|plugin| plugin.uri().turtle_token())
+            .collect();
+        assert!(supported.contains(&format!("<{}>", crate::testing::GAIN_URI)));
+        // eg-metro needs urid:map and connects an atom port.
+        let metro = world.new_uri(crate::testing::MIDI_THROUGH_URI).unwrap();
+        let metro = world.plugins().plugin(&metro).unwrap();
+        let features = FeatureSet::new()
+            .with_feature("http://lv2plug.in/ns/ext/urid#map")
+            .unwrap();
+        assert!(!metro.is_supported_by(&features, &caps));
+        assert!(metro.is_supported_by(&features, &caps.with_port_kind(PortKind::Atom)));
+    }
+
+    #[test]
+    fn test_instantiate_with() {
+        let world = crate::testing::fixture_world().unwrap();
+        let amp = world
+            .plugins()
+            .plugin(&world.new_uri(crate::testing::GAIN_URI).unwrap())
+            .unwrap();
+        let caps = HostCaps::new();
+        assert!(amp
+            .instantiate_with(&FeatureSet::new(), &caps, 44100.0)
+            .is_ok());
+
+        let sampler = world
+            .plugins()
+            .plugin(&world.new_uri(crate::testing::SAMPLER_URI).unwrap())
+            .unwrap();
+        match sampler.instantiate_with(&FeatureSet::new(), &caps, 44100.0) {
+            Err(crate::instance::InstantiateError::MissingFeatures(missing)) => {
+                assert!(missing.contains(&"http://lv2plug.in/ns/ext/urid#map".to_string()));
+            }
+            _ => panic!("eg-sampler was instantiated without urid:map"),
+        }
+    }
+
+    #[test]
+    fn test_check_caps() {
+        let world = crate::testing::fixture_world().unwrap();
+        let amp = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let amp = world.plugins().plugin(&amp).unwrap();
+        let caps = HostCaps::new().with_fixed_block_length(64);
+        assert!(amp
+            .instantiate_with(&FeatureSet::new(), &caps, 44100.0)
+            .is_ok());
+
+        let sampler = world.new_uri(crate::testing::SAMPLER_URI).unwrap();
+        let sampler = world.plugins().plugin(&sampler).unwrap();
+        // The features are only checked, so they need no data.
+        let features = FeatureSet::new()
+            .with_feature("http://lv2plug.in/ns/ext/urid#map")
+            .unwrap()
+            .with_feature("http://lv2plug.in/ns/ext/state#loadDefaultState")
+            .unwrap();
+        match sampler.check_caps(&features, &caps) {
+            Err(InstantiateError::UnsupportedPorts(ports)) => {
+                assert!(ports.contains(&"control (atom)".to_string()), "{:?}", ports);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        let caps = caps.with_port_kind(PortKind::Atom);
+        match sampler.check_caps(&features, &caps) {
+            Err(InstantiateError::MissingFeatures(missing)) => {
+                let worker = "http://lv2plug.in/ns/ext/worker#schedule".to_string();
+                assert_eq!(missing, vec![worker]);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(
+            sampler.check_caps(&features, &caps.clone().with_worker()),
+            Ok(())
+        );
+        // A provided worker counts without `with_worker`.
+        let features = features
+            .with_feature("http://lv2plug.in/ns/ext/worker#schedule")
+            .unwrap();
+        assert!(sampler.is_supported_by(&features, &caps));
+    }
+
+    #[test]
+    fn test_features_summary() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world
+            .plugins()
+            .plugin(&uri)
+            .unwrap_or_else(|| panic!("Could not find plugin {:?}", uri));
+        let summary = plugin.features_summary();
+        assert!(summary.hard_rt_capable);
+        assert!(!summary.in_place_broken);
+        assert!(!summary.fixed_block_length);
+        assert!(!plugin.in_place_broken());
+        assert_eq!(summary.latency_port_index, None);
+
+        // Only required block length features constrain the block length.
+        let dir = crate::testing::TempDir::new("features-summary").unwrap();
+        let bundle = dir.join("blocks.lv2");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(
+            bundle.join("manifest.ttl"),
+            "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+             @prefix bufsz: <http://lv2plug.in/ns/ext/buf-size#> .\n\
+             <urn:lilv-rs:test:blocks> a lv2:Plugin ;\n\
+             lv2:optionalFeature bufsz:fixedBlockLength ;\n\
+             lv2:requiredFeature bufsz:powerOf2BlockLength .\n",
+        )
+        .unwrap();
+        let world = World::new();
+        world.load_bundle_dir(&bundle).unwrap();
+        let uri = world.new_uri("urn:lilv-rs:test:blocks").unwrap();
+        let summary = world.plugins().plugin(&uri).unwrap().features_summary();
+        assert!(!summary.fixed_block_length);
+        assert!(summary.power_of_2_block_length);
+    }
+
+    #[test]
+    fn test_connection_plan() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let plan = plugin.connection_plan();
+        assert_eq!(
+            plan.iter()
+                .map(|port| (port.index, port.kind.clone(), port.direction))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, PortKind::Control, Some(PortDirection::Input)),
+                (1, PortKind::Audio, Some(PortDirection::Input)),
+                (2, PortKind::Audio, Some(PortDirection::Output)),
+            ]
+        );
+        assert!(plan.iter().all(|port| !port.optional));
+    }
+
+    #[test]
+    #[cfg(feature = "mod-ext")]
+    fn test_mod_metadata() {
+        let world = crate::testing::fixture_world().unwrap();
+        let gain = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let gain = world.plugins().plugin(&gain).unwrap();
+        assert_eq!(
+            gain.mod_metadata(),
+            crate::plugin::ModMetadata {
+                label: Some("Gain".to_string()),
+                brand: Some("lilv-rs".to_string()),
+                has_modgui: false,
+            }
+        );
+        let sampler = world.new_uri(crate::testing::SAMPLER_URI).unwrap();
+        let sampler = world.plugins().plugin(&sampler).unwrap();
+        assert_eq!(
+            sampler.mod_metadata(),
+            crate::plugin::ModMetadata::default()
+        );
+    }
+
+    #[test]
+    fn test_port_summary() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        assert_eq!(
+            plugin.port_summary(),
+            PortSummary {
+                audio_in: 1,
+                audio_out: 1,
+                control_in: 1,
+                ..PortSummary::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_data_files() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let files = plugin.data_files();
+        assert!(files[0].ends_with("gain.lv2/manifest.ttl"));
+        assert!(files.iter().any(|file| file.ends_with("gain.lv2/gain.ttl")));
+        assert!(plugin.missing_data_files().is_empty());
+    }
+
+    #[test]
+    fn test_sorted() {
+        let world = crate::testing::fixture_world().unwrap();
+        let plugins = world.plugins();
+        let uris: Vec<String> = plugins
+            .sorted_by_uri()
+            .iter()
+            .map(|plugin| plugin.uri().turtle_token())
+            .collect();
+        assert_eq!(uris.len(), plugins.count());
+        assert!(uris.windows(2).all(|w| w[0] <= w[1]));
+        let names: Vec<String> = plugins
+            .sorted_by_name()
+            .iter()
+            .map(|plugin| plugin.name().as_str().unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(names.len(), plugins.count());
+        assert!(names.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_replacement() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugins = world.plugins();
+        let plugin = plugins.plugin(&uri).unwrap();
+        assert!(!plugin.is_replaced());
+        assert_eq!(plugin.replaced_by(), None);
+        assert_eq!(
+            plugins.resolve_replacement(&uri).map(|plugin| plugin.uri()),
+            Some(uri)
+        );
+        let missing = world.new_uri("http://example.org/missing").unwrap();
+        assert!(plugins.resolve_replacement(&missing).is_none());
+
+        // `v3` replaces `v2`, which is not loaded and replaces `v1`, and `a`
+        // and `b` replace each other.
+        let dir = crate::testing::TempDir::new("replacement").unwrap();
+        let bundle = dir.join("replacement.lv2");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(
+            bundle.join("manifest.ttl"),
+            "@prefix dc: <http://purl.org/dc/terms/> .\n\
+             @prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+             <urn:lilv-rs:test:v1> a lv2:Plugin .\n\
+             <urn:lilv-rs:test:v2> dc:replaces <urn:lilv-rs:test:v1> .\n\
+             <urn:lilv-rs:test:v3> a lv2:Plugin ;\n\
+             dc:replaces <urn:lilv-rs:test:v2> .\n\
+             <urn:lilv-rs:test:a> a lv2:Plugin ;\n\
+             dc:replaces <urn:lilv-rs:test:b> .\n\
+             <urn:lilv-rs:test:b> a lv2:Plugin ;\n\
+             dc:replaces <urn:lilv-rs:test:a> .\n",
+        )
+        .unwrap();
+        let world = World::new();
+        world.load_bundle_dir(&bundle).unwrap();
+        let plugins = world.plugins();
+        let uri = |name: &str| {
+            world
+                .new_uri(&format!("urn:lilv-rs:test:{}", name))
+                .unwrap()
+        };
+        let resolve = |name: &str| plugins.resolve_replacement(&uri(name)).map(|p| p.uri());
+        let v1 = plugins.plugin(&uri("v1")).unwrap();
+        assert_eq!(v1.replaced_by(), Some(uri("v2")));
+        assert_eq!(resolve("v1"), Some(uri("v3")));
+        assert_eq!(resolve("v2"), Some(uri("v3")));
+        assert_eq!(resolve("v3"), Some(uri("v3")));
+        // The cycle ends at the first plugin that was already seen.
+        assert_eq!(resolve("a"), Some(uri("b")));
+        assert_eq!(resolve("b"), Some(uri("a")));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world
+            .plugins()
+            .plugin(&uri)
+            .unwrap_or_else(|| panic!("Could not find plugin {:?}", uri));
+        assert_eq!(
+            plugin.license().as_deref(),
+            Some("http://opensource.org/licenses/isc")
+        );
+        assert_eq!(
+            plugin.comment().as_deref(),
+            Some("Scales its input by a gain in dB.")
+        );
+        assert_eq!(
+            plugin.documentation(),
+            Some(Documentation {
+                text: "# Fixture Gain\n\n- `gain` is in dB, and -90 dB or less is silence.\n"
+                    .to_string(),
+                format: DocumentationFormat::Markdown,
+            })
+        );
+        let sampler = world.new_uri(crate::testing::SAMPLER_URI).unwrap();
+        let sampler = world.plugins().plugin(&sampler).unwrap();
+        assert_eq!(sampler.comment(), None);
+        assert_eq!(sampler.documentation(), None);
+    }
+
+    #[test]
+    fn test_identity_hash() {
+        let world = crate::testing::fixture_world().unwrap();
+        let amp = world
+            .plugins()
+            .plugin(&world.new_uri(crate::testing::GAIN_URI).unwrap())
+            .unwrap();
+        let sampler = world
+            .plugins()
+            .plugin(&world.new_uri(crate::testing::SAMPLER_URI).unwrap())
+            .unwrap();
+        assert_eq!(amp.identity_hash(), amp.clone().identity_hash());
+        assert_ne!(amp.identity_hash(), sampler.identity_hash());
+        // Session files store the hash, so it must not change between
+        // releases for the same plugin.
+        assert_eq!(amp.identity_hash(), 0xea33_e1ed_46db_bb94);
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        let hash = |fields: &[&str]| {
+            let mut hash = super::Fnv1a::default();
+            for field in fields {
+                hash.write(field.as_bytes());
+            }
+            hash.finish()
+        };
+        assert_eq!(hash(&["ab", "c"]), hash(&["ab", "c"]));
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+    }
+
+    #[test]
+    fn test_typed_get() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world
+            .plugins()
+            .plugin(&uri)
+            .unwrap_or_else(|| panic!("Could not find plugin {:?}", uri));
+        let license = world
+            .new_uri("http://usefulinc.com/ns/doap#license")
+            .unwrap();
+        assert_eq!(
+            plugin.get::<String>(&license).as_deref(),
+            Some("http://opensource.org/licenses/isc")
+        );
+        assert_eq!(plugin.get::<f32>(&license), None);
+
+        let port = plugin.port_by_index(0).unwrap();
+        let default = world
+            .new_uri("http://lv2plug.in/ns/lv2core#default")
+            .unwrap();
+        let minimum = world
+            .new_uri("http://lv2plug.in/ns/lv2core#minimum")
+            .unwrap();
+        assert_eq!(port.get::<f32>(&default), Some(0.0));
+        assert_eq!(port.get::<f32>(&minimum), Some(-90.0));
+        assert!(port.get::<Node>(&default).is_some());
+    }
+
+    #[test]
+    fn test_documentation_format() {
+        use super::DocumentationFormat;
+        assert_eq!(
+            DocumentationFormat::detect("A simple amplifier."),
+            DocumentationFormat::Plain
+        );
+        assert_eq!(
+            DocumentationFormat::detect("<p>A <em>simple</em> amplifier.</p>"),
+            DocumentationFormat::Html
+        );
+        assert_eq!(
+            DocumentationFormat::detect("Amplifies.\n\n* Gain in [dB](https://example.org)"),
+            DocumentationFormat::Markdown
+        );
+    }
 }
@@ -1,9 +1,52 @@
-use crate::node::{Node, Nodes};
+use crate::node::{FromNode, Node, Nodes};
 use crate::plugin::Plugin;
+use crate::world::World;
 use lilv_sys as lib;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::ptr::NonNull;
 
+const LV2_CORE_PORT: &str = "http://lv2plug.in/ns/lv2core#Port";
+const LV2_CORE_INPUT_PORT: &str = "http://lv2plug.in/ns/lv2core#InputPort";
+const LV2_CORE_OUTPUT_PORT: &str = "http://lv2plug.in/ns/lv2core#OutputPort";
+const LV2_CORE_AUDIO_PORT: &str = "http://lv2plug.in/ns/lv2core#AudioPort";
+const LV2_CORE_CONTROL_PORT: &str = "http://lv2plug.in/ns/lv2core#ControlPort";
+const LV2_CORE_CV_PORT: &str = "http://lv2plug.in/ns/lv2core#CVPort";
+const LV2_ATOM_ATOM_PORT: &str = "http://lv2plug.in/ns/ext/atom#AtomPort";
+const LV2_EVENT_EVENT_PORT: &str = "http://lv2plug.in/ns/ext/event#EventPort";
+const LV2_CORE_CONNECTION_OPTIONAL: &str = "http://lv2plug.in/ns/lv2core#connectionOptional";
+pub(crate) const LV2_CORE_ENUMERATION: &str = "http://lv2plug.in/ns/lv2core#enumeration";
+
+/// Whether a port is read or written by the plugin.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PortDirection {
+    /// An `lv2:InputPort`, read by the plugin.
+    Input,
+    /// An `lv2:OutputPort`, written by the plugin.
+    Output,
+}
+
+/// The type of data a port carries.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PortKind {
+    /// An `lv2:AudioPort`, connected to a buffer of `f32` samples.
+    Audio,
+    /// An `lv2:ControlPort`, connected to a single `f32`.
+    Control,
+    /// An `lv2:CVPort`, connected to a buffer of `f32` control values.
+    Cv,
+    /// An `atom:AtomPort`, usually connected to an atom sequence.
+    Atom,
+    /// An `ev:EventPort` of the deprecated event extension.
+    Event,
+    /// Any other type. Contains the first class of the port that is not a
+    /// direction, or `lv2:Port` if there is none.
+    Unknown(Node),
+}
+
 #[derive(Clone)]
 pub struct Port {
     pub(crate) inner: NonNull<lib::LilvPort>,
@@ -11,6 +54,12 @@ pub struct Port {
 }
 
 impl Port {
+    /// Get the plugin the port belongs to.
+    #[must_use]
+    pub fn plugin(&self) -> &Plugin {
+        &self.plugin
+    }
+
     /// Get the node for the port.
     ///
     /// # Panics
@@ -45,21 +94,27 @@ impl Port {
         let predicate = predicate.inner.as_ptr();
         let inner = unsafe { lib::lilv_port_get_value(plugin, port, predicate) };
         let world = self.plugin.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: false,
+            life: world,
+        }
     }
 
-    /// Get a single property value of a port.
+    /// Get a single property value of a port converted to `T`, like `Node`,
+    /// `String` or `f32`.
     ///
     /// This is equivalent to getting the first iterable value of
-    /// `self.value(predicate)`.
+    /// `self.value(predicate)`. `None` is returned if there is no value or it
+    /// can not be converted to `T`.
     #[must_use]
-    pub fn get(&self, predicate: &Node) -> Option<Node> {
-        let _life = self.plugin.life.inner.lock();
-        let plugin = self.plugin.inner.as_ptr();
-        let port = self.inner.as_ptr();
-        let predicate = predicate.inner.as_ptr();
+    pub fn get<T: FromNode>(&self, predicate: &Node) -> Option<T> {
+        let node = {
+            let _life = self.plugin.life.inner.lock();
+            let plugin = self.plugin.inner.as_ptr();
+            let port = self.inner.as_ptr();
+            let predicate = predicate.inner.as_ptr();
 
-        Some({
             let ptr = NonNull::new(unsafe { lib::lilv_port_get(plugin, port, predicate) })?;
             let world = self.plugin.life.clone();
             Node {
@@ -67,7 +122,8 @@ impl Port {
                 borrowed: false,
                 life: world,
             }
-        })
+        };
+        T::from_owned_node(node)
     }
 
     /// Return the LV2 port properties of a port.
@@ -78,7 +134,11 @@ impl Port {
         let port = self.inner.as_ptr();
         let inner = unsafe { lib::lilv_port_get_properties(plugin, port) };
         let world = self.plugin.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: false,
+            life: world,
+        }
     }
 
     /// Returns true if the port has the given property.
@@ -92,6 +152,28 @@ impl Port {
         unsafe { lib::lilv_port_has_property(plugin, port, property_uri) }
     }
 
+    /// Returns `true` if the port has the `lv2:connectionOptional` property,
+    /// so the instance may be run while the port is not connected.
+    #[must_use]
+    pub fn is_connection_optional(&self) -> bool {
+        let optional = World {
+            life: self.plugin.life.clone(),
+        }
+        .new_static_uri(LV2_CORE_CONNECTION_OPTIONAL);
+        self.has_property(&optional)
+    }
+
+    /// Returns `true` if the port has the `lv2:enumeration` property, so it
+    /// only takes the values of its scale points.
+    #[must_use]
+    pub fn is_enumeration(&self) -> bool {
+        let enumeration = World {
+            life: self.plugin.life.clone(),
+        }
+        .new_static_uri(LV2_CORE_ENUMERATION);
+        self.has_property(&enumeration)
+    }
+
     /// Returns `true` if the port supports a certain event type.
     ///
     /// More precisely, this returns `true` if and only iff the port has an
@@ -170,7 +252,51 @@ impl Port {
         let port = self.inner.as_ptr();
         let inner = unsafe { lib::lilv_port_get_classes(plugin, port) };
         let world = self.plugin.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: true,
+            life: world,
+        }
+    }
+
+    /// The direction of the port, or `None` if the data declares neither
+    /// `lv2:InputPort` nor `lv2:OutputPort`, which is not valid LV2.
+    #[must_use]
+    pub fn direction(&self) -> Option<PortDirection> {
+        self.classes()
+            .iter()
+            .find_map(|class| match class.as_uri() {
+                Some(LV2_CORE_INPUT_PORT) => Some(PortDirection::Input),
+                Some(LV2_CORE_OUTPUT_PORT) => Some(PortDirection::Output),
+                _ => None,
+            })
+    }
+
+    /// The type of data the port carries, from the classes of the port.
+    #[must_use]
+    pub fn kind(&self) -> PortKind {
+        let mut unknown = None;
+        for class in self.classes().iter() {
+            let kind = match class.as_uri() {
+                Some(LV2_CORE_AUDIO_PORT) => PortKind::Audio,
+                Some(LV2_CORE_CONTROL_PORT) => PortKind::Control,
+                Some(LV2_CORE_CV_PORT) => PortKind::Cv,
+                Some(LV2_ATOM_ATOM_PORT) => PortKind::Atom,
+                Some(LV2_EVENT_EVENT_PORT) => PortKind::Event,
+                Some(LV2_CORE_INPUT_PORT | LV2_CORE_OUTPUT_PORT) => continue,
+                _ => {
+                    unknown.get_or_insert(class);
+                    continue;
+                }
+            };
+            return kind;
+        }
+        PortKind::Unknown(unknown.unwrap_or_else(|| {
+            World {
+                life: self.plugin.life.clone(),
+            }
+            .new_static_uri(LV2_CORE_PORT)
+        }))
     }
 
     /// Returns `true` if the port is of the given type.
@@ -239,6 +365,102 @@ impl Port {
             refs: std::sync::Arc::new(1.into()),
         }
     }
+
+    /// Get the scale points of the port as a map from value to label.
+    ///
+    /// Scale points with a non numeric value or a non string label are
+    /// skipped.
+    #[must_use]
+    pub fn scale_point_map(&self) -> BTreeMap<OrderedFloat, String> {
+        self.scale_point_entries().collect()
+    }
+
+    /// Get the label of the scale point with the given value or `None` if
+    /// there is no scale point with that exact value.
+    ///
+    /// Each call reads the scale points of the port, so build
+    /// `scale_point_map` once to look up many values.
+    #[must_use]
+    pub fn label_for_value(&self, value: f32) -> Option<String> {
+        // The last scale point with the value wins, like in the map.
+        self.scale_point_entries()
+            .filter(|(v, _)| *v == OrderedFloat(value))
+            .last()
+            .map(|(_, label)| label)
+    }
+
+    /// Get the value of the scale point with the given label or `None` if there
+    /// is no scale point with that label.
+    ///
+    /// Each call reads the scale points of the port, like `label_for_value`.
+    #[must_use]
+    pub fn value_for_label(&self, label: &str) -> Option<f32> {
+        self.scale_point_entries()
+            .filter(|(_, l)| l == label)
+            .map(|(value, _)| value)
+            .min()
+            .map(|value| value.0)
+    }
+
+    // The scale points with a numeric value and a string label, in the order
+    // of lilv.
+    fn scale_point_entries(&self) -> impl Iterator<Item = (OrderedFloat, String)> {
+        self.scale_points().into_iter().filter_map(|point| {
+            let value = point.value();
+            let value = value
+                .as_float()
+                .or_else(|| value.as_int().map(|v| v as f32))?;
+            let label = point.label().as_str()?.to_string();
+            Some((OrderedFloat(value), label))
+        })
+    }
+
+    /// Get the scale points of the port as `(value, label, ident)` sorted by
+    /// value, where `ident` is a unique CamelCase Rust identifier derived from
+    /// the label, like `LowPass` for `"Low pass"`.
+    ///
+    /// Scale points are skipped like in `scale_point_map`.
+    #[must_use]
+    pub fn enum_variants(&self) -> Vec<(f32, String, String)> {
+        let mut idents: Vec<String> = Vec::new();
+        self.scale_point_map()
+            .into_iter()
+            .map(|(value, label)| {
+                let base = camel_case_ident(&label);
+                let mut ident = base.clone();
+                let mut n = 1;
+                while idents.contains(&ident) {
+                    n += 1;
+                    ident = format!("{}{}", base, n);
+                }
+                idents.push(ident.clone());
+                (value.0, label, ident)
+            })
+            .collect()
+    }
+}
+
+// Converts `label` to a CamelCase identifier by capitalizing each run of
+// ASCII alphanumerics and dropping everything else.
+pub(crate) fn camel_case_ident(label: &str) -> String {
+    let mut ident: String = label
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, 'V');
+    }
+    if ident == "Self" {
+        ident.push('_');
+    }
+    ident
 }
 
 impl Debug for Port {
@@ -253,6 +475,20 @@ impl Debug for Port {
     }
 }
 
+impl std::fmt::Display for Port {
+    /// Formats the index, symbol and name of the port, like `0: gain "Gain"`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.index())?;
+        if let Some(symbol) = self.symbol() {
+            write!(f, " {}", symbol)?;
+        }
+        if let Some(name) = self.name() {
+            write!(f, " {:?}", name.to_string())?;
+        }
+        Ok(())
+    }
+}
+
 unsafe impl Sync for ScalePoint {}
 
 #[derive(Clone)]
@@ -266,6 +502,18 @@ pub struct ScalePoint {
 }
 
 impl ScalePoint {
+    /// Get the port the scale point belongs to.
+    #[must_use]
+    pub fn port(&self) -> &Port {
+        &self.port
+    }
+
+    /// Get the plugin of the port the scale point belongs to.
+    #[must_use]
+    pub fn plugin(&self) -> &Plugin {
+        &self.port.plugin
+    }
+
     /// Get the label of the scale point (enumeration value).
     ///
     /// # Panics
@@ -276,11 +524,15 @@ impl ScalePoint {
         let inner = self.inner.as_ptr();
 
         {
-            let ptr = NonNull::new(unsafe { lib::lilv_scale_point_get_label(inner) as _ }).unwrap();
+            // Copied since the node is freed with the scale points.
+            let ptr = NonNull::new(unsafe {
+                lib::lilv_node_duplicate(lib::lilv_scale_point_get_label(inner))
+            })
+            .unwrap();
             let world = self.port.plugin.life.clone();
             Node {
                 inner: ptr,
-                borrowed: true,
+                borrowed: false,
                 life: world,
             }
         }
@@ -296,11 +548,15 @@ impl ScalePoint {
         let inner = self.inner.as_ptr();
 
         {
-            let ptr = NonNull::new(unsafe { lib::lilv_scale_point_get_value(inner) as _ }).unwrap();
+            // Copied since the node is freed with the scale points.
+            let ptr = NonNull::new(unsafe {
+                lib::lilv_node_duplicate(lib::lilv_scale_point_get_value(inner))
+            })
+            .unwrap();
             let world = self.port.plugin.life.clone();
             Node {
                 inner: ptr,
-                borrowed: true,
+                borrowed: false,
                 life: world,
             }
         }
@@ -316,6 +572,13 @@ impl Debug for ScalePoint {
     }
 }
 
+impl std::fmt::Display for ScalePoint {
+    /// Formats the label and value of the scale point, like `Sine = 0`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.label(), self.value())
+    }
+}
+
 pub struct ScalePoints {
     pub(crate) inner: *const lib::LilvScalePoints,
     pub(crate) port: Port,
@@ -323,6 +586,12 @@ pub struct ScalePoints {
 }
 
 impl ScalePoints {
+    /// Get the port the scale points belong to.
+    #[must_use]
+    pub fn port(&self) -> &Port {
+        &self.port
+    }
+
     /// The number of scale points within the collection.
     #[must_use]
     pub fn count(&self) -> usize {
@@ -345,7 +614,8 @@ impl ScalePoints {
 impl Drop for ScalePoints {
     fn drop(&mut self) {
         let refs = self.refs.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
-        if refs == 0 {
+        if refs == 1 && !self.inner.is_null() {
+            let _life = self.port.plugin.life.inner.lock();
             unsafe {
                 lib::lilv_scale_points_free(self.inner as *mut _);
             }
@@ -429,3 +699,126 @@ pub struct FloatRanges {
     /// The maximum value of the port.
     pub max: f32,
 }
+
+/// An `f32` with a total order so it can be used as a map key.
+///
+/// Ordering follows `f32::total_cmp`.
+#[derive(Copy, Clone, Debug)]
+pub struct OrderedFloat(pub f32);
+
+impl PartialEq for OrderedFloat {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OrderedFloat {}
+
+impl PartialOrd for OrderedFloat {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedFloat {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordered_float() {
+        let mut values = vec![OrderedFloat(2.0), OrderedFloat(-1.0), OrderedFloat(0.5)];
+        values.sort();
+        assert_eq!(
+            values,
+            vec![OrderedFloat(-1.0), OrderedFloat(0.5), OrderedFloat(2.0)]
+        );
+    }
+
+    #[test]
+    fn test_camel_case_ident() {
+        assert_eq!(camel_case_ident("Low pass"), "LowPass");
+        assert_eq!(camel_case_ident("HP 12dB/oct"), "HP12dBOct");
+        assert_eq!(camel_case_ident("2x"), "V2x");
+        assert_eq!(camel_case_ident("--"), "V");
+        assert_eq!(camel_case_ident("self"), "Self_");
+    }
+
+    #[test]
+    fn test_scale_point_map() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let port = plugin
+            .port_by_symbol(&world.new_string("gain").unwrap())
+            .unwrap();
+        let map = port.scale_point_map();
+        assert_eq!(
+            map.iter()
+                .map(|(value, label)| (value.0, label.as_str()))
+                .collect::<Vec<_>>(),
+            [(-90.0, "Silence"), (0.0, "Unity"), (6.0, "Boost")]
+        );
+        assert_eq!(port.label_for_value(0.0).as_deref(), Some("Unity"));
+        assert_eq!(port.label_for_value(6.0).as_deref(), Some("Boost"));
+        assert_eq!(port.label_for_value(1.0), None);
+        assert_eq!(port.value_for_label("Silence"), Some(-90.0));
+        assert_eq!(port.value_for_label("Loud"), None);
+        for point in port.scale_points().iter() {
+            assert_eq!(point.port().index(), port.index());
+            assert_eq!(point.plugin().uri(), plugin.uri());
+        }
+        assert_eq!(
+            port.enum_variants(),
+            [
+                (-90.0, "Silence".to_string(), "Silence".to_string()),
+                (0.0, "Unity".to_string(), "Unity".to_string()),
+                (6.0, "Boost".to_string(), "Boost".to_string()),
+            ]
+        );
+
+        let input = plugin
+            .port_by_symbol(&world.new_string("in").unwrap())
+            .unwrap();
+        assert!(input.scale_point_map().is_empty());
+        assert!(input.enum_variants().is_empty());
+    }
+
+    #[test]
+    fn test_display() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        assert_eq!(
+            plugin.to_string(),
+            format!("{} <{}>", plugin.name(), crate::testing::GAIN_URI)
+        );
+        let port = plugin.port_by_index(0).unwrap();
+        assert_eq!(port.to_string(), "0: gain \"Gain\"");
+        assert_eq!(world.new_int(3).to_string(), "3");
+    }
+
+    #[test]
+    fn test_direction_and_kind() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let ports: Vec<_> = plugin
+            .iter_ports()
+            .map(|port| (port.direction(), port.kind()))
+            .collect();
+        assert_eq!(
+            ports,
+            vec![
+                (Some(PortDirection::Input), PortKind::Control),
+                (Some(PortDirection::Input), PortKind::Audio),
+                (Some(PortDirection::Output), PortKind::Audio),
+            ]
+        );
+    }
+}
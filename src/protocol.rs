@@ -0,0 +1,131 @@
+use lv2_raw::LV2Urid;
+use std::convert::TryFrom;
+use std::mem::size_of;
+
+/// The URI of the protocol for `PeakData` notifications from audio ports.
+pub const PEAK_PROTOCOL: &str = "http://lv2plug.in/ns/extensions/ui#peakProtocol";
+/// The URI of the protocol for atoms from `pack_atom`, like the value of an
+/// atom port.
+pub const ATOM_TRANSFER: &str = "http://lv2plug.in/ns/ext/atom#atomTransfer";
+/// The URI of the protocol for atoms from `pack_atom` that are events of an
+/// atom sequence port, without their time stamp.
+pub const EVENT_TRANSFER: &str = "http://lv2plug.in/ns/ext/atom#eventTransfer";
+
+const ATOM_HEADER_SIZE: usize = 2 * size_of::<u32>();
+
+/// The payload of `PEAK_PROTOCOL`, with the same layout as `LV2UI_Peak_Data`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PeakData {
+    /// The frame the period starts at. It counts up from when the host
+    /// started and wraps around.
+    pub period_start: u32,
+    /// The number of frames in the period.
+    pub period_size: u32,
+    /// The largest absolute sample value in the period.
+    pub peak: f32,
+}
+
+impl PeakData {
+    /// The size of the payload in bytes.
+    pub const SIZE: usize = size_of::<PeakData>();
+
+    /// Measure the peak of `samples`, a period of audio that starts at
+    /// `period_start`. NaN samples are ignored.
+    #[must_use]
+    pub fn measure(period_start: u32, samples: &[f32]) -> PeakData {
+        PeakData {
+            period_start,
+            period_size: u32::try_from(samples.len()).unwrap_or(u32::MAX),
+            peak: samples.iter().fold(0.0, |peak, s| s.abs().max(peak)),
+        }
+    }
+
+    /// The payload in native byte order, to pass to a UI as a port event.
+    #[must_use]
+    pub fn to_bytes(self) -> [u8; PeakData::SIZE] {
+        let mut bytes = [0; PeakData::SIZE];
+        bytes[0..4].copy_from_slice(&self.period_start.to_ne_bytes());
+        bytes[4..8].copy_from_slice(&self.period_size.to_ne_bytes());
+        bytes[8..12].copy_from_slice(&self.peak.to_ne_bytes());
+        bytes
+    }
+
+    /// Read a payload from `to_bytes`, or `None` if `bytes` does not have the
+    /// size of a payload.
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8]) -> Option<PeakData> {
+        if bytes.len() != PeakData::SIZE {
+            return None;
+        }
+        let word = |i: usize| <[u8; 4]>::try_from(&bytes[i..i + 4]).unwrap();
+        Some(PeakData {
+            period_start: u32::from_ne_bytes(word(0)),
+            period_size: u32::from_ne_bytes(word(4)),
+            peak: f32::from_ne_bytes(word(8)),
+        })
+    }
+}
+
+/// Pack an atom of type `atom_type` with `body` for `ATOM_TRANSFER` or
+/// `EVENT_TRANSFER`. The atom header is in native byte order.
+///
+/// # Panics
+/// Panics if `body` is larger than an atom can be.
+#[must_use]
+pub fn pack_atom(atom_type: LV2Urid, body: &[u8]) -> Vec<u8> {
+    let size = u32::try_from(body.len()).expect("atom body is too large");
+    let mut bytes = Vec::with_capacity(ATOM_HEADER_SIZE + body.len());
+    bytes.extend_from_slice(&size.to_ne_bytes());
+    bytes.extend_from_slice(&atom_type.to_ne_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Unpack an atom from `pack_atom` into its type and its body, or `None` if
+/// `bytes` is shorter than the size in the atom header says.
+///
+/// Bytes after the body, like padding, are ignored.
+#[must_use]
+pub fn unpack_atom(bytes: &[u8]) -> Option<(LV2Urid, &[u8])> {
+    let word = |i: usize| Some(<[u8; 4]>::try_from(bytes.get(i..i + 4)?).unwrap());
+    let size = u32::from_ne_bytes(word(0)?) as usize;
+    let atom_type = u32::from_ne_bytes(word(4)?);
+    let body = bytes.get(ATOM_HEADER_SIZE..ATOM_HEADER_SIZE.checked_add(size)?)?;
+    Some((atom_type, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_data() {
+        let peak = PeakData::measure(512, &[0.25, -0.75, f32::NAN, 0.5]);
+        assert_eq!(
+            peak,
+            PeakData {
+                period_start: 512,
+                period_size: 4,
+                peak: 0.75,
+            }
+        );
+        assert_eq!(PeakData::SIZE, 12);
+        assert_eq!(PeakData::from_bytes(&peak.to_bytes()), Some(peak));
+        assert_eq!(PeakData::from_bytes(&[0; 11]), None);
+        assert_eq!(PeakData::measure(0, &[]).peak, 0.0);
+    }
+
+    #[test]
+    fn test_pack_atom() {
+        let bytes = pack_atom(7, b"note");
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(unpack_atom(&bytes), Some((7, &b"note"[..])));
+        let mut padded = bytes.clone();
+        padded.extend_from_slice(&[0; 4]);
+        assert_eq!(unpack_atom(&padded), Some((7, &b"note"[..])));
+        assert_eq!(unpack_atom(&bytes[..11]), None);
+        assert_eq!(unpack_atom(&bytes[..7]), None);
+        assert_eq!(unpack_atom(&pack_atom(3, &[])), Some((3, &[][..])));
+    }
+}
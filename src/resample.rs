@@ -0,0 +1,273 @@
+use crate::graph::Chain;
+use rubato::{FftFixedInOut, Resampler, ResamplerConstructionError};
+
+/// Runs a chain at a different sample rate than the host, like a chain of
+/// plugins that only support 44.1 kHz in a 48 kHz host.
+///
+/// The input is resampled to the rate of the chain, processed in blocks of
+/// `Chain::block_length` frames and resampled back. The buffering of the
+/// resamplers and the blocks delays the output by a fixed number of frames,
+/// which `latency` reports together with the latency of the resamplers and
+/// the chain. `process` does not allocate, so it can be called from the
+/// audio thread.
+pub struct Resampled {
+    chain: Chain,
+    chain_rate: usize,
+    host_rate: usize,
+    down: FftFixedInOut<f32>,
+    up: FftFixedInOut<f32>,
+    // The audio between the steps, per channel. Channels the chain does not
+    // have on a side are kept as one silent channel, since the resamplers
+    // need at least one.
+    host_inputs: Vec<Vec<f32>>,
+    chain_inputs: Vec<Vec<f32>>,
+    chain_outputs: Vec<Vec<f32>>,
+    host_outputs: Vec<Vec<f32>>,
+    // The output buffers of the resamplers.
+    down_output: Vec<Vec<f32>>,
+    up_output: Vec<Vec<f32>>,
+    // The silence `host_outputs` starts with, so output is ready before the
+    // first block is through.
+    buffering: usize,
+}
+
+impl Resampled {
+    /// Run `chain`, which was instantiated at `chain_rate`, in a host that
+    /// runs at `host_rate`. The rates are rounded to whole numbers. A chain
+    /// with a `block_length` of 0 outputs silence.
+    ///
+    /// # Errors
+    /// Returns an error if rubato can not resample between the rates, like
+    /// when one of them is 0.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(
+        chain: Chain,
+        chain_rate: f64,
+        host_rate: f64,
+    ) -> Result<Resampled, ResamplerConstructionError> {
+        let chain_rate = chain_rate.round().max(0.0) as usize;
+        let host_rate = host_rate.round().max(0.0) as usize;
+        let block_length = chain.block_length().max(1);
+        let inputs = chain.audio_inputs().max(1);
+        let outputs = chain.audio_outputs().max(1);
+        let host_block = to_rate(block_length, chain_rate, host_rate).max(1);
+        let down = FftFixedInOut::new(host_rate, chain_rate, host_block, inputs)?;
+        let up = FftFixedInOut::new(chain_rate, host_rate, block_length, outputs)?;
+        // The most frames that can wait in the pipeline before the output of
+        // the host has enough, since each step only passes whole chunks.
+        let buffering = down.input_frames_next()
+            + to_rate(block_length + up.input_frames_next(), chain_rate, host_rate);
+        let buffers = |channels: usize, capacity: usize| -> Vec<Vec<f32>> {
+            (0..channels)
+                .map(|_| Vec::with_capacity(capacity))
+                .collect()
+        };
+        let mut host_outputs = buffers(outputs, buffering + 2 * up.output_frames_max());
+        for buffer in &mut host_outputs {
+            buffer.resize(buffering, 0.0);
+        }
+        Ok(Resampled {
+            host_inputs: buffers(inputs, down.input_frames_max()),
+            chain_inputs: buffers(inputs, block_length + down.output_frames_max()),
+            chain_outputs: buffers(outputs, block_length + up.input_frames_max()),
+            host_outputs,
+            down_output: vec![vec![0.0; down.output_frames_max()]; inputs],
+            up_output: vec![vec![0.0; up.output_frames_max()]; outputs],
+            chain,
+            chain_rate,
+            host_rate,
+            down,
+            up,
+            buffering,
+        })
+    }
+
+    /// The number of frames at the rate of the host the output is delayed
+    /// by. This is the buffering, the delay of the resamplers and the
+    /// latency of the chain, which can change while it runs.
+    #[must_use]
+    pub fn latency(&self) -> usize {
+        let chain_frames = self.down.output_delay() + self.chain.total_latency();
+        self.buffering
+            + self.up.output_delay()
+            + to_rate(chain_frames, self.chain_rate, self.host_rate)
+    }
+
+    /// The chain, to schedule events or create a `Chain::param_queue`.
+    /// Event times are in frames at the rate of the chain.
+    pub fn chain_mut(&mut self) -> &mut Chain {
+        &mut self.chain
+    }
+
+    /// Stop resampling and return the chain.
+    #[must_use]
+    pub fn into_chain(self) -> Chain {
+        self.chain
+    }
+
+    /// Feed `inputs` at the rate of the host to the chain and fill `outputs`
+    /// with its output at the same rate, processing the chain each time
+    /// enough input for a block is resampled.
+    ///
+    /// All the channels must have the same number of frames. Channels beyond
+    /// the ones of the chain are ignored, and outputs without a channel are
+    /// filled with silence.
+    ///
+    /// # Safety
+    /// Running calls the plugins' code, which itself may be unsafe.
+    pub unsafe fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        let frames = inputs
+            .iter()
+            .map(|input| input.len())
+            .chain(outputs.iter().map(|output| output.len()))
+            .min()
+            .unwrap_or(0);
+        for output in outputs.iter_mut().skip(self.chain.audio_outputs()) {
+            output.fill(0.0);
+        }
+        if self.chain.block_length() == 0 {
+            for output in outputs.iter_mut() {
+                output.fill(0.0);
+            }
+            return;
+        }
+        let mut offset = 0;
+        while offset < frames {
+            let wanted = self.down.input_frames_next() - self.host_inputs[0].len();
+            let len = wanted.min(frames - offset);
+            for (channel, buffer) in self.host_inputs.iter_mut().enumerate() {
+                match inputs.get(channel) {
+                    Some(input) if channel < self.chain.audio_inputs() => {
+                        buffer.extend_from_slice(&input[offset..offset + len]);
+                    }
+                    _ => buffer.resize(buffer.len() + len, 0.0),
+                }
+            }
+            if len == wanted {
+                unsafe { self.run() };
+            }
+            let available = self.host_outputs[0].len().min(len);
+            let channels = self.chain.audio_outputs();
+            for (output, buffer) in outputs.iter_mut().zip(&self.host_outputs).take(channels) {
+                let output = &mut output[offset..offset + len];
+                output[..available].copy_from_slice(&buffer[..available]);
+                // The pipeline can not run out, but silence is the safe
+                // answer if it does.
+                output[available..].fill(0.0);
+            }
+            for buffer in &mut self.host_outputs {
+                buffer.drain(..available);
+            }
+            offset += len;
+        }
+    }
+
+    // Pass a full chunk of input through the resamplers and the chain, as far
+    // as the chunks and blocks allow.
+    unsafe fn run(&mut self) {
+        let block_length = self.chain.block_length();
+        // The buffers have the sizes rubato asks for, so it does not fail.
+        let (_, frames) = self
+            .down
+            .process_into_buffer(&self.host_inputs, &mut self.down_output, None)
+            .unwrap_or((0, 0));
+        clear(&mut self.host_inputs);
+        for (buffer, output) in self.chain_inputs.iter_mut().zip(&self.down_output) {
+            buffer.extend_from_slice(&output[..frames]);
+        }
+        while self.chain_inputs[0].len() >= block_length {
+            for (channel, buffer) in self.chain_inputs.iter_mut().enumerate() {
+                if let Some(input) = self.chain.input_mut(channel) {
+                    input.copy_from_slice(&buffer[..block_length]);
+                }
+                buffer.drain(..block_length);
+            }
+            unsafe { self.chain.process(block_length) };
+            for (channel, buffer) in self.chain_outputs.iter_mut().enumerate() {
+                match self.chain.output(channel) {
+                    Some(output) => buffer.extend_from_slice(output),
+                    None => buffer.resize(buffer.len() + block_length, 0.0),
+                }
+            }
+        }
+        while self.chain_outputs[0].len() >= self.up.input_frames_next() {
+            let (used, frames) = self
+                .up
+                .process_into_buffer(&self.chain_outputs, &mut self.up_output, None)
+                .unwrap_or((self.up.input_frames_next(), 0));
+            for buffer in &mut self.chain_outputs {
+                buffer.drain(..used);
+            }
+            for (buffer, output) in self.host_outputs.iter_mut().zip(&self.up_output) {
+                buffer.extend_from_slice(&output[..frames]);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Resampled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Resampled")
+            .field("chain", &self.chain)
+            .field("chain_rate", &self.chain_rate)
+            .field("host_rate", &self.host_rate)
+            .field("latency", &self.latency())
+            .finish()
+    }
+}
+
+fn clear(buffers: &mut [Vec<f32>]) {
+    for buffer in buffers {
+        buffer.clear();
+    }
+}
+
+// `frames` at the rate `from` as frames at the rate `to`, rounded up.
+fn to_rate(frames: usize, from: usize, to: usize) -> usize {
+    (frames * to).div_ceil(from.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Gain;
+
+    #[test]
+    fn test_to_rate() {
+        assert_eq!(to_rate(147, 44100, 48000), 160);
+        assert_eq!(to_rate(1, 48000, 44100), 1);
+        assert_eq!(to_rate(0, 48000, 44100), 0);
+    }
+
+    #[test]
+    fn test_resampled() {
+        let mut chain = Chain::new(64);
+        chain
+            .push_native("gain", Box::new(Gain::new(1, 2.0)))
+            .unwrap();
+        let mut resampled = Resampled::new(chain, 44100.0, 48000.0).unwrap();
+        let latency = resampled.latency();
+        assert!(latency > 0);
+
+        // A constant input comes out doubled after the latency, once the
+        // resamplers settled.
+        let input = vec![0.25; 4800];
+        let mut output = vec![1.0; 4800];
+        for _ in 0..4 {
+            unsafe { resampled.process(&[&input], &mut [&mut output]) };
+        }
+        assert!(output.iter().all(|sample| (sample - 0.5).abs() < 1e-2));
+
+        let mut chain = Chain::new(64);
+        chain
+            .push_native("gain", Box::new(Gain::new(1, 1.0)))
+            .unwrap();
+        let mut resampled = Resampled::new(chain, 44100.0, 48000.0).unwrap();
+        let mut output = vec![1.0; 4800];
+        unsafe { resampled.process(&[&input], &mut [&mut output]) };
+        // The buffering comes out first.
+        assert!(output[..resampled.buffering]
+            .iter()
+            .all(|sample| *sample == 0.0));
+    }
+}
@@ -0,0 +1,647 @@
+use crate::host::Host;
+use crate::world::World;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+#[cfg(feature = "scan-progress")]
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+const LV2_CORE_PLUGIN: &str = "http://lv2plug.in/ns/lv2core#Plugin";
+
+/// Reported after each bundle is loaded by `World::load_all_with_progress`
+/// and `World::load_all_in_background`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanEvent {
+    /// The directory of the bundle that was loaded.
+    pub bundle: PathBuf,
+    /// The index of the bundle, starting at 0.
+    pub index: usize,
+    /// The total number of bundles that will be loaded.
+    pub total: usize,
+    /// The number of plugins the bundle added to the world.
+    pub plugins_found: usize,
+    /// How long it took to load the bundle.
+    pub elapsed: Duration,
+}
+
+/// The directories that are searched for LV2 bundles.
+///
+/// This is `LV2_PATH` if it is set, or the default LV2 path for the platform
+/// otherwise. Lilv may be built with a different default path, so set
+/// `LV2_PATH` if the results must match `World::load_all` exactly.
+#[must_use]
+pub fn lv2_path() -> Vec<PathBuf> {
+    match std::env::var_os("LV2_PATH") {
+        Some(path) => std::env::split_paths(&path).collect(),
+        None => default_lv2_path(),
+    }
+}
+
+fn default_lv2_path() -> Vec<PathBuf> {
+    let home = |dir: &str| std::env::var_os("HOME").map(|home| Path::new(&home).join(dir));
+    let env = |var: &str, dir: &str| std::env::var_os(var).map(|base| Path::new(&base).join(dir));
+    let dirs = if cfg!(target_os = "macos") {
+        vec![
+            home("Library/Audio/Plug-Ins/LV2"),
+            home(".lv2"),
+            Some(PathBuf::from("/usr/local/lib/lv2")),
+            Some(PathBuf::from("/usr/lib/lv2")),
+            Some(PathBuf::from("/Library/Audio/Plug-Ins/LV2")),
+        ]
+    } else if cfg!(windows) {
+        vec![env("APPDATA", "LV2"), env("COMMONPROGRAMFILES", "LV2")]
+    } else {
+        vec![
+            home(".lv2"),
+            Some(PathBuf::from("/usr/local/lib/lv2")),
+            Some(PathBuf::from("/usr/lib/lv2")),
+        ]
+    };
+    dirs.into_iter().flatten().collect()
+}
+
+/// Find the bundles, the directories with a `manifest.ttl`, directly inside
+/// the directories of `path`. Directories that can not be read are skipped.
+#[must_use]
+pub fn find_bundles(path: &[PathBuf]) -> Vec<PathBuf> {
+    let mut bundles = Vec::new();
+    for dir in path {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|bundle| bundle.join("manifest.ttl").is_file())
+            .collect();
+        found.sort();
+        bundles.extend(found);
+    }
+    bundles
+}
+
+/// A plugin URI that is declared by the manifests of more than one bundle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Duplicate {
+    /// The URI of the plugin.
+    pub uri: String,
+    /// The bundles that declare the plugin, in the order they are searched.
+    pub bundles: Vec<PathBuf>,
+}
+
+/// Find the plugins that are declared by more than one of `bundles`, like
+/// an old copy of a plugin that shadows an update installed elsewhere.
+///
+/// Lilv loads the first bundle of `bundles` that declares a plugin and
+/// ignores the others. Only the manifests are read, and bundles with a
+/// manifest that can not be parsed are ignored.
+#[must_use]
+pub fn find_duplicates(bundles: &[PathBuf]) -> Vec<Duplicate> {
+    let mut plugins: Vec<Duplicate> = Vec::new();
+    for bundle in bundles {
+        for uri in crate::turtle::subjects_of_type(&bundle.join("manifest.ttl"), LV2_CORE_PLUGIN) {
+            match plugins.iter_mut().find(|plugin| plugin.uri == uri) {
+                Some(plugin) => plugin.bundles.push(bundle.clone()),
+                None => plugins.push(Duplicate {
+                    uri,
+                    bundles: vec![bundle.clone()],
+                }),
+            }
+        }
+    }
+    plugins.retain(|plugin| plugin.bundles.len() > 1);
+    plugins
+}
+
+/// Which bundle to load a plugin from when several bundles declare it. See
+/// `ScanOptions::duplicate_policy`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Load the bundle that comes first in the LV2 path, like lilv does.
+    PreferFirst,
+    /// Load the bundle with the most recently modified manifest.
+    PreferNewest,
+    /// Load the bundle inside the given directory, or the first bundle if
+    /// none is inside it.
+    PreferPath(PathBuf),
+}
+
+impl DuplicatePolicy {
+    fn choose<'a>(&self, bundles: &'a [PathBuf]) -> Option<&'a PathBuf> {
+        let modified = |bundle: &PathBuf| {
+            std::fs::metadata(bundle.join("manifest.ttl"))
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        };
+        match self {
+            DuplicatePolicy::PreferFirst => bundles.first(),
+            // `max_by_key` returns the last maximum, so search from the end to
+            // keep the first bundle on ties.
+            DuplicatePolicy::PreferNewest => bundles.iter().rev().max_by_key(|b| modified(b)),
+            DuplicatePolicy::PreferPath(dir) => bundles
+                .iter()
+                .find(|bundle| bundle.starts_with(dir))
+                .or_else(|| bundles.first()),
+        }
+    }
+}
+
+/// Options for `World::load_all_with_options`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// Bundles that take longer than this to load are abandoned, not loaded
+    /// and recorded in `ScanReport::abandoned`.
+    ///
+    /// Lilv can not abort loading a bundle, so if this is set, each bundle
+    /// is first loaded into a separate world on a watchdog thread. This only
+    /// reads the RDF of the bundle and runs no plugin code. A thread that
+    /// times out is left to run or hang on its own, and its world is leaked
+    /// until it finishes. Hosts can store the abandoned bundles and pass them
+    /// in `skip` on the next scan.
+    pub per_bundle_timeout: Option<Duration>,
+    /// Bundles that are not loaded, like the slow bundles of a previous scan.
+    pub skip: Vec<PathBuf>,
+    /// How to choose between bundles that declare the same plugin. If this is
+    /// set, the duplicates are reported in `ScanReport::duplicates`, and
+    /// hosts that treat duplicates as an error can check it. `None` skips the
+    /// detection, which needs to parse every manifest again.
+    pub duplicate_policy: Option<DuplicatePolicy>,
+    /// The directories to search for bundles instead of `lv2_path`, like
+    /// the directory of `testing::fixtures_dir`.
+    pub path: Option<Vec<PathBuf>>,
+}
+
+/// A bundle or plugin that was abandoned because it took longer than the
+/// timeouts of `ScanOptions`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Abandoned {
+    /// The directory of the bundle.
+    pub bundle: PathBuf,
+    /// The URI of the plugin that did not instantiate in time with
+    /// `World::load_all_with_instantiate_probe`, or `None` if the bundle did
+    /// not load in time and was not loaded.
+    pub plugin: Option<String>,
+}
+
+/// The result of `World::load_all_with_options`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanReport {
+    /// The bundles that were loaded.
+    pub loaded: Vec<ScanEvent>,
+    /// The bundles and plugins that took longer than the timeouts of
+    /// `ScanOptions`.
+    pub abandoned: Vec<Abandoned>,
+    /// The bundles that were not loaded because they were in
+    /// `ScanOptions::skip` or rejected by the filter of
+    /// `World::load_all_with_filter`.
+    pub skipped: Vec<PathBuf>,
+    /// The plugins that are declared by more than one of the loaded bundles,
+    /// if `ScanOptions::duplicate_policy` is set.
+    pub duplicates: Vec<Duplicate>,
+}
+
+/// A scan that loads bundles into a world on a background thread, started
+/// by `World::load_all_in_background`. Requires the `scan-progress`
+/// feature.
+///
+/// The events of the loaded bundles are received without blocking with
+/// `try_next`, or by iterating, which waits for the next bundle. Dropping the
+/// scan does not stop it.
+#[cfg(feature = "scan-progress")]
+#[derive(Debug)]
+pub struct BackgroundScan {
+    events: Receiver<ScanEvent>,
+    thread: JoinHandle<ScanReport>,
+}
+
+#[cfg(feature = "scan-progress")]
+impl BackgroundScan {
+    /// The event of the next bundle that was loaded, or `None` if no bundle
+    /// was loaded since the last call.
+    #[must_use]
+    pub fn try_next(&self) -> Option<ScanEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Whether the scan is done. Events may still be left to receive.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the scan to finish and return its report.
+    ///
+    /// # Panics
+    /// Panics if the scan panicked.
+    #[must_use]
+    pub fn wait(self) -> ScanReport {
+        match self.thread.join() {
+            Ok(report) => report,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[cfg(feature = "scan-progress")]
+impl Iterator for BackgroundScan {
+    type Item = ScanEvent;
+
+    fn next(&mut self) -> Option<ScanEvent> {
+        self.events.recv().ok()
+    }
+}
+
+#[cfg(feature = "scan-progress")]
+impl World {
+    /// Load all the installed LV2 bundles like `load_all`, calling `progress`
+    /// after each bundle is loaded. Requires the `scan-progress` feature.
+    ///
+    /// Bundles are found with `scan::lv2_path` and `scan::find_bundles`.
+    /// Bundles that can not be converted to a URI are skipped.
+    pub fn load_all_with_progress(&self, progress: impl FnMut(&ScanEvent)) {
+        self.scan(&ScanOptions::default(), None, |_| true, progress);
+    }
+
+    /// Load all the installed LV2 bundles like `load_all_with_options` on a
+    /// background thread, so a GUI can show the progress while it keeps
+    /// running. Requires the `scan-progress` feature.
+    ///
+    /// The world can be used while it is loaded, and has all the plugins of
+    /// the report once `BackgroundScan::wait` returns.
+    ///
+    /// # Errors
+    /// Returns an error if the thread could not be spawned.
+    pub fn load_all_in_background(&self, options: ScanOptions) -> std::io::Result<BackgroundScan> {
+        let (sender, events) = mpsc::channel();
+        let world = World {
+            life: self.life.clone(),
+        };
+        let thread = std::thread::Builder::new()
+            .name("lilv-scan".to_string())
+            .spawn(move || {
+                world.scan(
+                    &options,
+                    None,
+                    |_| true,
+                    |event| {
+                        let _ = sender.send(event.clone());
+                    },
+                )
+            })?;
+        Ok(BackgroundScan { events, thread })
+    }
+}
+
+impl World {
+    /// Load all the installed LV2 bundles like `load_all_with_progress`, but
+    /// skip and time bundles as configured by `options`.
+    pub fn load_all_with_options(&self, options: &ScanOptions) -> ScanReport {
+        self.scan(options, None, |_| true, |_| {})
+    }
+
+    /// Load all the installed LV2 bundles like `load_all_with_options`, and
+    /// instantiate each plugin with a `host::Host` on the watchdog thread
+    /// first. Plugins that take longer than `timeout` to instantiate are
+    /// recorded in `ScanReport::abandoned`.
+    ///
+    /// The bundle of such a plugin is still loaded, since its other plugins
+    /// may work, and the plugins of a bundle after the one that timed out are
+    /// not checked.
+    ///
+    /// # Safety
+    /// Instantiating runs the code of every plugin in this process, which
+    /// itself may be unsafe, and a plugin that crashes takes down the
+    /// process. A thread that times out keeps running the plugin, and its
+    /// world and host are leaked until it returns. Only run this scan in a
+    /// separate process, or before the host starts.
+    pub unsafe fn load_all_with_instantiate_probe(
+        &self,
+        options: &ScanOptions,
+        timeout: Duration,
+    ) -> ScanReport {
+        self.scan(options, Some(timeout), |_| true, |_| {})
+    }
+
+    /// Load the installed LV2 bundles like `load_all_with_options`, but only
+    /// the bundles for which `filter` returns `true`.
+    ///
+    /// The filter is called with the bundle directory before the bundle is
+    /// read, so tools that only need a few bundles, like validators, do not
+    /// pay for parsing the rest.
+    pub fn load_all_with_filter(
+        &self,
+        options: &ScanOptions,
+        filter: impl FnMut(&Path) -> bool,
+    ) -> ScanReport {
+        self.scan(options, None, filter, |_| {})
+    }
+
+    fn scan(
+        &self,
+        options: &ScanOptions,
+        instantiate_timeout: Option<Duration>,
+        mut filter: impl FnMut(&Path) -> bool,
+        mut progress: impl FnMut(&ScanEvent),
+    ) -> ScanReport {
+        let mut report = ScanReport::default();
+        let path = options.path.clone().unwrap_or_else(lv2_path);
+        let (skipped, mut bundles): (Vec<PathBuf>, Vec<PathBuf>) = find_bundles(&path)
+            .into_iter()
+            .partition(|bundle| options.skip.contains(bundle) || !filter(bundle));
+        report.skipped = skipped;
+        if let Some(policy) = &options.duplicate_policy {
+            report.duplicates = find_duplicates(&bundles);
+            // Lilv keeps the plugin from the bundle it loads first, so the
+            // chosen bundle is moved before the others.
+            for duplicate in &report.duplicates {
+                let chosen = match policy.choose(&duplicate.bundles) {
+                    Some(chosen) => chosen,
+                    None => continue,
+                };
+                let position = |bundle: &PathBuf| bundles.iter().position(|b| b == bundle);
+                let first = duplicate.bundles.iter().filter_map(position).min();
+                if let (Some(first), Some(from)) = (first, position(chosen)) {
+                    let bundle = bundles.remove(from);
+                    bundles.insert(first.min(from), bundle);
+                }
+            }
+        }
+        let total = bundles.len();
+        let watchdog = options.per_bundle_timeout.is_some() || instantiate_timeout.is_some();
+        for (index, bundle) in bundles.into_iter().enumerate() {
+            if watchdog {
+                match probe(&bundle, options.per_bundle_timeout, instantiate_timeout) {
+                    Probed::Loaded => {}
+                    Probed::PluginTimedOut(uri) => report.abandoned.push(Abandoned {
+                        bundle: bundle.clone(),
+                        plugin: Some(uri),
+                    }),
+                    Probed::BundleTimedOut => {
+                        report.abandoned.push(Abandoned {
+                            bundle,
+                            plugin: None,
+                        });
+                        continue;
+                    }
+                }
+            }
+            let start = Instant::now();
+            let before = self.plugins().count();
+            let _ = self.load_bundle_dir(&bundle);
+            let event = ScanEvent {
+                bundle,
+                index,
+                total,
+                plugins_found: self.plugins().count().saturating_sub(before),
+                elapsed: start.elapsed(),
+            };
+            progress(&event);
+            report.loaded.push(event);
+        }
+        self.load_specifications();
+        self.load_plugin_classes();
+        report
+    }
+}
+
+// What the watchdog thread of `probe` reports.
+enum Progress {
+    Loaded,
+    Instantiating(String),
+    Instantiated,
+}
+
+// The outcome of `probe`.
+enum Probed {
+    Loaded,
+    BundleTimedOut,
+    PluginTimedOut(String),
+}
+
+// Load `bundle` into a separate world on a watchdog thread, and instantiate
+// its plugins if `instantiate_timeout` is set, to find out whether it hangs.
+// A thread that panics or can not be spawned counts as done, so the bundle
+// is loaded like without a watchdog.
+fn probe(
+    bundle: &Path,
+    bundle_timeout: Option<Duration>,
+    instantiate_timeout: Option<Duration>,
+) -> Probed {
+    let (sender, receiver) = mpsc::channel();
+    let instantiate = instantiate_timeout.is_some();
+    let dir = bundle.to_path_buf();
+    let spawned = std::thread::Builder::new()
+        .name("lilv-scan".to_string())
+        .spawn(move || {
+            let world = World::new();
+            let _ = world.load_bundle_dir(&dir);
+            if sender.send(Progress::Loaded).is_err() || !instantiate {
+                return;
+            }
+            let host = Host::new(48000.0, 1024);
+            for plugin in world.plugins() {
+                let uri = plugin.uri().as_uri().unwrap_or_default().to_string();
+                if sender.send(Progress::Instantiating(uri)).is_err() {
+                    return;
+                }
+                let _ = plugin.instantiate_with(host.features(), &host.caps(), host.sample_rate());
+                if sender.send(Progress::Instantiated).is_err() {
+                    return;
+                }
+            }
+        });
+    if spawned.is_err() {
+        return Probed::Loaded;
+    }
+    match wait(&receiver, bundle_timeout) {
+        Err(RecvTimeoutError::Timeout) => return Probed::BundleTimedOut,
+        Err(RecvTimeoutError::Disconnected) => return Probed::Loaded,
+        Ok(_) => {}
+    }
+    let mut plugin = None;
+    loop {
+        match wait(&receiver, instantiate_timeout) {
+            Ok(Progress::Instantiating(uri)) => plugin = Some(uri),
+            Ok(_) => plugin = None,
+            Err(RecvTimeoutError::Timeout) => {
+                return plugin.map_or(Probed::Loaded, Probed::PluginTimedOut)
+            }
+            Err(RecvTimeoutError::Disconnected) => return Probed::Loaded,
+        }
+    }
+}
+
+// Wait for the next message, or forever if there is no timeout.
+fn wait<T>(receiver: &Receiver<T>, timeout: Option<Duration>) -> Result<T, RecvTimeoutError> {
+    match timeout {
+        Some(timeout) => receiver.recv_timeout(timeout),
+        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_bundles() {
+        let dir = crate::testing::TempDir::new("find-bundles").unwrap();
+        let bundle = dir.join("b.lv2");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::create_dir_all(dir.join("not-a-bundle")).unwrap();
+        std::fs::write(bundle.join("manifest.ttl"), "").unwrap();
+        assert_eq!(
+            find_bundles(&[dir.path().to_path_buf(), PathBuf::from("/lilv-rs/missing")]),
+            vec![bundle]
+        );
+    }
+
+    // Options that only scan the fixture bundles.
+    fn fixture_options() -> ScanOptions {
+        ScanOptions {
+            path: Some(vec![crate::testing::fixtures_dir().unwrap()]),
+            ..ScanOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_scan_progress() {
+        let world = World::new();
+        let mut events = Vec::new();
+        world.scan(
+            &fixture_options(),
+            None,
+            |_| true,
+            |event| {
+                events.push(event.clone());
+            },
+        );
+        assert_eq!(events.len(), 3);
+        for (index, event) in events.iter().enumerate() {
+            assert_eq!(event.index, index);
+            assert_eq!(event.total, events.len());
+            assert_eq!(event.plugins_found, 1);
+        }
+        assert_eq!(world.plugins().count(), 3);
+    }
+
+    #[cfg(feature = "scan-progress")]
+    #[test]
+    fn test_load_all_in_background() {
+        let world = World::new();
+        let mut scan = world.load_all_in_background(fixture_options()).unwrap();
+        let events: Vec<ScanEvent> = scan.by_ref().collect();
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|event| event.total == 3));
+        assert!(scan.try_next().is_none());
+        let report = scan.wait();
+        assert_eq!(report.loaded, events);
+        assert_eq!(world.plugins().count(), 3);
+    }
+
+    #[test]
+    fn test_load_all_with_options() {
+        let all = find_bundles(fixture_options().path.as_deref().unwrap());
+        let world = World::new();
+        let report = world.load_all_with_options(&ScanOptions {
+            per_bundle_timeout: Some(Duration::from_secs(0)),
+            skip: all[..1].to_vec(),
+            ..fixture_options()
+        });
+        assert_eq!(report.skipped, all[..1]);
+        assert!(report.abandoned.iter().all(|a| a.plugin.is_none()));
+        assert_eq!(
+            report.loaded.len() + report.skipped.len() + report.abandoned.len(),
+            all.len()
+        );
+        assert!(report
+            .abandoned
+            .iter()
+            .all(|a| !report.skipped.contains(&a.bundle)));
+
+        // A generous timeout abandons nothing.
+        let world = World::new();
+        let report = world.load_all_with_options(&ScanOptions {
+            per_bundle_timeout: Some(Duration::from_secs(60)),
+            ..fixture_options()
+        });
+        assert!(report.abandoned.is_empty());
+        assert_eq!(report.loaded.len(), all.len());
+        assert_eq!(world.plugins().count(), all.len());
+    }
+
+    #[test]
+    fn test_load_all_with_instantiate_probe() {
+        let world = World::new();
+        let report = unsafe {
+            world.load_all_with_instantiate_probe(&fixture_options(), Duration::from_secs(60))
+        };
+        assert!(report.abandoned.is_empty());
+        assert_eq!(report.loaded.len(), 3);
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        assert!(world.plugins().plugin(&uri).is_some());
+    }
+
+    #[test]
+    fn test_load_all_with_filter() {
+        let world = World::new();
+        let report =
+            world.load_all_with_filter(&fixture_options(), |bundle| bundle.ends_with("gain.lv2"));
+        assert_eq!(report.loaded.len(), 1);
+        assert!(report.loaded[0].bundle.ends_with("gain.lv2"));
+        assert_eq!(report.skipped.len(), 2);
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        assert!(world.plugins().plugin(&uri).is_some());
+        assert_eq!(world.plugins().count(), 1);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let dir = crate::testing::TempDir::new("find-duplicates").unwrap();
+        let manifest = "<urn:dup> a <http://lv2plug.in/ns/lv2core#Plugin> .\n";
+        let bundles: Vec<PathBuf> = ["old", "new", "other"]
+            .iter()
+            .map(|name| dir.join(format!("{}.lv2", name)))
+            .collect();
+        for bundle in &bundles {
+            std::fs::create_dir_all(bundle).unwrap();
+        }
+        // The second bundle is the newest.
+        for (bundle, seconds) in bundles[..2].iter().zip(&[1_000_000, 2_000_000]) {
+            let path = bundle.join("manifest.ttl");
+            std::fs::write(&path, manifest).unwrap();
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(*seconds);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+        std::fs::write(
+            bundles[2].join("manifest.ttl"),
+            "<urn:single> a <http://lv2plug.in/ns/lv2core#Plugin> .\n",
+        )
+        .unwrap();
+        let duplicates = find_duplicates(&bundles);
+        assert_eq!(
+            duplicates,
+            vec![Duplicate {
+                uri: "urn:dup".to_string(),
+                bundles: bundles[..2].to_vec(),
+            }]
+        );
+        let dup = &duplicates[0].bundles;
+        assert_eq!(DuplicatePolicy::PreferFirst.choose(dup), Some(&bundles[0]));
+        assert_eq!(DuplicatePolicy::PreferNewest.choose(dup), Some(&bundles[1]));
+        assert_eq!(
+            DuplicatePolicy::PreferPath(bundles[1].clone()).choose(dup),
+            Some(&bundles[1])
+        );
+        assert_eq!(
+            DuplicatePolicy::PreferPath(PathBuf::from("/lilv-rs/missing")).choose(dup),
+            Some(&bundles[0])
+        );
+    }
+}
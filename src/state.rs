@@ -0,0 +1,1529 @@
+use crate::atom::{
+    from_json, read_u32, to_json, ATOM_BOOL, ATOM_DOUBLE, ATOM_FLOAT, ATOM_INT, ATOM_PATH,
+};
+use crate::error::{Error, ParseError};
+use crate::feature::{FeatureSet, LV2UridUnmap};
+use crate::ffi::lv2::{
+    MapPath, Retrieve, StateInterface, Store, LV2_STATE_INTERFACE, LV2_STATE_MAP_PATH,
+};
+use crate::instance::Instance;
+use crate::node::Node;
+use crate::path::path_to_cstring;
+use crate::plugin::Plugin;
+use crate::port::Port;
+use crate::world::{Life, World};
+use lilv_sys as lib;
+use lv2_raw::{LV2Descriptor, LV2Feature, LV2Handle, LV2Urid, LV2UridMap};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt::Debug;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+unsafe impl Send for State {}
+unsafe impl Sync for State {}
+
+/// A snapshot of the state of a plugin instance, like a preset.
+///
+/// A state contains the values of the input control ports and any properties
+/// the plugin stores through the LV2 state extension.
+pub struct State {
+    pub(crate) inner: NonNull<lib::LilvStateImpl>,
+    pub(crate) life: Arc<Life>,
+}
+
+/// Flags that describe how a state may be stored, `LV2_State_Flags` in C.
+///
+/// Flags are combined with `|`, like `StateFlags::POD | StateFlags::PORTABLE`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct StateFlags(u32);
+
+impl StateFlags {
+    /// The values are plain old data that can be copied with `memcpy`.
+    pub const POD: StateFlags = StateFlags(1);
+    /// The values are portable across machines, for example free of
+    /// endianness and pointer issues.
+    pub const PORTABLE: StateFlags = StateFlags(2);
+    /// The values are only valid on this machine, like a state saved with
+    /// native paths for a quick snapshot.
+    pub const NATIVE: StateFlags = StateFlags(4);
+
+    const ALL: u32 = 1 | 2 | 4;
+
+    /// No flags.
+    #[must_use]
+    pub const fn empty() -> StateFlags {
+        StateFlags(0)
+    }
+
+    /// Convert raw `LV2_State_Flags`, or return `None` if `bits` contains
+    /// unknown flags.
+    #[must_use]
+    pub const fn from_bits(bits: u32) -> Option<StateFlags> {
+        if bits & !StateFlags::ALL == 0 {
+            Some(StateFlags(bits))
+        } else {
+            None
+        }
+    }
+
+    /// The raw `LV2_State_Flags`.
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if all the flags in `other` are set.
+    #[must_use]
+    pub const fn contains(self, other: StateFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for StateFlags {
+    type Output = StateFlags;
+
+    fn bitor(self, other: StateFlags) -> StateFlags {
+        StateFlags(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for StateFlags {
+    fn bitor_assign(&mut self, other: StateFlags) {
+        self.0 |= other.0;
+    }
+}
+
+impl std::ops::BitAnd for StateFlags {
+    type Output = StateFlags;
+
+    fn bitand(self, other: StateFlags) -> StateFlags {
+        StateFlags(self.0 & other.0)
+    }
+}
+
+/// A state in plain Rust types, for session formats other than Turtle. With
+/// the `serde` feature, it can be serialized with serde.
+///
+/// See `State::to_portable`. The properties are atoms in the JSON of
+/// `atom::to_json`. Paths to files in the directory of the state are
+/// relative to it, so the files can be moved with the session.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortableState {
+    /// The URI of the plugin the state applies to.
+    pub plugin_uri: String,
+    /// The label of the state.
+    pub label: Option<String>,
+    /// The symbols and values of the port values in the state.
+    pub port_values: Vec<(String, f32)>,
+    /// The properties the plugin stored in the state.
+    pub properties: Vec<PortableProperty>,
+}
+
+/// A property of a `PortableState`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortableProperty {
+    /// The URI of the key.
+    pub key: String,
+    /// The value as atom JSON, like
+    /// `{"type": "http://lv2plug.in/ns/ext/atom#Path", "value": "sample.wav"}`.
+    pub value: String,
+}
+
+/// The directories lilv uses for files when the state of an instance is
+/// created. See `State::new_from_instance`. Directories that are `None` are
+/// not used.
+///
+/// Files outside of these directories are stored with their absolute path,
+/// which does not work on other machines. So unless `absolute_paths` is set,
+/// `copy_dir` and `link_dir` default to `save_dir`, which copies and links the
+/// files into the saved bundle like Jalv does.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDirs {
+    /// The directory of files the plugin created earlier with
+    /// `state:makePath`. The files are copied into `copy_dir` and not referred
+    /// to directly by the state, so a temporary directory is appropriate.
+    pub scratch_dir: Option<PathBuf>,
+    /// The directory of the copies of the files in `scratch_dir`. The copies
+    /// are kept per revision so the state is not ruined if a scratch file
+    /// changes later.
+    pub copy_dir: Option<PathBuf>,
+    /// The directory of links to external files the state refers to.
+    pub link_dir: Option<PathBuf>,
+    /// The directory of files the plugin creates while saving. This is
+    /// typically the directory later passed to `State::save`.
+    pub save_dir: Option<PathBuf>,
+    /// Do not default `copy_dir` and `link_dir` to `save_dir`.
+    pub absolute_paths: bool,
+}
+
+impl StateDirs {
+    /// The directories to pass to lilv, with the defaults filled in.
+    fn resolved(&self) -> (Option<&Path>, Option<&Path>) {
+        let save_dir = self.save_dir.as_deref().filter(|_| !self.absolute_paths);
+        (
+            self.copy_dir.as_deref().or(save_dir),
+            self.link_dir.as_deref().or(save_dir),
+        )
+    }
+}
+
+impl State {
+    /// Load the state described by `subject` from the world, like a preset
+    /// URI. Passing the URI of a plugin loads its default state.
+    ///
+    /// The description of `subject` must already be loaded into the world. Use
+    /// `World::load_preset` to load a preset and its state in one call.
+    ///
+    /// URIDs in the state are mapped with `map`. Returns `None` if `subject`
+    /// does not describe the state of a plugin.
+    #[must_use]
+    pub fn new_from_world(world: &World, map: &LV2UridMap, subject: &Node) -> Option<State> {
+        #[cfg(feature = "tracing")]
+        let subject_uri = subject.to_string();
+        traced!("lilv::state_new_from_world" (subject = %subject_uri) => {
+            let world_ptr = world.life.inner.lock();
+            let subject = subject.inner.as_ptr();
+
+            let inner = unsafe {
+                lib::lilv_state_new_from_world(world_ptr.as_ptr(), map_ptr(map), subject)
+            };
+            State::from_lilv(world, inner)
+        })
+    }
+
+    /// Load the state described by `subject` from the Turtle file at `path`.
+    /// If `subject` is `None`, the state is the one described by the file,
+    /// like a file written by `State::save`.
+    ///
+    /// URIDs in the state are mapped with `map`.
+    ///
+    /// # Errors
+    /// Returns an error if `path` can not be passed to lilv and
+    /// `Error::StateNotFound` if the file does not describe a state.
+    pub fn new_from_file(
+        world: &World,
+        map: &LV2UridMap,
+        subject: Option<&Node>,
+        path: &Path,
+    ) -> Result<State, Error> {
+        let c_path = path_to_cstring(path)?;
+        traced!("lilv::state_new_from_file" (path = %path.display()) => {
+            let world_ptr = world.life.inner.lock();
+            let subject = subject.map_or(std::ptr::null(), |s| s.inner.as_ptr() as *const _);
+
+            let inner = unsafe {
+                lib::lilv_state_new_from_file(
+                    world_ptr.as_ptr(),
+                    map_ptr(map),
+                    subject,
+                    c_path.as_ptr(),
+                )
+            };
+            State::from_lilv(world, inner)
+                .ok_or_else(|| Error::StateNotFound(path.display().to_string()))
+        })
+    }
+
+    /// Load the state described by the Turtle document `string`, like a
+    /// preset stored in a session.
+    ///
+    /// URIDs in the state are mapped with `map`.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidTurtle` with the position of the first syntax
+    /// error if `string` is not valid Turtle, see `State::validate_string`,
+    /// and `Error::StateNotFound` with an empty URI if it does not describe a
+    /// state.
+    pub fn new_from_string(world: &World, map: &LV2UridMap, string: &str) -> Result<State, Error> {
+        State::validate_string(string).map_err(Error::InvalidTurtle)?;
+        let c_string = CString::new(string)?;
+        traced!("lilv::state_new_from_string" (len = string.len()) => {
+            let world_ptr = world.life.inner.lock();
+            let inner = unsafe {
+                lib::lilv_state_new_from_string(
+                    world_ptr.as_ptr(),
+                    map_ptr(map),
+                    c_string.as_ptr(),
+                )
+            };
+            drop(world_ptr);
+            State::from_lilv(world, inner).ok_or_else(|| Error::StateNotFound(String::new()))
+        })
+    }
+
+    /// Check that `string` is valid Turtle, so hosts can show why a stored
+    /// state can not be loaded. This only checks the syntax, not that
+    /// `string` describes a state.
+    ///
+    /// # Errors
+    /// Returns the first syntax error in `string`.
+    pub fn validate_string(string: &str) -> Result<(), ParseError> {
+        crate::turtle::validate(string)
+    }
+
+    /// Save the state of `instance`.
+    ///
+    /// Files the plugin refers to are handled as described by `dirs`, which
+    /// makes the state portable by default.
+    /// `port_value` is called for each port of `plugin` and returns the value
+    /// of the control inputs to store in the state. It is called before lilv
+    /// is, so it may use the rest of this crate. `flags` and `features` are
+    /// passed to the save function of the plugin, which
+    /// adds `state:makePath` and `state:mapPath` itself.
+    ///
+    /// Returns `None` if lilv could not create the state.
+    ///
+    /// # Safety
+    /// Saving calls the plugin's code, which itself may be unsafe. `instance`
+    /// must be an instance of `plugin` and must not be running.
+    ///
+    /// # Errors
+    /// Returns an error if one of `dirs` can not be passed to lilv.
+    pub unsafe fn new_from_instance(
+        plugin: &Plugin,
+        instance: &Instance,
+        map: &LV2UridMap,
+        dirs: &StateDirs,
+        mut port_value: impl FnMut(&Port) -> Option<f32>,
+        flags: StateFlags,
+        features: &FeatureSet,
+    ) -> Result<Option<State>, Error> {
+        let dir = |dir: Option<&Path>| dir.map(path_to_cstring).transpose();
+        let (copy_dir, link_dir) = dirs.resolved();
+        let (copy_dir, link_dir) = (dir(copy_dir)?, dir(link_dir)?);
+        let scratch_dir = dir(dirs.scratch_dir.as_deref())?;
+        let save_dir = dir(dirs.save_dir.as_deref())?;
+        let mut values = PortValues {
+            values: plugin
+                .iter_ports()
+                .filter_map(|port| {
+                    let value = port_value(&port)?;
+                    let symbol = CString::new(port.symbol()?.as_str()?).ok()?;
+                    Some((symbol, value))
+                })
+                .collect(),
+            float: map_uri(map, ATOM_FLOAT),
+        };
+        let features = features.as_ptrs();
+        let world = World {
+            life: plugin.life.clone(),
+        };
+        #[cfg(feature = "tracing")]
+        let plugin_uri = plugin.uri().to_string();
+        traced!("lilv::state_new_from_instance" (plugin = %plugin_uri) => {
+            let life = plugin.life.inner.lock();
+
+            let c_dir =
+                |dir: &Option<CString>| dir.as_ref().map_or(std::ptr::null(), |d| d.as_ptr());
+            let inner = unsafe {
+                lib::lilv_state_new_from_instance(
+                    plugin.inner.as_ptr(),
+                    instance.inner.as_ptr(),
+                    map_ptr(map),
+                    c_dir(&scratch_dir),
+                    c_dir(&copy_dir),
+                    c_dir(&link_dir),
+                    c_dir(&save_dir),
+                    Some(get_port_value),
+                    (&mut values as *mut PortValues).cast(),
+                    flags.bits(),
+                    features.as_ptr(),
+                )
+            };
+            drop(life);
+            Ok(State::from_lilv(&world, inner))
+        })
+    }
+
+    /// Restore `instance` from the state.
+    ///
+    /// `set_value` is called with the symbol and value of each port value in
+    /// the state, after the plugin restored its properties. Values of types
+    /// other than `atom:Float`, `atom:Double`, `atom:Int` and `atom:Bool` are
+    /// skipped. `map` must be the map the state was created with. `flags` and
+    /// `features` are passed to the restore function of the plugin.
+    ///
+    /// # Safety
+    /// Restoring calls the plugin's code, which itself may be unsafe.
+    /// `instance` must be an instance of the plugin of the state.
+    pub unsafe fn restore(
+        &self,
+        instance: &mut Instance,
+        map: &LV2UridMap,
+        mut set_value: impl FnMut(&str, f32),
+        flags: StateFlags,
+        features: &FeatureSet,
+    ) {
+        let mut values = RestoredValues {
+            values: Vec::new(),
+            float: map_uri(map, ATOM_FLOAT),
+            double: map_uri(map, ATOM_DOUBLE),
+            int: map_uri(map, ATOM_INT),
+            bool: map_uri(map, ATOM_BOOL),
+        };
+        let features = features.as_ptrs();
+        #[cfg(feature = "tracing")]
+        let plugin_uri = self.plugin_uri().to_string();
+        traced!("lilv::state_restore" (plugin = %plugin_uri) => {
+            let _life = self.life.inner.lock();
+            unsafe {
+                lib::lilv_state_restore(
+                    self.inner.as_ptr(),
+                    instance.inner.as_ptr(),
+                    Some(set_port_value),
+                    (&mut values as *mut RestoredValues).cast(),
+                    flags.bits(),
+                    features.as_ptr(),
+                );
+            }
+        });
+        for (symbol, value) in &values.values {
+            set_value(symbol, *value);
+        }
+    }
+
+    /// Save the state to `filename` in the bundle directory `dir`, which is
+    /// created if it does not exist. The state gets the URI `uri`, or the
+    /// file URI of the saved file if `uri` is `None`.
+    ///
+    /// The manifest of the bundle is updated, so the state can be found as a
+    /// preset after the bundle is loaded into a world.
+    ///
+    /// # Errors
+    /// Returns an error if an argument can not be passed to lilv and
+    /// `Error::StateNotSaved` if lilv fails to write the files.
+    pub fn save(
+        &self,
+        world: &World,
+        map: &LV2UridMap,
+        unmap: &LV2UridUnmap,
+        uri: Option<&str>,
+        dir: &Path,
+        filename: &str,
+    ) -> Result<(), Error> {
+        let c_uri = uri.map(CString::new).transpose()?;
+        let c_dir = path_to_cstring(dir)?;
+        let c_filename = CString::new(filename)?;
+        let status = traced!("lilv::state_save" (dir = %dir.display(), filename) => {
+            let world_ptr = world.life.inner.lock();
+            unsafe {
+                lib::lilv_state_save(
+                    world_ptr.as_ptr(),
+                    map_ptr(map),
+                    (unmap as *const LV2UridUnmap as *mut LV2UridUnmap).cast(),
+                    self.inner.as_ptr(),
+                    c_uri.as_ref().map_or(std::ptr::null(), |u| u.as_ptr()),
+                    c_dir.as_ptr(),
+                    c_filename.as_ptr(),
+                )
+            }
+        });
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(Error::StateNotSaved(dir.join(filename)))
+        }
+    }
+
+    /// The state as a Turtle document with the subject `uri`, which
+    /// `new_from_string` loads again. Unlike `save`, this does not use the
+    /// filesystem.
+    ///
+    /// Returns `None` if lilv could not write the state.
+    ///
+    /// # Errors
+    /// Returns an error if `uri` contains a NUL byte.
+    pub fn save_to_string(
+        &self,
+        world: &World,
+        map: &LV2UridMap,
+        unmap: &LV2UridUnmap,
+        uri: &str,
+    ) -> Result<Option<String>, Error> {
+        let c_uri = CString::new(uri)?;
+        let string = traced!("lilv::state_to_string" (uri) => {
+            let world_ptr = world.life.inner.lock();
+            unsafe {
+                lib::lilv_state_to_string(
+                    world_ptr.as_ptr(),
+                    map_ptr(map),
+                    (unmap as *const LV2UridUnmap as *mut LV2UridUnmap).cast(),
+                    self.inner.as_ptr(),
+                    c_uri.as_ptr(),
+                    std::ptr::null(),
+                )
+            }
+        });
+        if string.is_null() {
+            return Ok(None);
+        }
+        let turtle = unsafe { CStr::from_ptr(string) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { lib::lilv_free(string.cast()) };
+        Ok(Some(turtle))
+    }
+
+    fn from_lilv(world: &World, inner: *mut lib::LilvStateImpl) -> Option<State> {
+        let inner = NonNull::new(inner)?;
+        // Lilv returns a state for any resource, even if nothing says which
+        // plugin it applies to.
+        if unsafe { lib::lilv_state_get_plugin_uri(inner.as_ptr()) }.is_null() {
+            unsafe { lib::lilv_state_free(inner.as_ptr()) };
+            return None;
+        }
+        Some(State {
+            inner,
+            life: world.life.clone(),
+        })
+    }
+
+    /// The URI of the plugin the state applies to.
+    ///
+    /// # Panics
+    /// Panics if the state has no plugin URI.
+    #[must_use]
+    pub fn plugin_uri(&self) -> Node {
+        let _life = self.life.inner.lock();
+        let state = self.inner.as_ptr();
+
+        // Copied since the node belongs to the state and not the world.
+        let ptr = NonNull::new(unsafe {
+            lib::lilv_node_duplicate(lib::lilv_state_get_plugin_uri(state))
+        })
+        .unwrap();
+        Node {
+            inner: ptr,
+            borrowed: false,
+            life: self.life.clone(),
+        }
+    }
+
+    /// The URI of the state or `None` if the state was not loaded from or
+    /// saved to a resource.
+    #[must_use]
+    pub fn uri(&self) -> Option<Node> {
+        let _life = self.life.inner.lock();
+        let state = self.inner.as_ptr();
+
+        let uri = unsafe { lib::lilv_state_get_uri(state) };
+        if uri.is_null() {
+            return None;
+        }
+        Some(Node {
+            inner: NonNull::new(unsafe { lib::lilv_node_duplicate(uri) })?,
+            borrowed: false,
+            life: self.life.clone(),
+        })
+    }
+
+    /// The label of the state or `None` if it has no label or the label is
+    /// not valid UTF-8.
+    #[must_use]
+    pub fn label(&self) -> Option<String> {
+        let _life = self.life.inner.lock();
+        let state = self.inner.as_ptr();
+
+        let label = unsafe { lib::lilv_state_get_label(state) };
+        if label.is_null() {
+            return None;
+        }
+        unsafe { CStr::from_ptr(label) }
+            .to_str()
+            .ok()
+            .map(str::to_string)
+    }
+
+    /// Set the label of the state.
+    ///
+    /// # Errors
+    /// Returns an error if `label` contains a NUL byte.
+    pub fn set_label(&mut self, label: &str) -> Result<(), Error> {
+        let label = std::ffi::CString::new(label)?;
+        let _life = self.life.inner.lock();
+        unsafe { lib::lilv_state_set_label(self.inner.as_ptr(), label.as_ptr()) };
+        Ok(())
+    }
+
+    /// Convert the state to a `PortableState`. Port values are converted like
+    /// in `restore`. `map` must be the map the state was created with, and
+    /// `unmap` the matching unmap.
+    ///
+    /// Paths in the directory of the state, like the bundle of a preset, are
+    /// made relative to it. Other paths stay absolute.
+    ///
+    /// Returns `None` if a property could not be converted.
+    #[must_use]
+    pub fn to_portable(&self, map: &LV2UridMap, unmap: &LV2UridUnmap) -> Option<PortableState> {
+        let mut values = RestoredValues {
+            values: Vec::new(),
+            float: map_uri(map, ATOM_FLOAT),
+            double: map_uri(map, ATOM_DOUBLE),
+            int: map_uri(map, ATOM_INT),
+            bool: map_uri(map, ATOM_BOOL),
+        };
+        {
+            let _life = self.life.inner.lock();
+            unsafe {
+                lib::lilv_state_emit_port_values(
+                    self.inner.as_ptr(),
+                    Some(set_port_value),
+                    (&mut values as *mut RestoredValues).cast(),
+                );
+            }
+        }
+        Some(PortableState {
+            plugin_uri: self.plugin_uri().as_uri()?.to_string(),
+            label: self.label(),
+            port_values: values.values,
+            properties: self.portable_properties(map, unmap)?,
+        })
+    }
+
+    // Lilv only passes the properties to the state interface of an instance
+    // and only writes their keys to Turtle. So the keys are read from the
+    // Turtle of the state, and the values are restored into an instance that
+    // retrieves them.
+    fn portable_properties(
+        &self,
+        map: &LV2UridMap,
+        unmap: &LV2UridUnmap,
+    ) -> Option<Vec<PortableProperty>> {
+        if self.num_properties() == 0 {
+            return Some(Vec::new());
+        }
+        let world = World {
+            life: self.life.clone(),
+        };
+        let turtle = self
+            .save_to_string(&world, map, unmap, "urn:lilv-rs:portable")
+            .ok()??;
+        let keys = crate::turtle::state_keys(&turtle);
+        let mut properties = Properties {
+            keys: keys.iter().map(|key| map_uri(map, key)).collect(),
+            path: map_uri(map, ATOM_PATH),
+            values: Vec::new(),
+            dir: None,
+        };
+        {
+            let _life = self.life.inner.lock();
+            properties.with_instance(|instance| unsafe {
+                lib::lilv_state_restore(
+                    self.inner.as_ptr(),
+                    instance,
+                    None,
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null(),
+                );
+            });
+        }
+        if properties.values.len() != self.num_properties() {
+            return None;
+        }
+        let (path, dir) = (properties.path, properties.dir.as_deref().map(Path::new));
+        keys.into_iter()
+            .zip(properties.values)
+            .map(|(key, (value_type, mut body))| {
+                if value_type == path {
+                    if let Some(relative) = relative_path(&body, dir) {
+                        body = relative;
+                    }
+                }
+                let atom = atom_words(value_type, &body)?;
+                let value = unsafe { to_json(atom.as_ptr().cast(), unmap) }.ok()?;
+                Some(PortableProperty { key, value })
+            })
+            .collect()
+    }
+
+    /// The number of properties, excluding port values, in the state.
+    #[must_use]
+    pub fn num_properties(&self) -> usize {
+        let _life = self.life.inner.lock();
+        unsafe { lib::lilv_state_get_num_properties(self.inner.as_ptr()) as _ }
+    }
+}
+
+impl PortableState {
+    /// Create a `State` from the portable state. URIDs in the state are
+    /// mapped with `map`.
+    ///
+    /// Relative paths in the properties are relative to `dir`, like the
+    /// bundle the state was saved to, which must exist. A state with
+    /// properties can only be created for a plugin in `world`.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidUri` if the plugin URI is not a valid URI,
+    /// `Error::ResourceNotLoaded` if the state has properties and the plugin
+    /// is not in `world`, `Error::InvalidAtom` if a property is not valid
+    /// atom JSON, `Error::StateNotFound` with the plugin URI if lilv does not
+    /// accept the state, and an error if the state contains a NUL byte.
+    pub fn instantiate_state(
+        &self,
+        world: &World,
+        map: &LV2UridMap,
+        dir: Option<&Path>,
+    ) -> Result<State, Error> {
+        // Characters that would end the IRI in the Turtle document.
+        let invalid = |c: char| c.is_whitespace() || "<>\"{}|^`\\".contains(c);
+        if self.plugin_uri.is_empty() || self.plugin_uri.contains(invalid) {
+            return Err(Error::InvalidUri(self.plugin_uri.clone()));
+        }
+        if !self.properties.is_empty() {
+            return self.instantiate_with_properties(world, map, dir);
+        }
+        let turtle = CString::new(self.to_turtle())?;
+        let world_ptr = world.life.inner.lock();
+        let inner = unsafe {
+            lib::lilv_state_new_from_string(world_ptr.as_ptr(), map_ptr(map), turtle.as_ptr())
+        };
+        drop(world_ptr);
+        State::from_lilv(world, inner).ok_or_else(|| Error::StateNotFound(self.plugin_uri.clone()))
+    }
+
+    // Turtle can not hold the properties as atoms, so the state is saved
+    // from an instance that stores them.
+    fn instantiate_with_properties(
+        &self,
+        world: &World,
+        map: &LV2UridMap,
+        dir: Option<&Path>,
+    ) -> Result<State, Error> {
+        let plugin = world
+            .new_uri(&self.plugin_uri)
+            .ok()
+            .and_then(|uri| world.plugins().plugin(&uri))
+            .ok_or_else(|| Error::ResourceNotLoaded(self.plugin_uri.clone()))?;
+        let mut properties = Properties {
+            keys: Vec::new(),
+            path: map_uri(map, ATOM_PATH),
+            values: Vec::new(),
+            dir: None,
+        };
+        for property in &self.properties {
+            let atom = from_json(&property.value, map).map_err(Error::InvalidAtom)?;
+            let bytes: Vec<u8> = atom.iter().flat_map(|word| word.to_ne_bytes()).collect();
+            let (size, value_type) = (read_u32(&bytes, 0) as usize, read_u32(&bytes, 4));
+            CString::new(&*property.key)?;
+            properties.keys.push(map_uri(map, &property.key));
+            properties
+                .values
+                .push((value_type, bytes[8..8 + size].to_vec()));
+        }
+        let mut values = PortValues {
+            values: self
+                .port_values
+                .iter()
+                .map(|(symbol, value)| Ok((CString::new(&**symbol)?, *value)))
+                .collect::<Result<_, Error>>()?,
+            float: map_uri(map, ATOM_FLOAT),
+        };
+        let dir = dir.map(path_to_cstring).transpose()?;
+        let inner = {
+            let _life = world.life.inner.lock();
+            properties.with_instance(|instance| unsafe {
+                lib::lilv_state_new_from_instance(
+                    plugin.inner.as_ptr(),
+                    instance,
+                    map_ptr(map),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    dir.as_ref().map_or(std::ptr::null(), |dir| dir.as_ptr()),
+                    Some(get_port_value),
+                    (&mut values as *mut PortValues).cast(),
+                    (StateFlags::POD | StateFlags::PORTABLE).bits(),
+                    std::ptr::null(),
+                )
+            })
+        };
+        let mut state = State::from_lilv(world, inner)
+            .ok_or_else(|| Error::StateNotFound(self.plugin_uri.clone()))?;
+        if let Some(label) = &self.label {
+            state.set_label(label)?;
+        }
+        Ok(state)
+    }
+
+    // A preset in Turtle, the only format lilv creates states from.
+    pub(crate) fn to_turtle(&self) -> String {
+        let mut turtle = format!(
+            "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+             @prefix pset: <http://lv2plug.in/ns/ext/presets#> .\n\
+             @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n\
+             @prefix xsd: <http://www.w3.org/2001/XMLSchema#> .\n\
+             _:state a pset:Preset ;\n    lv2:appliesTo <{}>",
+            self.plugin_uri
+        );
+        if let Some(label) = &self.label {
+            turtle.push_str(&format!(" ;\n    rdfs:label {}", turtle_string(label)));
+        }
+        for (symbol, value) in &self.port_values {
+            let value = if value.is_nan() {
+                "\"NaN\"^^xsd:float".to_string()
+            } else if value.is_infinite() {
+                let sign = if *value < 0.0 { "-" } else { "" };
+                format!("\"{}INF\"^^xsd:float", sign)
+            } else {
+                format!("\"{:?}\"^^xsd:float", value)
+            };
+            turtle.push_str(&format!(
+                " ;\n    lv2:port [ lv2:symbol {} ; pset:value {} ]",
+                turtle_string(symbol),
+                value
+            ));
+        }
+        turtle.push_str(" .\n");
+        turtle
+    }
+}
+
+fn turtle_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Lilv only reads the map, but takes it as a mutable pointer.
+fn map_ptr(map: &LV2UridMap) -> *mut LV2UridMap {
+    map as *const LV2UridMap as *mut LV2UridMap
+}
+
+// The URIs are constants without NUL bytes.
+fn map_uri(map: &LV2UridMap, uri: &str) -> LV2Urid {
+    let uri = CString::new(uri).unwrap();
+    (map.map)(map.handle, uri.as_ptr())
+}
+
+// The port values for `get_port_value`, collected before calling lilv.
+struct PortValues {
+    values: Vec<(CString, f32)>,
+    float: LV2Urid,
+}
+
+unsafe extern "C" fn get_port_value(
+    symbol: *const c_char,
+    user_data: *mut c_void,
+    size: *mut u32,
+    value_type: *mut u32,
+) -> *const c_void {
+    let values = unsafe { &*user_data.cast::<PortValues>() };
+    let symbol = unsafe { CStr::from_ptr(symbol) };
+    // Called from C so it must not unwind. A panic is reported as no value.
+    let value = std::panic::catch_unwind(|| {
+        let (_, value) = values.values.iter().find(|(s, _)| s.as_c_str() == symbol)?;
+        Some(value)
+    });
+    match value.ok().flatten() {
+        Some(value) => {
+            unsafe {
+                *size = 4;
+                *value_type = values.float;
+            }
+            (value as *const f32).cast()
+        }
+        None => {
+            unsafe {
+                *size = 0;
+                *value_type = 0;
+            }
+            std::ptr::null()
+        }
+    }
+}
+
+// The port values from `set_port_value`, passed to the host after lilv
+// returns.
+struct RestoredValues {
+    values: Vec<(String, f32)>,
+    float: LV2Urid,
+    double: LV2Urid,
+    int: LV2Urid,
+    bool: LV2Urid,
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+unsafe extern "C" fn set_port_value(
+    symbol: *const c_char,
+    user_data: *mut c_void,
+    value: *const c_void,
+    size: u32,
+    value_type: u32,
+) {
+    let values = unsafe { &mut *user_data.cast::<RestoredValues>() };
+    let value = match (value_type, size) {
+        (t, 4) if t == values.float => unsafe { value.cast::<f32>().read_unaligned() },
+        (t, 8) if t == values.double => unsafe { value.cast::<f64>().read_unaligned() as f32 },
+        (t, 4) if t == values.int || t == values.bool => unsafe {
+            value.cast::<i32>().read_unaligned() as f32
+        },
+        _ => return,
+    };
+    let symbol = unsafe { CStr::from_ptr(symbol) };
+    // Called from C so it must not unwind. The value is dropped on a panic.
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        values
+            .values
+            .push((symbol.to_string_lossy().into_owned(), value));
+    }));
+}
+
+// The properties an instance of `with_instance` stores or retrieves, since
+// lilv only passes the properties of a state to the state interface of an
+// instance.
+struct Properties {
+    keys: Vec<LV2Urid>,
+    path: LV2Urid,
+    // The type and body of the value of each key.
+    values: Vec<(LV2Urid, Vec<u8>)>,
+    // The directory of the state, which `restore` reads with `state:mapPath`.
+    dir: Option<String>,
+}
+
+static PROPERTIES_INTERFACE: StateInterface = StateInterface {
+    save: store_properties,
+    restore: retrieve_properties,
+};
+
+impl Properties {
+    // Call `f` with an instance that stores `values` when it is saved and
+    // retrieves `keys` into `values` when it is restored, without calling
+    // the code of a plugin.
+    fn with_instance<R>(&mut self, f: impl FnOnce(*mut lib::LilvInstance) -> R) -> R {
+        extern "C" fn instantiate(
+            _: *const LV2Descriptor,
+            _: f64,
+            _: *const c_char,
+            _: *const *const LV2Feature,
+        ) -> LV2Handle {
+            std::ptr::null_mut()
+        }
+        extern "C" fn connect_port(_: LV2Handle, _: u32, _: *mut c_void) {}
+        extern "C" fn run(_: LV2Handle, _: u32) {}
+        extern "C" fn cleanup(_: LV2Handle) {}
+        extern "C" fn extension_data(uri: *const u8) -> *const c_void {
+            if unsafe { CStr::from_ptr(uri.cast()) }.to_bytes() == LV2_STATE_INTERFACE.as_bytes() {
+                (&PROPERTIES_INTERFACE as *const StateInterface).cast()
+            } else {
+                std::ptr::null()
+            }
+        }
+        let descriptor = LV2Descriptor {
+            uri: b"urn:lilv-rs:properties\0".as_ptr().cast(),
+            instantiate,
+            connect_port,
+            activate: None,
+            run,
+            deactivate: None,
+            cleanup,
+            extension_data,
+        };
+        let mut instance = lib::LilvInstanceImpl {
+            lv2_descriptor: &descriptor,
+            lv2_handle: (self as *mut Properties).cast(),
+            pimpl: std::ptr::null_mut(),
+        };
+        f(&mut instance)
+    }
+}
+
+// Called from C so they must not unwind. A panic leaves the properties
+// incomplete, which `to_portable` reports.
+
+unsafe extern "C" fn store_properties(
+    handle: *mut c_void,
+    store: Store,
+    state: *mut c_void,
+    _flags: u32,
+    _features: *const *const LV2Feature,
+) -> u32 {
+    let properties = unsafe { &*handle.cast::<Properties>() };
+    let flags = (StateFlags::POD | StateFlags::PORTABLE).bits();
+    for (key, (value_type, body)) in properties.keys.iter().zip(&properties.values) {
+        unsafe {
+            store(
+                state,
+                *key,
+                body.as_ptr().cast(),
+                body.len(),
+                *value_type,
+                flags,
+            )
+        };
+    }
+    0
+}
+
+unsafe extern "C" fn retrieve_properties(
+    handle: *mut c_void,
+    retrieve: Retrieve,
+    state: *mut c_void,
+    _flags: u32,
+    features: *const *const LV2Feature,
+) -> u32 {
+    let properties = unsafe { &mut *handle.cast::<Properties>() };
+    let map_path = unsafe { feature_data(features, LV2_STATE_MAP_PATH) }
+        .map(|data| unsafe { &*data.cast::<MapPath>() });
+    // The absolute path of a path, freed with `lilv_free` like `state:freePath`
+    // of lilv does.
+    let absolute = |path: *const c_char| {
+        let map_path = map_path?;
+        let absolute = (map_path.absolute_path)(map_path.handle, path);
+        if absolute.is_null() {
+            return None;
+        }
+        let bytes = unsafe { CStr::from_ptr(absolute) }
+            .to_bytes_with_nul()
+            .to_vec();
+        unsafe { lib::lilv_free(absolute.cast()) };
+        Some(bytes)
+    };
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        // Lilv joins the directory of the state with the empty path, or
+        // returns it as it is if the state has no directory.
+        properties.dir = absolute(b"\0".as_ptr().cast())
+            .and_then(|dir| {
+                CStr::from_bytes_with_nul(&dir)
+                    .ok()?
+                    .to_str()
+                    .ok()
+                    .map(str::to_string)
+            })
+            .filter(|dir| !dir.is_empty());
+        for key in &properties.keys {
+            let (mut size, mut value_type, mut flags) = (0, 0, 0);
+            let value = unsafe { retrieve(state, *key, &mut size, &mut value_type, &mut flags) };
+            if value.is_null() {
+                continue;
+            }
+            let body = if value_type == properties.path {
+                absolute(value.cast())
+            } else {
+                None
+            };
+            let body = body.unwrap_or_else(|| {
+                unsafe { std::slice::from_raw_parts(value.cast::<u8>(), size) }.to_vec()
+            });
+            properties.values.push((value_type, body));
+        }
+    }));
+    0
+}
+
+// The path in the body of a path atom relative to `dir`, as the body of a
+// path atom, or `None` if it is not in `dir`.
+fn relative_path(body: &[u8], dir: Option<&Path>) -> Option<Vec<u8>> {
+    let path = CStr::from_bytes_until_nul(body).ok()?.to_str().ok()?;
+    let relative = Path::new(path).strip_prefix(dir?).ok()?.to_str()?;
+    let mut body = relative.as_bytes().to_vec();
+    body.push(0);
+    Some(body)
+}
+
+// An atom with `value_type` and `body`, aligned for `to_json`.
+fn atom_words(value_type: LV2Urid, body: &[u8]) -> Option<Vec<u64>> {
+    let size = u32::try_from(body.len()).ok()?;
+    let mut bytes = Vec::with_capacity(8 + body.len());
+    bytes.extend_from_slice(&size.to_ne_bytes());
+    bytes.extend_from_slice(&value_type.to_ne_bytes());
+    bytes.extend_from_slice(body);
+    let mut words = vec![0_u64; bytes.len().div_ceil(8)];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+        let mut padded = [0; 8];
+        padded[..chunk.len()].copy_from_slice(chunk);
+        *word = u64::from_ne_bytes(padded);
+    }
+    Some(words)
+}
+
+// The data of the feature with `uri` in the null terminated `features`.
+unsafe fn feature_data(features: *const *const LV2Feature, uri: &str) -> Option<*mut c_void> {
+    if features.is_null() {
+        return None;
+    }
+    let mut feature = features;
+    unsafe {
+        while !(*feature).is_null() {
+            if CStr::from_ptr((**feature).uri).to_bytes() == uri.as_bytes() {
+                return Some((**feature).data);
+            }
+            feature = feature.add(1);
+        }
+    }
+    None
+}
+
+impl PartialEq for State {
+    fn eq(&self, other: &State) -> bool {
+        let _life = self.life.inner.lock();
+        unsafe { lib::lilv_state_equals(self.inner.as_ptr(), other.inner.as_ptr()) }
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("uri", &self.uri())
+            .field("plugin_uri", &self.plugin_uri())
+            .field("label", &self.label())
+            .field("num_properties", &self.num_properties())
+            .finish()
+    }
+}
+
+impl std::fmt::Display for State {
+    /// Formats the label of the state, or its URI if it has no label.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.label(), self.uri()) {
+            (Some(label), _) => f.write_str(&label),
+            (None, Some(uri)) => write!(f, "<{}>", uri),
+            (None, None) => write!(f, "state of <{}>", self.plugin_uri()),
+        }
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        let _life = self.life.inner.lock();
+        unsafe { lib::lilv_state_free(self.inner.as_ptr()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::ActiveInstance;
+    use crate::testing::TestHost;
+
+    #[test]
+    fn test_load_preset() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = TestHost::new(44100.0, 512);
+        let preset = format!("{}#no-such-preset", crate::testing::GAIN_URI);
+        let preset = world.new_uri(&preset).unwrap();
+        let state = world.load_preset(&preset, host.urid_map());
+        assert!(
+            matches!(&state, Err(Error::StateNotFound(_))),
+            "{:?}",
+            state
+        );
+
+        // The default state of a plugin is part of the plugin data.
+        let plugin = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let state = State::new_from_world(&world, host.urid_map(), &plugin).unwrap();
+        assert_eq!(state.plugin_uri(), plugin);
+        assert_eq!(state.num_properties(), 0);
+    }
+
+    #[test]
+    fn test_load_fixture_preset() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = TestHost::new(44100.0, 512);
+        let preset = format!("{}#loud", crate::testing::GAIN_URI);
+        let preset = world.new_uri(&preset).unwrap();
+        let state = world.load_preset(&preset, host.urid_map()).unwrap();
+        assert_eq!(state.label().as_deref(), Some("Loud"));
+        assert_eq!(state.uri(), Some(preset));
+        assert_eq!(state.plugin_uri().as_uri(), Some(crate::testing::GAIN_URI));
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = TestHost::new(44100.0, 512);
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let instance = unsafe { plugin.instantiate(host.sample_rate(), host.features().iter()) };
+        let instance = instance.unwrap();
+
+        let temp = crate::testing::TempDir::new("state-round-trip").unwrap();
+        let dir = temp.join("quiet.lv2");
+        let dirs = StateDirs {
+            save_dir: Some(dir.clone()),
+            ..StateDirs::default()
+        };
+        let gain = |port: &Port| if port.index() == 0 { Some(-6.0) } else { None };
+        let mut state = unsafe {
+            State::new_from_instance(
+                &plugin,
+                &instance,
+                host.urid_map(),
+                &dirs,
+                gain,
+                StateFlags::POD | StateFlags::PORTABLE,
+                host.features(),
+            )
+        }
+        .unwrap()
+        .unwrap();
+        state.set_label("Quiet").unwrap();
+        state
+            .save(
+                &world,
+                host.urid_map(),
+                host.urid_unmap(),
+                None,
+                &dir,
+                "quiet.ttl",
+            )
+            .unwrap();
+
+        let loaded =
+            State::new_from_file(&world, host.urid_map(), None, &dir.join("quiet.ttl")).unwrap();
+        assert_eq!(loaded.label().as_deref(), Some("Quiet"));
+        assert_eq!(loaded.plugin_uri(), uri);
+        assert_eq!(loaded, state);
+
+        let mut restored =
+            unsafe { plugin.instantiate(host.sample_rate(), host.features().iter()) };
+        let mut values = Vec::new();
+        unsafe {
+            loaded.restore(
+                restored.as_mut().unwrap(),
+                host.urid_map(),
+                |symbol, value| values.push((symbol.to_string(), value)),
+                StateFlags::empty(),
+                host.features(),
+            );
+        }
+        assert_eq!(values, vec![("gain".to_string(), -6.0)]);
+
+        let missing = State::new_from_file(&world, host.urid_map(), None, &dir.join("missing.ttl"));
+        assert!(matches!(missing, Err(Error::StateNotFound(_))));
+    }
+
+    #[test]
+    fn test_sampler_round_trip() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = TestHost::new(48000.0, 4);
+        let uri = world.new_uri(crate::testing::SAMPLER_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let instantiate = || {
+            let instance =
+                unsafe { plugin.instantiate(host.sample_rate(), host.features().iter()) };
+            instance.unwrap()
+        };
+        // Run `instance` for a block with `events` on its control port.
+        let run = |instance: &mut ActiveInstance, events: &str| {
+            let json = format!(
+                r#"{{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": [{}]}}"#,
+                events
+            );
+            let control = crate::atom::from_json(&json, host.urid_map()).unwrap();
+            let mut output = [0.0_f32; 4];
+            unsafe {
+                instance.instance_mut().connect_port(0, control.as_ptr());
+                instance
+                    .instance_mut()
+                    .connect_port_mut(1, output.as_mut_ptr());
+                instance.run(4);
+            }
+            output
+        };
+        let is_link = |path: &Path| {
+            std::fs::symlink_metadata(path)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        };
+        let canonical = |path: &Path| std::fs::canonicalize(path).unwrap();
+
+        // The sample is outside of all the directories of the state.
+        let samples = crate::testing::TempDir::new("sampler-samples").unwrap();
+        let sample = samples.join("sample.raw");
+        let frames = [0.5_f32, -0.25, 1.0];
+        let bytes: Vec<u8> = frames.iter().flat_map(|f| f.to_le_bytes()).collect();
+        std::fs::write(&sample, bytes).unwrap();
+
+        // The sampler loads the sample in its worker and writes an overview of
+        // it with `state:makePath`.
+        let mut instance = unsafe { instantiate().activate() };
+        let set = format!(
+            concat!(
+                r#"{{"time": 0, "value": {{"type": "http://lv2plug.in/ns/ext/atom#Object", "id": null, "#,
+                r#""otype": "http://lv2plug.in/ns/ext/patch#Set", "properties": ["#,
+                r#"{{"key": "http://lv2plug.in/ns/ext/patch#property", "value": "#,
+                r#"{{"type": "http://lv2plug.in/ns/ext/atom#URID", "value": "urn:lilv-rs:fixture:sampler#sample"}}}}, "#,
+                r#"{{"key": "http://lv2plug.in/ns/ext/patch#value", "value": "#,
+                r#"{{"type": "http://lv2plug.in/ns/ext/atom#Path", "value": "{}"}}}}]}}}}"#,
+            ),
+            sample.display()
+        );
+        assert_eq!(run(&mut instance, &set), [0.0; 4]);
+        assert_eq!(unsafe { host.run_worker(instance.instance()) }, 1);
+        assert_eq!(run(&mut instance, ""), [0.5, -0.25, 1.0, 0.5]);
+        let overview = host.scratch_dir().join("overview.raw");
+        let peaks: Vec<u8> = frames.iter().flat_map(|f| f.abs().to_le_bytes()).collect();
+        assert_eq!(std::fs::read(&overview).unwrap(), peaks);
+
+        // The overview in the scratch directory is copied into the bundle,
+        // which `copy_dir` defaults to, and the sample is linked from it.
+        let temp = crate::testing::TempDir::new("sampler-state").unwrap();
+        let dir = temp.join("sampler.lv2");
+        let dirs = StateDirs {
+            scratch_dir: Some(host.scratch_dir().to_path_buf()),
+            save_dir: Some(dir.clone()),
+            ..StateDirs::default()
+        };
+        let flags = StateFlags::POD | StateFlags::PORTABLE;
+        let save = |instance: &Instance, dirs: &StateDirs, dir: &Path| {
+            let state = unsafe {
+                State::new_from_instance(
+                    &plugin,
+                    instance,
+                    host.urid_map(),
+                    dirs,
+                    |_| None,
+                    flags,
+                    host.state_features(),
+                )
+            };
+            let state = state.unwrap().unwrap();
+            state
+                .save(
+                    &world,
+                    host.urid_map(),
+                    host.urid_unmap(),
+                    None,
+                    dir,
+                    "state.ttl",
+                )
+                .unwrap();
+            state
+        };
+        let state = save(instance.instance(), &dirs, &dir);
+        assert!(!is_link(&dir.join("overview.raw")));
+        assert_eq!(std::fs::read(dir.join("overview.raw")).unwrap(), peaks);
+        assert!(is_link(&dir.join("sample.raw")));
+        assert_eq!(canonical(&dir.join("sample.raw")), canonical(&sample));
+        // The paths are stored relative to the bundle.
+        let turtle = std::fs::read_to_string(dir.join("state.ttl")).unwrap();
+        assert!(turtle.contains("sample.raw") && turtle.contains("overview.raw"));
+        assert!(!turtle.contains(&*samples.path().to_string_lossy()));
+        assert!(!turtle.contains(&*host.scratch_dir().to_string_lossy()));
+
+        // The restored sampler maps the paths back into the bundle.
+        let loaded = State::new_from_file(&world, host.urid_map(), None, &dir.join("state.ttl"));
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, state);
+        let mut restored = instantiate();
+        unsafe {
+            loaded.restore(
+                &mut restored,
+                host.urid_map(),
+                |_, _| {},
+                flags,
+                host.state_features(),
+            );
+        }
+        let mut restored = unsafe { restored.activate() };
+        assert_eq!(run(&mut restored, ""), [0.5, -0.25, 1.0, 0.5]);
+
+        // Saving the restored sampler elsewhere links both files, since they
+        // are in another bundle now, and gives an equal state.
+        let other = temp.join("other.lv2");
+        let other_dirs = StateDirs {
+            save_dir: Some(other.clone()),
+            ..StateDirs::default()
+        };
+        let resaved = save(restored.instance(), &other_dirs, &other);
+        assert!(is_link(&other.join("overview.raw")));
+        assert_eq!(
+            canonical(&other.join("overview.raw")),
+            canonical(&dir.join("overview.raw"))
+        );
+        assert_eq!(canonical(&other.join("sample.raw")), canonical(&sample));
+        assert_eq!(resaved, loaded);
+    }
+
+    #[test]
+    fn test_portable_state() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = TestHost::new(44100.0, 512);
+        let portable = PortableState {
+            plugin_uri: crate::testing::GAIN_URI.to_string(),
+            label: Some("Quiet \"-6\"".to_string()),
+            port_values: vec![("gain".to_string(), -6.0)],
+            properties: Vec::new(),
+        };
+        let state = portable
+            .instantiate_state(&world, host.urid_map(), None)
+            .unwrap();
+        assert_eq!(state.label().as_deref(), Some("Quiet \"-6\""));
+        assert_eq!(
+            state.to_portable(host.urid_map(), host.urid_unmap()),
+            Some(portable)
+        );
+
+        let invalid = PortableState {
+            plugin_uri: "not a uri>".to_string(),
+            ..PortableState::default()
+        };
+        assert_eq!(
+            invalid
+                .instantiate_state(&world, host.urid_map(), None)
+                .unwrap_err(),
+            Error::InvalidUri("not a uri>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_portable_properties() {
+        let world = crate::testing::fixture_world().unwrap();
+        let host = TestHost::new(44100.0, 512);
+        let dir = crate::testing::TempDir::new("portable-properties").unwrap();
+        let property = |key: &str, value: &str| PortableProperty {
+            key: format!("urn:lilv-rs:test#{}", key),
+            value: value.to_string(),
+        };
+        let path = |path: &str| {
+            format!(
+                r#"{{"type": "http://lv2plug.in/ns/ext/atom#Path", "value": "{}"}}"#,
+                path
+            )
+        };
+        let mut portable = PortableState {
+            plugin_uri: crate::testing::GAIN_URI.to_string(),
+            label: Some("Sample".to_string()),
+            port_values: vec![("gain".to_string(), 2.0)],
+            properties: vec![
+                property(
+                    "count",
+                    r#"{"type": "http://lv2plug.in/ns/ext/atom#Int", "value": 3}"#,
+                ),
+                // Paths in the directory are relative to it, others stay
+                // absolute.
+                property("sample", &path("samples/sample.raw")),
+                property("other", &path("/lilv-rs/other.raw")),
+            ],
+        };
+        let state = portable
+            .instantiate_state(&world, host.urid_map(), Some(dir.path()))
+            .unwrap();
+        assert_eq!(state.num_properties(), 3);
+        assert_eq!(state.label().as_deref(), Some("Sample"));
+        assert_eq!(
+            state.to_portable(host.urid_map(), host.urid_unmap()),
+            Some(portable.clone())
+        );
+
+        // Without a directory, the relative path stays relative.
+        let state = portable
+            .instantiate_state(&world, host.urid_map(), None)
+            .unwrap();
+        assert_eq!(
+            state.to_portable(host.urid_map(), host.urid_unmap()),
+            Some(portable.clone())
+        );
+
+        portable.properties[0].value = "{".to_string();
+        assert!(matches!(
+            portable.instantiate_state(&world, host.urid_map(), None),
+            Err(Error::InvalidAtom(_))
+        ));
+        portable.plugin_uri = "urn:lilv-rs:test:missing".to_string();
+        assert_eq!(
+            portable
+                .instantiate_state(&world, host.urid_map(), None)
+                .unwrap_err(),
+            Error::ResourceNotLoaded("urn:lilv-rs:test:missing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let dir = Path::new("/tmp/preset.lv2");
+        assert_eq!(
+            relative_path(b"/tmp/preset.lv2/a/b.wav\0", Some(dir)).as_deref(),
+            Some(&b"a/b.wav\0"[..])
+        );
+        assert_eq!(relative_path(b"/tmp/other.wav\0", Some(dir)), None);
+        assert_eq!(relative_path(b"/tmp/preset.lv2/b.wav\0", None), None);
+        assert_eq!(relative_path(b"no nul", Some(dir)), None);
+    }
+
+    #[test]
+    fn test_turtle_string() {
+        assert_eq!(turtle_string("a \"b\"\n\\"), "\"a \\\"b\\\"\\n\\\\\"");
+    }
+
+    #[test]
+    fn test_new_from_string() {
+        let world = crate::World::new();
+        let host = crate::testing::TestHost::new(44100.0, 512);
+        let portable = PortableState {
+            plugin_uri: crate::testing::GAIN_URI.to_string(),
+            label: None,
+            port_values: vec![("gain".to_string(), 2.0)],
+            properties: Vec::new(),
+        };
+        let state = State::new_from_string(&world, host.urid_map(), &portable.to_turtle()).unwrap();
+        assert_eq!(
+            state.to_portable(host.urid_map(), host.urid_unmap()),
+            Some(portable)
+        );
+
+        let broken = "<urn:a> <urn:b> <urn:c> .\n<urn:a> <urn:b> .\n";
+        match State::new_from_string(&world, host.urid_map(), broken) {
+            Err(Error::InvalidTurtle(err)) => assert_eq!(err.line, 2),
+            _ => panic!("broken Turtle was accepted"),
+        }
+        assert!(State::validate_string(broken).is_err());
+        assert_eq!(
+            State::new_from_string(&world, host.urid_map(), "<urn:a> <urn:b> <urn:c> ."),
+            Err(Error::StateNotFound(String::new()))
+        );
+    }
+
+    #[test]
+    fn test_state_dirs() {
+        let dir = PathBuf::from("/tmp/preset.lv2");
+        let mut dirs = StateDirs {
+            save_dir: Some(dir.clone()),
+            ..StateDirs::default()
+        };
+        assert_eq!(dirs.resolved(), (Some(dir.as_path()), Some(dir.as_path())));
+        let copy_dir = PathBuf::from("/tmp/copies");
+        dirs.copy_dir = Some(copy_dir.clone());
+        assert_eq!(
+            dirs.resolved(),
+            (Some(copy_dir.as_path()), Some(dir.as_path()))
+        );
+        dirs.absolute_paths = true;
+        assert_eq!(dirs.resolved(), (Some(copy_dir.as_path()), None));
+        assert_eq!(StateDirs::default().resolved(), (None, None));
+    }
+
+    #[test]
+    fn test_state_flags() {
+        let mut flags = StateFlags::POD | StateFlags::PORTABLE;
+        assert_eq!(flags.bits(), 3);
+        assert!(flags.contains(StateFlags::POD));
+        assert!(!flags.contains(StateFlags::NATIVE));
+        flags |= StateFlags::NATIVE;
+        assert_eq!(flags & StateFlags::NATIVE, StateFlags::NATIVE);
+        assert_eq!(StateFlags::from_bits(7), Some(flags));
+        assert_eq!(StateFlags::from_bits(8), None);
+        assert_eq!(StateFlags::default(), StateFlags::empty());
+    }
+}
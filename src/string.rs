@@ -0,0 +1,74 @@
+use crate::ffi::serd::serd_free;
+use lilv_sys as lib;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::ptr::NonNull;
+
+/// A string returned by lilv that the caller owns. The string is freed with
+/// the function matching the library that allocated it when dropped.
+pub(crate) struct LilvString {
+    ptr: NonNull<c_char>,
+    free: unsafe extern "C" fn(*mut c_void),
+}
+
+impl LilvString {
+    /// Take ownership of a string that must be freed with `lilv_free`.
+    ///
+    /// # Safety
+    /// `ptr` must be null or a valid string that is freed with `lilv_free`.
+    pub(crate) unsafe fn from_lilv(ptr: *mut c_char) -> Option<LilvString> {
+        Some(LilvString {
+            ptr: NonNull::new(ptr)?,
+            free: lib::lilv_free,
+        })
+    }
+
+    /// Take ownership of a string that must be freed with `serd_free`.
+    ///
+    /// # Safety
+    /// `ptr` must be null or a valid string that is freed with `serd_free`.
+    pub(crate) unsafe fn from_serd(ptr: *mut c_char) -> Option<LilvString> {
+        Some(LilvString {
+            ptr: NonNull::new(ptr)?,
+            free: serd_free,
+        })
+    }
+
+    /// The string as a `CStr`.
+    pub(crate) fn as_c_str(&self) -> &CStr {
+        unsafe { CStr::from_ptr(self.ptr.as_ptr()) }
+    }
+
+    /// Copy the string into a `String`, replacing invalid UTF-8.
+    pub(crate) fn to_string_lossy(&self) -> String {
+        self.as_c_str().to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for LilvString {
+    fn drop(&mut self) {
+        unsafe { (self.free)(self.ptr.as_ptr().cast()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_uri_parse() {
+        let mut raw_hostname = std::ptr::null_mut();
+        // `lilv_file_uri_parse` forwards to Serd so both strings are Serd's.
+        let path = unsafe {
+            LilvString::from_serd(lib::lilv_file_uri_parse(
+                b"file://host/tmp/a%20b.lv2\0".as_ptr().cast(),
+                &mut raw_hostname,
+            ))
+        }
+        .unwrap();
+        let hostname = unsafe { LilvString::from_serd(raw_hostname) }.unwrap();
+        assert_eq!(path.to_string_lossy(), "/tmp/a b.lv2");
+        assert_eq!(hostname.to_string_lossy(), "host");
+        assert!(unsafe { LilvString::from_lilv(std::ptr::null_mut()) }.is_none());
+    }
+}
@@ -0,0 +1,661 @@
+use crate::feature::{FeatureSet, LV2UridUnmap, LV2_WORKER_SCHEDULE};
+use crate::ffi::lv2::{
+    FreePath, MakePath, MapPath, WorkerInterface, WorkerSchedule, LV2_STATE_FREE_PATH,
+    LV2_STATE_MAKE_PATH, LV2_STATE_MAP_PATH, WORKER_ERR_UNKNOWN, WORKER_SUCCESS,
+};
+use crate::host::Host;
+use crate::instance::Instance;
+use crate::path::path_to_cstring;
+use crate::scan::find_bundles;
+use crate::world::World;
+use lv2_raw::{LV2Urid, LV2UridMap};
+use parking_lot::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The URI of the gain fixture, with the same ports as `eg-amp` and a
+/// `Loud` preset.
+pub const GAIN_URI: &str = "urn:lilv-rs:fixture:gain";
+/// The URI of the MIDI through fixture, with an atom input and output that
+/// support MIDI. It requires `urid:map`.
+pub const MIDI_THROUGH_URI: &str = "urn:lilv-rs:fixture:midi-through";
+/// The URI of the sampler fixture, which requires `urid:map` and
+/// `work:schedule` and stores its state through the state extension.
+pub const SAMPLER_URI: &str = "urn:lilv-rs:fixture:sampler";
+
+// The plugins of all the fixture bundles, compiled from
+// `fixtures/plugins.rs` by the build script.
+const PLUGINS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fixtures.so"));
+
+// The name and contents of a file in a fixture bundle.
+type File = (&'static str, &'static [u8]);
+
+// The files of each fixture bundle. The bundles are embedded so they do not
+// depend on the directory the crate was built in.
+const BUNDLES: &[(&str, &[File])] = &[
+    (
+        "gain.lv2",
+        &[
+            (
+                "manifest.ttl",
+                include_bytes!("../fixtures/gain.lv2/manifest.ttl"),
+            ),
+            ("gain.ttl", include_bytes!("../fixtures/gain.lv2/gain.ttl")),
+            (
+                "presets.ttl",
+                include_bytes!("../fixtures/gain.lv2/presets.ttl"),
+            ),
+            ("gain.so", PLUGINS),
+        ],
+    ),
+    (
+        "midi-through.lv2",
+        &[
+            (
+                "manifest.ttl",
+                include_bytes!("../fixtures/midi-through.lv2/manifest.ttl"),
+            ),
+            (
+                "midi-through.ttl",
+                include_bytes!("../fixtures/midi-through.lv2/midi-through.ttl"),
+            ),
+            ("midi-through.so", PLUGINS),
+        ],
+    ),
+    (
+        "sampler.lv2",
+        &[
+            (
+                "manifest.ttl",
+                include_bytes!("../fixtures/sampler.lv2/manifest.ttl"),
+            ),
+            (
+                "sampler.ttl",
+                include_bytes!("../fixtures/sampler.lv2/sampler.ttl"),
+            ),
+            ("sampler.so", PLUGINS),
+        ],
+    ),
+];
+
+/// The directory that holds the fixture bundles.
+///
+/// The bundles are written to the temporary directory of the system the
+/// first time this is called, and reused by later calls and other processes
+/// with the same fixtures. If the build script could not compile the fixture
+/// plugins, the bundles can be queried but not instantiated.
+///
+/// # Errors
+/// Returns an error if the bundles could not be written.
+pub fn fixtures_dir() -> io::Result<PathBuf> {
+    static DIR: Mutex<Option<PathBuf>> = parking_lot::const_mutex(None);
+    let mut dir = DIR.lock();
+    if let Some(dir) = &*dir {
+        return Ok(dir.clone());
+    }
+    let mut hasher = DefaultHasher::new();
+    BUNDLES.hash(&mut hasher);
+    let path = std::env::temp_dir().join(format!("lilv-rs-fixtures-{:016x}", hasher.finish()));
+    if !path.exists() {
+        write_fixtures(&path)?;
+    }
+    *dir = Some(path.clone());
+    Ok(path)
+}
+
+// Write the bundles next to `path` and move them into place, so other
+// processes never see a partial directory.
+fn write_fixtures(path: &Path) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(format!(".{}", std::process::id()));
+    let partial = PathBuf::from(partial);
+    for (bundle, files) in BUNDLES {
+        let dir = partial.join(bundle);
+        fs::create_dir_all(&dir)?;
+        for (name, contents) in *files {
+            fs::write(dir.join(name), contents)?;
+        }
+    }
+    match fs::rename(&partial, path) {
+        // Another process was faster.
+        Err(_) if path.exists() => fs::remove_dir_all(&partial),
+        result => result,
+    }
+}
+
+/// Create a world with only the fixture bundles loaded.
+///
+/// # Errors
+/// Returns an error if the fixture bundles could not be written, see
+/// `fixtures_dir`.
+pub fn fixture_world() -> io::Result<World> {
+    let dir = fixtures_dir()?;
+    let bundles = find_bundles(std::slice::from_ref(&dir));
+    if bundles.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no fixture bundles in {}", dir.display()),
+        ));
+    }
+    let world = World::new();
+    for bundle in bundles {
+        world
+            .load_bundle_dir(&bundle)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    }
+    Ok(world)
+}
+
+/// A directory under the temp dir that is unique to the value and is removed
+/// when it is dropped.
+///
+/// Tests that run in parallel, or in several processes at once, each get
+/// their own directory.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a new empty directory whose name starts with `lilv-rs-{prefix}`.
+    ///
+    /// # Errors
+    /// Returns an error if the directory could not be created.
+    pub fn new(prefix: &str) -> io::Result<TempDir> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "lilv-rs-{}-{}-{}",
+            prefix,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        // Left over by a process with the same id that did not clean up.
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path)?;
+        Ok(TempDir { path })
+    }
+
+    /// The path of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of `name` in the directory.
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A minimal host for tests that provides the features most plugins need.
+///
+/// The host provides the features of `host::Host`, `work:schedule` and the
+/// `state:makePath`, `state:mapPath` and `state:freePath` features of a
+/// scratch directory that is removed with the host. Scheduled work runs when
+/// the test calls `run_worker`. The features are not real-time safe and are
+/// only meant for tests.
+///
+/// # Example
+/// ```no_run
+/// let host = lilv::testing::TestHost::new(44100.0, 512);
+/// let world = lilv::testing::fixture_world().unwrap();
+/// let uri = world.new_uri(lilv::testing::GAIN_URI).unwrap();
+/// let plugin = world.plugins().plugin(&uri).unwrap();
+/// let _instance = unsafe { plugin.instantiate(host.sample_rate(), host.features().iter()) };
+/// ```
+pub struct TestHost {
+    host: Host,
+    // The feature data is boxed so it keeps its address when the host is
+    // moved, like in `Host`.
+    scratch: Box<TempDir>,
+    work: Box<Mutex<Vec<Vec<u8>>>>,
+    _schedule: Box<WorkerSchedule>,
+    _make_path: Box<MakePath>,
+    _map_path: Box<MapPath>,
+    _free_path: Box<FreePath>,
+    features: FeatureSet,
+    state_features: FeatureSet,
+}
+
+unsafe impl Send for TestHost {}
+unsafe impl Sync for TestHost {}
+
+// The callbacks are called from C so they must not unwind. Panics are
+// reported as errors, or as a NULL path.
+
+extern "C" fn schedule_work(handle: *mut c_void, size: u32, data: *const c_void) -> u32 {
+    let work = unsafe { &*handle.cast::<Mutex<Vec<Vec<u8>>>>() };
+    let data = unsafe { bytes(data, size) };
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        work.lock().push(data.to_vec());
+    }))
+    .map_or(WORKER_ERR_UNKNOWN, |()| WORKER_SUCCESS)
+}
+
+extern "C" fn respond(handle: *mut c_void, size: u32, data: *const c_void) -> u32 {
+    let responses = unsafe { &mut *handle.cast::<Vec<Vec<u8>>>() };
+    let data = unsafe { bytes(data, size) };
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        responses.push(data.to_vec());
+    }))
+    .map_or(WORKER_ERR_UNKNOWN, |()| WORKER_SUCCESS)
+}
+
+// The `size` bytes at `data`, which may be NULL if `size` is 0.
+unsafe fn bytes<'a>(data: *const c_void, size: u32) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data.cast(), size as usize) }
+    }
+}
+
+// A path from a plugin, the inverse of `path_to_cstring`.
+fn path_from_cstr(path: &CStr) -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(PathBuf::from(std::ffi::OsStr::from_bytes(path.to_bytes())))
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_str().ok().map(PathBuf::from)
+    }
+}
+
+extern "C" fn make_path(handle: *mut c_void, path: *const c_char) -> *mut c_char {
+    let scratch = unsafe { &*handle.cast::<TempDir>() };
+    let path = unsafe { CStr::from_ptr(path) };
+    std::panic::catch_unwind(|| {
+        let path = scratch.join(path_from_cstr(path)?);
+        // The host creates the directories of the path.
+        fs::create_dir_all(path.parent()?).ok()?;
+        Some(path_to_cstring(&path).ok()?.into_raw())
+    })
+    .ok()
+    .flatten()
+    .unwrap_or(std::ptr::null_mut())
+}
+
+extern "C" fn abstract_path(handle: *mut c_void, path: *const c_char) -> *mut c_char {
+    let scratch = unsafe { &*handle.cast::<TempDir>() };
+    let path = unsafe { CStr::from_ptr(path) };
+    std::panic::catch_unwind(|| {
+        let path = path_from_cstr(path)?;
+        let path = path.strip_prefix(scratch.path()).unwrap_or(&path);
+        Some(path_to_cstring(path).ok()?.into_raw())
+    })
+    .ok()
+    .flatten()
+    .unwrap_or(std::ptr::null_mut())
+}
+
+extern "C" fn absolute_path(handle: *mut c_void, path: *const c_char) -> *mut c_char {
+    let scratch = unsafe { &*handle.cast::<TempDir>() };
+    let path = unsafe { CStr::from_ptr(path) };
+    std::panic::catch_unwind(|| {
+        let path = scratch.join(path_from_cstr(path)?);
+        Some(path_to_cstring(&path).ok()?.into_raw())
+    })
+    .ok()
+    .flatten()
+    .unwrap_or(std::ptr::null_mut())
+}
+
+extern "C" fn free_path(_handle: *mut c_void, path: *mut c_char) {
+    if !path.is_null() {
+        // The paths were allocated by `CString::into_raw`.
+        drop(unsafe { CString::from_raw(path) });
+    }
+}
+
+impl TestHost {
+    /// Create a host that runs at `sample_rate` with blocks of
+    /// `block_length` frames.
+    ///
+    /// # Panics
+    /// Panics if `block_length` does not fit into an `i32` or if the scratch
+    /// directory could not be created.
+    #[must_use]
+    pub fn new(sample_rate: f64, block_length: usize) -> TestHost {
+        let host = Host::new(sample_rate, block_length);
+        let scratch = Box::new(TempDir::new("scratch").unwrap());
+        let scratch_handle = (&*scratch as *const TempDir as *mut TempDir).cast();
+        let mut work: Box<Mutex<Vec<Vec<u8>>>> = Box::default();
+        let mut schedule = Box::new(WorkerSchedule {
+            handle: (&mut *work as *mut Mutex<Vec<Vec<u8>>>).cast(),
+            schedule_work,
+        });
+        let mut make_path = Box::new(MakePath {
+            handle: scratch_handle,
+            path: self::make_path,
+        });
+        let mut map_path = Box::new(MapPath {
+            handle: scratch_handle,
+            abstract_path,
+            absolute_path,
+        });
+        let mut free_path = Box::new(FreePath {
+            handle: std::ptr::null_mut(),
+            free_path: self::free_path,
+        });
+        // lilv adds its own path features when it saves or restores a state,
+        // which must not be hidden by those of the host.
+        let with_host_features = |extra: &[(&str, *mut c_void)]| {
+            let mut features = FeatureSet::new();
+            let host_features = host.features().iter().map(|feature| {
+                let uri = unsafe { CStr::from_ptr(feature.uri) };
+                (uri.to_str().unwrap(), feature.data)
+            });
+            for (uri, data) in host_features.chain(extra.iter().copied()) {
+                features = unsafe { features.with_feature_data(uri, data) }.unwrap();
+            }
+            features
+        };
+        let schedule_feature = (
+            LV2_WORKER_SCHEDULE,
+            (&mut *schedule as *mut WorkerSchedule).cast(),
+        );
+        let state_features = with_host_features(&[schedule_feature]);
+        let features = with_host_features(&[
+            schedule_feature,
+            (
+                LV2_STATE_MAKE_PATH,
+                (&mut *make_path as *mut MakePath).cast(),
+            ),
+            (LV2_STATE_MAP_PATH, (&mut *map_path as *mut MapPath).cast()),
+            (
+                LV2_STATE_FREE_PATH,
+                (&mut *free_path as *mut FreePath).cast(),
+            ),
+        ]);
+        TestHost {
+            host,
+            scratch,
+            work,
+            _schedule: schedule,
+            _make_path: make_path,
+            _map_path: map_path,
+            _free_path: free_path,
+            features,
+            state_features,
+        }
+    }
+
+    /// The features of the host. They can be passed to
+    /// `Plugin::instantiate` with `features().iter()` and stay valid until
+    /// the host is dropped.
+    #[must_use]
+    pub fn features(&self) -> &FeatureSet {
+        &self.features
+    }
+
+    /// The features of the host without the path features, to pass to
+    /// `State::new_from_instance` and `State::restore`, which add their own.
+    #[must_use]
+    pub fn state_features(&self) -> &FeatureSet {
+        &self.state_features
+    }
+
+    /// The directory of the paths the plugins make with `state:makePath`,
+    /// which is the `scratch_dir` of `StateDirs` when a state is saved.
+    /// Relative paths of `state:mapPath` are relative to it.
+    #[must_use]
+    pub fn scratch_dir(&self) -> &Path {
+        self.scratch.path()
+    }
+
+    /// Run the work the plugins scheduled with `work:schedule` since the
+    /// last call. The work of `instance` and its responses run on the
+    /// calling thread, then the instance is told the run ended. Returns the
+    /// number of work requests.
+    ///
+    /// # Safety
+    /// This calls the worker interface of the plugin, which itself may be
+    /// unsafe. `instance` must be the instance that scheduled the work, and
+    /// must not be running at the same time.
+    pub unsafe fn run_worker(&self, instance: &Instance) -> usize {
+        let requests = std::mem::take(&mut *self.work.lock());
+        let worker = unsafe {
+            instance.extension_data::<WorkerInterface>("http://lv2plug.in/ns/ext/worker#interface")
+        };
+        let worker = match worker {
+            Some(worker) => unsafe { worker.as_ref() },
+            None => return 0,
+        };
+        let handle = instance.handle();
+        let mut responses: Vec<Vec<u8>> = Vec::new();
+        #[allow(clippy::cast_possible_truncation)]
+        for request in &requests {
+            unsafe {
+                (worker.work)(
+                    handle,
+                    respond,
+                    (&mut responses as *mut Vec<Vec<u8>>).cast(),
+                    request.len() as u32,
+                    request.as_ptr().cast(),
+                )
+            };
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        for response in &responses {
+            unsafe {
+                (worker.work_response)(handle, response.len() as u32, response.as_ptr().cast())
+            };
+        }
+        if let Some(end_run) = worker.end_run {
+            unsafe { end_run(handle) };
+        }
+        requests.len()
+    }
+
+    /// The URID map of the host, like for `World::load_preset`.
+    #[must_use]
+    pub fn urid_map(&self) -> &LV2UridMap {
+        self.host.urid_map()
+    }
+
+    /// The URID unmap of the host, like for `State::save`.
+    #[must_use]
+    pub fn urid_unmap(&self) -> &LV2UridUnmap {
+        self.host.urid_unmap()
+    }
+
+    /// The sample rate of the host.
+    #[must_use]
+    pub fn sample_rate(&self) -> f64 {
+        self.host.sample_rate()
+    }
+
+    /// The block length of the host in frames.
+    #[must_use]
+    pub fn block_length(&self) -> usize {
+        self.host.block_length()
+    }
+
+    /// Map `uri` to a URID like the plugins of the host do.
+    ///
+    /// # Panics
+    /// Panics if `uri` contains a NUL byte.
+    #[must_use]
+    pub fn map(&self, uri: &str) -> LV2Urid {
+        self.host.map(uri)
+    }
+
+    /// The URI of `urid`, or `None` if it was not mapped.
+    #[must_use]
+    pub fn unmap(&self, urid: LV2Urid) -> Option<String> {
+        self.host.unmap(urid)
+    }
+}
+
+impl std::fmt::Debug for TestHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TestHost")
+            .field("host", &self.host)
+            .field("scratch_dir", &self.scratch_dir())
+            .field("features", &self.features)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port::{PortDirection, PortKind};
+
+    #[test]
+    fn test_fixture_world() {
+        let world = fixture_world().unwrap();
+        assert_eq!(world.plugins().count(), 3);
+
+        let gain = world.plugins().plugin(&world.new_uri(GAIN_URI).unwrap());
+        let gain = gain.unwrap();
+        assert_eq!(gain.name().as_str(), Some("Fixture Gain"));
+        assert_eq!(gain.port_by_index(2).unwrap().kind(), PortKind::Audio);
+        assert!(gain.required_features().iter().next().is_none());
+
+        let midi = world.new_uri(MIDI_THROUGH_URI).unwrap();
+        let midi = world.plugins().plugin(&midi).unwrap();
+        let port = midi.port_by_index(1).unwrap();
+        assert_eq!(port.direction(), Some(PortDirection::Output));
+        assert_eq!(port.kind(), PortKind::Atom);
+
+        let sampler = world.new_uri(SAMPLER_URI).unwrap();
+        let sampler = world.plugins().plugin(&sampler).unwrap();
+        assert_eq!(sampler.required_features().iter().count(), 2);
+    }
+
+    #[test]
+    fn test_fixture_plugins() {
+        let world = fixture_world().unwrap();
+        let host = TestHost::new(48000.0, 4);
+        let plugin = |uri| {
+            world
+                .plugins()
+                .plugin(&world.new_uri(uri).unwrap())
+                .unwrap()
+        };
+
+        let gain = plugin(GAIN_URI);
+        let mut instance = unsafe { gain.instantiate(48000.0, []) }.unwrap();
+        let db = -6.0_f32;
+        let input = [1.0_f32, -0.5, 0.0, 2.0];
+        let mut output = [0.0_f32; 4];
+        unsafe {
+            instance.connect_port(0, &db);
+            instance.connect_port(1, input.as_ptr());
+            instance.connect_port_mut(2, output.as_mut_ptr());
+            instance.activate().run(4);
+        }
+        assert!((output[0] - 0.501).abs() < 1e-3, "{:?}", output);
+        assert!((output[3] + 2.0 * output[1]).abs() < 1e-6);
+
+        let through = plugin(MIDI_THROUGH_URI);
+        assert!(unsafe { through.instantiate(48000.0, []) }.is_err());
+        let mut instance = unsafe { through.instantiate(48000.0, host.features().iter()) }.unwrap();
+        let sequence = |events: &str| {
+            let json = format!(
+                r#"{{"type": "http://lv2plug.in/ns/ext/atom#Sequence", "unit": null, "value": [{}]}}"#,
+                events
+            );
+            crate::atom::from_json(&json, host.urid_map()).unwrap()
+        };
+        let note = r#"{"time": 1, "value": {"type": "http://lv2plug.in/ns/ext/midi#MidiEvent", "value": "903c64"}}"#;
+        let int =
+            r#"{"time": 2, "value": {"type": "http://lv2plug.in/ns/ext/atom#Int", "value": 7}}"#;
+        let input = sequence(&format!("{}, {}", note, int));
+        let mut output = vec![0_u64; 16];
+        // The size of the output is its capacity when the plugin runs.
+        output[0] = 8 * 15;
+        unsafe {
+            instance.connect_port(0, input.as_ptr());
+            instance.connect_port_mut(1, output.as_mut_ptr());
+        }
+        unsafe { instance.activate().run(4) };
+        let json = |atom: &[u64]| {
+            unsafe { crate::atom::to_json(atom.as_ptr().cast(), host.urid_unmap()) }.unwrap()
+        };
+        assert_eq!(json(&output), json(&sequence(note)));
+    }
+
+    #[test]
+    fn test_test_host() {
+        let host = TestHost::new(48000.0, 256);
+        assert!(host
+            .features()
+            .contains("http://lv2plug.in/ns/ext/urid#map"));
+        let urid = host.map("urn:lilv-rs:test");
+        assert_eq!(host.unmap(urid).as_deref(), Some("urn:lilv-rs:test"));
+
+        let world = fixture_world().unwrap();
+        let uri = world.new_uri(MIDI_THROUGH_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        assert_eq!(plugin.check_features(host.features()), Ok(()));
+        assert!(!host.state_features().contains(LV2_STATE_MAP_PATH));
+        assert!(host.state_features().contains(LV2_WORKER_SCHEDULE));
+    }
+
+    #[test]
+    fn test_test_host_paths() {
+        let host = TestHost::new(48000.0, 256);
+        let data = |uri: &str| {
+            let feature = host
+                .features()
+                .iter()
+                .find(|f| unsafe { CStr::from_ptr(f.uri) }.to_bytes() == uri.as_bytes());
+            feature.unwrap().data
+        };
+        let make_path = unsafe { &*data(LV2_STATE_MAKE_PATH).cast::<MakePath>() };
+        let map_path = unsafe { &*data(LV2_STATE_MAP_PATH).cast::<MapPath>() };
+        let free_path = unsafe { &*data(LV2_STATE_FREE_PATH).cast::<FreePath>() };
+        let take = |path: *mut c_char| {
+            assert!(!path.is_null());
+            let string = unsafe { CStr::from_ptr(path) }
+                .to_str()
+                .unwrap()
+                .to_string();
+            (free_path.free_path)(free_path.handle, path);
+            PathBuf::from(string)
+        };
+        let c = |path: &Path| path_to_cstring(path).unwrap();
+
+        let made = take((make_path.path)(
+            make_path.handle,
+            c(Path::new("a/b.raw")).as_ptr(),
+        ));
+        assert_eq!(made, host.scratch_dir().join("a/b.raw"));
+        assert!(host.scratch_dir().join("a").is_dir());
+
+        let relative = take((map_path.abstract_path)(map_path.handle, c(&made).as_ptr()));
+        assert_eq!(relative, Path::new("a/b.raw"));
+        let absolute = take((map_path.absolute_path)(
+            map_path.handle,
+            c(&relative).as_ptr(),
+        ));
+        assert_eq!(absolute, made);
+        // Paths outside of the scratch directory stay absolute.
+        let outside = Path::new("/lilv-rs/sample.raw");
+        let mapped = take((map_path.abstract_path)(
+            map_path.handle,
+            c(outside).as_ptr(),
+        ));
+        assert_eq!(mapped, outside);
+        let mapped = take((map_path.absolute_path)(
+            map_path.handle,
+            c(outside).as_ptr(),
+        ));
+        assert_eq!(mapped, outside);
+    }
+}
@@ -0,0 +1,23 @@
+/// Evaluates `$body` within a `tracing` debug span and emits an event with the
+/// elapsed time once it completes. Without the `tracing` feature, this is just
+/// `$body`.
+///
+/// Fields use the `tracing` syntax and are only evaluated when the feature is
+/// enabled, so they must not take the world lock. Values that need it, like
+/// the URI of a node, are captured in a `#[cfg(feature = "tracing")]` binding
+/// before the macro.
+macro_rules! traced {
+    ($name:literal $(($($field:tt)*))? => $body:block) => {{
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($name $(, $($field)*)?).entered();
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+        let result = $body;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_us = u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX),
+            concat!($name, " finished")
+        );
+        result
+    }};
+}
@@ -0,0 +1,254 @@
+use crate::buffer::PortBuffer;
+use crate::host::Host;
+use crate::info::ControlInfo;
+use crate::instance::{Instance, InstantiateError};
+use crate::plugin::Plugin;
+use crate::state::{State, StateDirs, StateFlags};
+use crate::world::World;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// A terminal editor for the controls of a plugin, for plugin authors that
+/// debug a plugin without its UI.
+///
+/// The editor instantiates the plugin with a `host::Host` and connects its
+/// input control ports to values it owns. It reads one command per line:
+///
+/// - `list` shows the controls from `Plugin::control_model` and their
+///   values.
+/// - `set <symbol> <value>` sets a control port, clamped to its range.
+/// - `save <dir> <label>` saves the state of the instance as a preset in the
+///   bundle directory `dir`.
+/// - `quit` stops the editor.
+///
+/// Patch parameters are listed but can not be set, since the instance is not
+/// run.
+pub struct ParameterEditor {
+    instance: Instance,
+    plugin: Plugin,
+    controls: Vec<ControlInfo>,
+    // The value of each port, by index. The buffer keeps its address since
+    // the control ports are connected to it.
+    values: PortBuffer,
+    host: Host,
+}
+
+impl ParameterEditor {
+    /// Instantiate `plugin` at `sample_rate` and set its controls to their
+    /// default values.
+    ///
+    /// # Safety
+    /// Instantiating calls the plugin's code, which itself may be unsafe.
+    ///
+    /// # Errors
+    /// Returns an error if the plugin needs features the `Host` does not
+    /// provide or could not be instantiated.
+    pub unsafe fn new(
+        plugin: Plugin,
+        sample_rate: f64,
+    ) -> Result<ParameterEditor, InstantiateError> {
+        let host = Host::new(sample_rate, 512);
+        let controls = plugin.control_model();
+        let mut values = vec![0.0; plugin.ports_count()].into_boxed_slice();
+        for control in &controls {
+            if let Some(index) = control.port_index {
+                values[index] = control.default.or(control.minimum).unwrap_or(0.0);
+            }
+        }
+        let values = PortBuffer::new(values);
+        let mut instance = unsafe { plugin.instantiate(sample_rate, host.features().iter())? };
+        for index in controls.iter().filter_map(|control| control.port_index) {
+            unsafe { instance.connect_port_mut(index, values.as_mut_ptr(index)) };
+        }
+        Ok(ParameterEditor {
+            instance,
+            plugin,
+            controls,
+            values,
+            host,
+        })
+    }
+
+    /// The value of the control port `symbol`.
+    #[must_use]
+    pub fn value(&self, symbol: &str) -> Option<f32> {
+        let index = self.port_control(symbol)?.port_index?;
+        self.values.get(index)
+    }
+
+    /// Run the command `line` and return the text to show. Errors are
+    /// returned as text too, since they are shown like any other output.
+    pub fn execute(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("list"), None, _) => self.list(),
+            (Some("set"), Some(symbol), Some(value)) => match value.parse() {
+                Ok(value) => self.set(symbol, value),
+                Err(_) => format!("not a number: {}", value),
+            },
+            (Some("save"), Some(dir), Some(_)) => {
+                let label = line
+                    .trim_start()
+                    .strip_prefix("save")
+                    .and_then(|rest| rest.trim_start().strip_prefix(dir))
+                    .unwrap_or_default()
+                    .trim();
+                self.save(Path::new(dir), label)
+            }
+            (None, _, _) => String::new(),
+            _ => "commands: list, set <symbol> <value>, save <dir> <label>, quit".to_string(),
+        }
+    }
+
+    /// Read commands from `input` and write their output to `output` until
+    /// `quit` or the end of the input.
+    ///
+    /// # Errors
+    /// Returns an error if reading or writing fails.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> std::io::Result<()> {
+        write!(output, "{}\n> ", self.list())?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if line.trim() == "quit" {
+                break;
+            }
+            write!(output, "{}\n> ", self.execute(&line))?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    fn list(&self) -> String {
+        // Writing to a `String` can not fail.
+        let mut s = format!("{}\n", self.plugin);
+        for control in &self.controls {
+            let _ = write!(s, "  {}", control.key);
+            match control.port_index {
+                Some(index) => {
+                    let _ = write!(s, " = {}", self.values.value(index));
+                    if let Some(unit) = &control.unit_symbol {
+                        let _ = write!(s, " {}", unit);
+                    }
+                    if let (Some(min), Some(max)) = (control.minimum, control.maximum) {
+                        let _ = write!(s, " ({} .. {})", min, max);
+                    }
+                }
+                None => {
+                    let value_type = control.value_type.as_deref().unwrap_or("parameter");
+                    let _ = write!(s, " ({}, read only)", value_type);
+                }
+            }
+            let _ = writeln!(s, "  {}", control.label);
+        }
+        s
+    }
+
+    fn set(&mut self, symbol: &str, value: f32) -> String {
+        let control = match self.port_control(symbol) {
+            Some(control) => control,
+            None => return format!("no control port {}", symbol),
+        };
+        let value = match (control.minimum, control.maximum) {
+            (Some(min), Some(max)) if min <= max => value.clamp(min, max),
+            _ => value,
+        };
+        let value = if control.integer || control.toggled || control.enumeration {
+            value.round()
+        } else {
+            value
+        };
+        let index = control.port_index.unwrap_or_default();
+        self.values.set(index, value);
+        format!("{} = {}", symbol, value)
+    }
+
+    fn save(&mut self, dir: &Path, label: &str) -> String {
+        let world = World {
+            life: self.plugin.life.clone(),
+        };
+        let dirs = StateDirs {
+            save_dir: Some(dir.to_path_buf()),
+            ..StateDirs::default()
+        };
+        let values = &self.values;
+        let state = unsafe {
+            State::new_from_instance(
+                &self.plugin,
+                &self.instance,
+                self.host.urid_map(),
+                &dirs,
+                |port| values.get(port.index()),
+                StateFlags::POD | StateFlags::PORTABLE,
+                self.host.features(),
+            )
+        };
+        let mut state = match state {
+            Ok(Some(state)) => state,
+            Ok(None) => return "the plugin state could not be saved".to_string(),
+            Err(err) => return err.to_string(),
+        };
+        let filename: String = label
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .chain(".ttl".chars())
+            .collect();
+        let saved = state.set_label(label).and_then(|()| {
+            let (map, unmap) = (self.host.urid_map(), self.host.urid_unmap());
+            state.save(&world, map, unmap, None, dir, &filename)
+        });
+        match saved {
+            Ok(()) => format!("saved {}", dir.join(filename).display()),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn port_control(&self, symbol: &str) -> Option<&ControlInfo> {
+        self.controls
+            .iter()
+            .find(|control| control.port_index.is_some() && control.key == symbol)
+    }
+}
+
+impl std::fmt::Debug for ParameterEditor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParameterEditor")
+            .field("plugin", &self.plugin)
+            .field("controls", &self.controls)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameter_editor() {
+        let world = crate::testing::fixture_world().unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        let plugin = world.plugins().plugin(&uri).unwrap();
+        let mut editor = unsafe { ParameterEditor::new(plugin, 44100.0).unwrap() };
+        assert_eq!(editor.value("gain"), Some(0.0));
+        assert_eq!(editor.execute("set gain -3"), "gain = -3");
+        assert_eq!(editor.value("gain"), Some(-3.0));
+        assert_eq!(editor.execute("set gain 100"), "gain = 24");
+        assert_eq!(editor.execute("set in 1"), "no control port in");
+        assert_eq!(editor.execute("set gain loud"), "not a number: loud");
+        assert!(editor.execute("list").contains("gain = 24"));
+
+        let temp = crate::testing::TempDir::new("parameter-editor").unwrap();
+        let dir = temp.join("loud.lv2");
+        let saved = editor.execute(&format!("save {} Very loud", dir.display()));
+        assert!(saved.starts_with("saved"), "{}", saved);
+        assert!(dir.join("Very_loud.ttl").is_file());
+
+        let mut output = Vec::new();
+        editor
+            .run(&b"set gain 1\nquit\nset gain 2\n"[..], &mut output)
+            .unwrap();
+        assert_eq!(editor.value("gain"), Some(1.0));
+        assert!(String::from_utf8(output).unwrap().contains("gain = 1\n> "));
+    }
+}
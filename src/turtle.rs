@@ -0,0 +1,297 @@
+use crate::error::ParseError;
+use crate::ffi::serd::{
+    serd_env_expand_node, serd_env_free, serd_env_new, serd_env_set_base_uri, serd_env_set_prefix,
+    serd_node_free, serd_node_new_file_uri, serd_reader_free, serd_reader_new,
+    serd_reader_read_string, serd_reader_set_error_sink, serd_strerror, SerdError, SerdNode,
+    SERD_BLANK, SERD_CURIE, SERD_ERR_UNKNOWN, SERD_SUCCESS, SERD_TURTLE, SERD_URI,
+};
+use crate::world::RDF_TYPE;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_uint, c_void};
+use std::path::Path;
+
+/// Check that `turtle` is valid Turtle, returning the first error Serd
+/// reports.
+pub(crate) fn validate(turtle: &str) -> Result<(), ParseError> {
+    let c_turtle = CString::new(turtle).map_err(|err| {
+        let (line, column) = position(&turtle[..err.nul_position()]);
+        ParseError {
+            line,
+            column,
+            message: "interior NUL byte".to_string(),
+        }
+    })?;
+    let mut error: Option<ParseError> = None;
+    let status = unsafe {
+        let reader = serd_reader_new(
+            SERD_TURTLE,
+            std::ptr::null_mut(),
+            None,
+            None,
+            None,
+            None,
+            std::ptr::null(),
+        );
+        if reader.is_null() {
+            return Err(ParseError {
+                line: 0,
+                column: 0,
+                message: "failed to create a Turtle reader".to_string(),
+            });
+        }
+        serd_reader_set_error_sink(
+            reader,
+            error_sink,
+            (&mut error as *mut Option<ParseError>).cast(),
+        );
+        let status = serd_reader_read_string(reader, c_turtle.as_ptr().cast());
+        serd_reader_free(reader);
+        status
+    };
+    match (status, error) {
+        (_, Some(error)) => Err(error),
+        (SERD_SUCCESS, None) => Ok(()),
+        (status, None) => Err(ParseError {
+            line: 0,
+            column: 0,
+            message: strerror(status),
+        }),
+    }
+}
+
+// The state of `read_statements` while Serd reads the document.
+struct Statements<'a> {
+    env: *mut c_void,
+    statement: &'a mut dyn FnMut(Option<String>, Option<String>, Option<String>),
+}
+
+const STATE_STATE: &str = "http://lv2plug.in/ns/ext/state#state";
+
+/// The URIs of the subjects with the type `type_uri` in the Turtle file at
+/// `path`, like the plugins in a manifest. Relative URIs are resolved against
+/// the file. Returns an empty list if the file can not be read or parsed.
+pub(crate) fn subjects_of_type(path: &Path, type_uri: &str) -> Vec<String> {
+    let turtle = match std::fs::read(path).map(CString::new) {
+        Ok(Ok(turtle)) => turtle,
+        _ => return Vec::new(),
+    };
+    let c_path = match crate::path::path_to_cstring(path) {
+        Ok(c_path) => c_path,
+        Err(_) => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    unsafe {
+        let mut base = serd_node_new_file_uri(
+            c_path.as_ptr().cast(),
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            true,
+        );
+        read_statements(&turtle, &base, &mut |subject, predicate, object| {
+            if predicate.as_deref() == Some(RDF_TYPE) && object.as_deref() == Some(type_uri) {
+                if let Some(subject) = subject.filter(|subject| !found.contains(subject)) {
+                    found.push(subject);
+                }
+            }
+        });
+        serd_node_free(&mut base);
+    }
+    found
+}
+
+/// The keys of the properties of the states in `turtle`, which are the
+/// predicates of the nodes that are the object of `state:state`, in the
+/// order of the document. Returns the keys read before an error.
+pub(crate) fn state_keys(turtle: &str) -> Vec<String> {
+    let turtle = match CString::new(turtle) {
+        Ok(turtle) => turtle,
+        Err(_) => return Vec::new(),
+    };
+    let mut states = Vec::new();
+    let mut keys = Vec::new();
+    let mut statement = |subject: Option<String>, predicate: Option<String>, object| {
+        if predicate.as_deref() == Some(STATE_STATE) {
+            states.extend(object);
+        } else if let (Some(subject), Some(predicate)) = (subject, predicate) {
+            if states.contains(&subject) {
+                keys.push(predicate);
+            }
+        }
+    };
+    unsafe { read_statements(&turtle, std::ptr::null(), &mut statement) };
+    keys
+}
+
+// Call `statement` with the subject, predicate and object of each statement
+// in `turtle`. URIs are expanded and resolved against `base`, which may be
+// null, and blank nodes are `_:` and their id. Literals are `None`.
+unsafe fn read_statements(
+    turtle: &CStr,
+    base: *const SerdNode,
+    statement: &mut dyn FnMut(Option<String>, Option<String>, Option<String>),
+) {
+    unsafe {
+        let mut statements = Statements {
+            env: serd_env_new(base),
+            statement,
+        };
+        let reader = serd_reader_new(
+            SERD_TURTLE,
+            (&mut statements as *mut Statements<'_>).cast(),
+            None,
+            Some(base_sink),
+            Some(prefix_sink),
+            Some(statement_sink),
+            std::ptr::null(),
+        );
+        if !reader.is_null() {
+            serd_reader_set_error_sink(reader, ignore_error, std::ptr::null_mut());
+            serd_reader_read_string(reader, turtle.as_ptr().cast());
+            serd_reader_free(reader);
+        }
+        serd_env_free(statements.env);
+    }
+}
+
+// The sinks are called from C so they must not unwind. Panics are reported to
+// serd as `SERD_ERR_UNKNOWN`, which stops reading.
+
+unsafe extern "C" fn base_sink(handle: *mut c_void, uri: *const SerdNode) -> c_uint {
+    let env = unsafe { (*handle.cast::<Statements<'_>>()).env };
+    std::panic::catch_unwind(|| unsafe { serd_env_set_base_uri(env, uri) })
+        .unwrap_or(SERD_ERR_UNKNOWN)
+}
+
+unsafe extern "C" fn prefix_sink(
+    handle: *mut c_void,
+    name: *const SerdNode,
+    uri: *const SerdNode,
+) -> c_uint {
+    let env = unsafe { (*handle.cast::<Statements<'_>>()).env };
+    std::panic::catch_unwind(|| unsafe { serd_env_set_prefix(env, name, uri) })
+        .unwrap_or(SERD_ERR_UNKNOWN)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "C" fn statement_sink(
+    handle: *mut c_void,
+    _flags: u32,
+    _graph: *const SerdNode,
+    subject: *const SerdNode,
+    predicate: *const SerdNode,
+    object: *const SerdNode,
+    _datatype: *const SerdNode,
+    _lang: *const SerdNode,
+) -> c_uint {
+    let statements = unsafe { &mut *handle.cast::<Statements<'_>>() };
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let env = statements.env;
+        let node = |node| unsafe { expand(env, node) };
+        (statements.statement)(node(subject), node(predicate), node(object));
+    }))
+    .map_or(SERD_ERR_UNKNOWN, |()| SERD_SUCCESS)
+}
+
+// The expanded URI of `node`, `_:` and the id of a blank node, or `None` for
+// a literal.
+unsafe fn expand(env: *const c_void, node: *const SerdNode) -> Option<String> {
+    let node_ref = unsafe { node.as_ref()? };
+    let string = |node: &SerdNode| unsafe {
+        let bytes = std::slice::from_raw_parts(node.buf, node.n_bytes);
+        String::from_utf8_lossy(bytes).into_owned()
+    };
+    match node_ref.kind {
+        SERD_BLANK => Some(format!("_:{}", string(node_ref))),
+        SERD_URI | SERD_CURIE => unsafe {
+            let mut expanded = serd_env_expand_node(env, node);
+            if expanded.buf.is_null() {
+                return None;
+            }
+            let uri = string(&expanded);
+            serd_node_free(&mut expanded);
+            Some(uri)
+        },
+        _ => None,
+    }
+}
+
+unsafe extern "C" fn ignore_error(_handle: *mut c_void, _error: *const SerdError) -> c_uint {
+    SERD_SUCCESS
+}
+
+// Keeps the first error, since later errors are often caused by it.
+unsafe extern "C" fn error_sink(handle: *mut c_void, error: *const SerdError) -> c_uint {
+    let first = unsafe { &mut *handle.cast::<Option<ParseError>>() };
+    let error = unsafe { &*error };
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        if first.is_none() {
+            *first = Some(ParseError {
+                line: error.line as usize,
+                column: error.col as usize,
+                message: strerror(error.status),
+            });
+        }
+    }))
+    .map_or(SERD_ERR_UNKNOWN, |()| SERD_SUCCESS)
+}
+
+fn strerror(status: c_uint) -> String {
+    let message = unsafe { serd_strerror(status) };
+    if message.is_null() {
+        return format!("error {}", status);
+    }
+    unsafe { CStr::from_ptr(message.cast()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The 1 based line and column after `text`.
+fn position(text: &str) -> (usize, usize) {
+    let line = text.matches('\n').count() + 1;
+    let column = text.len() - text.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate("<urn:a> <urn:b> \"c\" .\n"), Ok(()));
+        let error = validate("<urn:a> <urn:b> \"c\" .\n<urn:a> <urn:b> .\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(!error.message.is_empty());
+        let error = validate("<urn:a>\n <urn:b> \"\0\" .").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 11));
+    }
+
+    #[test]
+    fn test_subjects_of_type() {
+        let dir = crate::testing::TempDir::new("subjects-of-type").unwrap();
+        let manifest = dir.join("manifest.ttl");
+        std::fs::write(
+            &manifest,
+            "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+             <urn:a> a lv2:Plugin .\n\
+             <b> a <http://lv2plug.in/ns/lv2core#Plugin> .\n\
+             <urn:c> a lv2:Port .\n",
+        )
+        .unwrap();
+        let found = subjects_of_type(&manifest, "http://lv2plug.in/ns/lv2core#Plugin");
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], "urn:a");
+        assert!(found[1].starts_with("file://") && found[1].ends_with("/b"));
+        assert!(subjects_of_type(&dir.join("missing.ttl"), "urn:x").is_empty());
+    }
+
+    #[test]
+    fn test_state_keys() {
+        let turtle = "@prefix state: <http://lv2plug.in/ns/ext/state#> .\n\
+                      @prefix eg: <urn:eg#> .\n\
+                      <urn:a> eg:ignored 1 ;\n\
+                          state:state [ eg:b 2 ; <urn:c> [ eg:nested 3 ] ] .\n";
+        assert_eq!(state_keys(turtle), ["urn:eg#b", "urn:c"]);
+        assert!(state_keys("<urn:a> <urn:b> <urn:c> .").is_empty());
+    }
+}
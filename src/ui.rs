@@ -1,8 +1,11 @@
 use crate::node::{Node, Nodes};
 use crate::plugin::Plugin;
-use crate::world::Life;
+use crate::world::{Life, World};
 use lilv_sys as lib;
+use std::convert::TryFrom;
 use std::ffi::CStr;
+use std::fmt::Debug;
+use std::os::raw::c_char;
 use std::ptr::NonNull;
 use std::sync::Arc;
 
@@ -41,7 +44,11 @@ impl UI {
 
         let inner = unsafe { lib::lilv_ui_get_classes(ui) };
         let world = self.plugin.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: true,
+            life: world,
+        }
     }
 
     /// Returns `true` if the plugin UI has a given type.
@@ -130,6 +137,189 @@ impl UI {
             }
         })
     }
+
+    /// Returns `true` if the UI uses `ui:idleInterface`, so the host must call
+    /// its idle function regularly.
+    ///
+    /// Like `port_notifications`, this reads the data file of the UI.
+    #[must_use]
+    pub fn requires_idle(&self) -> bool {
+        self.declares(
+            &[
+                LV2_EXTENSION_DATA,
+                LV2_REQUIRED_FEATURE,
+                LV2_OPTIONAL_FEATURE,
+            ],
+            UI_IDLE_INTERFACE,
+        )
+    }
+
+    /// Returns `true` if the UI provides `ui:showInterface`, so the host can
+    /// show and hide it without embedding it.
+    ///
+    /// Like `port_notifications`, this reads the data file of the UI.
+    #[must_use]
+    pub fn has_show_interface(&self) -> bool {
+        self.declares(&[LV2_EXTENSION_DATA], UI_SHOW_INTERFACE)
+    }
+
+    /// Classify the UI by the toolkit or windowing system it uses.
+    #[must_use]
+    pub fn ui_type(&self) -> UIType {
+        let classes: Vec<String> = self
+            .classes()
+            .iter()
+            .filter_map(|class| class.as_uri().map(str::to_string))
+            .collect();
+        classes
+            .iter()
+            .find_map(|class| UIType::from_uri(class))
+            .unwrap_or_else(|| UIType::Other(classes.into_iter().next().unwrap_or_default()))
+    }
+
+    fn declares(&self, predicates: &[&'static str], object: &'static str) -> bool {
+        let world = World {
+            life: self.life.clone(),
+        };
+        let subject = self.uri();
+        let object = world.new_static_uri(object);
+        predicates.iter().any(|predicate| {
+            let predicate = world.new_static_uri(predicate);
+            world.ask(Some(&subject), Some(&predicate), Some(&object))
+        })
+    }
+
+    /// Get the ports the UI wants to be notified about through
+    /// `ui:portNotification`.
+    ///
+    /// The notifications are usually declared in the data file of the UI, so
+    /// the UI should be loaded with `World::load_resource(&ui.uri())` first.
+    #[must_use]
+    pub fn port_notifications(&self) -> Vec<PortNotification> {
+        let world = World {
+            life: self.life.clone(),
+        };
+        let notification = world.new_static_uri(UI_PORT_NOTIFICATION);
+        let port_index = world.new_static_uri(UI_PORT_INDEX);
+        let symbol = world.new_static_uri(LV2_SYMBOL);
+        let protocol = world.new_static_uri(UI_PROTOCOL);
+        let get =
+            |subject: &Node, predicate: &Node| world.get(Some(subject), Some(predicate), None);
+
+        world
+            .find_nodes(Some(&self.uri()), &notification, None)
+            .iter()
+            .map(|n| {
+                let symbol = get(&n, &symbol);
+                let port_index = get(&n, &port_index)
+                    .and_then(|i| i.as_int())
+                    .and_then(|i| usize::try_from(i).ok())
+                    .or_else(|| {
+                        let port = self.plugin.port_by_symbol(symbol.as_ref()?)?;
+                        Some(port.index())
+                    });
+                PortNotification {
+                    port_index,
+                    symbol: symbol.as_ref().and_then(Node::as_str).map(str::to_string),
+                    protocol: get(&n, &protocol)
+                        .as_ref()
+                        .and_then(Node::as_uri)
+                        .map(str::to_string),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Debug for UI {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UI")
+            .field("uri", &self.uri())
+            .field("classes", &self.classes())
+            .field("bundle_uri", &self.bundle_uri())
+            .field("binary_uri", &self.binary_uri())
+            .finish()
+    }
+}
+
+impl std::fmt::Display for UI {
+    /// Formats the URI of the UI.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{}>", self.uri())
+    }
+}
+
+const LV2_EXTENSION_DATA: &str = "http://lv2plug.in/ns/lv2core#extensionData";
+const LV2_OPTIONAL_FEATURE: &str = "http://lv2plug.in/ns/lv2core#optionalFeature";
+const LV2_REQUIRED_FEATURE: &str = "http://lv2plug.in/ns/lv2core#requiredFeature";
+const LV2_SYMBOL: &str = "http://lv2plug.in/ns/lv2core#symbol";
+const UI_IDLE_INTERFACE: &str = "http://lv2plug.in/ns/extensions/ui#idleInterface";
+const UI_SHOW_INTERFACE: &str = "http://lv2plug.in/ns/extensions/ui#showInterface";
+const UI_PORT_INDEX: &str = "http://lv2plug.in/ns/extensions/ui#portIndex";
+const UI_PORT_NOTIFICATION: &str = "http://lv2plug.in/ns/extensions/ui#portNotification";
+const UI_PROTOCOL: &str = "http://lv2plug.in/ns/extensions/ui#protocol";
+
+/// A port that a UI wants to receive notifications for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortNotification {
+    /// The index of the port. If the UI only gave a symbol then this is the
+    /// index of the port with that symbol.
+    pub port_index: Option<usize>,
+    /// The symbol of the port if the UI declared it.
+    pub symbol: Option<String>,
+    /// The URI of the protocol to notify with, like `ui:floatProtocol` or
+    /// `ui:peakProtocol`. `None` means the default `ui:floatProtocol` for
+    /// control ports.
+    pub protocol: Option<String>,
+}
+
+/// The toolkit or windowing system of a UI. See `UI::ui_type`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UIType {
+    /// `ui:X11UI`, embeddable in an X11 window.
+    X11,
+    /// `ui:GtkUI`, a Gtk 2 widget.
+    Gtk,
+    /// `ui:Gtk3UI`, a Gtk 3 widget.
+    Gtk3,
+    /// `ui:Qt4UI`, a Qt 4 widget.
+    Qt4,
+    /// `ui:Qt5UI`, a Qt 5 widget.
+    Qt5,
+    /// `ui:CocoaUI`, an NSView on macOS.
+    Cocoa,
+    /// `ui:WindowsUI`, an HWND on Windows.
+    Windows,
+    /// A UI that opens its own window, like the KXStudio external UI.
+    External,
+    /// Any other type. Contains the URI of the first class of the UI.
+    Other(String),
+}
+
+impl UIType {
+    /// Get the type for the URI of a UI class or `None` if it is not known.
+    #[must_use]
+    pub fn from_uri(uri: &str) -> Option<UIType> {
+        match uri {
+            "http://lv2plug.in/ns/extensions/ui#X11UI" => Some(UIType::X11),
+            "http://lv2plug.in/ns/extensions/ui#GtkUI" => Some(UIType::Gtk),
+            "http://lv2plug.in/ns/extensions/ui#Gtk3UI" => Some(UIType::Gtk3),
+            "http://lv2plug.in/ns/extensions/ui#Qt4UI" => Some(UIType::Qt4),
+            "http://lv2plug.in/ns/extensions/ui#Qt5UI" => Some(UIType::Qt5),
+            "http://lv2plug.in/ns/extensions/ui#CocoaUI" => Some(UIType::Cocoa),
+            "http://lv2plug.in/ns/extensions/ui#WindowsUI" => Some(UIType::Windows),
+            "http://kxstudio.sf.net/ns/lv2ext/external-ui#Widget"
+            | "http://lv2plug.in/ns/extensions/ui#external" => Some(UIType::External),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the UI is embedded in a window provided by the host.
+    #[must_use]
+    pub fn is_embeddable(&self) -> bool {
+        !matches!(self, UIType::External | UIType::Other(_))
+    }
 }
 
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
@@ -139,15 +329,20 @@ pub trait UISupport {
     fn supported(container: &str, ui: &str) -> UISupportQuality;
 }
 
+// Called from C so it must not unwind. Invalid URIs and panics in
+// `S::supported` are reported as unsupported.
 unsafe extern "C" fn supported_func<S: UISupport>(
-    container_type_uri: *const i8,
-    ui_type_uri: *const i8,
+    container_type_uri: *const c_char,
+    ui_type_uri: *const c_char,
 ) -> u32 {
-    S::supported(
-        CStr::from_ptr(container_type_uri).to_str().unwrap(),
-        CStr::from_ptr(ui_type_uri).to_str().unwrap(),
-    )
-    .0
+    let container = unsafe { CStr::from_ptr(container_type_uri) }.to_str();
+    let ui = unsafe { CStr::from_ptr(ui_type_uri) }.to_str();
+    match (container, ui) {
+        (Ok(container), Ok(ui)) => {
+            std::panic::catch_unwind(|| S::supported(container, ui).0).unwrap_or(0)
+        }
+        _ => 0,
+    }
 }
 
 /// A collection of plugin UIs.
@@ -181,6 +376,45 @@ impl Uis {
     pub fn iter(&self) -> impl Iterator<Item = UI> {
         self.clone().into_iter()
     }
+
+    /// The UI that is best supported in `container_type` and its UI type,
+    /// or `None` if no UI is supported.
+    ///
+    /// `quality` ranks each type of each UI like `UISupport::supported`, and
+    /// returns 0 for the types the host can not show in the container. The
+    /// first UI wins ties.
+    pub fn best_supported(
+        &self,
+        container_type: &Node,
+        mut quality: impl FnMut(&str, &str) -> UISupportQuality,
+    ) -> Option<(UI, Node)> {
+        let container = container_type.as_uri()?;
+        let mut best = None;
+        let mut best_quality = UISupportQuality(0);
+        for ui in self.iter() {
+            let mut ui_type = None;
+            for class in ui.classes() {
+                let q = match class.as_uri() {
+                    Some(uri) => quality(container, uri),
+                    None => continue,
+                };
+                if q > best_quality {
+                    best_quality = q;
+                    ui_type = Some(class);
+                }
+            }
+            if let Some(ui_type) = ui_type {
+                best = Some((ui, ui_type));
+            }
+        }
+        best
+    }
+}
+
+impl Debug for Uis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
 }
 
 impl IntoIterator for Uis {
@@ -219,3 +453,161 @@ impl Iterator for Iter {
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    struct PanickingSupport;
+
+    impl UISupport for PanickingSupport {
+        fn supported(_container: &str, _ui: &str) -> UISupportQuality {
+            panic!("UISupport implementation panicked.");
+        }
+    }
+
+    struct AnySupport;
+
+    impl UISupport for AnySupport {
+        fn supported(_container: &str, _ui: &str) -> UISupportQuality {
+            UISupportQuality(1)
+        }
+    }
+
+    #[test]
+    fn test_supported_func_does_not_unwind() {
+        let container = std::ffi::CString::new("http://lv2plug.in/ns/extensions/ui#X11UI").unwrap();
+        let ui = std::ffi::CString::new("http://lv2plug.in/ns/extensions/ui#X11UI").unwrap();
+        let quality =
+            unsafe { supported_func::<PanickingSupport>(container.as_ptr(), ui.as_ptr()) };
+        assert_eq!(quality, 0);
+        let quality = unsafe { supported_func::<AnySupport>(container.as_ptr(), ui.as_ptr()) };
+        assert_eq!(quality, 1);
+    }
+
+    #[test]
+    fn test_supported_func_invalid_utf8() {
+        let container = std::ffi::CString::new(vec![0xff, 0xfe]).unwrap();
+        let ui = std::ffi::CString::new("http://lv2plug.in/ns/extensions/ui#X11UI").unwrap();
+        let quality = unsafe { supported_func::<AnySupport>(container.as_ptr(), ui.as_ptr()) };
+        assert_eq!(quality, 0);
+    }
+
+    const TEST_PLUGIN: &str = "urn:lilv-rs:test:ui";
+
+    // Load a bundle with a plugin with two control ports and the UIs described
+    // by `uis`. The bundle is removed when the returned directory is dropped.
+    fn load_ui_bundle(name: &str, uis: &str) -> (TempDir, World, Plugin) {
+        let dir = TempDir::new(name).unwrap();
+        let bundle = dir.join("ui.lv2");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(
+            bundle.join("manifest.ttl"),
+            format!(
+                r#"@prefix lv2: <http://lv2plug.in/ns/lv2core#> .
+@prefix ui: <http://lv2plug.in/ns/extensions/ui#> .
+<{plugin}> a lv2:Plugin ;
+    lv2:port [ a lv2:InputPort , lv2:ControlPort ; lv2:index 0 ; lv2:symbol "gain" ; lv2:name "Gain" ] ,
+        [ a lv2:OutputPort , lv2:ControlPort ; lv2:index 1 ; lv2:symbol "level" ; lv2:name "Level" ] .
+{uis}"#,
+                plugin = TEST_PLUGIN,
+                uis = uis
+            ),
+        )
+        .unwrap();
+
+        let world = World::new();
+        let mut bundle_path = bundle.into_os_string();
+        bundle_path.push("/");
+        world.load_bundle(&world.new_file_uri(None, &bundle_path).unwrap());
+        let plugin = world
+            .plugins()
+            .plugin(&world.new_uri(TEST_PLUGIN).unwrap())
+            .unwrap();
+        (dir, world, plugin)
+    }
+
+    #[test]
+    fn test_port_notifications() {
+        let (_dir, _world, plugin) = load_ui_bundle(
+            "port-notifications",
+            r#"<urn:lilv-rs:test:ui> ui:ui <urn:lilv-rs:test:ui#ui> .
+<urn:lilv-rs:test:ui#ui> a ui:X11UI ;
+    ui:binary <ui.so> ;
+    ui:portNotification [ ui:plugin <urn:lilv-rs:test:ui> ; lv2:symbol "level" ; ui:protocol ui:peakProtocol ] ,
+        [ ui:plugin <urn:lilv-rs:test:ui> ; ui:portIndex 0 ] .
+"#,
+        );
+        let ui = plugin.uis().unwrap().iter().next().unwrap();
+        assert_eq!(ui.ui_type(), UIType::X11);
+        assert!(!ui.requires_idle());
+        assert!(!ui.has_show_interface());
+        let mut notifications = ui.port_notifications();
+        notifications.sort_by_key(|n| n.port_index);
+        assert_eq!(
+            notifications,
+            vec![
+                PortNotification {
+                    port_index: Some(0),
+                    symbol: None,
+                    protocol: None,
+                },
+                PortNotification {
+                    port_index: Some(1),
+                    symbol: Some("level".to_string()),
+                    protocol: Some("http://lv2plug.in/ns/extensions/ui#peakProtocol".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ui_interfaces() {
+        let (_dir, _world, plugin) = load_ui_bundle(
+            "ui-interfaces",
+            r#"<urn:lilv-rs:test:ui> ui:ui <urn:lilv-rs:test:ui#ui> .
+<urn:lilv-rs:test:ui#ui> a <http://kxstudio.sf.net/ns/lv2ext/external-ui#Widget> ;
+    ui:binary <ui.so> ;
+    lv2:optionalFeature ui:idleInterface ;
+    lv2:extensionData ui:idleInterface , ui:showInterface .
+"#,
+        );
+        let ui = plugin.uis().unwrap().iter().next().unwrap();
+        assert_eq!(ui.ui_type(), UIType::External);
+        assert!(!ui.ui_type().is_embeddable());
+        assert!(ui.requires_idle());
+        assert!(ui.has_show_interface());
+    }
+
+    #[test]
+    fn test_best_supported() {
+        let (_dir, world, plugin) = load_ui_bundle(
+            "best-supported",
+            r#"<urn:lilv-rs:test:ui> ui:ui <urn:lilv-rs:test:ui#x11> , <urn:lilv-rs:test:ui#gtk> .
+<urn:lilv-rs:test:ui#x11> a ui:X11UI ;
+    ui:binary <x11.so> .
+<urn:lilv-rs:test:ui#gtk> a ui:GtkUI ;
+    ui:binary <gtk.so> .
+"#,
+        );
+        let uis = plugin.uis().unwrap();
+        let container = world
+            .new_uri("http://lv2plug.in/ns/extensions/ui#Gtk3UI")
+            .unwrap();
+        let rank = |_: &str, ui: &str| match ui {
+            "http://lv2plug.in/ns/extensions/ui#X11UI" => UISupportQuality(1),
+            "http://lv2plug.in/ns/extensions/ui#GtkUI" => UISupportQuality(2),
+            _ => UISupportQuality(0),
+        };
+        let (ui, ui_type) = uis.best_supported(&container, rank).unwrap();
+        assert_eq!(ui.uri().as_uri(), Some("urn:lilv-rs:test:ui#gtk"));
+        assert_eq!(
+            ui_type.as_uri(),
+            Some("http://lv2plug.in/ns/extensions/ui#GtkUI")
+        );
+        assert!(uis
+            .best_supported(&container, |_, _| UISupportQuality(0))
+            .is_none());
+    }
+}
@@ -1,11 +1,20 @@
+use crate::error::Error;
 use crate::node::{Node, Nodes};
+use crate::path::path_to_cstring;
 use crate::plugin::Class;
 use crate::plugin::Plugins;
+use crate::state::State;
 use lilv_sys as lib;
+use lv2_raw::LV2UridMap;
 use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::Path;
 use std::ptr::NonNull;
 use std::sync::Arc;
 
+pub(crate) const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+pub(crate) const LV2_PRESETS_PRESET: &str = "http://lv2plug.in/ns/ext/presets#Preset";
+
 unsafe impl Send for Life {}
 unsafe impl Sync for Life {}
 
@@ -19,6 +28,29 @@ pub struct World {
 #[allow(clippy::non_send_fields_in_send_ty)]
 pub struct Life {
     pub(crate) inner: Mutex<NonNull<lib::LilvWorldImpl>>,
+    // The nodes of `Uris`, freed with the world. Always locked before
+    // `inner`.
+    uris: Mutex<HashMap<Box<str>, NonNull<lib::LilvNodeImpl>>>,
+}
+
+/// Counts of the data loaded into a world. See `World::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorldStats {
+    /// The number of distinct bundles that contain plugins.
+    pub bundles: usize,
+    /// The number of plugins.
+    pub plugins: usize,
+    /// The number of plugin classes.
+    pub plugin_classes: usize,
+    /// The number of presets listed in the loaded manifests.
+    pub presets: usize,
+    /// The number of URIs in the `World::uris` cache.
+    pub cached_uris: usize,
+}
+
+/// A cache of URI nodes that lives as long as the world. See `World::uris`.
+pub struct Uris {
+    life: Arc<Life>,
 }
 
 impl World {
@@ -31,6 +63,7 @@ impl World {
         Self {
             life: Arc::new(Life {
                 inner: Mutex::new(NonNull::new(unsafe { lib::lilv_world_new() }).unwrap()),
+                uris: Mutex::default(),
             }),
         }
     }
@@ -81,53 +114,79 @@ impl World {
 
 impl World {
     /// Sets an option for the world.
-    /// # Panics
-    /// Panics if uri could not be converted to a `CString`.
-    pub fn set_option(&self, uri: &str, value: &Node) {
+    ///
+    /// # Errors
+    /// Returns an error if `uri` contains a NUL byte.
+    pub fn set_option(&self, uri: &str, value: &Node) -> Result<(), Error> {
+        let uri = std::ffi::CString::new(uri)?;
         let world = self.life.inner.lock();
-        let uri = std::ffi::CString::new(uri).unwrap();
         let value = value.inner.as_ptr();
 
-        unsafe { lib::lilv_world_set_option(world.as_ptr(), uri.as_ptr().cast(), value) }
+        unsafe { lib::lilv_world_set_option(world.as_ptr(), uri.as_ptr().cast(), value) };
+        Ok(())
     }
 }
 
 impl World {
     /// Creates a new URI value.
     ///
+    /// # Errors
+    /// Returns an error if `uri` contains a NUL byte.
+    ///
     /// # Panics
-    /// Panics on failure.
-    #[must_use]
-    pub fn new_uri(&self, uri: &str) -> Node {
+    /// Panics if lilv fails to create the node.
+    pub fn new_uri(&self, uri: &str) -> Result<Node, Error> {
+        let uri = std::ffi::CString::new(uri)?;
         let world = self.life.inner.lock();
-        let uri = std::ffi::CString::new(uri).unwrap();
 
         {
             let ptr =
                 NonNull::new(unsafe { lib::lilv_new_uri(world.as_ptr(), uri.as_ptr().cast()) })
                     .unwrap();
             let world = self.life.clone();
-            Node {
+            Ok(Node {
                 inner: ptr,
                 borrowed: false,
                 life: world,
-            }
+            })
+        }
+    }
+
+    /// Creates a new URI value for a URI defined by this crate, like the URI of
+    /// an LV2 property. The node is cached in `uris`.
+    pub(crate) fn new_static_uri(&self, uri: &'static str) -> Node {
+        self.uris()
+            .get(uri)
+            .unwrap_or_else(|err| panic!("invalid static URI {:?}: {}", uri, err))
+    }
+
+    /// The cache of URI nodes of the world.
+    ///
+    /// Unlike `new_uri`, which creates a node for each call, the cache creates
+    /// each URI node once and keeps it until the world is dropped. This is
+    /// useful for URIs that are used over and over, like the predicates of
+    /// metadata queries.
+    #[must_use]
+    pub fn uris(&self) -> Uris {
+        Uris {
+            life: self.life.clone(),
         }
     }
 
     /// Creates a new file URI value.
     ///
+    /// # Errors
+    /// Returns an error if `host` or `path` contain a NUL byte or if `path`
+    /// can not be encoded for lilv on this platform.
+    ///
     /// # Panics
-    /// Panics on failure.
-    #[must_use]
-    pub fn new_file_uri(&self, host: Option<&str>, path: &str) -> Node {
+    /// Panics if lilv fails to create the node.
+    pub fn new_file_uri(&self, host: Option<&str>, path: impl AsRef<Path>) -> Result<Node, Error> {
+        let host = host.map(std::ffi::CString::new).transpose()?;
+        let path = path_to_cstring(path.as_ref())?;
         let world = self.life.inner.lock();
-        let host = host
-            .iter()
-            .find_map(|h| std::ffi::CString::new(h.as_bytes()).ok());
-        let path = std::ffi::CString::new(path.as_bytes()).unwrap();
 
-        let host_ptr = host.map_or(std::ptr::null(), |h| h.as_ptr());
+        let host_ptr = host.as_ref().map_or(std::ptr::null(), |h| h.as_ptr());
         let path_ptr = path.as_ptr();
 
         {
@@ -135,33 +194,35 @@ impl World {
                 NonNull::new(unsafe { lib::lilv_new_file_uri(world.as_ptr(), host_ptr, path_ptr) })
                     .unwrap();
             let world = self.life.clone();
-            Node {
+            Ok(Node {
                 inner: ptr,
                 borrowed: false,
                 life: world,
-            }
+            })
         }
     }
 
     /// Creates a new string value (with no language).
     ///
+    /// # Errors
+    /// Returns an error if `string` contains a NUL byte.
+    ///
     /// # Panics
-    /// Panics on failure.
-    #[must_use]
-    pub fn new_string(&self, string: &str) -> Node {
+    /// Panics if lilv fails to create the node.
+    pub fn new_string(&self, string: &str) -> Result<Node, Error> {
+        let string = std::ffi::CString::new(string)?;
         let world = self.life.inner.lock();
-        let string = std::ffi::CString::new(string).unwrap();
 
         {
             let ptr =
                 NonNull::new(unsafe { lib::lilv_new_string(world.as_ptr(), string.as_ptr()) })
                     .unwrap();
             let world = self.life.clone();
-            Node {
+            Ok(Node {
                 inner: ptr,
                 borrowed: false,
                 life: world,
-            }
+            })
         }
     }
 
@@ -229,17 +290,40 @@ impl World {
     /// world.load_all();
     /// ```
     pub fn load_all(&self) {
-        let world = self.life.inner.lock();
-        unsafe { lib::lilv_world_load_all(world.as_ptr()) }
+        traced!("lilv::load_all" => {
+            let world = self.life.inner.lock();
+            unsafe { lib::lilv_world_load_all(world.as_ptr()) }
+        })
     }
 
     /// Loads a specific bundle. `bundle_uri` must be a fully qualified URI to the bundle directory,
     /// with the trailing slash, eg `file:///usr/lib/lv2/foo.lv2/`.
     pub fn load_bundle(&self, bundle_uri: &Node) {
-        let world = self.life.inner.lock();
-        let bundle_uri = bundle_uri.inner.as_ptr();
+        #[cfg(feature = "tracing")]
+        let bundle = bundle_uri.to_string();
+        traced!("lilv::load_bundle" (bundle = %bundle) => {
+            let world = self.life.inner.lock();
+            let bundle_uri = bundle_uri.inner.as_ptr();
+
+            unsafe { lib::lilv_world_load_bundle(world.as_ptr(), bundle_uri) }
+        })
+    }
 
-        unsafe { lib::lilv_world_load_bundle(world.as_ptr(), bundle_uri) }
+    /// Loads the bundle in the directory `dir`, like `load_bundle`.
+    ///
+    /// Together with `World::new`, this lets tools that only inspect a few
+    /// bundles, like validators, skip scanning all the installed bundles.
+    /// Call `load_specifications` and `load_plugin_classes` after loading the
+    /// bundles if plugin classes are needed.
+    ///
+    /// # Errors
+    /// Returns an error if `dir` can not be converted to a URI.
+    pub fn load_bundle_dir(&self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        let mut dir = dir.as_ref().as_os_str().to_owned();
+        dir.push(std::path::MAIN_SEPARATOR.to_string());
+        let uri = self.new_file_uri(None, &dir)?;
+        self.load_bundle(&uri);
+        Ok(())
     }
 
     /// Loads all specifications from currently loaded bundles.
@@ -248,8 +332,10 @@ impl World {
     /// necessary when using [`load_all`](#method.load_all). This function parses the specifications
     /// and adds them to the model.
     pub fn load_specifications(&self) {
-        let world = self.life.inner.lock();
-        unsafe { lib::lilv_world_load_specifications(world.as_ptr()) }
+        traced!("lilv::load_specifications" => {
+            let world = self.life.inner.lock();
+            unsafe { lib::lilv_world_load_specifications(world.as_ptr()) }
+        })
     }
 
     /// Load all plugin classes from currently loaded specifications.
@@ -258,8 +344,10 @@ impl World {
     /// that explicitly load specific bundles; its use is not necessary when using
     /// [`load_all`](#method.load_all).
     pub fn load_plugin_classes(&self) {
-        let world = self.life.inner.lock();
-        unsafe { lib::lilv_world_load_plugin_classes(world.as_ptr()) }
+        traced!("lilv::load_plugin_classes" => {
+            let world = self.life.inner.lock();
+            unsafe { lib::lilv_world_load_plugin_classes(world.as_ptr()) }
+        })
     }
 
     /// Unload a specific bundle.
@@ -277,7 +365,7 @@ impl World {
         let world = self.life.inner.lock();
         let bundle_uri = bundle_uri.inner.as_ptr();
 
-        lib::lilv_world_unload_bundle(world.as_ptr(), bundle_uri) == 0
+        unsafe { lib::lilv_world_unload_bundle(world.as_ptr(), bundle_uri) == 0 }
     }
 
     /// Load all the data associated with the given resource.
@@ -287,13 +375,34 @@ impl World {
     #[allow(clippy::cast_sign_loss)]
     #[must_use]
     pub fn load_resource(&self, resource: &Node) -> Option<usize> {
-        let world = self.life.inner.lock();
-        let resource = resource.inner.as_ptr();
+        #[cfg(feature = "tracing")]
+        let resource_uri = resource.to_string();
+        traced!("lilv::load_resource" (resource = %resource_uri) => {
+            let world = self.life.inner.lock();
+            let resource = resource.inner.as_ptr();
+
+            match unsafe { lib::lilv_world_load_resource(world.as_ptr(), resource) } {
+                -1 => None,
+                n => Some(n as usize),
+            }
+        })
+    }
 
-        match unsafe { lib::lilv_world_load_resource(world.as_ptr(), resource) } {
-            -1 => None,
-            n => Some(n as usize),
-        }
+    /// Load a preset and return its state.
+    ///
+    /// This loads the data of the preset into the world, like
+    /// `load_resource`, and creates the state with `State::new_from_world`.
+    /// URIDs in the state are mapped with `map`.
+    ///
+    /// # Errors
+    /// Returns `Error::ResourceNotLoaded` if the data of the preset could not
+    /// be loaded and `Error::StateNotFound` if the loaded data does not
+    /// describe a state.
+    pub fn load_preset(&self, preset_uri: &Node, map: &LV2UridMap) -> Result<State, Error> {
+        let uri = || preset_uri.turtle_token();
+        self.load_resource(preset_uri)
+            .ok_or_else(|| Error::ResourceNotLoaded(uri()))?;
+        State::new_from_world(self, map, preset_uri).ok_or_else(|| Error::StateNotFound(uri()))
     }
 
     /// Unload all the data associated with the given resource.
@@ -306,7 +415,7 @@ impl World {
         let world = self.life.inner.lock();
         let resource = resource.inner.as_ptr();
 
-        lib::lilv_world_unload_resource(world.as_ptr(), resource) == 0
+        unsafe { lib::lilv_world_unload_resource(world.as_ptr(), resource) == 0 }
     }
 }
 
@@ -326,7 +435,11 @@ impl World {
         let inner =
             unsafe { lib::lilv_world_find_nodes(world.as_ptr(), subject, predicate, object) };
         let world = self.life.clone();
-        Nodes { inner, life: world }
+        Nodes {
+            inner,
+            borrowed: false,
+            life: world,
+        }
     }
 
     /// Find a single node that matches a pattern. Exactly one of `subject`, `predicate`, or
@@ -372,10 +485,32 @@ impl World {
         unsafe { lib::lilv_world_ask(world.as_ptr(), subject, predicate, object) }
     }
 
+    /// Returns `true` if the world has any statement about `resource`.
+    #[must_use]
+    pub fn resource_exists(&self, resource: &Node) -> bool {
+        self.ask(Some(resource), None, None)
+    }
+
+    /// Returns `true` if `resource` has the type `type_uri` (`rdf:type`).
+    #[must_use]
+    pub fn is_a(&self, resource: &Node, type_uri: &Node) -> bool {
+        let rdf_type = self.new_static_uri(RDF_TYPE);
+        self.ask(Some(resource), Some(&rdf_type), Some(type_uri))
+    }
+
+    /// Get all the types (`rdf:type`) of `resource`.
+    #[must_use]
+    pub fn types_of(&self, resource: &Node) -> Nodes {
+        let rdf_type = self.new_static_uri(RDF_TYPE);
+        self.find_nodes(Some(resource), &rdf_type, None)
+    }
+
     /// Get an LV2 symbol for some subject.
     ///
     /// This will return the lv2:symbol property of the subject if it is given explicitly. Otherwise
-    /// it will attempt to derive a symbol from the URI.
+    /// it will attempt to derive a symbol from the URI. The result is always a
+    /// valid LV2 symbol, which makes it suitable for generating identifiers
+    /// (e.g. for state or automation) from arbitrary URIs.
     #[must_use]
     pub fn symbol(&self, subject: &Node) -> Option<Node> {
         let world = self.life.inner.lock();
@@ -393,6 +528,42 @@ impl World {
     }
 }
 
+impl World {
+    /// Count the data loaded into the world, to monitor the memory used by
+    /// metadata.
+    ///
+    /// Lilv does not expose the size of its RDF model, so the counts of nodes
+    /// and statements are not available. Plugin data is loaded lazily and is
+    /// not loaded by this function.
+    #[must_use]
+    pub fn stats(&self) -> WorldStats {
+        let mut bundles: Vec<String> = self
+            .plugins()
+            .iter()
+            .filter_map(|plugin| plugin.bundle_uri().as_uri().map(str::to_string))
+            .collect();
+        bundles.sort();
+        bundles.dedup();
+        let preset = self.new_static_uri(LV2_PRESETS_PRESET);
+        let rdf_type = self.new_static_uri(RDF_TYPE);
+        let presets = self.find_nodes(None, &rdf_type, Some(&preset)).count();
+        let plugin_classes = {
+            let world = self.life.inner.lock();
+            unsafe {
+                let classes = lib::lilv_world_get_plugin_classes(world.as_ptr());
+                lib::lilv_plugin_classes_size(classes) as usize
+            }
+        };
+        WorldStats {
+            bundles: bundles.len(),
+            plugins: self.plugins().count(),
+            plugin_classes,
+            presets,
+            cached_uris: self.uris().len(),
+        }
+    }
+}
+
 impl World {
     /// Get the underlying pointer to the `World`.
     pub fn as_ptr(&self) -> *mut lib::LilvWorldImpl {
@@ -401,6 +572,14 @@ impl World {
     }
 }
 
+impl std::fmt::Debug for World {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("World")
+            .field("plugins", &self.plugins().count())
+            .finish()
+    }
+}
+
 impl Default for World {
     /// Return a new empty world.
     fn default() -> World {
@@ -408,9 +587,60 @@ impl Default for World {
     }
 }
 
+impl Uris {
+    /// Get the node for `uri`, creating it if it is not in the cache yet.
+    ///
+    /// # Errors
+    /// Returns an error if `uri` contains a NUL byte.
+    ///
+    /// # Panics
+    /// Panics if lilv fails to create the node.
+    pub fn get(&self, uri: &str) -> Result<Node, Error> {
+        let mut uris = self.life.uris.lock();
+        let ptr = match uris.get(uri) {
+            Some(ptr) => *ptr,
+            None => {
+                let c_uri = std::ffi::CString::new(uri)?;
+                let world = self.life.inner.lock();
+                let ptr =
+                    NonNull::new(unsafe { lib::lilv_new_uri(world.as_ptr(), c_uri.as_ptr()) })
+                        .unwrap();
+                *uris.entry(uri.into()).or_insert(ptr)
+            }
+        };
+        // Borrowed since the cache owns the node until the world is dropped.
+        Ok(Node {
+            inner: ptr,
+            borrowed: true,
+            life: self.life.clone(),
+        })
+    }
+
+    /// The number of URIs in the cache.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.life.uris.lock().len()
+    }
+
+    /// Returns `true` if the cache is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.life.uris.lock().is_empty()
+    }
+}
+
+impl std::fmt::Debug for Uris {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Uris").field("len", &self.len()).finish()
+    }
+}
+
 impl Drop for Life {
     fn drop(&mut self) {
         unsafe {
+            for (_, node) in self.uris.get_mut().drain() {
+                lib::lilv_node_free(node.as_ptr());
+            }
             let world = self.inner.lock();
             lib::lilv_world_free(world.as_ptr());
         }
@@ -433,8 +663,107 @@ mod tests {
         assert!(w.new_bool(true).is_bool());
         assert!(w.new_float(0.1).is_float());
         assert!(w.new_int(1).is_int());
-        assert!(w.new_file_uri(None, "/some/path").is_uri());
-        assert!(w.new_file_uri(Some("me"), "/some/path").is_uri());
-        assert!(w.new_string("string").is_string());
+        assert!(w.new_file_uri(None, "/some/path").unwrap().is_uri());
+        assert!(w.new_file_uri(Some("me"), "/some/path").unwrap().is_uri());
+        assert!(w.new_string("string").unwrap().is_string());
+    }
+
+    #[test]
+    fn test_interior_nul() {
+        let w = World::new();
+        let err = Error::InteriorNul("a\0b".to_string());
+        assert_eq!(w.new_uri("a\0b").unwrap_err(), err);
+        assert_eq!(w.new_string("a\0b").unwrap_err(), err);
+        assert_eq!(w.new_file_uri(None, "a\0b").unwrap_err(), err);
+        assert_eq!(w.new_file_uri(Some("a\0b"), "/some/path").unwrap_err(), err);
+        assert_eq!(w.set_option("a\0b", &w.new_bool(true)).unwrap_err(), err);
+    }
+
+    #[test]
+    fn test_non_ascii_file_uri() {
+        let w = World::new();
+        let path = std::path::Path::new("/tmp/ñandú/bundle.lv2/");
+        let uri = w.new_file_uri(None, path).unwrap();
+        let (_, uri_path) = uri.path().expect("Missing path.");
+        assert_eq!(std::path::Path::new(&uri_path), path);
+    }
+
+    #[test]
+    fn test_load_non_ascii_bundle() {
+        let dir = crate::testing::TempDir::new("non-ascii").unwrap();
+        let bundle = dir.join("ñandú-テスト.lv2");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(
+            bundle.join("manifest.ttl"),
+            "<urn:lilv-rs:test:non-ascii> a <http://lv2plug.in/ns/lv2core#Plugin> .\n",
+        )
+        .unwrap();
+
+        let w = World::new();
+        let mut bundle_path = bundle.into_os_string();
+        bundle_path.push("/");
+        w.load_bundle(&w.new_file_uri(None, &bundle_path).unwrap());
+        let uri = w.new_uri("urn:lilv-rs:test:non-ascii").unwrap();
+        assert!(w.plugins().plugin(&uri).is_some());
+    }
+
+    #[test]
+    fn test_resource_queries() {
+        let w = crate::testing::fixture_world().unwrap();
+        let amp = w.new_uri(crate::testing::GAIN_URI).unwrap();
+        let missing = w.new_uri("urn:lilv-rs:test:missing").unwrap();
+        let plugin_class = w.new_uri("http://lv2plug.in/ns/lv2core#Plugin").unwrap();
+        assert!(w.resource_exists(&amp));
+        assert!(!w.resource_exists(&missing));
+        assert!(w.is_a(&amp, &plugin_class));
+        assert!(!w.is_a(&missing, &plugin_class));
+        assert!(w.types_of(&amp).contains(&plugin_class));
+        assert_eq!(w.types_of(&missing).count(), 0);
+    }
+
+    #[test]
+    fn test_load_bundle_dir() {
+        let world = World::new();
+        world
+            .load_bundle_dir(crate::testing::fixtures_dir().unwrap().join("gain.lv2"))
+            .unwrap();
+        let uri = world.new_uri(crate::testing::GAIN_URI).unwrap();
+        assert_eq!(world.plugins().count(), 1);
+        assert!(world.plugins().plugin(&uri).is_some());
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(World::new().stats().plugins, 0);
+        let world = crate::testing::fixture_world().unwrap();
+        let stats = world.stats();
+        assert_eq!(stats.plugins, world.plugins().count());
+        assert_eq!(stats.bundles, 3);
+        assert!(stats.presets >= 1);
+        assert!(stats.cached_uris >= 2);
+    }
+
+    #[test]
+    fn test_symbol() {
+        let w = World::new();
+        let uri = w.new_uri("http://example.org/plugins/my-plugin").unwrap();
+        let symbol = w.symbol(&uri).expect("Could not derive a symbol.");
+        assert_eq!(symbol.as_str(), Some("my_plugin"));
+    }
+
+    #[test]
+    fn test_uris() {
+        let world = World::new();
+        let uris = world.uris();
+        assert!(uris.is_empty());
+        let a = uris.get("http://example.org/a").unwrap();
+        let b = uris.get("http://example.org/a").unwrap();
+        assert_eq!(a.inner, b.inner);
+        assert_eq!(a, world.new_uri("http://example.org/a").unwrap());
+        assert_eq!(uris.len(), 1);
+        assert!(matches!(uris.get("a\0b"), Err(Error::InteriorNul(_))));
+        drop(world);
+        // The nodes keep the world and the cache alive.
+        assert_eq!(a.as_uri(), Some("http://example.org/a"));
     }
 }